@@ -52,6 +52,7 @@ pub fn run(matches: &ArgMatches) -> Result<Response, Error> {
             _ => Err(Error::UsageErr("ilp-cli help accounts")),
         },
         ("pay", Some(pay_matches)) => client.post_account_payments(pay_matches),
+        ("quote", Some(quote_matches)) => client.post_account_quotes(quote_matches),
         ("rates", Some(rates_matches)) => match rates_matches.subcommand() {
             ("list", Some(submatches)) => client.get_rates(submatches),
             ("set-all", Some(submatches)) => client.put_rates(submatches),
@@ -59,6 +60,9 @@ pub fn run(matches: &ArgMatches) -> Result<Response, Error> {
         },
         ("routes", Some(routes_matches)) => match routes_matches.subcommand() {
             ("list", Some(submatches)) => client.get_routes(submatches),
+            ("resolve", Some(submatches)) => client.get_route_resolution(submatches),
+            ("peers", Some(submatches)) => client.get_ccp_peers(submatches),
+            ("resync", Some(submatches)) => client.post_ccp_peer_resync(submatches),
             ("set", Some(submatches)) => client.put_route_static(submatches),
             ("set-all", Some(submatches)) => client.put_routes_static(submatches),
             _ => Err(Error::UsageErr("ilp-cli help routes")),
@@ -75,6 +79,12 @@ pub fn run(matches: &ArgMatches) -> Result<Response, Error> {
         },
         ("payments", Some(payments_matches)) => match payments_matches.subcommand() {
             ("incoming", Some(submatches)) => client.ws_payments_incoming(submatches),
+            ("outgoing", Some(submatches)) => client.get_payments_outgoing(submatches),
+            ("stream", Some(submatches)) => client.post_payments_streaming(submatches),
+            ("stream-status", Some(submatches)) => client.get_payments_streaming(submatches),
+            ("stream-control", Some(submatches)) => {
+                client.post_payments_streaming_action(submatches)
+            }
             _ => Err(Error::UsageErr("ilp-cli help payments")),
         },
         _ => Err(Error::UsageErr("ilp-cli help")),
@@ -161,6 +171,59 @@ impl NodeClient<'_> {
         }
     }
 
+    // GET /accounts/:username/payments/outgoing
+    fn get_payments_outgoing(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, mut args) = extract_args(matches);
+        let user = args.remove("username").unwrap(); // infallible unwrap
+        self.client
+            .get(&format!("{}/accounts/{}/payments/outgoing", self.url, user))
+            .bearer_auth(auth)
+            .query(&args)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // POST /accounts/:username/payments/streaming
+    fn post_payments_streaming(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, mut args) = extract_args(matches);
+        let user = args.remove("sender_username").unwrap(); // infallible unwrap
+        self.client
+            .post(&format!(
+                "{}/accounts/{}/payments/streaming",
+                self.url, user
+            ))
+            .bearer_auth(auth)
+            .json(&args)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET /accounts/:username/payments/streaming/:id
+    fn get_payments_streaming(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, args) = extract_args(matches);
+        self.client
+            .get(&format!(
+                "{}/accounts/{}/payments/streaming/{}",
+                self.url, args["username"], args["id"]
+            ))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // POST /accounts/:username/payments/streaming/:id/:action
+    fn post_payments_streaming_action(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, args) = extract_args(matches);
+        self.client
+            .post(&format!(
+                "{}/accounts/{}/payments/streaming/{}/{}",
+                self.url, args["username"], args["id"], args["action"]
+            ))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
     // WebSocket /payments/incoming
     fn ws_payments_incoming(&self, matches: &ArgMatches) -> Result<Response, Error> {
         let (auth, _args) = extract_args(matches);
@@ -233,6 +296,18 @@ impl NodeClient<'_> {
             .map_err(Error::SendErr)
     }
 
+    // POST /accounts/:username/quotes
+    fn post_account_quotes(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, mut args) = extract_args(matches);
+        let user = args.remove("sender_username").unwrap(); // infallible unwrap
+        self.client
+            .post(&format!("{}/accounts/{}/quotes", self.url, user))
+            .bearer_auth(auth)
+            .json(&args)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
     // GET /rates
     fn get_rates(&self, _matches: &ArgMatches) -> Result<Response, Error> {
         self.client
@@ -260,6 +335,38 @@ impl NodeClient<'_> {
             .map_err(Error::SendErr)
     }
 
+    // GET /routes/resolve/:address
+    fn get_route_resolution(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let address = matches.value_of("address").unwrap(); // infallible unwrap
+        self.client
+            .get(&format!("{}/routes/resolve/{}", self.url, address))
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // GET /ccp/peers
+    fn get_ccp_peers(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, _) = extract_args(matches);
+        self.client
+            .get(&format!("{}/ccp/peers", self.url))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
+    // POST /ccp/peers/:username/resync
+    fn post_ccp_peer_resync(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, args) = extract_args(matches);
+        self.client
+            .post(&format!(
+                "{}/ccp/peers/{}/resync",
+                self.url, args["username"]
+            ))
+            .bearer_auth(auth)
+            .send()
+            .map_err(Error::SendErr)
+    }
+
     // PUT /routes/static/:prefix
     fn put_route_static(&self, matches: &ArgMatches) -> Result<Response, Error> {
         let (auth, args) = extract_args(matches);
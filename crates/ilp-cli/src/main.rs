@@ -75,7 +75,7 @@ mod interface_tests {
     #[test]
     fn accounts_create() {
         should_parse(&[
-            "ilp-cli accounts create alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
+            "ilp-cli accounts create alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --min-incoming-packet-amount 10 --min-incoming-exchange-rate 0.9 --route-import-allow example.a,example.b --route-import-deny example.a.c --route-export-allow example. --route-export-deny example.d --max-route-path-length 5 --max-imported-routes 100 --strict-route-auth true --route-cost 3 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
             "ilp-cli accounts create alice --auth foo --asset-code ABC --asset-scale 3 --min-balance -1000 --settle-threshold -10", // negative numbers
         ]);
     }
@@ -84,7 +84,7 @@ mod interface_tests {
     fn accounts_update() {
         should_parse(&[
             "ilp-cli accounts update alice --auth foo --asset-code ABC --asset-scale 9", // minimal
-            "ilp-cli accounts update alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
+            "ilp-cli accounts update alice --auth foo --asset-code XYZ --asset-scale 6 --ilp-address bar --max-packet-amount 100 --min-balance 0 --ilp-over-http-url qux --ilp-over-http-incoming-token baz --ilp-over-http-outgoing-token qaz --ilp-over-btp-url spam --ilp-over-btp-outgoing-token ham --ilp-over-btp-incoming-token eggs --settle-threshold 0 --settle-to 0 --routing-relation foobar --round-trip-time 1000 --amount-per-minute-limit 42 --packets-per-minute-limit 4 --min-incoming-packet-amount 10 --min-incoming-exchange-rate 0.9 --route-import-allow example.a,example.b --route-import-deny example.a.c --route-export-allow example. --route-export-deny example.d --max-route-path-length 5 --max-imported-routes 100 --strict-route-auth true --route-cost 3 --settlement-engine-url if_you_can_read_this_congratulations_youve_scrolled_too_far_right", // maximal
         ]);
     }
 
@@ -137,6 +137,34 @@ mod interface_tests {
     fn pay() {
         should_parse(&[
             "ilp-cli pay alice --auth foo --amount 500 --to bar", // minimal
+            "ilp-cli pay alice --auth foo --amount 500 --deliver 400 --to bar", // fixed delivery
+            "ilp-cli pay alice --auth foo --amount 500 --destination-account example.bar --shared-secret Zm9v", // without SPSP
+            "ilp-cli pay alice --auth foo --amount 500 --to bar --congestion-control low_latency", // congestion control
+        ]);
+    }
+
+    #[test]
+    fn payments_outgoing() {
+        should_parse(&[
+            "ilp-cli payments outgoing alice --auth foo", // minimal
+            "ilp-cli payments outgoing alice --auth foo --offset 20 --limit 10", // paginated
+        ]);
+    }
+
+    #[test]
+    fn payments_stream() {
+        should_parse(&[
+            "ilp-cli payments stream alice --auth foo --rate 10 --max 500 --to bar", // minimal
+            "ilp-cli payments stream alice --auth foo --rate 10 --max 500 --duration 30 --destination-account example.bar --shared-secret Zm9v --congestion-control low_latency", // maximal
+            "ilp-cli payments stream-status alice 4e8f7a53-5e4a-4d9c-8c57-0b3b0d3a7e4f --auth foo", // status
+            "ilp-cli payments stream-control alice 4e8f7a53-5e4a-4d9c-8c57-0b3b0d3a7e4f pause --auth foo", // control
+        ]);
+    }
+
+    #[test]
+    fn quote() {
+        should_parse(&[
+            "ilp-cli quote alice --auth foo --amount 500 --to bar", // minimal
         ]);
     }
 
@@ -164,6 +192,27 @@ mod interface_tests {
         ]);
     }
 
+    #[test]
+    fn routes_resolve() {
+        should_parse(&[
+            "ilp-cli routes resolve foo", // minimal
+        ]);
+    }
+
+    #[test]
+    fn routes_peers() {
+        should_parse(&[
+            "ilp-cli routes peers --auth foo", // minimal
+        ]);
+    }
+
+    #[test]
+    fn routes_resync() {
+        should_parse(&[
+            "ilp-cli routes resync alice --auth foo", // minimal
+        ]);
+    }
+
     #[test]
     fn routes_set() {
         should_parse(&[
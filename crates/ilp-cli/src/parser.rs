@@ -13,13 +13,27 @@ pub fn build<'a, 'b>() -> App<'a, 'b> {
             accounts_update_settings(),
         ]),
         pay(),
+        quote(),
         rates().subcommands(vec![rates_list(), rates_set_all()]),
-        routes().subcommands(vec![routes_list(), routes_set(), routes_set_all()]),
+        routes().subcommands(vec![
+            routes_list(),
+            routes_resolve(),
+            routes_peers(),
+            routes_resync(),
+            routes_set(),
+            routes_set_all(),
+        ]),
         settlement_engines().subcommands(vec![settlement_engines_set_all()]),
         status(),
         logs(),
         testnet().subcommands(vec![testnet_setup()]),
-        payments().subcommands(vec![payments_incoming()]),
+        payments().subcommands(vec![
+            payments_incoming(),
+            payments_outgoing(),
+            payments_stream(),
+            payments_stream_status(),
+            payments_stream_control(),
+        ]),
     ])
 }
 
@@ -143,6 +157,37 @@ fn accounts_create<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("packets_per_minute_limit")
                 .long("packets-per-minute-limit")
                 .takes_value(true),
+            Arg::with_name("min_incoming_packet_amount")
+                .long("min-incoming-packet-amount")
+                .takes_value(true),
+            Arg::with_name("min_incoming_exchange_rate")
+                .long("min-incoming-exchange-rate")
+                .takes_value(true),
+            Arg::with_name("route_import_allow")
+                .long("route-import-allow")
+                .takes_value(true),
+            Arg::with_name("route_import_deny")
+                .long("route-import-deny")
+                .takes_value(true),
+            Arg::with_name("route_export_allow")
+                .long("route-export-allow")
+                .takes_value(true),
+            Arg::with_name("route_export_deny")
+                .long("route-export-deny")
+                .takes_value(true),
+            Arg::with_name("max_route_path_length")
+                .long("max-route-path-length")
+                .takes_value(true),
+            Arg::with_name("max_imported_routes")
+                .long("max-imported-routes")
+                .takes_value(true),
+            Arg::with_name("strict_route_auth")
+                .long("strict-route-auth")
+                .takes_value(true)
+                .possible_values(&["true", "false"]),
+            Arg::with_name("route_cost")
+                .long("route-cost")
+                .takes_value(true),
             Arg::with_name("settlement_engine_url")
                 .long("settlement-engine-url")
                 .takes_value(true),
@@ -214,6 +259,37 @@ fn accounts_update<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("packets_per_minute_limit")
                 .long("packets-per-minute-limit")
                 .takes_value(true),
+            Arg::with_name("min_incoming_packet_amount")
+                .long("min-incoming-packet-amount")
+                .takes_value(true),
+            Arg::with_name("min_incoming_exchange_rate")
+                .long("min-incoming-exchange-rate")
+                .takes_value(true),
+            Arg::with_name("route_import_allow")
+                .long("route-import-allow")
+                .takes_value(true),
+            Arg::with_name("route_import_deny")
+                .long("route-import-deny")
+                .takes_value(true),
+            Arg::with_name("route_export_allow")
+                .long("route-export-allow")
+                .takes_value(true),
+            Arg::with_name("route_export_deny")
+                .long("route-export-deny")
+                .takes_value(true),
+            Arg::with_name("max_route_path_length")
+                .long("max-route-path-length")
+                .takes_value(true),
+            Arg::with_name("max_imported_routes")
+                .long("max-imported-routes")
+                .takes_value(true),
+            Arg::with_name("strict_route_auth")
+                .long("strict-route-auth")
+                .takes_value(true)
+                .possible_values(&["true", "false"]),
+            Arg::with_name("route_cost")
+                .long("route-cost")
+                .takes_value(true),
             Arg::with_name("settlement_engine_url")
                 .long("settlement-engine-url")
                 .takes_value(true),
@@ -313,12 +389,54 @@ fn pay<'a, 'b>() -> App<'a, 'b> {
                 .long("amount")
                 .takes_value(true)
                 .required(true)
-                .help("The amount to transfer from the sender to the receiver, denominated in units of the sender's assets"),
+                .help("The amount to transfer from the sender to the receiver, denominated in units of the sender's assets. If --deliver is used, the maximum amount to transfer"),
+            Arg::with_name("destination_amount")
+                .long("deliver")
+                .takes_value(true)
+                .help("The exact amount the receiver should get, denominated in units of the receiver's assets"),
             Arg::with_name("receiver")
                 .long("to")
                 .takes_value(true)
-                .required(true)
+                .required_unless("destination_account")
+                .conflicts_with("destination_account")
                 .help("The Payment Pointer or SPSP address of the account receiving the payment"),
+            Arg::with_name("destination_account")
+                .long("destination-account")
+                .takes_value(true)
+                .requires("shared_secret")
+                .help("The ILP address of the receiver's STREAM server, if its credentials were exchanged without SPSP"),
+            Arg::with_name("shared_secret")
+                .long("shared-secret")
+                .takes_value(true)
+                .requires("destination_account")
+                .help("The base64-encoded STREAM shared secret to use with --destination-account"),
+            Arg::with_name("congestion_control")
+                .long("congestion-control")
+                .takes_value(true)
+                .possible_values(&["aimd", "low_latency"])
+                .help("The congestion control algorithm: aimd sends as much as possible per packet, low_latency gets money flowing quickly with small packets [default: aimd]"),
+        ])
+}
+
+fn quote<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("quote")
+        .about("Quote a payment from an account on this node without sending any money")
+        .args(&[
+            Arg::with_name("sender_username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account on this node that would issue the payment"),
+            Arg::with_name("source_amount")
+                .long("amount")
+                .takes_value(true)
+                .required(true)
+                .help("The amount to quote, denominated in units of the sender's assets"),
+            Arg::with_name("receiver")
+                .long("to")
+                .takes_value(true)
+                .required(true)
+                .help("The Payment Pointer or SPSP address of the account that would receive the payment"),
         ])
 }
 
@@ -350,6 +468,35 @@ fn routes_list<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("list").about("View this node's routing table")
 }
 
+fn routes_resolve<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("resolve")
+        .about("Explain which account this node would forward packets for an address to")
+        .arg(
+            Arg::with_name("address")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The ILP address to resolve"),
+        )
+}
+
+fn routes_peers<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("peers")
+        .about("View the state of the peers this node exchanges routes with")
+}
+
+fn routes_resync<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("resync")
+        .about("Ask a peer to send this node all of its routes again")
+        .arg(
+            Arg::with_name("username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the peer's account"),
+        )
+}
+
 fn routes_set<'a, 'b>() -> App<'a, 'b> {
     AuthorizedSubCommand::with_name("set")
         .about("Configure a single static route on this node")
@@ -439,3 +586,110 @@ fn payments_incoming<'a, 'b>() -> App<'a, 'b> {
     AuthorizedSubCommand::with_name("incoming")
         .about("Open a persistent connection to a node for monitoring all incoming payments")
 }
+
+fn payments_outgoing<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("outgoing")
+        .about("List the payments sent from an account, most recent first")
+        .args(&[
+            Arg::with_name("username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account whose payments to list"),
+            Arg::with_name("offset")
+                .long("offset")
+                .takes_value(true)
+                .help("The number of most recent payments to skip"),
+            Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .help("The maximum number of payments to list (defaults to 20)"),
+        ])
+}
+
+fn payments_stream<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("stream")
+        .about("Start sending money from an account on this node at a fixed rate, until stopped or the maximum amount is sent")
+        .args(&[
+            Arg::with_name("sender_username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account on this node issuing the payment"),
+            Arg::with_name("amount_per_second")
+                .long("rate")
+                .takes_value(true)
+                .required(true)
+                .help("The amount to send per second, denominated in units of the sender's assets"),
+            Arg::with_name("max_amount")
+                .long("max")
+                .takes_value(true)
+                .required(true)
+                .help("The maximum total amount to send, denominated in units of the sender's assets"),
+            Arg::with_name("duration")
+                .long("duration")
+                .takes_value(true)
+                .help("The number of seconds to stream for, not counting the time the payment is paused"),
+            Arg::with_name("receiver")
+                .long("to")
+                .takes_value(true)
+                .required_unless("destination_account")
+                .conflicts_with("destination_account")
+                .help("The Payment Pointer or SPSP address of the account receiving the payment"),
+            Arg::with_name("destination_account")
+                .long("destination-account")
+                .takes_value(true)
+                .requires("shared_secret")
+                .help("The ILP address of the receiver's STREAM server, if its credentials were exchanged without SPSP"),
+            Arg::with_name("shared_secret")
+                .long("shared-secret")
+                .takes_value(true)
+                .requires("destination_account")
+                .help("The base64-encoded STREAM shared secret to use with --destination-account"),
+            Arg::with_name("congestion_control")
+                .long("congestion-control")
+                .takes_value(true)
+                .possible_values(&["aimd", "low_latency"])
+                .help("The congestion control algorithm [default: aimd]"),
+        ])
+}
+
+fn payments_stream_status<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("stream-status")
+        .about("Show the status of a streaming payment and the amounts sent and delivered so far")
+        .args(&[
+            Arg::with_name("username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account sending the payment"),
+            Arg::with_name("id")
+                .index(2)
+                .takes_value(true)
+                .required(true)
+                .help("The id of the streaming payment"),
+        ])
+}
+
+fn payments_stream_control<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("stream-control")
+        .about("Pause, resume or stop a streaming payment")
+        .args(&[
+            Arg::with_name("username")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account sending the payment"),
+            Arg::with_name("id")
+                .index(2)
+                .takes_value(true)
+                .required(true)
+                .help("The id of the streaming payment"),
+            Arg::with_name("action")
+                .index(3)
+                .takes_value(true)
+                .required(true)
+                .possible_values(&["pause", "resume", "stop"])
+                .help("Whether to pause, resume or stop the payment. A stopped payment cannot be resumed"),
+        ])
+}
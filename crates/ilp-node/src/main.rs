@@ -95,6 +95,38 @@ async fn main() {
             .long("route_broadcast_interval")
             .takes_value(true)
             .help("Interval, defined in milliseconds, on which the node will broadcast routing information to other nodes using CCP. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("route_expiry_time")
+            .long("route_expiry_time")
+            .takes_value(true)
+            .help("How long, defined in milliseconds, other nodes should keep the routes we broadcast if they stop receiving updates from us. This should be longer than the route broadcast interval. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("route_flap_dampening_window")
+            .long("route_flap_dampening_window")
+            .takes_value(true)
+            .help("Window, defined in milliseconds, in which route withdrawals are counted for route flap dampening. Defaults to 300000ms (5 minutes)."),
+        Arg::with_name("route_flap_dampening_threshold")
+            .long("route_flap_dampening_threshold")
+            .takes_value(true)
+            .help("Number of times a route may be withdrawn within the dampening window before the node stops re-advertising it. Defaults to 3. Set to 0 to disable route flap dampening."),
+        Arg::with_name("max_routing_state_age")
+            .long("max_routing_state_age")
+            .takes_value(true)
+            .help("How old, defined in milliseconds, the routing tables saved in the database may be for the node to load them when it restarts. Defaults to 300000ms (5 minutes). Set to 0 to disable saving the routing tables."),
+        Arg::with_name("route_auth_anchor_ttl")
+            .long("route_auth_anchor_ttl")
+            .takes_value(true)
+            .help("How long, defined in milliseconds, the node remembers the auth anchor it accepted for a prefix after it last received a route with it. Defaults to 604800000ms (7 days)."),
+        Arg::with_name("route_selection.path_length_weight")
+            .long("route_selection.path_length_weight")
+            .takes_value(true)
+            .help("Weight of the path length when choosing between CCP routes to the same prefix from accounts with the same routing relation. The route with the lowest combined score is used. Defaults to 1."),
+        Arg::with_name("route_selection.cost_weight")
+            .long("route_selection.cost_weight")
+            .takes_value(true)
+            .help("Weight of the route cost (the cost advertised by the peer plus the route_cost configured for its account) when choosing between CCP routes. Defaults to 0."),
+        Arg::with_name("route_selection.rtt_weight")
+            .long("route_selection.rtt_weight")
+            .takes_value(true)
+            .help("Weight of the round trip time, defined in milliseconds, of the CCP messages sent to the peer when choosing between CCP routes. Defaults to 0."),
         Arg::with_name("exchange_rate.provider")
             .long("exchange_rate.provider")
             .takes_value(true)
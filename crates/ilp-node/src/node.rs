@@ -22,7 +22,7 @@ cfg_if! {
         };
         use interledger::service::IncomingService;
         use futures::FutureExt;
-        use std::{io::{self, Stdout}, sync::Arc};
+        use std::io::{self, Stdout};
     }
 }
 
@@ -35,7 +35,10 @@ use hex::FromHex;
 use interledger::{
     api::{NodeApi, NodeStore},
     btp::{btp_service_as_filter, connect_client, BtpOutgoingService, BtpStore},
-    ccp::{CcpRouteManagerBuilder, CcpRoutingAccount, CcpRoutingStore, RoutingRelation},
+    ccp::{
+        CcpRouteManagerBuilder, CcpRoutingAccount, CcpRoutingStore, RouteSelectionPolicy,
+        RoutingRelation,
+    },
     errors::*,
     http::{HttpClientService, HttpServer as IlpOverHttpServer, HttpStore},
     ildcp::IldcpService,
@@ -59,15 +62,21 @@ use interledger::{
         },
     },
     store::account::Account,
-    stream::{StreamNotificationsStore, StreamReceiverService},
+    stream::{
+        InMemoryConnectionStore, IncomingPaymentStore, StreamNotificationsStore,
+        StreamReceiverService,
+    },
 };
 use num_bigint::BigUint;
 use once_cell::sync::Lazy;
+use ring::hmac;
 use serde::{de::Error as DeserializeError, Deserialize, Deserializer};
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     net::SocketAddr,
     str::{self, FromStr},
+    sync::Arc,
     time::Duration,
 };
 use tokio::spawn;
@@ -85,6 +94,7 @@ use interledger::service_util::BalanceService;
 pub use interledger::rates::ExchangeRateProvider;
 
 static DEFAULT_ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("local.host").unwrap());
+static ROUTING_SECRET_GENERATION_STRING: &str = "ilp_routing_secret";
 
 fn default_settlement_api_bind_address() -> SocketAddr {
     SocketAddr::from(([127, 0, 0, 1], 7771))
@@ -103,6 +113,18 @@ fn default_database_url() -> String {
     panic!("no backing store configured")
 }
 
+/// Derive the secret used to authenticate the CCP routes we advertise.
+/// It is derived from the secret seed so that it stays the same across restarts.
+fn generate_routing_secret(secret_seed: &[u8; 32]) -> [u8; 32] {
+    let mut routing_secret: [u8; 32] = [0; 32];
+    let sig = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret_seed),
+        ROUTING_SECRET_GENERATION_STRING.as_bytes(),
+    );
+    routing_secret.copy_from_slice(sig.as_ref());
+    routing_secret
+}
+
 fn deserialize_optional_address<'de, D>(deserializer: D) -> Result<Option<Address>, D::Error>
 where
     D: Deserializer<'de>,
@@ -128,6 +150,26 @@ where
     })
 }
 
+fn deserialize_route_auth_anchors<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, [u8; 32]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(prefix, anchor)| {
+            let anchor = <[u8; 32]>::from_hex(&anchor).map_err(|err| {
+                DeserializeError::custom(format!(
+                    "Invalid auth anchor for prefix {} (must be 32 hex-encoded bytes): {:?}",
+                    prefix, err
+                ))
+            })?;
+            Ok((prefix, anchor))
+        })
+        .collect()
+}
+
 fn deserialize_optional_username<'de, D>(deserializer: D) -> Result<Option<Username>, D::Error>
 where
     D: Deserializer<'de>,
@@ -178,6 +220,40 @@ impl ExchangeRateConfig {
     }
 }
 
+/// Configuration for how the node chooses between CCP routes to the same prefix
+/// from accounts with the same routing relation. Each route gets a score that
+/// combines the following weights, and the route with the lowest score is used.
+#[derive(Deserialize, Clone)]
+pub struct RouteSelectionConfig {
+    /// Weight of the number of connectors in the route's path. Defaults to 1.
+    #[serde(default = "RouteSelectionConfig::default_path_length_weight")]
+    pub path_length_weight: u32,
+    /// Weight of the route's cost, which is the cost advertised by the peer plus the
+    /// `route_cost` configured for the peer's account. Defaults to 0.
+    #[serde(default)]
+    pub cost_weight: u32,
+    /// Weight of the round trip time, defined in milliseconds, of the CCP messages
+    /// the node sends to the peer. Defaults to 0.
+    #[serde(default)]
+    pub rtt_weight: u32,
+}
+
+impl RouteSelectionConfig {
+    fn default_path_length_weight() -> u32 {
+        1
+    }
+}
+
+impl Default for RouteSelectionConfig {
+    fn default() -> Self {
+        RouteSelectionConfig {
+            path_length_weight: RouteSelectionConfig::default_path_length_weight(),
+            cost_weight: 0,
+            rtt_weight: 0,
+        }
+    }
+}
+
 /// An all-in-one Interledger node that includes sender and receiver functionality,
 /// a connector, and a management API.
 /// Will connect to the database at the given URL; see the crate features defined in
@@ -215,6 +291,31 @@ pub struct InterledgerNode {
     /// Interval, defined in milliseconds, on which the node will broadcast routing
     /// information to other nodes using CCP. Defaults to 30000ms (30 seconds).
     pub route_broadcast_interval: Option<u64>,
+    /// How long, defined in milliseconds, other nodes should keep the routes we broadcast
+    /// if they stop receiving updates from us. Defaults to 30000ms (30 seconds).
+    pub route_expiry_time: Option<u32>,
+    /// Window, defined in milliseconds, in which route withdrawals are counted for
+    /// route flap dampening. Defaults to 300000ms (5 minutes).
+    pub route_flap_dampening_window: Option<u64>,
+    /// Number of times a route may be withdrawn within the dampening window before the node
+    /// stops re-advertising it. Defaults to 3. Setting this to 0 disables route flap dampening.
+    pub route_flap_dampening_threshold: Option<u32>,
+    /// How old, defined in milliseconds, the routing tables saved in the store may be for the
+    /// node to load them on startup. Defaults to 300000ms (5 minutes). Setting this to 0
+    /// disables saving the routing tables.
+    pub max_routing_state_age: Option<u64>,
+    /// Hex-encoded auth anchors of the CCP routes for specific prefixes, as advertised by the
+    /// nodes that own them. Routes for these prefixes are only accepted if they are
+    /// authenticated with the configured anchor.
+    #[serde(default, deserialize_with = "deserialize_route_auth_anchors")]
+    pub route_auth_anchors: HashMap<String, [u8; 32]>,
+    /// How long, defined in milliseconds, the node remembers the auth anchor it accepted for
+    /// a prefix after it last received a route with it. Defaults to 604800000ms (7 days).
+    pub route_auth_anchor_ttl: Option<u64>,
+    /// Configuration for choosing between CCP routes to the same prefix.
+    /// By default, the route with the shortest path is used.
+    #[serde(default)]
+    pub route_selection: RouteSelectionConfig,
     #[serde(default)]
     /// Configuration for calculating exchange rates between various pairs.
     pub exchange_rate: ExchangeRateConfig,
@@ -297,6 +398,7 @@ impl InterledgerNode {
             + BtpStore<Account = Account>
             + HttpStore<Account = Account>
             + StreamNotificationsStore<Account = Account>
+            + IncomingPaymentStore
             + BalanceStore
             + SettlementStore<Account = Account>
             + ExchangeRateStore
@@ -326,6 +428,13 @@ impl InterledgerNode {
         let admin_auth_token = self.admin_auth_token.clone();
         let default_spsp_account = self.default_spsp_account.clone();
         let route_broadcast_interval = self.route_broadcast_interval;
+        let route_expiry_time = self.route_expiry_time;
+        let route_flap_dampening_window = self.route_flap_dampening_window;
+        let route_flap_dampening_threshold = self.route_flap_dampening_threshold;
+        let max_routing_state_age = self.max_routing_state_age;
+        let route_auth_anchors = self.route_auth_anchors.clone();
+        let route_auth_anchor_ttl = self.route_auth_anchor_ttl;
+        let route_selection = self.route_selection.clone();
         let exchange_rate_provider = self.exchange_rate.provider.clone();
         let exchange_rate_poll_interval = self.exchange_rate.poll_interval;
         let exchange_rate_poll_failure_tolerance = self.exchange_rate.poll_failure_tolerance;
@@ -389,8 +498,13 @@ impl InterledgerNode {
         // is shortened before we check whether there is enough time left
         let outgoing_service = ValidatorService::outgoing(store.clone(), outgoing_service);
         let outgoing_service = ExpiryShortenerService::new(outgoing_service);
+        // Connections set up with receipts are tracked so that the receiver can sign them.
+        // The in-memory store drops the connections that were idle the longest once it is full
         let outgoing_service =
-            StreamReceiverService::new(secret_seed.clone(), store.clone(), outgoing_service);
+            StreamReceiverService::new(secret_seed.clone(), store.clone(), outgoing_service)
+                .with_connection_store(InMemoryConnectionStore::new(), u64::max_value())
+                .track_receipt_connections_only()
+                .with_incoming_payment_store(store.clone());
         #[cfg(feature = "balance-tracking")]
         let outgoing_service = BalanceService::new(store.clone(), outgoing_service);
         let outgoing_service =
@@ -423,13 +537,38 @@ impl InterledgerNode {
             outgoing_service.clone(),
             incoming_service,
         );
-        ccp_builder.ilp_address(ilp_address.clone());
+        ccp_builder
+            .ilp_address(ilp_address.clone())
+            .routing_secret(generate_routing_secret(&self.secret_seed));
         if let Some(ms) = route_broadcast_interval {
             ccp_builder.broadcast_interval(ms);
         }
+        if let Some(ms) = route_expiry_time {
+            ccp_builder.route_expiry_time(ms);
+        }
+        if let Some(ms) = route_flap_dampening_window {
+            ccp_builder.flap_dampening_window(ms);
+        }
+        if let Some(max_flaps) = route_flap_dampening_threshold {
+            ccp_builder.max_route_flaps(max_flaps);
+        }
+        if let Some(ms) = max_routing_state_age {
+            ccp_builder.max_routing_state_age(ms);
+        }
+        for (prefix, anchor) in route_auth_anchors {
+            ccp_builder.trusted_route_anchor(prefix, anchor);
+        }
+        if let Some(ms) = route_auth_anchor_ttl {
+            ccp_builder.route_auth_anchor_ttl(ms);
+        }
+        ccp_builder.route_selection_policy(RouteSelectionPolicy {
+            path_length_weight: route_selection.path_length_weight,
+            cost_weight: route_selection.cost_weight,
+            rtt_weight: route_selection.rtt_weight,
+        });
 
-        let incoming_service = ccp_builder.to_service();
-        let incoming_service = EchoService::new(store.clone(), incoming_service);
+        let ccp_service = ccp_builder.to_service_with_saved_routes().await;
+        let incoming_service = EchoService::new(store.clone(), ccp_service.clone());
         let incoming_service = SettlementMessageService::new(incoming_service);
         let incoming_service = IldcpService::new(incoming_service);
         let incoming_service = MaxPacketAmountService::new(store.clone(), incoming_service);
@@ -493,6 +632,8 @@ impl InterledgerNode {
             api.default_spsp_account(username);
         }
         api.node_version(env!("CARGO_PKG_VERSION").to_string());
+        api.route_explainer(Arc::new(ccp_service.clone()));
+        api.peer_inspector(Arc::new(ccp_service));
 
         cfg_if! {
             if #[cfg(feature = "monitoring")] {
@@ -1,8 +1,9 @@
 #![type_length_limit = "1707074"]
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use interledger_btp::{BtpAccount, BtpOutgoingService};
-use interledger_ccp::CcpRoutingAccount;
+use interledger_ccp::{CcpRoutingAccount, PeerInspector, RouteExplainer};
 use interledger_errors::NodeStoreError;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_packet::Address;
@@ -12,11 +13,14 @@ use interledger_service::{
     Account, AccountStore, AddressStore, IncomingService, OutgoingService, Username,
 };
 use interledger_service_util::BalanceStore;
-use interledger_settlement::core::types::{SettlementAccount, SettlementStore};
+use interledger_settlement::core::{
+    idempotency::IdempotentStore,
+    types::{SettlementAccount, SettlementStore},
+};
 use interledger_stream::StreamNotificationsStore;
 use secrecy::SecretString;
 use serde::{de, Deserialize, Serialize};
-use std::{boxed::*, collections::HashMap, fmt::Display, net::SocketAddr, str::FromStr};
+use std::{boxed::*, collections::HashMap, fmt::Display, net::SocketAddr, str::FromStr, sync::Arc};
 use url::Url;
 use uuid::Uuid;
 use warp::{self, Filter};
@@ -57,6 +61,30 @@ where
     }
 }
 
+// Lists (such as route prefixes) may be sent either as an array of strings
+// or as a single comma-separated string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListOrStr {
+    List(Vec<String>),
+    Str(String),
+}
+
+pub fn optional_list_or_string<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    match ListOrStr::deserialize(deserializer)? {
+        ListOrStr::List(list) => Ok(Some(list)),
+        ListOrStr::Str(s) => Ok(Some(
+            s.split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+        )),
+    }
+}
+
 pub fn map_of_number_or_string<'de, D>(deserializer: D) -> Result<HashMap<String, f64>, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -138,6 +166,154 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         &self,
         asset_code: &str,
     ) -> Result<Option<Url>, NodeStoreError>;
+
+    /// Saves the record of a payment sent from the account corresponding to the provided id
+    async fn save_outgoing_payment(
+        &self,
+        account_id: Uuid,
+        payment: OutgoingPayment,
+    ) -> Result<(), NodeStoreError>;
+
+    /// Gets the payments sent from the account corresponding to the provided id,
+    /// most recent first, skipping `offset` payments and returning at most `limit`
+    async fn get_outgoing_payments(
+        &self,
+        account_id: Uuid,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<OutgoingPayment>, NodeStoreError>;
+
+    /// Saves a new incoming payment for the account corresponding to the provided id
+    async fn create_incoming_payment(
+        &self,
+        account_id: Uuid,
+        payment: IncomingPayment,
+    ) -> Result<(), NodeStoreError>;
+
+    /// Gets one of the incoming payments of the account corresponding to the provided id
+    async fn get_incoming_payment(
+        &self,
+        account_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<IncomingPayment>, NodeStoreError>;
+
+    /// Gets the incoming payments of the account corresponding to the provided id,
+    /// most recent first, skipping `offset` payments and returning at most `limit`
+    async fn get_incoming_payments(
+        &self,
+        account_id: Uuid,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<IncomingPayment>, NodeStoreError>;
+}
+
+/// Outcome of a payment sent from an account on this node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutgoingPaymentStatus {
+    /// The full amount was sent (or delivered, for fixed-delivery payments)
+    Completed,
+    /// The payment stopped because of an error
+    Failed,
+}
+
+/// Record of a STREAM payment sent from an account on this node, so that users
+/// can reconcile what they sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutgoingPayment {
+    /// Unique id of the payment
+    pub id: Uuid,
+    /// The Payment Pointer or SPSP URL of the receiver, if the payment was set up with SPSP
+    pub receiver: Option<String>,
+    /// The receiver's ILP Address, if the payment got far enough to know it
+    pub destination_account: Option<Address>,
+    /// Asset code of the sender
+    pub source_asset_code: String,
+    /// Asset scale of the sender
+    pub source_asset_scale: u8,
+    /// Amount requested to be sent (or the maximum to send, for fixed-delivery payments), in source units
+    pub source_amount: u64,
+    /// Amount requested to be delivered for fixed-delivery payments, in destination units
+    pub destination_amount: Option<u64>,
+    /// Amount fulfilled, in source units
+    pub sent_amount: u64,
+    /// Amount the receiver got, in destination units
+    pub delivered_amount: u64,
+    /// Asset code of the receiver, if they reported it
+    pub destination_asset_code: Option<String>,
+    /// Asset scale of the receiver, if they reported it
+    pub destination_asset_scale: Option<u8>,
+    /// Whether the payment completed or failed
+    pub status: OutgoingPaymentStatus,
+    /// Why the payment failed
+    pub error: Option<String>,
+    /// The time the payment was started in RFC3339 format
+    pub started_at: String,
+    /// The time the payment completed or failed in RFC3339 format
+    pub finished_at: String,
+}
+
+/// Whether an incoming payment still accepts money
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncomingPaymentStatus {
+    /// Waiting for (the rest of) the amount
+    Pending,
+    /// The full amount was received
+    Completed,
+    /// The incoming payment expired before the full amount was received
+    Expired,
+}
+
+/// An amount an account on this node expects to receive, such as an invoice. It is paid over
+/// STREAM connections tagged with its id, so that the money received can be attributed to it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IncomingPayment {
+    /// Unique id of the incoming payment, which is also the tag of its STREAM connections
+    pub id: Uuid,
+    /// The ILP Address payers send to, which the STREAM shared secret is derived from
+    pub destination_account: Address,
+    /// Asset code of the receiving account
+    pub asset_code: String,
+    /// Asset scale of the receiving account
+    pub asset_scale: u8,
+    /// Amount expected, in the receiving account's units
+    pub amount: u64,
+    /// Amount received so far, in the receiving account's units
+    pub received_amount: u64,
+    /// What the incoming payment is for
+    pub description: Option<String>,
+    /// The time the incoming payment was created in RFC3339 format
+    pub created_at: String,
+    /// The time after which the incoming payment no longer accepts money in RFC3339 format
+    pub expires_at: Option<String>,
+    /// The time the full amount was received in RFC3339 format
+    pub completed_at: Option<String>,
+}
+
+impl IncomingPayment {
+    pub fn status(&self) -> IncomingPaymentStatus {
+        let is_expired = self
+            .expires_at
+            .as_ref()
+            .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+            .map_or(false, |expires_at| expires_at <= Utc::now());
+        if self.completed_at.is_some() {
+            IncomingPaymentStatus::Completed
+        } else if is_expired {
+            IncomingPaymentStatus::Expired
+        } else {
+            IncomingPaymentStatus::Pending
+        }
+    }
+
+    /// How much more the incoming payment accepts, which is nothing once it is completed or expired
+    pub fn remaining_amount(&self) -> u64 {
+        match self.status() {
+            IncomingPaymentStatus::Pending => self.amount.saturating_sub(self.received_amount),
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -256,6 +432,38 @@ pub struct AccountDetails {
     /// The limit of packets the account can send per minute
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub packets_per_minute_limit: Option<u32>,
+    /// The minimum amount each STREAM packet paying the account must deliver
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub min_incoming_packet_amount: Option<u64>,
+    /// The minimum exchange rate at which STREAM packets paying the account may
+    /// have been converted from the source amount their sender declares
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub min_incoming_exchange_rate: Option<f64>,
+    /// Prefixes the node accepts CCP routes for from the account (all if not set)
+    #[serde(default, deserialize_with = "optional_list_or_string")]
+    pub route_import_allow: Option<Vec<String>>,
+    /// Prefixes the node never accepts CCP routes for from the account
+    #[serde(default, deserialize_with = "optional_list_or_string")]
+    pub route_import_deny: Option<Vec<String>>,
+    /// Prefixes the node advertises CCP routes for to the account (all if not set)
+    #[serde(default, deserialize_with = "optional_list_or_string")]
+    pub route_export_allow: Option<Vec<String>>,
+    /// Prefixes the node never advertises CCP routes for to the account
+    #[serde(default, deserialize_with = "optional_list_or_string")]
+    pub route_export_deny: Option<Vec<String>>,
+    /// The maximum number of hops in routes accepted from the account
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub max_route_path_length: Option<u32>,
+    /// The maximum number of routes accepted from the account
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub max_imported_routes: Option<u32>,
+    /// Whether the node drops unauthenticated CCP routes from the account (defaults to false)
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub strict_route_auth: Option<bool>,
+    /// The cost of sending packets through the account, used to rank the routes it sends us
+    /// and added to the cost of the routes we advertise through it (defaults to 0)
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub route_cost: Option<u32>,
     /// The account's settlement engine URL. If a global engine url is configured
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
@@ -278,6 +486,10 @@ pub struct NodeApi<S, I, O, B, A: Account> {
     /// Server secret used to instantiate SPSP/Stream connections
     server_secret: Bytes,
     node_version: Option<String>,
+    /// Used to explain where the routes in the routing table came from
+    route_explainer: Option<Arc<dyn RouteExplainer>>,
+    /// Used to inspect the state of the node's CCP peers and request routes from them
+    peer_inspector: Option<Arc<dyn PeerInspector>>,
 }
 
 impl<S, I, O, B, A> NodeApi<S, I, O, B, A>
@@ -290,7 +502,8 @@ where
         + SettlementStore<Account = A>
         + StreamNotificationsStore<Account = A>
         + RouterStore
-        + ExchangeRateStore,
+        + ExchangeRateStore
+        + IdempotentStore,
     I: IncomingService<A> + Clone + Send + Sync + 'static,
     O: OutgoingService<A> + Clone + Send + Sync + 'static,
     B: OutgoingService<A> + Clone + Send + Sync + 'static,
@@ -321,6 +534,8 @@ where
             btp,
             server_secret,
             node_version: None,
+            route_explainer: None,
+            peer_inspector: None,
         }
     }
 
@@ -338,6 +553,19 @@ where
         self
     }
 
+    /// Sets the service used to explain the candidates for each route,
+    /// which is typically the CCP route manager
+    pub fn route_explainer(&mut self, route_explainer: Arc<dyn RouteExplainer>) -> &mut Self {
+        self.route_explainer = Some(route_explainer);
+        self
+    }
+
+    /// Sets the service used to inspect and re-sync the node's CCP peers
+    pub fn peer_inspector(&mut self, peer_inspector: Arc<dyn PeerInspector>) -> &mut Self {
+        self.peer_inspector = Some(peer_inspector);
+        self
+    }
+
     /// Returns a Warp Filter which exposes the accounts and admin APIs
     pub fn into_warp_filter(self) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
         routes::accounts_api(
@@ -353,6 +581,8 @@ where
             self.admin_api_token,
             self.node_version,
             self.store,
+            self.route_explainer,
+            self.peer_inspector,
         ))
         .boxed()
     }
@@ -388,6 +618,38 @@ mod tests {
         assert!(serde_json::from_str::<One>("{\"val\":\"-1\"}").is_err());
     }
 
+    #[test]
+    fn optional_list_or_string_deserialization() {
+        #[derive(PartialEq, Deserialize, Debug)]
+        struct List {
+            #[serde(default, deserialize_with = "optional_list_or_string")]
+            val: Option<Vec<String>>,
+        }
+        assert_eq!(
+            serde_json::from_str::<List>("{\"val\":[\"example.a\",\"example.b\"]}").unwrap(),
+            List {
+                val: Some(vec!["example.a".to_string(), "example.b".to_string()])
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<List>("{\"val\":\"example.a, example.b\"}").unwrap(),
+            List {
+                val: Some(vec!["example.a".to_string(), "example.b".to_string()])
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<List>("{\"val\":\"\"}").unwrap(),
+            List {
+                val: Some(Vec::new())
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<List>("{}").unwrap(),
+            List { val: None }
+        );
+        assert!(serde_json::from_str::<List>("{\"val\":1}").is_err());
+    }
+
     #[test]
     fn optional_number_or_string_deserialization() {
         #[derive(PartialEq, Deserialize, Debug)]
@@ -1,12 +1,18 @@
-use crate::{number_or_string, AccountDetails, AccountSettings, NodeStore};
+use crate::{
+    number_or_string, optional_number_or_string, AccountDetails, AccountSettings, IncomingPayment,
+    IncomingPaymentStatus, NodeStore, OutgoingPayment, OutgoingPaymentStatus,
+};
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use futures::{Future, FutureExt, StreamExt, TryFutureExt};
+use http::{HeaderMap, Response, StatusCode};
 use interledger_btp::{connect_to_service_account, BtpAccount, BtpOutgoingService};
 use interledger_ccp::{CcpRoutingAccount, Mode, RouteControlRequest, RoutingRelation};
 use interledger_errors::*;
 use interledger_http::{deserialize_json, HttpAccount, HttpStore};
 use interledger_ildcp::IldcpRequest;
 use interledger_ildcp::IldcpResponse;
+use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
 use interledger_router::RouterStore;
 use interledger_service::{
@@ -14,14 +20,29 @@ use interledger_service::{
     Username,
 };
 use interledger_service_util::BalanceStore;
-use interledger_settlement::core::{types::SettlementAccount, SettlementClient};
-use interledger_spsp::{pay, SpspResponder};
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_settlement::core::{
+    get_hash_of,
+    idempotency::{check_idempotency, idempotent_result, make_idempotent_call, IdempotentStore},
+    types::{ApiResponse, ApiResult, SettlementAccount},
+    SettlementClient,
+};
+use interledger_spsp::{
+    pay_at_rate, pay_fixed_delivery, pay_with_options, quote, Error as SpspError, SpspResponder,
+};
+use interledger_stream::{
+    send_money_at_rate, send_money_fixed_delivery, send_money_with_options, CongestionStrategy,
+    PaymentNotification, SendMoneyOptions, StreamDelivery, StreamNotificationsStore,
+    StreamingPaymentHandle, StreamingRate, StreamingStatus,
+};
+use parking_lot::RwLock;
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, trace};
 use uuid::Uuid;
 use warp::{self, reply::Json, Filter, Rejection};
@@ -34,14 +55,219 @@ const fn get_default_max_slippage() -> f64 {
 
 #[derive(Deserialize, Debug)]
 struct SpspPayRequest {
-    receiver: String,
+    /// Payment Pointer or SPSP URL of the receiver. Either this or both `destination_account`
+    /// and `shared_secret` must be provided
+    #[serde(default)]
+    receiver: Option<String>,
+    /// ILP Address of the receiver's STREAM server, if the credentials were exchanged out of band
+    #[serde(default)]
+    destination_account: Option<Address>,
+    /// Base64-encoded STREAM shared secret, if the credentials were exchanged out of band
+    #[serde(default)]
+    shared_secret: Option<String>,
+    /// Amount to send, or the maximum amount to send if `destination_amount` is set
     #[serde(deserialize_with = "number_or_string")]
     source_amount: u64,
+    /// Exact amount the receiver should get, in their asset's units
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    destination_amount: Option<u64>,
+    #[serde(
+        deserialize_with = "number_or_string",
+        default = "get_default_max_slippage"
+    )]
+    slippage: f64,
+    /// Congestion control algorithm to send the payment with
+    #[serde(default)]
+    congestion_control: CongestionStrategy,
+}
+
+/// Where a payment should be sent to
+enum PaymentDestination {
+    /// Payment Pointer or SPSP URL to query for the STREAM credentials
+    Spsp(String),
+    /// STREAM credentials which were exchanged without SPSP
+    Stream {
+        destination_account: Address,
+        shared_secret: Vec<u8>,
+    },
+}
+
+impl SpspPayRequest {
+    fn destination(&self) -> Result<PaymentDestination, ApiError> {
+        payment_destination(
+            &self.receiver,
+            &self.destination_account,
+            &self.shared_secret,
+        )
+    }
+}
+
+fn payment_destination(
+    receiver: &Option<String>,
+    destination_account: &Option<Address>,
+    shared_secret: &Option<String>,
+) -> Result<PaymentDestination, ApiError> {
+    match (receiver, destination_account, shared_secret) {
+        (Some(receiver), None, None) => Ok(PaymentDestination::Spsp(receiver.clone())),
+        (None, Some(destination_account), Some(shared_secret)) => {
+            let shared_secret = base64::decode(shared_secret).map_err(|err| {
+                ApiError::bad_request().detail(format!("Invalid shared secret: {}", err))
+            })?;
+            if shared_secret.len() != 32 {
+                return Err(
+                    ApiError::bad_request().detail("Invalid shared secret: must be 32 bytes long")
+                );
+            }
+            Ok(PaymentDestination::Stream {
+                destination_account: destination_account.clone(),
+                shared_secret,
+            })
+        }
+        _ => Err(ApiError::bad_request().detail(
+            "Either a receiver or both a destination_account and shared_secret must be provided",
+        )),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamingPaymentRequest {
+    /// Payment Pointer or SPSP URL of the receiver. Either this or both `destination_account`
+    /// and `shared_secret` must be provided
+    #[serde(default)]
+    receiver: Option<String>,
+    #[serde(default)]
+    destination_account: Option<Address>,
+    #[serde(default)]
+    shared_secret: Option<String>,
+    /// Amount to send per second
+    #[serde(deserialize_with = "number_or_string")]
+    amount_per_second: u64,
+    /// Maximum total amount to send
+    #[serde(deserialize_with = "number_or_string")]
+    max_amount: u64,
+    /// Number of seconds to stream for, not counting the time the payment is paused
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    duration: Option<u64>,
     #[serde(
         deserialize_with = "number_or_string",
         default = "get_default_max_slippage"
     )]
     slippage: f64,
+    #[serde(default)]
+    congestion_control: CongestionStrategy,
+}
+
+#[derive(Serialize, Debug)]
+struct StreamingPaymentResponse {
+    id: Uuid,
+    status: StreamingStatus,
+    /// Cumulative amounts sent and delivered so far
+    delivery: Option<StreamDelivery>,
+    error: Option<String>,
+}
+
+impl StreamingPaymentResponse {
+    fn new(id: Uuid, handle: &StreamingPaymentHandle) -> Self {
+        StreamingPaymentResponse {
+            id,
+            status: handle.status(),
+            delivery: handle.delivery(),
+            error: handle.error(),
+        }
+    }
+}
+
+/// Streaming payments started through the API, along with the id of the account sending each one.
+/// Payments are kept for a while after they stop so their final delivery can still be queried
+type StreamingPayments = Arc<RwLock<HashMap<Uuid, (Uuid, StreamingPaymentHandle)>>>;
+
+/// How long stopped streaming payments can still be queried
+const STOPPED_STREAMING_PAYMENT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Forget the streaming payments that stopped longer than `STOPPED_STREAMING_PAYMENT_TTL` ago
+fn prune_streaming_payments(
+    streaming_payments: &mut HashMap<Uuid, (Uuid, StreamingPaymentHandle)>,
+    now: Instant,
+) {
+    streaming_payments.retain(|_id, (_account_id, handle)| {
+        handle.stopped_at().map_or(true, |stopped_at| {
+            now.duration_since(stopped_at) < STOPPED_STREAMING_PAYMENT_TTL
+        })
+    });
+}
+
+fn find_streaming_payment(
+    streaming_payments: &StreamingPayments,
+    account_id: Uuid,
+    id: Uuid,
+) -> Result<StreamingPaymentHandle, ApiError> {
+    match streaming_payments.read().get(&id) {
+        Some((sender_id, handle)) if *sender_id == account_id => Ok(handle.clone()),
+        _ => Err(ApiError::not_found().detail("streaming payment not found")),
+    }
+}
+
+const fn get_default_page_limit() -> usize {
+    20
+}
+
+/// Most items returned in a single page
+const MAX_PAGE_LIMIT: usize = 100;
+
+#[derive(Deserialize, Debug)]
+struct PaginationParams {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "get_default_page_limit")]
+    limit: usize,
+}
+
+impl PaginationParams {
+    /// The number of items to return, which is capped at `MAX_PAGE_LIMIT`
+    fn limit(&self) -> usize {
+        self.limit.min(MAX_PAGE_LIMIT)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct IncomingPaymentRequest {
+    /// Amount expected, in the account's units
+    #[serde(deserialize_with = "number_or_string")]
+    amount: u64,
+    /// The time after which the incoming payment no longer accepts money in RFC3339 format
+    #[serde(default)]
+    expires_at: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// An incoming payment, along with the STREAM details payers need to pay it
+#[derive(Serialize, Debug)]
+struct IncomingPaymentResponse {
+    #[serde(flatten)]
+    payment: IncomingPayment,
+    status: IncomingPaymentStatus,
+    /// Base64-encoded STREAM shared secret to use with the incoming payment's `destination_account`
+    shared_secret: String,
+}
+
+impl IncomingPaymentResponse {
+    fn new(payment: IncomingPayment, responder: &SpspResponder) -> Self {
+        let shared_secret =
+            base64::encode(&responder.rederive_secret(&payment.destination_account));
+        IncomingPaymentResponse {
+            status: payment.status(),
+            payment,
+            shared_secret,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SpspQuoteRequest {
+    receiver: String,
+    #[serde(deserialize_with = "number_or_string")]
+    source_amount: u64,
 }
 
 pub fn accounts_api<I, O, S, A, B>(
@@ -64,7 +290,8 @@ where
         + BalanceStore
         + StreamNotificationsStore<Account = A>
         + ExchangeRateStore
-        + RouterStore,
+        + RouterStore
+        + IdempotentStore,
     A: BtpAccount
         + CcpRoutingAccount
         + SettlementAccount
@@ -316,7 +543,7 @@ where
 
     // (Websocket) /accounts/:username/payments/incoming
     let incoming_payment_notifications = warp::path("accounts")
-        .and(admin_or_authorized_user_only)
+        .and(admin_or_authorized_user_only.clone())
         .and(warp::path("payments"))
         .and(warp::path("incoming"))
         .and(warp::path::end())
@@ -341,40 +568,313 @@ where
             })
         });
 
-    // POST /accounts/:username/payments
+    // GET /accounts/:username/payments/outgoing?offset=0&limit=20
+    let get_outgoing_payments = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only.clone())
+        .and(warp::path("payments"))
+        .and(warp::path("outgoing"))
+        .and(warp::path::end())
+        .and(warp::query::<PaginationParams>())
+        .and(with_store.clone())
+        .and_then(
+            |id: Uuid, pagination: PaginationParams, store: S| async move {
+                let payments = store
+                    .get_outgoing_payments(id, pagination.offset, pagination.limit())
+                    .await?;
+                Ok::<Json, Rejection>(warp::reply::json(&payments))
+            },
+        );
+
+    // POST /accounts/:username/payments (optional idempotency-key header)
     let post_payments = warp::post()
         .and(warp::path("accounts"))
-        .and(authorized_user_only)
+        .and(authorized_user_only.clone())
         .and(warp::path("payments"))
         .and(warp::path::end())
+        .and(warp::header::optional::<String>("idempotency-key"))
         .and(deserialize_json())
-        .and(with_incoming_handler)
+        .and(with_incoming_handler.clone())
         .and(with_store.clone())
         .and_then(
-            move |account: A, pay_request: SpspPayRequest, incoming_handler: I, store: S| {
+            move |account: A,
+                  idempotency_key: Option<String>,
+                  pay_request: SpspPayRequest,
+                  incoming_handler: I,
+                  store: S| {
                 async move {
-                    let receipt = pay(
+                    let destination = pay_request.destination()?;
+                    let input = format!("{}{:?}", account.username(), pay_request);
+                    let input_hash = get_hash_of(input.as_ref());
+
+                    let payment = send_payment(
+                        account,
+                        pay_request,
+                        destination,
                         incoming_handler,
-                        account.clone(),
-                        store,
-                        &pay_request.receiver,
-                        pay_request.source_amount,
-                        pay_request.slippage,
+                        store.clone(),
+                    );
+                    let (status_code, body) = match idempotency_key {
+                        Some(idempotency_key) => {
+                            make_idempotent_payment(store, payment, input_hash, idempotency_key)
+                                .await?
+                        }
+                        None => {
+                            make_idempotent_call(
+                                store,
+                                payment,
+                                input_hash,
+                                None,
+                                StatusCode::OK,
+                                Bytes::new(),
+                            )
+                            .await?
+                        }
+                    };
+
+                    Ok::<_, Rejection>(
+                        Response::builder()
+                            .status(status_code)
+                            .header("Content-Type", "application/json")
+                            .body(body)
+                            .unwrap(),
                     )
-                    .map_err(|err| {
-                        let msg = format!("Error sending SPSP payment: {}", err);
-                        error!("{}", msg);
-                        // TODO give a different error message depending on what type of error it is
-                        Rejection::from(ApiError::internal_server_error().detail(msg))
-                    })
+                }
+            },
+        );
+
+    // POST /accounts/:username/incoming-payments
+    let incoming_payments_secret = server_secret.clone();
+    let post_incoming_payments = warp::post()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only.clone())
+        .and(warp::path("incoming-payments"))
+        .and(warp::path::end())
+        .and(deserialize_json())
+        .and(with_store.clone())
+        .and_then(move |id: Uuid, request: IncomingPaymentRequest, store: S| {
+            let server_secret = incoming_payments_secret.clone();
+            async move {
+                if request.amount == 0 {
+                    return Err(Rejection::from(
+                        ApiError::bad_request().detail("amount must be positive"),
+                    ));
+                }
+                if let Some(ref expires_at) = request.expires_at {
+                    DateTime::parse_from_rfc3339(expires_at).map_err(|err| {
+                        ApiError::bad_request().detail(format!("Invalid expires_at: {}", err))
+                    })?;
+                }
+
+                let accounts = store.get_accounts(vec![id]).await?;
+                let responder =
+                    SpspResponder::new(accounts[0].ilp_address().clone(), server_secret);
+                // The id tags the incoming payment's connection, so the STREAM receiver
+                // can credit the money received over it to the incoming payment
+                let payment_id = Uuid::new_v4();
+                let (destination_account, _) = responder
+                    .generate_tagged_address_and_secret(&payment_id.to_string())
+                    .map_err(|err| ApiError::internal_server_error().detail(err.to_string()))?;
+                let payment = IncomingPayment {
+                    id: payment_id,
+                    destination_account,
+                    asset_code: accounts[0].asset_code().to_string(),
+                    asset_scale: accounts[0].asset_scale(),
+                    amount: request.amount,
+                    received_amount: 0,
+                    description: request.description,
+                    created_at: Utc::now().to_rfc3339(),
+                    expires_at: request.expires_at,
+                    completed_at: None,
+                };
+                store.create_incoming_payment(id, payment.clone()).await?;
+
+                Ok(warp::reply::with_status(
+                    warp::reply::json(&IncomingPaymentResponse::new(payment, &responder)),
+                    StatusCode::CREATED,
+                ))
+            }
+        });
+
+    // GET /accounts/:username/incoming-payments?offset=0&limit=20
+    let incoming_payments_secret = server_secret.clone();
+    let get_incoming_payments = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only.clone())
+        .and(warp::path("incoming-payments"))
+        .and(warp::path::end())
+        .and(warp::query::<PaginationParams>())
+        .and(with_store.clone())
+        .and_then(move |id: Uuid, pagination: PaginationParams, store: S| {
+            let server_secret = incoming_payments_secret.clone();
+            async move {
+                let payments = store
+                    .get_incoming_payments(id, pagination.offset, pagination.limit())
                     .await?;
+                let accounts = store.get_accounts(vec![id]).await?;
+                let responder =
+                    SpspResponder::new(accounts[0].ilp_address().clone(), server_secret);
+                let payments: Vec<IncomingPaymentResponse> = payments
+                    .into_iter()
+                    .map(|payment| IncomingPaymentResponse::new(payment, &responder))
+                    .collect();
+                Ok::<Json, Rejection>(warp::reply::json(&payments))
+            }
+        });
+
+    // GET /accounts/:username/incoming-payments/:id
+    let incoming_payments_secret = server_secret.clone();
+    let get_incoming_payment = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only.clone())
+        .and(warp::path("incoming-payments"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(with_store.clone())
+        .and_then(move |account_id: Uuid, id: Uuid, store: S| {
+            let server_secret = incoming_payments_secret.clone();
+            async move {
+                let payment = store
+                    .get_incoming_payment(account_id, id)
+                    .await?
+                    .ok_or_else(|| ApiError::not_found().detail("incoming payment not found"))?;
+                let accounts = store.get_accounts(vec![account_id]).await?;
+                let responder =
+                    SpspResponder::new(accounts[0].ilp_address().clone(), server_secret);
+                Ok::<Json, Rejection>(warp::reply::json(&IncomingPaymentResponse::new(
+                    payment, &responder,
+                )))
+            }
+        });
+
+    // POST /accounts/:username/payments/streaming
+    let streaming_payments = StreamingPayments::default();
+    let with_streaming_payments = warp::any().map(move || streaming_payments.clone());
+    let post_streaming_payment = warp::post()
+        .and(warp::path("accounts"))
+        .and(authorized_user_only.clone())
+        .and(warp::path("payments"))
+        .and(warp::path("streaming"))
+        .and(warp::path::end())
+        .and(deserialize_json())
+        .and(with_incoming_handler.clone())
+        .and(with_store.clone())
+        .and(with_streaming_payments.clone())
+        .and_then(
+            move |account: A,
+                  request: StreamingPaymentRequest,
+                  incoming_handler: I,
+                  store: S,
+                  streaming_payments: StreamingPayments| {
+                async move {
+                    let destination = payment_destination(
+                        &request.receiver,
+                        &request.destination_account,
+                        &request.shared_secret,
+                    )?;
+                    if request.amount_per_second == 0 {
+                        return Err(Rejection::from(
+                            ApiError::bad_request().detail("amount_per_second must be positive"),
+                        ));
+                    }
 
-                    debug!("Sent SPSP payment, receipt: {:?}", receipt);
-                    Ok::<Json, Rejection>(warp::reply::json(&json!(receipt)))
+                    let id = Uuid::new_v4();
+                    let handle = StreamingPaymentHandle::new();
+                    {
+                        let mut streaming_payments = streaming_payments.write();
+                        prune_streaming_payments(&mut streaming_payments, Instant::now());
+                        streaming_payments.insert(id, (account.id(), handle.clone()));
+                    }
+                    tokio::spawn(stream_payment(
+                        account,
+                        request,
+                        destination,
+                        incoming_handler,
+                        store,
+                        handle.clone(),
+                    ));
+
+                    Ok(warp::reply::with_status(
+                        warp::reply::json(&StreamingPaymentResponse::new(id, &handle)),
+                        StatusCode::CREATED,
+                    ))
                 }
             },
         );
 
+    // GET /accounts/:username/payments/streaming/:id
+    let get_streaming_payment = warp::get()
+        .and(warp::path("accounts"))
+        .and(admin_or_authorized_user_only)
+        .and(warp::path("payments"))
+        .and(warp::path("streaming"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(with_streaming_payments.clone())
+        .and_then(
+            |account_id: Uuid, id: Uuid, streaming_payments: StreamingPayments| async move {
+                let handle = find_streaming_payment(&streaming_payments, account_id, id)?;
+                Ok::<Json, Rejection>(warp::reply::json(&StreamingPaymentResponse::new(
+                    id, &handle,
+                )))
+            },
+        );
+
+    // POST /accounts/:username/payments/streaming/:id/(pause|resume|stop)
+    let control_streaming_payment = warp::post()
+        .and(warp::path("accounts"))
+        .and(authorized_user_only.clone())
+        .and(warp::path("payments"))
+        .and(warp::path("streaming"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(with_streaming_payments)
+        .and_then(
+            |account: A, id: Uuid, action: String, streaming_payments: StreamingPayments| {
+                async move {
+                    let handle = find_streaming_payment(&streaming_payments, account.id(), id)?;
+                    match action.as_str() {
+                        "pause" => handle.pause(),
+                        "resume" => handle.resume(),
+                        "stop" => handle.stop(),
+                        _ => return Err(Rejection::from(ApiError::not_found())),
+                    }
+                    Ok::<Json, Rejection>(warp::reply::json(&StreamingPaymentResponse::new(
+                        id, &handle,
+                    )))
+                }
+            },
+        );
+
+    // POST /accounts/:username/quotes
+    let post_quotes = warp::post()
+        .and(warp::path("accounts"))
+        .and(authorized_user_only)
+        .and(warp::path("quotes"))
+        .and(warp::path::end())
+        .and(deserialize_json())
+        .and(with_incoming_handler)
+        .and_then(
+            move |account: A, quote_request: SpspQuoteRequest, incoming_handler: I| async move {
+                let quote = quote(
+                    incoming_handler,
+                    account.clone(),
+                    &quote_request.receiver,
+                    quote_request.source_amount,
+                )
+                .map_err(|err| {
+                    let msg = format!("Error quoting SPSP payment: {}", err);
+                    error!("{}", msg);
+                    Rejection::from(ApiError::internal_server_error().detail(msg))
+                })
+                .await?;
+
+                debug!("Quoted SPSP payment: {:?}", quote);
+                Ok::<Json, Rejection>(warp::reply::json(&json!(quote)))
+            },
+        );
+
     // GET /accounts/:username/spsp
     let server_secret_clone = server_secret.clone();
     let get_spsp = warp::get()
@@ -382,8 +882,9 @@ where
         .and(account_username_to_id)
         .and(warp::path("spsp"))
         .and(warp::path::end())
+        .and(warp::header::headers_cloned())
         .and(with_store.clone())
-        .and_then(move |id: Uuid, store: S| {
+        .and_then(move |id: Uuid, headers: HeaderMap, store: S| {
             let server_secret_clone = server_secret_clone.clone();
             async move {
                 let accounts = store.get_accounts(vec![id]).await?;
@@ -393,7 +894,7 @@ where
                         accounts[0].ilp_address().clone(),
                         server_secret_clone.clone(),
                     )
-                    .generate_http_response(),
+                    .generate_http_response_from_headers(&headers),
                 )
             }
         });
@@ -405,8 +906,9 @@ where
         .and(warp::path(".well-known"))
         .and(warp::path("pay"))
         .and(warp::path::end())
+        .and(warp::header::headers_cloned())
         .and(with_store)
-        .and_then(move |store: S| {
+        .and_then(move |headers: HeaderMap, store: S| {
             let default_spsp_account = default_spsp_account.clone();
             let server_secret_clone = server_secret.clone();
             async move {
@@ -423,7 +925,7 @@ where
                             account.ilp_address().clone(),
                             server_secret_clone.clone(),
                         )
-                        .generate_http_response(),
+                        .generate_http_response_from_headers(&headers),
                     )
                 } else {
                     Err(Rejection::from(
@@ -444,7 +946,312 @@ where
         .or(put_account_settings)
         .or(incoming_payment_notifications)
         .or(all_payment_notifications)
+        .or(get_outgoing_payments)
+        .or(post_incoming_payments)
+        .or(get_incoming_payments)
+        .or(get_incoming_payment)
         .or(post_payments)
+        .or(post_streaming_payment)
+        .or(get_streaming_payment)
+        .or(control_streaming_payment)
+        .or(post_quotes)
+}
+
+/// Sends the payment unless its idempotency key was used before. The key is reserved with an
+/// in-progress response before any money is sent, so that retries made while the payment is being
+/// sent get a 202 response instead of sending the payment again. Failed payments release the key,
+/// so that they can be retried with it.
+async fn make_idempotent_payment<S>(
+    store: S,
+    payment: impl Future<Output = ApiResult>,
+    input_hash: [u8; 32],
+    idempotency_key: String,
+) -> Result<(StatusCode, Bytes), ApiError>
+where
+    S: IdempotentStore + Clone + Send + Sync,
+{
+    let in_progress = Bytes::from(json!({ "status": "in_progress" }).to_string());
+    loop {
+        let is_reserved = store
+            .reserve_idempotent_data(
+                idempotency_key.clone(),
+                input_hash,
+                StatusCode::ACCEPTED,
+                in_progress.clone(),
+            )
+            .await
+            .map_err(|_| IDEMPOTENT_STORE_CALL_ERROR.clone())?;
+        if is_reserved {
+            break;
+        }
+        // If the key was released by a failed attempt in the meantime, try to reserve it again
+        if let Some((status_code, data)) =
+            check_idempotency(store.clone(), idempotency_key.clone(), input_hash).await?
+        {
+            return idempotent_result(status_code, data);
+        }
+    }
+
+    let data = match payment.await {
+        Ok(ApiResponse::Data(data)) => data,
+        Ok(ApiResponse::Default) => Bytes::new(),
+        Err(err) => {
+            if store.delete_idempotent_data(idempotency_key).await.is_err() {
+                error!("Failed to connect to the store! Retries of this payment will get the in-progress response until the idempotency key expires.");
+            }
+            return Err(err);
+        }
+    };
+    store
+        .save_idempotent_data(idempotency_key, input_hash, StatusCode::OK, data.clone())
+        .await
+        .map_err(|_| {
+            error!(
+                "Failed to connect to the store! The payment will not be idempotent if retried."
+            );
+            IDEMPOTENT_STORE_CALL_ERROR.clone()
+        })?;
+    Ok((StatusCode::OK, data))
+}
+
+/// Sends the payment, records it in the account's outgoing payments, and returns the serialized
+/// receipt
+async fn send_payment<I, S, A>(
+    account: A,
+    pay_request: SpspPayRequest,
+    destination: PaymentDestination,
+    incoming_handler: I,
+    store: S,
+) -> ApiResult
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    S: NodeStore<Account = A> + ExchangeRateStore + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+{
+    let started_at = Utc::now().to_rfc3339();
+    let receiver = match &destination {
+        PaymentDestination::Spsp(receiver) => Some(receiver.clone()),
+        PaymentDestination::Stream { .. } => None,
+    };
+    let receipt = match (destination, pay_request.destination_amount) {
+        (PaymentDestination::Spsp(receiver), Some(destination_amount)) => {
+            pay_fixed_delivery(
+                incoming_handler,
+                account.clone(),
+                store.clone(),
+                &receiver,
+                destination_amount,
+                pay_request.source_amount,
+                pay_request.slippage,
+                pay_request.congestion_control,
+            )
+            .await
+        }
+        (PaymentDestination::Spsp(receiver), None) => {
+            pay_with_options(
+                incoming_handler,
+                account.clone(),
+                store.clone(),
+                &receiver,
+                pay_request.source_amount,
+                SendMoneyOptions {
+                    slippage: pay_request.slippage,
+                    congestion_strategy: pay_request.congestion_control,
+                    ..SendMoneyOptions::default()
+                },
+            )
+            .await
+        }
+        (
+            PaymentDestination::Stream {
+                destination_account,
+                shared_secret,
+            },
+            Some(destination_amount),
+        ) => send_money_fixed_delivery(
+            incoming_handler,
+            &account,
+            store.clone(),
+            destination_account,
+            shared_secret,
+            destination_amount,
+            pay_request.source_amount,
+            pay_request.slippage,
+            pay_request.congestion_control,
+        )
+        .await
+        .map_err(SpspError::from),
+        (
+            PaymentDestination::Stream {
+                destination_account,
+                shared_secret,
+            },
+            None,
+        ) => send_money_with_options(
+            incoming_handler,
+            &account,
+            store.clone(),
+            destination_account,
+            shared_secret,
+            pay_request.source_amount,
+            SendMoneyOptions {
+                slippage: pay_request.slippage,
+                congestion_strategy: pay_request.congestion_control,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .map_err(SpspError::from),
+    };
+
+    let payment = outgoing_payment(
+        &account,
+        receiver,
+        pay_request.destination_account.clone(),
+        pay_request.source_amount,
+        pay_request.destination_amount,
+        &receipt,
+        started_at,
+    );
+    if let Err(err) = store.save_outgoing_payment(account.id(), payment).await {
+        error!("Error saving outgoing payment: {}", err);
+    }
+
+    let receipt = receipt.map_err(|err| {
+        let msg = format!("Error sending payment: {}", err);
+        error!("{}", msg);
+        // TODO give a different error message depending on what type of error it is
+        ApiError::internal_server_error().detail(msg)
+    })?;
+
+    debug!("Sent payment, receipt: {:?}", receipt);
+    Ok(ApiResponse::Data(Bytes::from(
+        serde_json::to_vec(&receipt).unwrap(),
+    )))
+}
+
+/// Streams the payment in the background until it is stopped or reaches its limits, then records
+/// it in the account's outgoing payments
+async fn stream_payment<I, S, A>(
+    account: A,
+    request: StreamingPaymentRequest,
+    destination: PaymentDestination,
+    incoming_handler: I,
+    store: S,
+    handle: StreamingPaymentHandle,
+) where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    S: NodeStore<Account = A> + ExchangeRateStore + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+{
+    let started_at = Utc::now().to_rfc3339();
+    let rate = StreamingRate {
+        amount_per_second: request.amount_per_second,
+        max_amount: request.max_amount,
+        duration: request.duration.map(Duration::from_secs),
+    };
+    let receiver = match &destination {
+        PaymentDestination::Spsp(receiver) => Some(receiver.clone()),
+        PaymentDestination::Stream { .. } => None,
+    };
+    let receipt = match destination {
+        PaymentDestination::Spsp(receiver) => {
+            pay_at_rate(
+                incoming_handler,
+                account.clone(),
+                store.clone(),
+                &receiver,
+                rate,
+                request.slippage,
+                request.congestion_control,
+                handle,
+            )
+            .await
+        }
+        PaymentDestination::Stream {
+            destination_account,
+            shared_secret,
+        } => send_money_at_rate(
+            incoming_handler,
+            &account,
+            store.clone(),
+            destination_account,
+            shared_secret,
+            rate,
+            request.slippage,
+            request.congestion_control,
+            handle,
+        )
+        .await
+        .map_err(SpspError::from),
+    };
+    if let Err(err) = &receipt {
+        error!("Error sending streaming payment: {}", err);
+    }
+
+    let payment = outgoing_payment(
+        &account,
+        receiver,
+        request.destination_account,
+        request.max_amount,
+        None,
+        &receipt,
+        started_at,
+    );
+    if let Err(err) = store.save_outgoing_payment(account.id(), payment).await {
+        error!("Error saving outgoing payment: {}", err);
+    }
+}
+
+/// Builds the record of a finished payment from its outcome
+fn outgoing_payment<A: Account>(
+    account: &A,
+    receiver: Option<String>,
+    destination_account: Option<Address>,
+    source_amount: u64,
+    destination_amount: Option<u64>,
+    receipt: &Result<StreamDelivery, SpspError>,
+    started_at: String,
+) -> OutgoingPayment {
+    let mut payment = OutgoingPayment {
+        id: Uuid::new_v4(),
+        receiver,
+        destination_account,
+        source_asset_code: account.asset_code().to_string(),
+        source_asset_scale: account.asset_scale(),
+        source_amount,
+        destination_amount,
+        sent_amount: 0,
+        delivered_amount: 0,
+        destination_asset_code: None,
+        destination_asset_scale: None,
+        status: OutgoingPaymentStatus::Completed,
+        error: None,
+        started_at,
+        finished_at: Utc::now().to_rfc3339(),
+    };
+    // Failed payments may still have delivered part of the amount before they stopped
+    let delivery = match receipt {
+        Ok(receipt) => Some(receipt),
+        Err(err) => {
+            payment.status = OutgoingPaymentStatus::Failed;
+            payment.error = Some(err.to_string());
+            match err {
+                SpspError::StreamError(err) => err.delivery(),
+                _ => None,
+            }
+        }
+    };
+    if let Some(delivery) = delivery {
+        payment.destination_account = Some(delivery.to.clone());
+        payment.sent_amount = delivery
+            .sent_amount
+            .saturating_sub(delivery.in_flight_amount);
+        payment.delivered_amount = delivery.delivered_amount;
+        payment.destination_asset_code = delivery.destination_asset_code.clone();
+        payment.destination_asset_scale = delivery.destination_asset_scale;
+    }
+    payment
 }
 
 fn notify_user(
@@ -664,7 +1471,12 @@ where
 
 #[cfg(test)]
 mod tests {
+    use super::SpspPayRequest;
     use crate::routes::test_helpers::*;
+    use bytes::Bytes;
+    use http::{Response, StatusCode};
+    use interledger_settlement::core::{get_hash_of, idempotency::IdempotentData};
+    use serde_json::json;
     // TODO: Add test for GET /accounts/:username/spsp and /.well_known
 
     #[tokio::test]
@@ -811,4 +1623,236 @@ mod tests {
         .await;
         assert_eq!(resp.status().as_u16(), 401);
     }
+
+    #[tokio::test]
+    async fn only_admin_or_user_can_get_outgoing_payments() {
+        let api = test_accounts_api();
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/payments/outgoing",
+            "password",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/payments/outgoing?offset=20&limit=10",
+            "admin",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/payments/outgoing?offset=18446744073709551615&limit=18446744073709551615",
+            "admin",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/accounts/alice/payments/outgoing",
+            "wrong",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn payments_are_idempotent() {
+        async fn pay_with_idempotency_key<F>(api: &F, source_amount: u64) -> Response<Bytes>
+        where
+            F: warp::Filter + 'static,
+            F::Extract: warp::Reply,
+        {
+            warp::test::request()
+                .method("POST")
+                .path("/accounts/alice/payments")
+                .header("Authorization", "Bearer password")
+                .header("Idempotency-Key", "payment-idempotency-key")
+                .json(&json!({
+                    "receiver": "some_receiver",
+                    "source_amount": source_amount,
+                }))
+                .reply(api)
+                .await
+        }
+
+        let api = test_accounts_api();
+
+        // The payment fails since the receiver is invalid, which releases the key for retries
+        let resp = pay_with_idempotency_key(&api, 10).await;
+        assert_eq!(resp.status().as_u16(), 500);
+        assert!(IDEMPOTENCY_CACHE
+            .lock()
+            .unwrap()
+            .get("payment-idempotency-key")
+            .is_none());
+
+        // While the payment is in progress, retrying it doesn't send it again
+        let pay_request: SpspPayRequest = serde_json::from_value(json!({
+            "receiver": "some_receiver",
+            "source_amount": 10,
+        }))
+        .unwrap();
+        let input_hash = get_hash_of(format!("{}{:?}", *USERNAME, pay_request).as_ref());
+        let in_progress = IdempotentData::new(
+            StatusCode::ACCEPTED,
+            Bytes::from(json!({ "status": "in_progress" }).to_string()),
+            input_hash,
+        );
+        IDEMPOTENCY_CACHE
+            .lock()
+            .unwrap()
+            .insert("payment-idempotency-key".to_string(), in_progress.clone());
+        let resp = pay_with_idempotency_key(&api, 10).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        assert_eq!(
+            IDEMPOTENCY_CACHE
+                .lock()
+                .unwrap()
+                .get("payment-idempotency-key")
+                .cloned(),
+            Some(in_progress)
+        );
+
+        // Reusing the key for a different payment is a conflict
+        let resp = pay_with_idempotency_key(&api, 20).await;
+        assert_eq!(resp.status().as_u16(), 409);
+    }
+
+    #[tokio::test]
+    async fn pays_to_stream_credentials_without_spsp() {
+        let api = test_accounts_api();
+        let pay_request = Some(json!({
+            "destination_account": "example.receiver.connection",
+            "shared_secret": base64::encode(&[0; 32]),
+            "source_amount": 10,
+        }));
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            pay_request,
+        )
+        .await;
+        // The credentials are used as-is, so the payment fails at the incoming handler
+        assert_eq!(resp.status().as_u16(), 500);
+
+        let invalid_secret = Some(json!({
+            "destination_account": "example.receiver.connection",
+            "shared_secret": "not base64!",
+            "source_amount": 10,
+        }));
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            invalid_secret,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let missing_secret = Some(json!({
+            "destination_account": "example.receiver.connection",
+            "source_amount": 10,
+        }));
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            missing_secret,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let both_destinations = Some(json!({
+            "receiver": "some_receiver",
+            "destination_account": "example.receiver.connection",
+            "shared_secret": base64::encode(&[0; 32]),
+            "source_amount": 10,
+        }));
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            both_destinations,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn only_user_can_get_quote() {
+        let quote_request: Option<serde_json::Value> = Some(serde_json::json!({
+            "receiver": "some_receiver",
+            "source_amount" : 10,
+        }));
+        let api = test_accounts_api();
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/quotes",
+            "password",
+            quote_request.clone(),
+        )
+        .await;
+        // The receiver is invalid, so the quote fails after authorization succeeded
+        assert_eq!(resp.status().as_u16(), 500);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/quotes",
+            "admin",
+            quote_request.clone(),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/quotes",
+            "wrong",
+            quote_request,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[test]
+    fn prunes_streaming_payments_stopped_long_ago() {
+        use super::*;
+        let running = StreamingPaymentHandle::new();
+        let stopped = StreamingPaymentHandle::new();
+        stopped.stop();
+        let account_id = Uuid::new_v4();
+        let mut streaming_payments = HashMap::new();
+        streaming_payments.insert(Uuid::new_v4(), (account_id, running));
+        streaming_payments.insert(Uuid::new_v4(), (account_id, stopped));
+
+        prune_streaming_payments(&mut streaming_payments, Instant::now());
+        assert_eq!(streaming_payments.len(), 2);
+
+        let later = Instant::now() + STOPPED_STREAMING_PAYMENT_TTL;
+        prune_streaming_payments(&mut streaming_payments, later);
+        assert_eq!(streaming_payments.len(), 1);
+        let (_, handle) = streaming_payments.values().next().unwrap();
+        assert_eq!(handle.status(), StreamingStatus::Streaming);
+    }
 }
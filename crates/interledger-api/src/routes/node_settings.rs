@@ -1,6 +1,9 @@
 use crate::{ExchangeRates, NodeStore};
 use bytes::Bytes;
 use futures::TryFutureExt;
+use interledger_ccp::{
+    Mode, PeerInspector, PeerState, RouteCandidate, RouteExplainer, RouteSource, RoutingRelation,
+};
 use interledger_errors::*;
 use interledger_http::{deserialize_json, HttpAccount};
 use interledger_packet::Address;
@@ -14,6 +17,7 @@ use std::{
     collections::HashMap,
     iter::FromIterator,
     str::{self, FromStr},
+    sync::Arc,
 };
 use tracing::{error, trace};
 use url::Url;
@@ -29,10 +33,105 @@ struct StatusResponse {
     version: Option<String>,
 }
 
+/// A route that was considered for an address
+#[derive(Serialize)]
+struct RouteCandidateResponse {
+    next_hop: Username,
+    source: RouteSource,
+    prefix: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    path: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u32>,
+    cost: u32,
+}
+
+impl From<RouteCandidate> for RouteCandidateResponse {
+    fn from(candidate: RouteCandidate) -> Self {
+        RouteCandidateResponse {
+            next_hop: candidate.username,
+            source: candidate.source,
+            prefix: candidate.prefix,
+            path: candidate.path,
+            epoch: candidate.epoch,
+            cost: candidate.cost,
+        }
+    }
+}
+
+/// Explains which account the Router forwards packets for an address to and why
+#[derive(Serialize)]
+struct RouteResolutionResponse {
+    address: String,
+    /// The routing table prefix that matched the address
+    prefix: String,
+    next_hop: Username,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<RouteSource>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    path: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<u32>,
+    /// The other routes that were considered for the prefix, best first
+    alternatives: Vec<RouteCandidateResponse>,
+}
+
+#[derive(Serialize)]
+struct PeerBackoffResponse {
+    max_intervals: u8,
+    skip_intervals: u8,
+}
+
+#[derive(Serialize)]
+struct CcpPeerResponse {
+    username: Username,
+    relation: RoutingRelation,
+    mode: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    routing_table_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u32>,
+    routes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backoff: Option<PeerBackoffResponse>,
+}
+
+impl From<PeerState> for CcpPeerResponse {
+    fn from(peer: PeerState) -> Self {
+        CcpPeerResponse {
+            username: peer.username,
+            relation: peer.relation,
+            mode: match peer.mode {
+                Mode::Sync => "sync",
+                Mode::Idle => "idle",
+            },
+            routing_table_id: peer.routing_table_id.map(hex::encode),
+            epoch: peer.epoch,
+            routes: peer.routes,
+            backoff: peer.backoff.map(|backoff| PeerBackoffResponse {
+                max_intervals: backoff.max_intervals,
+                skip_intervals: backoff.skip_intervals,
+            }),
+        }
+    }
+}
+
+/// The state of the routing table we broadcast and of each CCP peer
+#[derive(Serialize)]
+struct CcpPeersResponse {
+    routing_table_id: String,
+    epoch: u32,
+    peers: Vec<CcpPeerResponse>,
+}
+
 pub fn node_settings_api<S, A>(
     admin_api_token: String,
     node_version: Option<String>,
     store: S,
+    route_explainer: Option<Arc<dyn RouteExplainer>>,
+    peer_inspector: Option<Arc<dyn PeerInspector>>,
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
 where
     S: NodeStore<Account = A>
@@ -61,6 +160,15 @@ where
         // success to the next filter, it just gets rid of it
         .untuple_one();
     let with_store = warp::any().map(move || store.clone());
+    let with_peer_inspector = warp::any().map(move || peer_inspector.clone()).and_then(
+        |peer_inspector: Option<Arc<dyn PeerInspector>>| async move {
+            peer_inspector.ok_or_else(|| {
+                Rejection::from(
+                    ApiError::not_found().detail("CCP peer state is not available".to_owned()),
+                )
+            })
+        },
+    );
 
     // GET /
     let get_root = warp::get()
@@ -121,6 +229,73 @@ where
             }
         });
 
+    // GET /routes/resolve/:address
+    let get_route_resolution = warp::get()
+        .and(warp::path("routes"))
+        .and(warp::path("resolve"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(with_store.clone())
+        .and_then(move |address: String, store: S| {
+            let route_explainer = route_explainer.clone();
+            async move {
+                Address::from_str(&address).map_err(|_| {
+                    Rejection::from(
+                        ApiError::bad_request().detail(format!("Invalid address: {}", address)),
+                    )
+                })?;
+                let (prefix, account_id) = store
+                    .routing_table()
+                    .resolve_entry(&address)
+                    .map(|(prefix, account_id)| (prefix.to_string(), *account_id))
+                    .ok_or_else(|| {
+                        Rejection::from(
+                            ApiError::not_found()
+                                .detail(format!("No route found for address: {}", address)),
+                        )
+                    })?;
+                let next_hop = store
+                    .get_accounts(vec![account_id])
+                    .await?
+                    .pop()
+                    .ok_or_else(|| Rejection::from(ApiError::account_not_found()))?;
+
+                // The Router uses the routing table in the store, so CCP's view
+                // only explains where the route came from
+                let mut candidates = match route_explainer {
+                    Some(explainer) => explainer.route_candidates(&prefix).await?,
+                    None => Vec::new(),
+                };
+                let chosen = candidates
+                    .iter()
+                    .position(|candidate| candidate.account_id == account_id)
+                    .map(|index| candidates.remove(index));
+                let (source, path, epoch, cost) = match chosen {
+                    Some(chosen) => (
+                        Some(chosen.source),
+                        chosen.path,
+                        chosen.epoch,
+                        Some(chosen.cost),
+                    ),
+                    None if prefix.is_empty() => {
+                        (Some(RouteSource::Default), Vec::new(), None, None)
+                    }
+                    None => (None, Vec::new(), None, None),
+                };
+
+                Ok::<Json, Rejection>(warp::reply::json(&RouteResolutionResponse {
+                    address,
+                    prefix,
+                    next_hop: next_hop.username().clone(),
+                    source,
+                    path,
+                    epoch,
+                    cost,
+                    alternatives: candidates.into_iter().map(From::from).collect(),
+                }))
+            }
+        });
+
     // PUT /routes/static
     // Body: Map of ILP Address prefix -> Username
     let put_static_routes = warp::put()
@@ -178,6 +353,46 @@ where
             }
         });
 
+    // GET /ccp/peers
+    let get_ccp_peers = warp::get()
+        .and(warp::path("ccp"))
+        .and(warp::path("peers"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(with_peer_inspector.clone())
+        .and_then(|peer_inspector: Arc<dyn PeerInspector>| async move {
+            let peers = peer_inspector.peers().await?;
+            Ok::<Json, Rejection>(warp::reply::json(&CcpPeersResponse {
+                routing_table_id: hex::encode(peers.routing_table_id),
+                epoch: peers.epoch,
+                peers: peers.peers.into_iter().map(From::from).collect(),
+            }))
+        });
+
+    // POST /ccp/peers/:username/resync
+    // Asks the peer to send us all of its routes again
+    let post_ccp_peer_resync = warp::post()
+        .and(warp::path("ccp"))
+        .and(warp::path("peers"))
+        .and(warp::path::param::<Username>())
+        .and(warp::path("resync"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(with_peer_inspector)
+        .and(with_store.clone())
+        .and_then(
+            |username: Username, peer_inspector: Arc<dyn PeerInspector>, store: S| async move {
+                let account_id = store.get_account_id_from_username(&username).await?;
+                if !peer_inspector.resync_peer(account_id).await? {
+                    return Err(Rejection::from(ApiError::bad_request().detail(format!(
+                        "Routes are not received from account: {}",
+                        username
+                    ))));
+                }
+                Ok::<String, Rejection>(username.to_string())
+            },
+        );
+
     // PUT /settlement/engines
     let put_settlement_engines = warp::put()
         .and(warp::path("settlement"))
@@ -225,14 +440,19 @@ where
         .or(put_rates)
         .or(get_rates)
         .or(get_routes)
+        .or(get_route_resolution)
         .or(put_static_routes)
         .or(put_static_route)
+        .or(get_ccp_peers)
+        .or(post_ccp_peer_resync)
         .or(put_settlement_engines)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::routes::test_helpers::{api_call, test_node_settings_api};
+    use crate::routes::test_helpers::{
+        api_call, test_node_settings_api, test_node_settings_api_with_ccp,
+    };
     use serde_json::{json, Value};
 
     #[tokio::test]
@@ -264,6 +484,100 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 200);
     }
 
+    #[tokio::test]
+    async fn resolves_route_for_address() {
+        let api = test_node_settings_api();
+        let resp = api_call(&api, "GET", "/routes/resolve/example.alice.1234", "", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap(),
+            json!({
+                "address": "example.alice.1234",
+                "prefix": "example.alice",
+                "next_hop": "alice",
+                "alternatives": [],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn explains_route_candidates() {
+        let api = test_node_settings_api_with_ccp();
+        let resp = api_call(&api, "GET", "/routes/resolve/example.alice", "", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap(),
+            json!({
+                "address": "example.alice",
+                "prefix": "example.alice",
+                "next_hop": "alice",
+                "source": "ccp",
+                "path": ["example.charlie"],
+                "epoch": 3,
+                "cost": 7,
+                "alternatives": [{
+                    "next_hop": "bob",
+                    "source": "static",
+                    "prefix": "example.alice",
+                    "cost": 0,
+                }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unroutable_or_invalid_addresses() {
+        let api = test_node_settings_api();
+        let resp = api_call(&api, "GET", "/routes/resolve/example.bob", "", None).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let resp = api_call(&api, "GET", "/routes/resolve/invalid%20address", "", None).await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn only_admin_can_get_ccp_peers() {
+        let api = test_node_settings_api_with_ccp();
+        let resp = api_call(&api, "GET", "/ccp/peers", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap(),
+            json!({
+                "routing_table_id": "01010101010101010101010101010101",
+                "epoch": 5,
+                "peers": [{
+                    "username": "alice",
+                    "relation": "Peer",
+                    "mode": "idle",
+                    "routing_table_id": "02020202020202020202020202020202",
+                    "epoch": 3,
+                    "routes": 10,
+                    "backoff": {"max_intervals": 2, "skip_intervals": 1},
+                }],
+            })
+        );
+
+        let resp = api_call(&api, "GET", "/ccp/peers", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn only_admin_can_resync_ccp_peer() {
+        let api = test_node_settings_api_with_ccp();
+        let resp = api_call(&api, "POST", "/ccp/peers/alice/resync", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(&api, "POST", "/ccp/peers/alice/resync", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn ccp_peers_unavailable_without_ccp() {
+        let api = test_node_settings_api();
+        let resp = api_call(&api, "GET", "/ccp/peers", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
     #[tokio::test]
     async fn only_admin_can_put_rates() {
         let api = test_node_settings_api();
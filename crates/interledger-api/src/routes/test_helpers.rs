@@ -1,23 +1,29 @@
 use crate::{
     routes::{accounts_api, node_settings_api},
-    AccountDetails, AccountSettings, NodeStore,
+    AccountDetails, AccountSettings, IncomingPayment, NodeStore, OutgoingPayment,
 };
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::channel::mpsc::UnboundedSender;
-use http::Response;
+use http::{Response, StatusCode};
 use interledger_btp::{BtpAccount, BtpOutgoingService};
-use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_ccp::{
+    CcpPeers, CcpRoutingAccount, Mode, PeerBackoff, PeerInspector, PeerState, RouteCandidate,
+    RouteExplainer, RouteSource, RoutingRelation,
+};
 use interledger_errors::*;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_packet::{Address, ErrorCode, FulfillBuilder, RejectBuilder};
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{PrefixMap, RouterStore};
 use interledger_service::{
     incoming_service_fn, outgoing_service_fn, Account, AccountStore, AddressStore, Username,
 };
 use interledger_service_util::BalanceStore;
-use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
+use interledger_settlement::core::{
+    idempotency::{IdempotentData, IdempotentStore},
+    types::{SettlementAccount, SettlementEngineDetails},
+};
 use interledger_stream::{PaymentNotification, StreamNotificationsStore};
 use once_cell::sync::Lazy;
 use secrecy::SecretString;
@@ -25,7 +31,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::broadcast;
 use url::Url;
 use uuid::Uuid;
@@ -56,7 +62,21 @@ where
 
 pub fn test_node_settings_api(
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    node_settings_api("admin".to_owned(), None, TestStore).recover(default_rejection_handler)
+    node_settings_api("admin".to_owned(), None, TestStore, None, None)
+        .recover(default_rejection_handler)
+}
+
+/// The node settings API with the CCP route explainer and peer inspector enabled
+pub fn test_node_settings_api_with_ccp(
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    node_settings_api(
+        "admin".to_owned(),
+        None,
+        TestStore,
+        Some(Arc::new(TestCcp)),
+        Some(Arc::new(TestCcp)),
+    )
+    .recover(default_rejection_handler)
 }
 
 pub fn test_accounts_api(
@@ -116,6 +136,11 @@ pub static DETAILS: Lazy<Option<Value>> = Lazy::new(|| {
     }))
 });
 const AUTH_PASSWORD: &str = "password";
+/// The account the routing table routes `example.alice` through
+pub static ROUTE_ACCOUNT_ID: Lazy<Uuid> = Lazy::new(|| Uuid::from_u128(1));
+/// Idempotent data saved by the API, shared between tests since the store has no state of its own
+pub static IDEMPOTENCY_CACHE: Lazy<Mutex<HashMap<String, IdempotentData>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TestAccount;
@@ -218,8 +243,68 @@ impl ExchangeRateStore for TestStore {
 }
 
 impl RouterStore for TestStore {
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-        Arc::new(HashMap::new())
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+        let mut routing_table = PrefixMap::new();
+        routing_table.insert("example.alice".to_string(), *ROUTE_ACCOUNT_ID);
+        Arc::new(routing_table)
+    }
+}
+
+struct TestCcp;
+
+#[async_trait]
+impl RouteExplainer for TestCcp {
+    async fn route_candidates(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<RouteCandidate>, CcpRoutingStoreError> {
+        Ok(vec![
+            RouteCandidate {
+                account_id: Uuid::from_u128(2),
+                username: Username::from_str("bob").unwrap(),
+                source: RouteSource::Static,
+                prefix: prefix.to_string(),
+                path: Vec::new(),
+                epoch: None,
+                cost: 0,
+            },
+            RouteCandidate {
+                account_id: *ROUTE_ACCOUNT_ID,
+                username: USERNAME.clone(),
+                source: RouteSource::Ccp,
+                prefix: prefix.to_string(),
+                path: vec!["example.charlie".to_string()],
+                epoch: Some(3),
+                cost: 7,
+            },
+        ])
+    }
+}
+
+#[async_trait]
+impl PeerInspector for TestCcp {
+    async fn peers(&self) -> Result<CcpPeers, CcpRoutingStoreError> {
+        Ok(CcpPeers {
+            routing_table_id: [1; 16],
+            epoch: 5,
+            peers: vec![PeerState {
+                account_id: *ROUTE_ACCOUNT_ID,
+                username: USERNAME.clone(),
+                relation: RoutingRelation::Peer,
+                mode: Mode::Idle,
+                routing_table_id: Some([2; 16]),
+                epoch: Some(3),
+                routes: 10,
+                backoff: Some(PeerBackoff {
+                    max_intervals: 2,
+                    skip_intervals: 1,
+                }),
+            }],
+        })
+    }
+
+    async fn resync_peer(&self, _account_id: Uuid) -> Result<bool, CcpRoutingStoreError> {
+        Ok(true)
     }
 }
 
@@ -290,6 +375,48 @@ impl NodeStore for TestStore {
     ) -> Result<Option<Url>, NodeStoreError> {
         Ok(None)
     }
+
+    async fn save_outgoing_payment(
+        &self,
+        _account_id: Uuid,
+        _payment: OutgoingPayment,
+    ) -> Result<(), NodeStoreError> {
+        Ok(())
+    }
+
+    async fn get_outgoing_payments(
+        &self,
+        _account_id: Uuid,
+        _offset: usize,
+        _limit: usize,
+    ) -> Result<Vec<OutgoingPayment>, NodeStoreError> {
+        Ok(Vec::new())
+    }
+
+    async fn create_incoming_payment(
+        &self,
+        _account_id: Uuid,
+        _payment: IncomingPayment,
+    ) -> Result<(), NodeStoreError> {
+        Ok(())
+    }
+
+    async fn get_incoming_payment(
+        &self,
+        _account_id: Uuid,
+        _id: Uuid,
+    ) -> Result<Option<IncomingPayment>, NodeStoreError> {
+        Ok(None)
+    }
+
+    async fn get_incoming_payments(
+        &self,
+        _account_id: Uuid,
+        _offset: usize,
+        _limit: usize,
+    ) -> Result<Vec<IncomingPayment>, NodeStoreError> {
+        Ok(Vec::new())
+    }
 }
 
 #[async_trait]
@@ -375,3 +502,57 @@ impl HttpStore for TestStore {
         }
     }
 }
+
+#[async_trait]
+impl IdempotentStore for TestStore {
+    async fn load_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Result<Option<IdempotentData>, IdempotentStoreError> {
+        Ok(IDEMPOTENCY_CACHE
+            .lock()
+            .unwrap()
+            .get(&idempotency_key)
+            .cloned())
+    }
+
+    async fn save_idempotent_data(
+        &self,
+        idempotency_key: String,
+        input_hash: [u8; 32],
+        status_code: StatusCode,
+        data: Bytes,
+    ) -> Result<(), IdempotentStoreError> {
+        IDEMPOTENCY_CACHE.lock().unwrap().insert(
+            idempotency_key,
+            IdempotentData::new(status_code, data, input_hash),
+        );
+        Ok(())
+    }
+
+    async fn reserve_idempotent_data(
+        &self,
+        idempotency_key: String,
+        input_hash: [u8; 32],
+        status_code: StatusCode,
+        data: Bytes,
+    ) -> Result<bool, IdempotentStoreError> {
+        let mut cache = IDEMPOTENCY_CACHE.lock().unwrap();
+        if cache.contains_key(&idempotency_key) {
+            return Ok(false);
+        }
+        cache.insert(
+            idempotency_key,
+            IdempotentData::new(status_code, data, input_hash),
+        );
+        Ok(true)
+    }
+
+    async fn delete_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Result<(), IdempotentStoreError> {
+        IDEMPOTENCY_CACHE.lock().unwrap().remove(&idempotency_key);
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::packet::{Route, RouteProp};
+use bytes::Bytes;
+use ring::{
+    digest::{digest, SHA256},
+    hmac,
+};
+
+/// ID of the route property that carries the anchor of the route's auth hash chain
+pub(crate) const AUTH_ANCHOR_PROP_ID: u16 = 16;
+/// The length of the hash chain. The node that owns a prefix counts as one hop,
+/// so an authenticated route can have at most one connector less in its path
+pub(crate) const MAX_AUTH_HOPS: usize = 32;
+
+pub(crate) fn hash(preimage: &[u8; 32]) -> [u8; 32] {
+    let mut out = [0; 32];
+    out.copy_from_slice(digest(&SHA256, preimage).as_ref());
+    out
+}
+
+fn hash_times(preimage: &[u8; 32], times: usize) -> [u8; 32] {
+    (0..times).fold(*preimage, |value, _| hash(&value))
+}
+
+/// An anchor we accepted for a prefix from the node that owns it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PinnedAnchor {
+    pub(crate) anchor: [u8; 32],
+    /// When we last had a route authenticated with this anchor
+    /// (in milliseconds since the UNIX epoch)
+    pub(crate) last_seen: u64,
+}
+
+/// The outcome of checking a route's auth value
+#[derive(Debug, PartialEq)]
+pub(crate) enum RouteAuth {
+    /// The auth value is consistent with the anchor the route carries
+    Valid([u8; 32]),
+    /// The route does not carry an anchor
+    Unauthenticated,
+    /// The auth value does not hash to the anchor in the expected number of steps
+    Invalid,
+}
+
+/// The secret at the start of the hash chain for a prefix we own
+pub(crate) fn chain_seed(routing_secret: &[u8; 32], prefix: &str) -> [u8; 32] {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, routing_secret);
+    let mut secret = [0; 32];
+    secret.copy_from_slice(hmac::sign(&key, prefix.as_bytes()).as_ref());
+    secret
+}
+
+/// Authenticate a route we originate.
+///
+/// The node that owns a prefix derives a secret for it from its routing secret
+/// and commits to that secret by attaching `hash^MAX_AUTH_HOPS(secret)` as the anchor.
+/// A route with `n` connectors in its path carries `hash^(n + 1)(secret)` as its auth value,
+/// which every connector that forwards the route hashes once more. Anyone can check
+/// that a route's auth value hashes to its anchor, but shortening the path requires
+/// a preimage that only the previous hops know. The owner counts as the first hop,
+/// so the secret itself is never sent to anyone.
+pub(crate) fn sign_route(routing_secret: &[u8; 32], route: &mut Route) {
+    let secret = chain_seed(routing_secret, &route.prefix);
+    route.auth = hash_times(&secret, route.path.len() + 1);
+    let anchor = hash_times(&secret, MAX_AUTH_HOPS);
+    route.props.retain(|prop| prop.id != AUTH_ANCHOR_PROP_ID);
+    route.props.push(RouteProp {
+        is_optional: true,
+        is_transitive: true,
+        is_partial: false,
+        is_utf8: false,
+        id: AUTH_ANCHOR_PROP_ID,
+        value: Bytes::from(&anchor[..]),
+    });
+}
+
+/// Check that a route's auth value is consistent with its anchor and path length
+pub(crate) fn verify_route(route: &Route) -> RouteAuth {
+    let anchor = match route
+        .props
+        .iter()
+        .find(|prop| prop.id == AUTH_ANCHOR_PROP_ID)
+    {
+        Some(prop) => &prop.value,
+        None => return RouteAuth::Unauthenticated,
+    };
+    if anchor.len() != 32 || route.path.len() >= MAX_AUTH_HOPS {
+        return RouteAuth::Invalid;
+    }
+
+    let mut expected = [0; 32];
+    expected.copy_from_slice(anchor);
+    if hash_times(&route.auth, MAX_AUTH_HOPS - route.path.len() - 1) == expected {
+        RouteAuth::Valid(expected)
+    } else {
+        RouteAuth::Invalid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [7; 32];
+
+    fn route(prefix: &str) -> Route {
+        Route {
+            prefix: prefix.to_string(),
+            path: Vec::new(),
+            auth: [0; 32],
+            props: Vec::new(),
+        }
+    }
+
+    /// What a connector does when it forwards a route
+    fn forward(route: &mut Route, connector: &str) {
+        route.path.insert(0, connector.to_string());
+        route.auth = hash(&route.auth);
+    }
+
+    #[test]
+    fn signed_route_verifies_after_forwarding() {
+        let mut route = route("example.alice");
+        sign_route(&SECRET, &mut route);
+        let anchor = match verify_route(&route) {
+            RouteAuth::Valid(anchor) => anchor,
+            other => panic!("Expected valid route, got: {:?}", other),
+        };
+
+        forward(&mut route, "example.b");
+        forward(&mut route, "example.c");
+        assert_eq!(verify_route(&route), RouteAuth::Valid(anchor));
+    }
+
+    #[test]
+    fn never_sends_the_chain_seed() {
+        let mut route = route("example.alice");
+        sign_route(&SECRET, &mut route);
+        let seed = chain_seed(&SECRET, "example.alice");
+        assert_ne!(route.auth, seed);
+        assert_eq!(route.auth, hash(&seed));
+    }
+
+    #[test]
+    fn rejects_shortened_path() {
+        let mut route = route("example.alice");
+        sign_route(&SECRET, &mut route);
+        forward(&mut route, "example.b");
+        forward(&mut route, "example.c");
+
+        route.path.remove(0);
+        assert_eq!(verify_route(&route), RouteAuth::Invalid);
+    }
+
+    #[test]
+    fn rejects_tampered_auth_or_anchor() {
+        let mut signed = route("example.alice");
+        sign_route(&SECRET, &mut signed);
+
+        let mut tampered_auth = signed.clone();
+        tampered_auth.auth = [1; 32];
+        assert_eq!(verify_route(&tampered_auth), RouteAuth::Invalid);
+
+        let mut short_anchor = signed;
+        short_anchor.props[0].value = Bytes::from(&[1; 16][..]);
+        assert_eq!(verify_route(&short_anchor), RouteAuth::Invalid);
+    }
+
+    #[test]
+    fn anchor_depends_on_prefix_and_secret() {
+        let mut alice = route("example.alice");
+        let mut bob = route("example.bob");
+        let mut other_alice = route("example.alice");
+        sign_route(&SECRET, &mut alice);
+        sign_route(&SECRET, &mut bob);
+        sign_route(&[8; 32], &mut other_alice);
+
+        assert_ne!(alice.props[0].value, bob.props[0].value);
+        assert_ne!(alice.props[0].value, other_alice.props[0].value);
+    }
+
+    #[test]
+    fn routes_without_anchor_are_unauthenticated() {
+        assert_eq!(
+            verify_route(&route("example.alice")),
+            RouteAuth::Unauthenticated
+        );
+    }
+}
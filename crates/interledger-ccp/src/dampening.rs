@@ -0,0 +1,152 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Keeps track of how often routes are withdrawn so that routes which keep
+/// disappearing and reappearing are not re-advertised to our peers every time.
+///
+/// A prefix is suppressed once it has been withdrawn `max_flaps` times within
+/// the window. It is released once enough of those withdrawals fall out of the window.
+#[derive(Debug)]
+pub(crate) struct FlapDampening {
+    window: Duration,
+    max_flaps: u32,
+    withdrawals: HashMap<String, VecDeque<Instant>>,
+    /// Prefixes we held back from the forwarding table because they were suppressed
+    suppressed: HashSet<String>,
+}
+
+impl FlapDampening {
+    /// A `max_flaps` of 0 disables dampening
+    pub(crate) fn new(window: Duration, max_flaps: u32) -> Self {
+        FlapDampening {
+            window,
+            max_flaps,
+            withdrawals: HashMap::new(),
+            suppressed: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn record_withdrawal(&mut self, prefix: &str, now: Instant) {
+        if self.max_flaps == 0 {
+            return;
+        }
+        let withdrawals = self.withdrawals.entry(prefix.to_string()).or_default();
+        withdrawals.push_back(now);
+        // Only the most recent withdrawals can affect whether the prefix is suppressed
+        while withdrawals.len() > self.max_flaps as usize {
+            withdrawals.pop_front();
+        }
+    }
+
+    /// Returns true if the prefix has been withdrawn too often recently to be advertised.
+    /// Suppressed prefixes are remembered so they can be advertised once they are released
+    pub(crate) fn suppress(&mut self, prefix: &str, now: Instant) -> bool {
+        let suppressed = self.is_suppressed(prefix, now);
+        if suppressed {
+            self.suppressed.insert(prefix.to_string());
+        }
+        suppressed
+    }
+
+    /// Returns the prefixes that were suppressed before but can be advertised again
+    pub(crate) fn take_released(&mut self, now: Instant) -> Vec<String> {
+        self.prune(now);
+        let withdrawals = &self.withdrawals;
+        let max_flaps = self.max_flaps as usize;
+        let released: Vec<String> = self
+            .suppressed
+            .iter()
+            .filter(|prefix| {
+                withdrawals
+                    .get(prefix.as_str())
+                    .map_or(true, |times| times.len() < max_flaps)
+            })
+            .cloned()
+            .collect();
+        for prefix in released.iter() {
+            self.suppressed.remove(prefix);
+        }
+        released
+    }
+
+    fn is_suppressed(&self, prefix: &str, now: Instant) -> bool {
+        self.max_flaps > 0
+            && self.withdrawals.get(prefix).map_or(false, |times| {
+                times
+                    .iter()
+                    .filter(|time| now.duration_since(**time) < self.window)
+                    .count()
+                    >= self.max_flaps as usize
+            })
+    }
+
+    /// Forget withdrawals that happened before the window
+    fn prune(&mut self, now: Instant) {
+        let window = self.window;
+        self.withdrawals.retain(|_, times| {
+            while times
+                .front()
+                .map_or(false, |time| now.duration_since(*time) >= window)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn suppresses_after_max_flaps_within_window() {
+        let mut dampening = FlapDampening::new(WINDOW, 2);
+        let now = Instant::now();
+        dampening.record_withdrawal("example.a", now);
+        assert!(!dampening.suppress("example.a", now));
+        dampening.record_withdrawal("example.a", now + Duration::from_secs(10));
+        assert!(dampening.suppress("example.a", now + Duration::from_secs(10)));
+        assert!(!dampening.suppress("example.b", now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn ignores_flaps_outside_window() {
+        let mut dampening = FlapDampening::new(WINDOW, 2);
+        let now = Instant::now();
+        dampening.record_withdrawal("example.a", now);
+        dampening.record_withdrawal("example.a", now + WINDOW);
+        assert!(!dampening.suppress("example.a", now + WINDOW));
+    }
+
+    #[test]
+    fn releases_suppressed_prefixes_after_window() {
+        let mut dampening = FlapDampening::new(WINDOW, 2);
+        let now = Instant::now();
+        dampening.record_withdrawal("example.a", now);
+        dampening.record_withdrawal("example.a", now);
+        assert!(dampening.suppress("example.a", now));
+        assert!(dampening.take_released(now).is_empty());
+
+        let later = now + WINDOW;
+        assert_eq!(
+            dampening.take_released(later),
+            vec!["example.a".to_string()]
+        );
+        assert!(dampening.take_released(later).is_empty());
+        assert!(!dampening.suppress("example.a", later));
+    }
+
+    #[test]
+    fn zero_max_flaps_disables_dampening() {
+        let mut dampening = FlapDampening::new(WINDOW, 0);
+        let now = Instant::now();
+        for _ in 0..5 {
+            dampening.record_withdrawal("example.a", now);
+        }
+        assert!(!dampening.suppress("example.a", now));
+    }
+}
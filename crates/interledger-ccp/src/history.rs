@@ -0,0 +1,169 @@
+use crate::packet::Route;
+
+/// The routes added and the prefixes withdrawn in one or more epochs of a routing table
+pub(crate) type NewAndWithdrawnRoutes = (Vec<Route>, Vec<String>);
+
+/// The changes made to the forwarding table in each epoch, which are sent to peers
+/// to bring their copy of the table up to date.
+///
+/// The updates of the oldest epochs can be merged into a single compacted update, which brings
+/// a table at any of those epochs up to date with the first epoch after them. Peers which are
+/// that far behind are sent the compacted update instead of each of the updates they missed,
+/// which keeps the history from growing with every epoch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct UpdateHistory {
+    /// Number of epochs merged into the compacted update
+    pub(crate) compacted_epochs: u32,
+    /// The routes of the compacted epochs which were still advertised at the end of them,
+    /// and every prefix withdrawn in them which was not advertised again
+    pub(crate) compacted: NewAndWithdrawnRoutes,
+    /// The update of each epoch after the compacted ones
+    pub(crate) updates: Vec<NewAndWithdrawnRoutes>,
+}
+
+impl UpdateHistory {
+    /// Number of epochs the history covers, which is the forwarding table's epoch
+    pub(crate) fn epochs(&self) -> usize {
+        self.compacted_epochs as usize + self.updates.len()
+    }
+
+    /// Add the update of the next epoch
+    pub(crate) fn push(&mut self, update: NewAndWithdrawnRoutes) {
+        self.updates.push(update);
+    }
+
+    /// The update of the most recent epoch, unless it was compacted
+    #[cfg(test)]
+    pub(crate) fn last(&self) -> Option<&NewAndWithdrawnRoutes> {
+        self.updates.last()
+    }
+
+    /// The updates to apply, in order, to bring a table at epoch `start` up to epoch `end`.
+    /// Tables at one of the compacted epochs are sent the compacted update first.
+    pub(crate) fn between(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = &NewAndWithdrawnRoutes> {
+        let compacted_epochs = self.compacted_epochs as usize;
+        let compacted = if start < compacted_epochs && start < end {
+            Some(&self.compacted)
+        } else {
+            None
+        };
+        let skip = start.saturating_sub(compacted_epochs);
+        let take = end.saturating_sub(compacted_epochs).saturating_sub(skip);
+        compacted
+            .into_iter()
+            .chain(self.updates.iter().skip(skip).take(take))
+    }
+
+    /// Merge the updates of the epochs before `end` into the compacted update
+    pub(crate) fn compact(&mut self, end: usize) {
+        let count = end
+            .saturating_sub(self.compacted_epochs as usize)
+            .min(self.updates.len());
+        if count == 0 {
+            return;
+        }
+        let (new_routes, withdrawn_routes) = &mut self.compacted;
+        // Peers apply the withdrawals of an update before its new routes
+        for (new, withdrawn) in self.updates.drain(..count) {
+            for prefix in withdrawn {
+                new_routes.retain(|route| route.prefix != prefix);
+                if !withdrawn_routes.contains(&prefix) {
+                    withdrawn_routes.push(prefix);
+                }
+            }
+            for route in new {
+                withdrawn_routes.retain(|prefix| *prefix != route.prefix);
+                new_routes.retain(|new_route| new_route.prefix != route.prefix);
+                new_routes.push(route);
+            }
+        }
+        self.compacted_epochs += count as u32;
+    }
+}
+
+impl From<Vec<NewAndWithdrawnRoutes>> for UpdateHistory {
+    fn from(updates: Vec<NewAndWithdrawnRoutes>) -> Self {
+        UpdateHistory {
+            compacted_epochs: 0,
+            compacted: (Vec::new(), Vec::new()),
+            updates,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(prefix: &str, auth: u8) -> Route {
+        Route {
+            prefix: prefix.to_string(),
+            path: vec!["example.x".to_string()],
+            auth: [auth; 32],
+            props: Vec::new(),
+        }
+    }
+
+    fn history() -> UpdateHistory {
+        UpdateHistory::from(vec![
+            (
+                vec![route("example.a", 1), route("example.b", 1)],
+                Vec::new(),
+            ),
+            (vec![route("example.a", 2)], vec!["example.b".to_string()]),
+            (vec![route("example.c", 1)], Vec::new()),
+            (Vec::new(), vec!["example.c".to_string()]),
+        ])
+    }
+
+    #[test]
+    fn compacts_oldest_epochs() {
+        let mut history = history();
+        history.compact(3);
+        assert_eq!(history.epochs(), 4);
+        assert_eq!(history.compacted_epochs, 3);
+        assert_eq!(
+            history.compacted,
+            (
+                vec![route("example.a", 2), route("example.c", 1)],
+                vec!["example.b".to_string()]
+            )
+        );
+        assert_eq!(history.updates.len(), 1);
+
+        // Compacting epochs which were already compacted does nothing
+        let compacted = history.clone();
+        history.compact(2);
+        assert_eq!(history, compacted);
+
+        history.compact(10);
+        assert_eq!(history.compacted_epochs, 4);
+        assert_eq!(
+            history.compacted,
+            (
+                vec![route("example.a", 2)],
+                vec!["example.b".to_string(), "example.c".to_string()]
+            )
+        );
+        assert!(history.updates.is_empty());
+    }
+
+    #[test]
+    fn sends_compacted_update_to_tables_in_compacted_epochs() {
+        let mut history = history();
+        history.compact(2);
+        for start in 0..2 {
+            let updates: Vec<_> = history.between(start, 4).collect();
+            assert_eq!(updates.len(), 3);
+            assert_eq!(updates[0], &history.compacted);
+            assert_eq!(updates[1], &history.updates[0]);
+        }
+        let updates: Vec<_> = history.between(3, 4).collect();
+        assert_eq!(updates, vec![&history.updates[1]]);
+        assert_eq!(history.between(4, 4).count(), 0);
+    }
+}
@@ -11,20 +11,27 @@
 
 use async_trait::async_trait;
 use interledger_errors::CcpRoutingStoreError;
-use interledger_service::Account;
+use interledger_service::{Account, Username};
 use std::collections::HashMap;
 use std::{fmt, str::FromStr};
 use uuid::Uuid;
 
+mod auth;
+mod dampening;
 #[cfg(test)]
 mod fixtures;
+mod history;
 mod packet;
+mod policy;
 mod routing_table;
+mod selection;
 mod server;
+mod snapshot;
 #[cfg(test)]
 mod test_helpers;
 
 pub use packet::{Mode, RouteControlRequest};
+pub use selection::RouteSelectionPolicy;
 pub use server::{CcpRouteManager, CcpRouteManagerBuilder};
 
 use serde::{Deserialize, Serialize};
@@ -93,6 +100,140 @@ pub trait CcpRoutingAccount: Account {
         self.routing_relation() == RoutingRelation::Parent
             || self.routing_relation() == RoutingRelation::Peer
     }
+
+    /// Prefixes we accept routes for from this account.
+    /// If empty, routes for any prefix are accepted (unless they are denied)
+    fn route_import_allow(&self) -> &[String] {
+        &[]
+    }
+
+    /// Prefixes we never accept routes for from this account
+    fn route_import_deny(&self) -> &[String] {
+        &[]
+    }
+
+    /// Prefixes we advertise routes for to this account.
+    /// If empty, routes for any prefix are advertised (unless they are denied)
+    fn route_export_allow(&self) -> &[String] {
+        &[]
+    }
+
+    /// Prefixes we never advertise routes for to this account
+    fn route_export_deny(&self) -> &[String] {
+        &[]
+    }
+
+    /// The maximum number of hops a route from this account may have before we ignore it
+    fn max_route_path_length(&self) -> Option<u32> {
+        None
+    }
+
+    /// The maximum number of routes we keep from this account
+    fn max_imported_routes(&self) -> Option<u32> {
+        None
+    }
+
+    /// Indicates whether we should drop routes from this account that are not authenticated
+    /// with an anchor we trust. Routes that fail authentication are always dropped
+    fn strict_route_auth(&self) -> bool {
+        false
+    }
+
+    /// The cost of sending packets through this account, which is added to the cost
+    /// of the routes it sends us and of the routes we advertise through it
+    fn route_cost(&self) -> u32 {
+        0
+    }
+}
+
+/// Where a route for a prefix comes from
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteSource {
+    /// A route configured by the node operator
+    Static,
+    /// The address of one of the node's accounts
+    Local,
+    /// A route learned from a peer's CCP route broadcasts
+    Ccp,
+    /// The node's default route, which is used if no other route matches
+    Default,
+}
+
+/// A route the CCP Route Manager could use for a prefix
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteCandidate {
+    pub account_id: Uuid,
+    pub username: Username,
+    pub source: RouteSource,
+    /// The prefix the route was configured or advertised for
+    pub prefix: String,
+    /// The connectors a CCP route passes through (empty for other routes)
+    pub path: Vec<String>,
+    /// The epoch we are up to date with in the routing table of the peer the CCP route was
+    /// learned from. This is not necessarily the epoch in which the route itself last changed
+    pub epoch: Option<u32>,
+    /// The route cost configured for the account plus the cost the CCP route advertises
+    pub cost: u32,
+}
+
+/// Explains which routes the CCP Route Manager considered for a prefix
+#[async_trait]
+pub trait RouteExplainer: Send + Sync {
+    /// Returns every route that could be used for the prefix, best first
+    async fn route_candidates(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<RouteCandidate>, CcpRoutingStoreError>;
+}
+
+/// How long we wait before broadcasting routes to a peer again after sending to it failed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerBackoff {
+    /// The number of broadcast intervals we wait after the latest failure
+    pub max_intervals: u8,
+    /// The number of broadcast intervals left before we try again
+    pub skip_intervals: u8,
+}
+
+/// What the CCP Route Manager knows about an account it exchanges routes with
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerState {
+    pub account_id: Uuid,
+    pub username: Username,
+    pub relation: RoutingRelation,
+    /// The mode the peer asked for in its last Route Control Request
+    /// (Sync if it has not sent us one)
+    pub mode: Mode,
+    /// The ID of the peer's routing table, if it has sent us a Route Update Request
+    pub routing_table_id: Option<[u8; 16]>,
+    /// The epoch of the peer's routing table we are up to date with
+    pub epoch: Option<u32>,
+    /// The number of routes we have from the peer
+    pub routes: usize,
+    /// Set if broadcasting to the peer failed and we are backing off
+    pub backoff: Option<PeerBackoff>,
+}
+
+/// The state of the CCP Route Manager's forwarding table and peers
+#[derive(Clone, Debug, PartialEq)]
+pub struct CcpPeers {
+    /// The ID of the routing table we broadcast to our peers
+    pub routing_table_id: [u8; 16],
+    /// The current epoch of the routing table we broadcast to our peers
+    pub epoch: u32,
+    pub peers: Vec<PeerState>,
+}
+
+/// Inspects and manages the peers the CCP Route Manager exchanges routes with
+#[async_trait]
+pub trait PeerInspector: Send + Sync {
+    /// Returns the state of every account we send routes to or receive routes from
+    async fn peers(&self) -> Result<CcpPeers, CcpRoutingStoreError>;
+
+    /// Asks the peer to send us its whole routing table again.
+    /// Returns false if we do not receive routes from the account
+    async fn resync_peer(&self, account_id: Uuid) -> Result<bool, CcpRoutingStoreError>;
 }
 
 // key = Bytes, key should be Address -- TODO
@@ -127,4 +268,20 @@ pub trait CcpRoutingStore: Clone {
         &mut self,
         routes: impl IntoIterator<Item = (String, Self::Account)> + Send + 'async_trait,
     ) -> Result<(), CcpRoutingStoreError>;
+
+    /// Sets the ranked alternative next hops for the given prefixes (prefix -> accounts, best first)
+    /// and removes the fallback routes of the removed prefixes.
+    /// The fallback routes of any other prefixes are left as they are.
+    async fn update_fallback_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<Self::Account>)> + Send + 'async_trait,
+        removed_prefixes: impl IntoIterator<Item = String> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError>;
+
+    /// Saves the CCP Route Manager's serialized routing tables, replacing the ones saved before.
+    /// These are loaded when the node restarts so it does not need to learn all of its routes again
+    async fn set_routing_state(&mut self, state: Vec<u8>) -> Result<(), CcpRoutingStoreError>;
+
+    /// Gets the routing tables that were last saved with `set_routing_state`, if any
+    async fn get_routing_state(&self) -> Result<Option<Vec<u8>>, CcpRoutingStoreError>;
 }
@@ -0,0 +1,60 @@
+/// Returns true if the route prefix is covered by the given policy prefix.
+/// Policy prefixes match on address segments, so "example.a" covers "example.a"
+/// and "example.a.b" but not "example.ab". The empty prefix covers everything.
+fn covers(policy_prefix: &str, route_prefix: &str) -> bool {
+    if policy_prefix.is_empty() || policy_prefix.ends_with('.') {
+        route_prefix.starts_with(policy_prefix)
+    } else {
+        route_prefix.starts_with(policy_prefix)
+            && (route_prefix.len() == policy_prefix.len()
+                || route_prefix[policy_prefix.len()..].starts_with('.'))
+    }
+}
+
+/// Check a route prefix against an allow list and a deny list.
+/// Denied prefixes take precedence. An empty allow list allows all prefixes.
+pub(crate) fn is_permitted(route_prefix: &str, allow: &[String], deny: &[String]) -> bool {
+    if deny.iter().any(|prefix| covers(prefix, route_prefix)) {
+        return false;
+    }
+    allow.is_empty() || allow.iter().any(|prefix| covers(prefix, route_prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixes(list: &[&str]) -> Vec<String> {
+        list.iter().map(|prefix| prefix.to_string()).collect()
+    }
+
+    #[test]
+    fn matches_on_segment_boundaries() {
+        assert!(covers("example.a", "example.a"));
+        assert!(covers("example.a", "example.a.b"));
+        assert!(!covers("example.a", "example.ab"));
+        assert!(covers("example.", "example.ab"));
+        assert!(covers("", "example.a"));
+    }
+
+    #[test]
+    fn allows_everything_by_default() {
+        assert!(is_permitted("example.a", &[], &[]));
+    }
+
+    #[test]
+    fn only_allows_listed_prefixes() {
+        let allow = prefixes(&["example.a", "example.b"]);
+        assert!(is_permitted("example.a.c", &allow, &[]));
+        assert!(is_permitted("example.b", &allow, &[]));
+        assert!(!is_permitted("example.c", &allow, &[]));
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        let allow = prefixes(&["example."]);
+        let deny = prefixes(&["example.a"]);
+        assert!(!is_permitted("example.a.b", &allow, &deny));
+        assert!(is_permitted("example.b", &allow, &deny));
+    }
+}
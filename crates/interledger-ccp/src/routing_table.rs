@@ -1,4 +1,5 @@
 use crate::packet::{Route, RouteUpdateRequest};
+use interledger_router::PrefixMap;
 use once_cell::sync::Lazy;
 use ring::rand::{SecureRandom, SystemRandom};
 use std::collections::HashMap;
@@ -7,36 +8,6 @@ use tracing::{debug, trace};
 
 static RANDOM: Lazy<SystemRandom> = Lazy::new(SystemRandom::new);
 
-#[derive(Debug, Clone)]
-struct PrefixMap<T> {
-    map: HashMap<String, T>,
-}
-
-impl<T> PrefixMap<T> {
-    pub fn new() -> Self {
-        PrefixMap {
-            map: HashMap::new(),
-        }
-    }
-
-    pub fn insert(&mut self, prefix: String, item: T) -> bool {
-        self.map.insert(prefix, item).is_none()
-    }
-
-    pub fn remove(&mut self, prefix: &str) -> bool {
-        self.map.remove(prefix).is_some()
-    }
-
-    pub fn resolve(&self, prefix: &str) -> Option<&T> {
-        // TODO use parallel iterator
-        self.map
-            .iter()
-            .filter(|(p, _)| prefix.starts_with(p.as_str()))
-            .max_by_key(|(p, _)| p.len())
-            .map(|(_prefix, item)| item)
-    }
-}
-
 /// The routing table is identified by an ID (a UUID in array form) and an "epoch".
 /// When an Interledger node reloads, it will generate a new UUID for its routing table.
 /// Each update applied increments the epoch number, so it acts as a version tracker.
@@ -60,6 +31,20 @@ where
         }
     }
 
+    /// Recreate a routing table that was saved to the store
+    pub(crate) fn restore(
+        id: [u8; 16],
+        epoch: u32,
+        routes: impl IntoIterator<Item = (A, Route)>,
+    ) -> Self {
+        let mut table = RoutingTable::new(id);
+        table.epoch = epoch;
+        for (account, route) in routes {
+            table.add_route(account, route);
+        }
+        table
+    }
+
     #[cfg(test)]
     pub(crate) fn set_id(&mut self, id: [u8; 16]) {
         self.id = id;
@@ -87,19 +72,33 @@ where
 
     /// Set a particular route, overwriting the one that was there before
     pub(crate) fn set_route(&mut self, prefix: String, account: A, route: Route) {
-        self.prefix_map.remove(&prefix);
         self.prefix_map.insert(prefix, (account, route));
     }
 
     /// Remove the route for the given prefix. Returns true if that route existed before
     pub(crate) fn delete_route(&mut self, prefix: &str) -> bool {
-        self.prefix_map.remove(prefix)
+        self.prefix_map.remove(prefix).is_some()
     }
 
     /// Add the given route. Returns true if that routed did not already exist
     pub(crate) fn add_route(&mut self, account: A, route: Route) -> bool {
         self.prefix_map
             .insert(route.prefix.clone(), (account, route))
+            .is_none()
+    }
+
+    /// Check whether there is a route for exactly the given prefix
+    pub(crate) fn has_route(&self, prefix: &str) -> bool {
+        self.prefix_map.contains_key(prefix)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.prefix_map.len()
+    }
+
+    /// Get the route for exactly the given prefix
+    pub(crate) fn get_exact_route(&self, prefix: &str) -> Option<&(A, Route)> {
+        self.prefix_map.get(prefix)
     }
 
     /// Get the best route we have for the given prefix
@@ -107,12 +106,16 @@ where
         self.prefix_map.resolve(prefix)
     }
 
+    /// Iterate over all of the prefixes in the table and their routes
+    pub(crate) fn routes(&self) -> impl Iterator<Item = (&str, &(A, Route))> {
+        self.prefix_map.iter()
+    }
+
     pub(crate) fn get_simplified_table(&self) -> HashMap<String, A> {
         HashMap::from_iter(
             self.prefix_map
-                .map
                 .iter()
-                .map(|(address, (account, _route))| (address.clone(), account.clone())),
+                .map(|(address, (account, _route))| (address.to_string(), account.clone())),
         )
     }
 
@@ -207,16 +210,16 @@ mod prefix_map {
     #[test]
     fn doesnt_insert_duplicates() {
         let mut map = PrefixMap::new();
-        assert!(map.insert("example.a".to_string(), 1));
-        assert!(!map.insert("example.a".to_string(), 1));
+        assert!(map.insert("example.a".to_string(), 1).is_none());
+        assert!(map.insert("example.a".to_string(), 1).is_some());
     }
 
     #[test]
     fn removes_entry() {
         let mut map = PrefixMap::new();
-        assert!(map.insert("example.a".to_string(), 1));
-        assert!(map.remove("example.a"));
-        assert!(map.map.is_empty());
+        assert!(map.insert("example.a".to_string(), 1).is_none());
+        assert!(map.remove("example.a").is_some());
+        assert!(map.is_empty());
     }
 
     #[test]
@@ -0,0 +1,243 @@
+use crate::{
+    packet::{Route, RouteProp},
+    CcpRoutingAccount,
+};
+use bytes::Bytes;
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// ID of the route property that carries the cost of reaching the prefix from the advertising node
+pub(crate) const ROUTE_COST_PROP_ID: u16 = 17;
+
+/// The cost the route advertises (0 if it does not carry one)
+pub(crate) fn route_cost(route: &Route) -> u32 {
+    route
+        .props
+        .iter()
+        .find(|prop| prop.id == ROUTE_COST_PROP_ID && prop.value.len() == 4)
+        .map(|prop| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&prop.value);
+            u32::from_be_bytes(bytes)
+        })
+        .unwrap_or(0)
+}
+
+/// Set the cost the route advertises. Routes with no cost do not carry the property
+pub(crate) fn set_route_cost(route: &mut Route, cost: u32) {
+    route.props.retain(|prop| prop.id != ROUTE_COST_PROP_ID);
+    if cost > 0 {
+        route.props.push(RouteProp {
+            is_optional: true,
+            is_transitive: true,
+            is_partial: false,
+            is_utf8: false,
+            id: ROUTE_COST_PROP_ID,
+            value: Bytes::from(&cost.to_be_bytes()[..]),
+        });
+    }
+}
+
+/// Combines the properties of a route into a score used to choose between routes
+/// to the same prefix from accounts with the same routing relation.
+/// Routes with a lower score are preferred.
+///
+/// The default policy only counts the length of the route's path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RouteSelectionPolicy {
+    /// Added to the score for each connector in the route's path
+    pub path_length_weight: u32,
+    /// Multiplied by the route's cost, which is the cost advertised by the peer
+    /// plus the route cost configured for the peer's account
+    pub cost_weight: u32,
+    /// Multiplied by the round trip time (in milliseconds) we observed for the peer's CCP messages.
+    /// This is the round trip time of the control plane, which may differ from how long
+    /// ILP packets forwarded through the peer take.
+    /// Peers we have not measured yet are treated as having a round trip time of 0
+    pub rtt_weight: u32,
+}
+
+impl Default for RouteSelectionPolicy {
+    fn default() -> Self {
+        RouteSelectionPolicy {
+            path_length_weight: 1,
+            cost_weight: 0,
+            rtt_weight: 0,
+        }
+    }
+}
+
+/// Scores routes according to the route selection policy and the round trip times we observed
+#[derive(Clone, Debug)]
+pub(crate) struct RouteScorer<'a> {
+    pub(crate) policy: RouteSelectionPolicy,
+    pub(crate) round_trip_times: &'a HashMap<Uuid, Duration>,
+}
+
+impl RouteScorer<'_> {
+    /// The cost of reaching the route's prefix through the account
+    pub(crate) fn cost<A: CcpRoutingAccount>(account: &A, route: &Route) -> u32 {
+        route_cost(route).saturating_add(account.route_cost())
+    }
+
+    pub(crate) fn score<A: CcpRoutingAccount>(&self, account: &A, route: &Route) -> u64 {
+        let rtt = self
+            .round_trip_times
+            .get(&account.id())
+            .map_or(0, |rtt| rtt.as_millis() as u64);
+        (route.path.len() as u64)
+            .saturating_mul(u64::from(self.policy.path_length_weight))
+            .saturating_add(
+                u64::from(Self::cost(account, route))
+                    .saturating_mul(u64::from(self.policy.cost_weight)),
+            )
+            .saturating_add(rtt.saturating_mul(u64::from(self.policy.rtt_weight)))
+    }
+}
+
+/// The round trip times we observed for each account. These are measured from the CCP
+/// messages we send, so they reflect the control plane rather than the data path
+#[derive(Debug, Default)]
+pub(crate) struct RoundTripTimes {
+    /// The smoothed round trip time of the CCP messages we sent to each account
+    pub(crate) smoothed: HashMap<Uuid, Duration>,
+    /// The round trip times routes are ranked with. Each one only follows the smoothed round
+    /// trip time once they differ by more than a quarter, so that routes are not ranked again
+    /// for every sample
+    pub(crate) ranked: HashMap<Uuid, Duration>,
+    /// Accounts whose ranked round trip time changed since their routes were last ranked
+    changed: HashSet<Uuid>,
+}
+
+impl RoundTripTimes {
+    /// Fold a new sample into the account's smoothed round trip time.
+    /// Returns true if the round trip time its routes are ranked with changed
+    pub(crate) fn record(&mut self, account_id: Uuid, sample: Duration) -> bool {
+        let smoothed = smooth_round_trip_time(self.smoothed.get(&account_id).cloned(), sample);
+        self.smoothed.insert(account_id, smoothed);
+        let changed = self.ranked.get(&account_id).map_or(true, |ranked| {
+            max(*ranked, smoothed) - min(*ranked, smoothed) > *ranked / 4
+        });
+        if changed {
+            self.ranked.insert(account_id, smoothed);
+            self.changed.insert(account_id);
+        }
+        changed
+    }
+
+    /// Returns the accounts whose routes need to be ranked again
+    pub(crate) fn take_changed(&mut self) -> Vec<Uuid> {
+        self.changed.drain().collect()
+    }
+
+    /// Forget the round trip times of all accounts except the given ones
+    pub(crate) fn retain(&mut self, account_ids: &HashSet<Uuid>) {
+        self.smoothed.retain(|id, _| account_ids.contains(id));
+        self.ranked.retain(|id, _| account_ids.contains(id));
+        self.changed.retain(|id| account_ids.contains(id));
+    }
+
+    /// A scorer which ranks routes with these round trip times
+    pub(crate) fn scorer(&self, policy: RouteSelectionPolicy) -> RouteScorer<'_> {
+        RouteScorer {
+            policy,
+            round_trip_times: &self.ranked,
+        }
+    }
+}
+
+/// Fold a new round trip time sample into the running average for a peer,
+/// weighting the new sample by 1/8 the way TCP smooths its round trip time
+pub(crate) fn smooth_round_trip_time(previous: Option<Duration>, sample: Duration) -> Duration {
+    match previous {
+        Some(previous) => (previous * 7 + sample) / 8,
+        None => sample,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TestAccount;
+
+    fn route(path_length: usize, cost: u32) -> Route {
+        let mut route = Route {
+            prefix: "example.a".to_string(),
+            path: vec!["example.hop".to_string(); path_length],
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        set_route_cost(&mut route, cost);
+        route
+    }
+
+    #[test]
+    fn route_cost_round_trips_through_props() {
+        let mut route = route(1, 42);
+        assert_eq!(route_cost(&route), 42);
+        set_route_cost(&mut route, 7);
+        assert_eq!(route_cost(&route), 7);
+        assert_eq!(route.props.len(), 1);
+        set_route_cost(&mut route, 0);
+        assert!(route.props.is_empty());
+        assert_eq!(route_cost(&route), 0);
+    }
+
+    #[test]
+    fn default_policy_only_counts_path_length() {
+        let account = TestAccount::new(Uuid::from_slice(&[1; 16]).unwrap(), "example.peer");
+        let scorer = RoundTripTimes::default().scorer(RouteSelectionPolicy::default());
+        assert_eq!(scorer.score(&account, &route(3, 1000)), 3);
+    }
+
+    #[test]
+    fn combines_path_length_cost_and_rtt() {
+        let id = Uuid::from_slice(&[1; 16]).unwrap();
+        let mut account = TestAccount::new(id, "example.peer");
+        account.policy.route_cost = 5;
+        let mut round_trip_times = HashMap::new();
+        round_trip_times.insert(id, Duration::from_millis(20));
+        let scorer = RouteScorer {
+            policy: RouteSelectionPolicy {
+                path_length_weight: 100,
+                cost_weight: 2,
+                rtt_weight: 3,
+            },
+            round_trip_times: &round_trip_times,
+        };
+        // 2 hops * 100 + (10 advertised + 5 configured) * 2 + 20ms * 3
+        assert_eq!(scorer.score(&account, &route(2, 10)), 290);
+    }
+
+    #[test]
+    fn smooths_round_trip_times() {
+        let first = smooth_round_trip_time(None, Duration::from_millis(80));
+        assert_eq!(first, Duration::from_millis(80));
+        assert_eq!(
+            smooth_round_trip_time(Some(first), Duration::from_millis(160)),
+            Duration::from_millis(90)
+        );
+    }
+
+    #[test]
+    fn ranks_with_round_trip_times_that_changed_enough() {
+        let id = Uuid::from_slice(&[1; 16]).unwrap();
+        let mut round_trip_times = RoundTripTimes::default();
+        assert!(round_trip_times.record(id, Duration::from_millis(80)));
+        // The smoothed round trip time is 90ms, which is within a quarter of 80ms
+        assert!(!round_trip_times.record(id, Duration::from_millis(160)));
+        assert_eq!(round_trip_times.smoothed[&id], Duration::from_millis(90));
+        assert_eq!(round_trip_times.ranked[&id], Duration::from_millis(80));
+        // 90ms * 7/8 + 800ms / 8 = 178.75ms
+        assert!(round_trip_times.record(id, Duration::from_millis(800)));
+        assert_eq!(round_trip_times.ranked[&id], round_trip_times.smoothed[&id]);
+        assert_eq!(round_trip_times.take_changed(), vec![id]);
+        assert!(round_trip_times.take_changed().is_empty());
+
+        round_trip_times.retain(&HashSet::new());
+        assert!(round_trip_times.smoothed.is_empty());
+        assert!(round_trip_times.ranked.is_empty());
+    }
+}
@@ -1,10 +1,17 @@
 use crate::{
+    auth::{hash, sign_route, verify_route, PinnedAnchor, RouteAuth},
+    dampening::FlapDampening,
+    history::UpdateHistory,
     packet::{
         Mode, Route, RouteControlRequest, RouteUpdateRequest, CCP_CONTROL_DESTINATION,
         CCP_RESPONSE, CCP_UPDATE_DESTINATION,
     },
+    policy::is_permitted,
     routing_table::RoutingTable,
-    CcpRoutingAccount, CcpRoutingStore, RoutingRelation,
+    selection::{route_cost, set_route_cost, RoundTripTimes, RouteScorer, RouteSelectionPolicy},
+    snapshot::{RoutingSnapshot, TableSnapshot},
+    CcpPeers, CcpRoutingAccount, CcpRoutingStore, PeerBackoff, PeerInspector, PeerState,
+    RouteCandidate, RouteExplainer, RouteSource, RoutingRelation,
 };
 use async_trait::async_trait;
 use futures::future::join_all;
@@ -15,9 +22,8 @@ use interledger_service::{
     OutgoingService,
 };
 use parking_lot::{Mutex, RwLock};
-use ring::digest::{digest, SHA256};
 use std::cmp::Ordering as StdOrdering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::{
     cmp::min,
     convert::TryFrom,
@@ -26,7 +32,7 @@ use std::{
         atomic::{AtomicU32, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tracing::{debug, error, trace, warn};
 use uuid::Uuid;
@@ -38,22 +44,18 @@ use futures::TryFutureExt;
 #[cfg(test)]
 use once_cell::sync::Lazy;
 
-// TODO should the route expiry be longer? we use 30 seconds now
-// because the expiry shortener will lower the expiry to 30 seconds
-// otherwise. we could make it longer and make sure the BTP server
-// comes after the expiry shortener
+/// The hold down time we send in our route updates unless it is set with
+/// [`CcpRouteManagerBuilder::route_expiry_time`]. This matches the 30 seconds the
+/// expiry shortener lowers expiries to, so longer values only help if the BTP server
+/// comes after the expiry shortener
 const DEFAULT_ROUTE_EXPIRY_TIME: u32 = 30000;
 const DEFAULT_BROADCAST_INTERVAL: u64 = 30000;
+const DEFAULT_FLAP_DAMPENING_WINDOW: u64 = 300_000;
+const DEFAULT_MAX_ROUTE_FLAPS: u32 = 3;
+const DEFAULT_MAX_ROUTING_STATE_AGE: u64 = 300_000;
+const DEFAULT_ROUTE_AUTH_ANCHOR_TTL: u64 = 7 * 24 * 60 * 60 * 1000;
 const DUMMY_ROUTING_TABLE_ID: [u8; 16] = [0; 16];
 
-fn hash(preimage: &[u8; 32]) -> [u8; 32] {
-    let mut out = [0; 32];
-    out.copy_from_slice(digest(&SHA256, preimage).as_ref());
-    out
-}
-
-type NewAndWithdrawnRoutes = (Vec<Route>, Vec<String>);
-
 /// Builder for [CcpRouteManager](./CcpRouteManager.html)
 /// See documentation on fields for more details.
 pub struct CcpRouteManagerBuilder<I, O, S> {
@@ -68,6 +70,14 @@ pub struct CcpRouteManagerBuilder<I, O, S> {
     store: S,
     ilp_address: Address,
     broadcast_interval: u64,
+    routing_secret: Option<[u8; 32]>,
+    trusted_route_anchors: HashMap<String, [u8; 32]>,
+    route_auth_anchor_ttl: u64,
+    route_expiry_time: u32,
+    flap_dampening_window: u64,
+    max_route_flaps: u32,
+    max_routing_state_age: u64,
+    route_selection_policy: RouteSelectionPolicy,
 }
 
 impl<I, O, S, A> CcpRouteManagerBuilder<I, O, S>
@@ -84,6 +94,14 @@ where
             outgoing,
             store,
             broadcast_interval: DEFAULT_BROADCAST_INTERVAL,
+            routing_secret: None,
+            trusted_route_anchors: HashMap::new(),
+            route_auth_anchor_ttl: DEFAULT_ROUTE_AUTH_ANCHOR_TTL,
+            route_expiry_time: DEFAULT_ROUTE_EXPIRY_TIME,
+            flap_dampening_window: DEFAULT_FLAP_DAMPENING_WINDOW,
+            max_route_flaps: DEFAULT_MAX_ROUTE_FLAPS,
+            max_routing_state_age: DEFAULT_MAX_ROUTING_STATE_AGE,
+            route_selection_policy: RouteSelectionPolicy::default(),
         }
     }
 
@@ -98,21 +116,122 @@ where
         self
     }
 
+    /// Set the secret used to authenticate the routes we advertise for our own address.
+    /// This should stay the same across restarts, because peers remember the
+    /// authentication anchor they first saw for each prefix.
+    /// If it is not set, our routes are advertised without authentication.
+    pub fn routing_secret(&mut self, secret: [u8; 32]) -> &mut Self {
+        self.routing_secret = Some(secret);
+        self
+    }
+
+    /// Trust the given auth anchor for the prefix.
+    /// Routes for the prefix are only accepted if they are authenticated with this anchor.
+    /// Without a configured anchor, we only trust the first anchor a peer sends us
+    /// for a prefix under the peer's own address.
+    pub fn trusted_route_anchor(&mut self, prefix: String, anchor: [u8; 32]) -> &mut Self {
+        self.trusted_route_anchors.insert(prefix, anchor);
+        self
+    }
+
+    /// Set how long (in milliseconds) we remember the anchor we accepted for a prefix
+    /// after we last had a route authenticated with it
+    pub fn route_auth_anchor_ttl(&mut self, ms: u64) -> &mut Self {
+        self.route_auth_anchor_ttl = ms;
+        self
+    }
+
+    /// Set how long (in milliseconds) peers should keep our routes if they stop hearing from us.
+    /// This is sent as the hold down time in our route updates, so it should be
+    /// longer than the broadcast interval
+    pub fn route_expiry_time(&mut self, ms: u32) -> &mut Self {
+        self.route_expiry_time = ms;
+        self
+    }
+
+    /// Set the window (in milliseconds) in which route withdrawals are counted for flap dampening
+    pub fn flap_dampening_window(&mut self, ms: u64) -> &mut Self {
+        self.flap_dampening_window = ms;
+        self
+    }
+
+    /// Stop re-advertising routes that were withdrawn this many times within the
+    /// flap dampening window, until enough of those withdrawals fall out of the window.
+    /// Setting this to 0 disables flap dampening
+    pub fn max_route_flaps(&mut self, max_flaps: u32) -> &mut Self {
+        self.max_route_flaps = max_flaps;
+        self
+    }
+
+    /// Set how old (in milliseconds) the routing tables saved in the store may be
+    /// for them to be loaded when the service starts. Older tables are likely to be
+    /// out of date, so we learn our routes from our peers again instead.
+    /// Setting this to 0 disables saving and loading the routing tables
+    pub fn max_routing_state_age(&mut self, ms: u64) -> &mut Self {
+        self.max_routing_state_age = ms;
+        self
+    }
+
+    /// Set how routes to the same prefix from accounts with the same routing relation
+    /// are ranked. By default, the route with the shortest path is chosen
+    pub fn route_selection_policy(&mut self, policy: RouteSelectionPolicy) -> &mut Self {
+        self.route_selection_policy = policy;
+        self
+    }
+
+    /// Create the service and start broadcasting routes.
+    /// The routing tables saved by a previous run are not loaded, use
+    /// [`to_service_with_saved_routes`](#method.to_service_with_saved_routes) for that
     pub fn to_service(&self) -> CcpRouteManager<I, O, S, A> {
-        #[allow(clippy::let_and_return)]
-        let service = CcpRouteManager {
+        let service = self.build();
+        self.spawn_broadcast_interval(&service);
+        service
+    }
+
+    /// Create the service, load the routing tables saved by a previous run and then
+    /// start broadcasting routes. The tables are loaded before the service is returned,
+    /// so that they do not overwrite the routes of any updates it handles afterwards
+    pub async fn to_service_with_saved_routes(&self) -> CcpRouteManager<I, O, S, A> {
+        let service = self.build();
+        if let Err(err) = service.restore_routing_state().await {
+            warn!("Error loading saved routing tables: {:?}", err);
+        }
+        self.spawn_broadcast_interval(&service);
+        service
+    }
+
+    fn build(&self) -> CcpRouteManager<I, O, S, A> {
+        CcpRouteManager {
             ilp_address: Arc::new(RwLock::new(self.ilp_address.clone())),
             next_incoming: self.next_incoming.clone(),
             outgoing: self.outgoing.clone(),
             store: self.store.clone(),
             forwarding_table: Arc::new(RwLock::new(RoutingTable::default())),
-            forwarding_table_updates: Arc::new(RwLock::new(Vec::new())),
+            forwarding_table_updates: Arc::new(RwLock::new(UpdateHistory::default())),
             last_epoch_updates_sent_for: Arc::new(AtomicU32::new(0)),
             local_table: Arc::new(RwLock::new(RoutingTable::default())),
             incoming_tables: Arc::new(RwLock::new(HashMap::new())),
+            fallback_routes: Arc::new(Mutex::new(HashMap::new())),
             unavailable_accounts: Arc::new(Mutex::new(HashMap::new())),
-        };
+            peer_modes: Arc::new(Mutex::new(HashMap::new())),
+            routing_secret: self.routing_secret,
+            trusted_route_anchors: Arc::new(self.trusted_route_anchors.clone()),
+            route_auth_anchors: Arc::new(Mutex::new(HashMap::new())),
+            route_auth_anchor_ttl: self.route_auth_anchor_ttl,
+            route_expiry_time: self.route_expiry_time,
+            flap_dampening: Arc::new(Mutex::new(FlapDampening::new(
+                Duration::from_millis(self.flap_dampening_window),
+                self.max_route_flaps,
+            ))),
+            max_routing_state_age: self.max_routing_state_age,
+            route_selection_policy: self.route_selection_policy,
+            round_trip_times: Arc::new(Mutex::new(RoundTripTimes::default())),
+            export_policies: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
 
+    #[allow(unused_variables)]
+    fn spawn_broadcast_interval(&self, service: &CcpRouteManager<I, O, S, A>) {
         #[cfg(not(test))]
         {
             let broadcast_interval = self.broadcast_interval;
@@ -123,8 +242,6 @@ where
                     .await
             });
         }
-
-        service
     }
 }
 
@@ -159,7 +276,7 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     forwarding_table: Arc<RwLock<RoutingTable<A>>>,
     last_epoch_updates_sent_for: Arc<AtomicU32>,
     /// These updates are stored such that index 0 is the transition from epoch 0 to epoch 1
-    forwarding_table_updates: Arc<RwLock<Vec<NewAndWithdrawnRoutes>>>,
+    forwarding_table_updates: Arc<RwLock<UpdateHistory>>,
     /// This is the routing table we have compile from configuration and
     /// broadcasts we have received from our peers. It is saved to the Store so that
     /// the Router services forwards packets according to what it says.
@@ -169,12 +286,44 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     /// Updates from peers are applied to our local_table if they are better than the
     /// existing best route and if they do not attempt to overwrite configured routes.
     incoming_tables: Arc<RwLock<HashMap<Uuid, RoutingTable<A>>>>,
+    /// The alternative next hops (best first) for each prefix that were last saved to the store
+    fallback_routes: Arc<Mutex<HashMap<String, Vec<Uuid>>>>,
     store: S,
     /// If we get final errors while sending to specific accounts, we'll
     /// wait before trying to broadcast to them
     /// This maps the account ID to the number of route brodcast intervals
     /// we should wait before trying again
     unavailable_accounts: Arc<Mutex<HashMap<Uuid, BackoffParams>>>,
+    /// The mode each account asked for in its last Route Control Request.
+    /// Accounts that have not sent us one are treated as being in Sync mode.
+    /// We do not broadcast route updates to accounts in Idle mode, and we never ask them
+    /// to switch back: an Idle account sends us a Sync request when it wants routes again.
+    peer_modes: Arc<Mutex<HashMap<Uuid, Mode>>>,
+    /// Used to authenticate the routes we advertise for our own address
+    routing_secret: Option<[u8; 32]>,
+    /// Anchors configured by the node operator. Routes for these prefixes
+    /// must be authenticated with the configured anchor.
+    trusted_route_anchors: Arc<HashMap<String, [u8; 32]>>,
+    /// The anchor of the first authenticated route we accepted for each prefix from the
+    /// node that owns it. Later routes for the same prefix must be authenticated with the same anchor.
+    route_auth_anchors: Arc<Mutex<HashMap<String, PinnedAnchor>>>,
+    /// How long (in milliseconds) pinned anchors are kept after they were last used
+    route_auth_anchor_ttl: u64,
+    /// The hold down time we send in our route updates (in milliseconds)
+    route_expiry_time: u32,
+    /// Keeps routes that are repeatedly withdrawn and added back out of the forwarding table
+    flap_dampening: Arc<Mutex<FlapDampening>>,
+    /// How old (in milliseconds) saved routing tables may be for us to load them.
+    /// If this is 0 the routing tables are not saved at all
+    max_routing_state_age: u64,
+    /// How routes to the same prefix are ranked
+    route_selection_policy: RouteSelectionPolicy,
+    /// The round trip times of the CCP messages we sent to each account.
+    /// These measure the control plane, not how long packets we forward take
+    round_trip_times: Arc<Mutex<RoundTripTimes>>,
+    /// The export allow and deny lists we last created a route update for each account with.
+    /// When these change, the routes the new lists deny are withdrawn from the account
+    export_policies: Arc<Mutex<HashMap<Uuid, (Vec<String>, Vec<String>)>>>,
 }
 
 impl<I, O, S, A> CcpRouteManager<I, O, S, A>
@@ -195,6 +344,14 @@ where
             self.update_ilp_address();
             // Do not consume the result if an error since we want to keep the loop going
             let _ = self.broadcast_routes().await;
+            self.compact_forwarding_table_updates();
+            self.expire_route_auth_anchors();
+            if let Err(err) = self.forget_removed_accounts().await {
+                warn!("Error pruning the state of removed accounts: {:?}", err);
+            }
+            if let Err(err) = self.save_routing_state().await {
+                warn!("Error saving routing tables: {:?}", err);
+            }
         }
     }
 
@@ -214,24 +371,211 @@ where
 
     pub async fn broadcast_routes(&self) -> Result<(), CcpRoutingStoreError> {
         self.update_best_routes(None).await?;
+        self.rank_routes_with_changed_round_trip_times().await?;
         self.send_route_updates().await
     }
 
     /// Request routes from all the peers we are willing to receive routes from.
     /// This is mostly intended for when the CCP server starts up and doesn't have any routes from peers.
+    /// If we loaded a peer's routing table from the store, we only ask for the epochs we are missing.
     async fn request_all_routes(&self) {
         let result = self.store.get_accounts_to_receive_routes_from().await;
         let accounts = result.unwrap_or_else(|_| Vec::new());
-        join_all(
+        let last_known_epochs: Vec<([u8; 16], u32)> = {
+            let incoming_tables = self.incoming_tables.read();
             accounts
-                .into_iter()
-                .map(|account| self.send_route_control_request(account, DUMMY_ROUTING_TABLE_ID, 0)),
-        )
+                .iter()
+                .map(|account| {
+                    incoming_tables
+                        .get(&account.id())
+                        .map(|table| (table.id(), table.epoch()))
+                        .unwrap_or((DUMMY_ROUTING_TABLE_ID, 0))
+                })
+                .collect()
+        };
+        join_all(accounts.into_iter().zip(last_known_epochs).map(
+            |(account, (last_known_routing_table_id, last_known_epoch))| {
+                self.send_route_control_request(
+                    account,
+                    last_known_routing_table_id,
+                    last_known_epoch,
+                )
+            },
+        ))
         .await;
     }
 
-    /// Handle a CCP Route Control Request. If this is from an account that we broadcast routes to,
-    /// we'll send an outgoing Route Update Request to them.
+    /// Merge the updates of the epochs every peer we broadcast routes to was already sent.
+    /// Peers which missed them, such as ones that were unavailable, are sent the compacted
+    /// update when they ask for them, which brings them up to date all the same
+    fn compact_forwarding_table_updates(&self) {
+        let sent_epoch = self.last_epoch_updates_sent_for.load(Ordering::SeqCst);
+        self.forwarding_table_updates
+            .write()
+            .compact(sent_epoch as usize);
+    }
+
+    /// Save the forwarding table and the routing tables of our peers to the store
+    /// so that they can be loaded again when the node restarts
+    async fn save_routing_state(&self) -> Result<(), CcpRoutingStoreError> {
+        if self.max_routing_state_age == 0 {
+            return Ok(());
+        }
+        let snapshot = {
+            let forwarding_table = self.forwarding_table.read();
+            let forwarding_table_updates = self.forwarding_table_updates.read();
+            let incoming_tables = self.incoming_tables.read();
+            RoutingSnapshot {
+                saved_at: unix_millis(SystemTime::now()),
+                forwarding_table: snapshot_table(&forwarding_table),
+                forwarding_table_updates: forwarding_table_updates.clone(),
+                incoming_tables: incoming_tables
+                    .iter()
+                    .map(|(account_id, table)| (*account_id, snapshot_table(table)))
+                    .collect(),
+                route_auth_anchors: self
+                    .route_auth_anchors
+                    .lock()
+                    .iter()
+                    .map(|(prefix, pinned)| (prefix.clone(), pinned.anchor, pinned.last_seen))
+                    .collect(),
+            }
+        };
+        trace!(
+            "Saving routing tables (forwarding table epoch: {}, peer tables: {})",
+            snapshot.forwarding_table.epoch,
+            snapshot.incoming_tables.len()
+        );
+        self.store
+            .clone()
+            .set_routing_state(snapshot.to_bytes())
+            .await
+    }
+
+    /// Load the routing tables saved by `save_routing_state`, unless they are older
+    /// than the configured limit, and update our best routes from them.
+    /// Keeping the forwarding table's ID and epoch means peers that are up to date
+    /// with it do not need to be sent all of our routes again.
+    /// Returns true if the saved routing tables were loaded.
+    async fn restore_routing_state(&self) -> Result<bool, CcpRoutingStoreError> {
+        if self.max_routing_state_age == 0 {
+            return Ok(false);
+        }
+        let state = match self.store.get_routing_state().await? {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        let snapshot = match RoutingSnapshot::try_from(&state[..]) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                warn!(
+                    "Ignoring saved routing tables that could not be parsed: {:?}",
+                    err
+                );
+                return Ok(false);
+            }
+        };
+        // The anchors we accepted are kept for much longer than the routing tables,
+        // so they are loaded even if the routing tables are out of date
+        let now = unix_millis(SystemTime::now());
+        {
+            let mut route_auth_anchors = self.route_auth_anchors.lock();
+            for (prefix, anchor, last_seen) in snapshot.route_auth_anchors.iter() {
+                if now.saturating_sub(*last_seen) <= self.route_auth_anchor_ttl {
+                    route_auth_anchors
+                        .entry(prefix.clone())
+                        .or_insert(PinnedAnchor {
+                            anchor: *anchor,
+                            last_seen: *last_seen,
+                        });
+                }
+            }
+        }
+
+        let age = now.saturating_sub(snapshot.saved_at);
+        if age > self.max_routing_state_age {
+            debug!(
+                "Not loading saved routing tables because they are {}ms old",
+                age
+            );
+            return Ok(false);
+        }
+
+        // Routes for accounts that were deleted or that we
+        // no longer receive routes from are not loaded
+        let (local_routes, configured_routes) =
+            self.store.get_local_and_configured_routes().await?;
+        let peers: HashMap<Uuid, A> = self
+            .store
+            .get_accounts_to_receive_routes_from()
+            .await?
+            .into_iter()
+            .map(|account| (account.id(), account))
+            .collect();
+        let accounts: HashMap<Uuid, A> = local_routes
+            .into_iter()
+            .chain(configured_routes.into_iter())
+            .map(|(_prefix, account)| (account.id(), account))
+            .chain(peers.iter().map(|(id, account)| (*id, account.clone())))
+            .collect();
+
+        let mut restored_prefixes: Vec<String> = Vec::new();
+        {
+            let mut incoming_tables = self.incoming_tables.write();
+            for (account_id, table) in snapshot.incoming_tables {
+                let account = match peers.get(&account_id) {
+                    Some(account) => account,
+                    None => continue,
+                };
+                for (_account_id, route) in table.routes.iter() {
+                    restored_prefixes.push(route.prefix.clone());
+                }
+                incoming_tables.insert(
+                    account_id,
+                    RoutingTable::restore(
+                        table.id,
+                        table.epoch,
+                        table
+                            .routes
+                            .into_iter()
+                            .map(|(_account_id, route)| (account.clone(), route)),
+                    ),
+                );
+            }
+        }
+
+        let forwarding_table = snapshot.forwarding_table;
+        if snapshot.forwarding_table_updates.epochs() == forwarding_table.epoch as usize {
+            *self.forwarding_table.write() = RoutingTable::restore(
+                forwarding_table.id,
+                forwarding_table.epoch,
+                forwarding_table
+                    .routes
+                    .into_iter()
+                    .filter_map(|(account_id, route)| {
+                        accounts
+                            .get(&account_id)
+                            .map(|account| (account.clone(), route))
+                    }),
+            );
+            *self.forwarding_table_updates.write() = snapshot.forwarding_table_updates;
+        } else {
+            warn!("Saved forwarding table does not match its updates, not loading it");
+        }
+
+        debug!(
+            "Loaded saved routing tables with {} routes from peers",
+            restored_prefixes.len()
+        );
+        // Put the loaded routes in the routing table the Router uses straight away
+        restored_prefixes.sort();
+        restored_prefixes.dedup();
+        self.update_best_routes(Some(restored_prefixes)).await?;
+        Ok(true)
+    }
+
+    /// Handle a CCP Route Control Request. If this is from an account that we broadcast routes to
+    /// and it is in Sync mode, we'll send an outgoing Route Update Request to them.
     async fn handle_route_control_request(&self, request: IncomingRequest<A>) -> IlpResult {
         if !request.from.should_send_routes() {
             return Err(RejectBuilder {
@@ -261,21 +605,9 @@ where
             control
         );
 
-        // TODO stop sending updates if they are in Idle mode
-        if control.mode == Mode::Sync {
-            // Don't skip them in the route update broadcasts anymore since this
-            // tells us that they are online
-            // TODO what happens if they can send to us but we can't send to them?
-            {
-                trace!("Checking whether account was previously listed as unavailable");
-                let mut unavailable_accounts = self.unavailable_accounts.lock();
-                if unavailable_accounts.remove(&request.from.id()).is_some() {
-                    debug!("Account {} (id: {}) is no longer unavailable, will resume broadcasting routes to it",
-                            request.from.username(),
-                            request.from.id());
-                }
-            }
+        self.set_peer_mode(&request.from, control.mode);
 
+        if control.mode == Mode::Sync {
             let (from_epoch_index, to_epoch_index) = {
                 let forwarding_table = self.forwarding_table.read();
                 let to_epoch_index = forwarding_table.epoch();
@@ -311,9 +643,49 @@ where
         Ok(CCP_RESPONSE.clone())
     }
 
+    /// Record the mode the account asked for in a Route Control Request.
+    ///
+    /// Either mode tells us the account is online, so it no longer needs to be backed off.
+    /// Accounts that switch to Idle are skipped in route broadcasts until they send
+    /// another request in Sync mode.
+    fn set_peer_mode(&self, account: &A, mode: Mode) {
+        // TODO what happens if they can send to us but we can't send to them?
+        if self
+            .unavailable_accounts
+            .lock()
+            .remove(&account.id())
+            .is_some()
+        {
+            debug!(
+                "Account {} (id: {}) is no longer unavailable",
+                account.username(),
+                account.id()
+            );
+        }
+
+        let previous_mode = self
+            .peer_modes
+            .lock()
+            .insert(account.id(), mode)
+            .unwrap_or(Mode::Sync);
+        match (previous_mode, mode) {
+            (Mode::Sync, Mode::Idle) => debug!(
+                "Account {} (id: {}) switched to Idle mode, will stop broadcasting routes to it",
+                account.username(),
+                account.id()
+            ),
+            (Mode::Idle, Mode::Sync) => debug!(
+                "Account {} (id: {}) switched to Sync mode, will resume broadcasting routes to it",
+                account.username(),
+                account.id()
+            ),
+            _ => {}
+        }
+    }
+
     /// Remove invalid routes before processing the Route Update Request
     #[allow(clippy::cognitive_complexity)]
-    fn filter_routes(&self, mut update: RouteUpdateRequest) -> RouteUpdateRequest {
+    fn filter_routes(&self, account: &A, mut update: RouteUpdateRequest) -> RouteUpdateRequest {
         update.new_routes = update
             .new_routes
             .into_iter()
@@ -337,13 +709,182 @@ where
                     );
                     false
                 } else {
-                    true
+                    self.authenticate_route(account, route)
                 }
             })
             .collect();
         update
     }
 
+    /// Check the route's auth value against its anchor and against the anchor we trust
+    /// for the prefix. We trust anchors configured by the node operator and anchors we
+    /// pinned before. If neither exists, a valid anchor is only trusted for prefixes under
+    /// the advertising account's own address, because any node can authenticate routes for
+    /// other prefixes with its own secret. Routes we cannot check against a trusted anchor
+    /// are treated as unauthenticated, which are only accepted from accounts that do not
+    /// require route authentication and for prefixes without a trusted anchor.
+    fn authenticate_route(&self, account: &A, route: &Route) -> bool {
+        let trusted_anchor = self.trusted_anchor(&route.prefix);
+        match verify_route(route) {
+            RouteAuth::Valid(anchor) => match trusted_anchor {
+                Some(trusted_anchor) if trusted_anchor != anchor => {
+                    warn!(
+                        "Got route broadcast from account {} with a different auth anchor than the one we trust for the prefix: {:?}",
+                        account.id(),
+                        route
+                    );
+                    false
+                }
+                Some(_) => true,
+                // The anchor will be pinned once the route is accepted
+                None if is_own_prefix(account, &route.prefix) => true,
+                None => self.accept_unauthenticated_route(account, route),
+            },
+            RouteAuth::Unauthenticated => {
+                if trusted_anchor.is_some() {
+                    warn!(
+                        "Got unauthenticated route broadcast from account {} for a prefix we trust an auth anchor for: {:?}",
+                        account.id(),
+                        route
+                    );
+                    false
+                } else {
+                    self.accept_unauthenticated_route(account, route)
+                }
+            }
+            RouteAuth::Invalid => {
+                warn!(
+                    "Got route broadcast from account {} that failed authentication: {:?}",
+                    account.id(),
+                    route
+                );
+                false
+            }
+        }
+    }
+
+    fn accept_unauthenticated_route(&self, account: &A, route: &Route) -> bool {
+        if account.strict_route_auth() {
+            trace!(
+                "Ignoring route broadcast from account {} that is not authenticated with a trusted anchor: {:?}",
+                account.id(),
+                route
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    /// The configured or pinned anchor for the prefix, if any
+    fn trusted_anchor(&self, prefix: &str) -> Option<[u8; 32]> {
+        self.trusted_route_anchors.get(prefix).cloned().or_else(|| {
+            self.route_auth_anchors
+                .lock()
+                .get(prefix)
+                .map(|pinned| pinned.anchor)
+        })
+    }
+
+    /// Remember the anchors of the routes we accepted from the account.
+    /// This is done after the import policy is applied so that routes we drop do not pin anchors
+    fn pin_route_anchors(&self, account: &A, routes: &[Route]) {
+        let now = unix_millis(SystemTime::now());
+        let mut anchors = self.route_auth_anchors.lock();
+        for route in routes.iter() {
+            if self.trusted_route_anchors.contains_key(&route.prefix) {
+                continue;
+            }
+            if let RouteAuth::Valid(anchor) = verify_route(route) {
+                match anchors.get_mut(&route.prefix) {
+                    Some(pinned) if pinned.anchor == anchor => pinned.last_seen = now,
+                    Some(_) => {}
+                    None if is_own_prefix(account, &route.prefix) => {
+                        debug!(
+                            "Pinning auth anchor for prefix {} from account {}",
+                            route.prefix,
+                            account.id()
+                        );
+                        anchors.insert(
+                            route.prefix.clone(),
+                            PinnedAnchor {
+                                anchor,
+                                last_seen: now,
+                            },
+                        );
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Forget pinned anchors that have not been used for longer than the TTL.
+    /// Anchors of routes we still have are kept
+    fn expire_route_auth_anchors(&self) {
+        let now = unix_millis(SystemTime::now());
+        let incoming_tables = self.incoming_tables.read();
+        let mut anchors = self.route_auth_anchors.lock();
+        for (prefix, pinned) in anchors.iter_mut() {
+            if incoming_tables
+                .values()
+                .any(|table| table.get_exact_route(prefix).is_some())
+            {
+                pinned.last_seen = now;
+            }
+        }
+        let ttl = self.route_auth_anchor_ttl;
+        anchors.retain(|prefix, pinned| {
+            let expired = now.saturating_sub(pinned.last_seen) > ttl;
+            if expired {
+                debug!("Forgetting auth anchor for prefix {}", prefix);
+            }
+            !expired
+        });
+    }
+
+    /// Filter out routes that the account's import policy does not allow.
+    /// The filtered routes replace any route the account sent us for the same prefixes before
+    fn apply_import_policy(
+        &self,
+        account: &A,
+        mut update: RouteUpdateRequest,
+    ) -> RouteUpdateRequest {
+        let max_path_length = account.max_route_path_length();
+        let (new_routes, filtered_routes): (Vec<Route>, Vec<Route>) = update
+            .new_routes
+            .into_iter()
+            .partition(|route| {
+                if !is_permitted(
+                    &route.prefix,
+                    account.route_import_allow(),
+                    account.route_import_deny(),
+                ) {
+                    trace!(
+                        "Ignoring route broadcast from account {} because its import policy does not allow the prefix: {:?}",
+                        account.id(),
+                        route
+                    );
+                    false
+                } else if let Some(max) =
+                    max_path_length.filter(|max| route.path.len() > *max as usize)
+                {
+                    trace!(
+                        "Ignoring route broadcast from account {} because the path is longer than {} hops: {:?}",
+                        account.id(),
+                        max,
+                        route
+                    );
+                    false
+                } else {
+                    true
+                }
+            });
+        update.new_routes = new_routes;
+        withdraw_filtered_routes(&mut update, filtered_routes);
+        update
+    }
+
     /// Check if this Route Update Request is valid and, if so, apply any updates it contains.
     /// If updates are applied to the Incoming Routing Table for this peer, we will
     /// then check whether those routes are better than the current best ones we have in the
@@ -378,7 +919,8 @@ where
         );
 
         // Filter out routes that don't make sense or that we won't accept
-        let update = self.filter_routes(update);
+        let update = self.filter_routes(&request.from, update);
+        let update = self.apply_import_policy(&request.from, update);
 
         // Ensure the mutex gets dropped before the async block
         let result = {
@@ -389,10 +931,12 @@ where
                     RoutingTable::new(update.routing_table_id),
                 );
             }
-            incoming_tables
+            let table = incoming_tables
                 .get_mut(&request.from.id())
-                .expect("Should have inserted a routing table for this account")
-                .handle_update_request(request.from.clone(), update)
+                .expect("Should have inserted a routing table for this account");
+            let update = limit_imported_routes(&request.from, table, update);
+            self.pin_route_anchors(&request.from, &update.new_routes);
+            table.handle_update_request(request.from.clone(), update)
         };
 
         // Update the routing table we maintain for the account we got this from.
@@ -472,6 +1016,9 @@ where
 
     /// Request a Route Update from the specified peer. This is sent when we get
     /// a Route Update Request from them with a gap in the epochs since the last one we saw.
+    ///
+    /// We always want our peers' routes, so we only ever ask for them in Sync mode
+    /// and never tell a peer that we are Idle.
     async fn send_route_control_request(
         &self,
         account: A,
@@ -491,6 +1038,7 @@ where
             hex::encode(&last_known_routing_table_id[..]),
             last_known_epoch);
         let prepare = control.to_prepare();
+        let sent_at = Instant::now();
         let result = self
             .clone()
             .outgoing
@@ -505,14 +1053,84 @@ where
             })
             .await;
 
-        if let Err(err) = result {
-            warn!(
+        match result {
+            Ok(_) => self.record_round_trip_time(account_id, sent_at.elapsed()),
+            Err(err) => warn!(
                 "Error sending Route Control Request to account {}: {:?}",
                 account_id, err
-            )
+            ),
         }
     }
 
+    /// Update the round trip time we observed for an account with a new sample.
+    /// If that changes how the account's routes rank, they are ranked again before the next broadcast
+    fn record_round_trip_time(&self, account_id: Uuid, sample: Duration) {
+        self.round_trip_times.lock().record(account_id, sample);
+    }
+
+    /// Rank the routes of the accounts whose round trip time changed enough since the last
+    /// broadcast again. This is done once per broadcast rather than for each sample,
+    /// so that the forwarding table does not change while we are sending updates to our peers
+    async fn rank_routes_with_changed_round_trip_times(&self) -> Result<(), CcpRoutingStoreError> {
+        let account_ids = self.round_trip_times.lock().take_changed();
+        if account_ids.is_empty() || self.route_selection_policy.rtt_weight == 0 {
+            return Ok(());
+        }
+        let mut prefixes: Vec<String> = {
+            let incoming_tables = self.incoming_tables.read();
+            account_ids
+                .iter()
+                .filter_map(|account_id| incoming_tables.get(account_id))
+                .flat_map(|table| table.routes().map(|(prefix, _)| prefix.to_string()))
+                .collect()
+        };
+        if prefixes.is_empty() {
+            return Ok(());
+        }
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        debug!(
+            "Round trip times of accounts {:?} changed, ranking their routes for {} prefixes again",
+            account_ids,
+            prefixes.len()
+        );
+        self.update_best_routes(Some(prefixes)).await
+    }
+
+    /// Forget the round trip times, modes and export policies of accounts
+    /// we no longer exchange routes with, such as deleted ones
+    async fn forget_removed_accounts(&self) -> Result<(), CcpRoutingStoreError> {
+        let mut account_ids: HashSet<Uuid> = self
+            .store
+            .get_accounts_to_send_routes_to(Vec::new())
+            .await?
+            .iter()
+            .map(|account| account.id())
+            .collect();
+        account_ids.extend(
+            self.store
+                .get_accounts_to_receive_routes_from()
+                .await?
+                .iter()
+                .map(|account| account.id()),
+        );
+        self.round_trip_times.lock().retain(&account_ids);
+        self.peer_modes
+            .lock()
+            .retain(|account_id, _| account_ids.contains(account_id));
+        self.export_policies
+            .lock()
+            .retain(|account_id, _| account_ids.contains(account_id));
+        Ok(())
+    }
+
+    /// Rank routes with the round trip times we have observed so far,
+    /// which stay locked while ranking so that they do not need to be copied
+    fn with_route_scorer<T>(&self, rank: impl FnOnce(&RouteScorer) -> T) -> T {
+        let round_trip_times = self.round_trip_times.lock();
+        rank(&round_trip_times.scorer(self.route_selection_policy))
+    }
+
     /// Check whether the Local Routing Table currently has the best routes for the
     /// given prefixes. This is triggered when we get an incoming Route Update Request
     /// with some new or modified routes that might be better than our existing ones.
@@ -532,8 +1150,14 @@ where
         let (local_routes, configured_routes) =
             self.store.get_local_and_configured_routes().await?;
 
+        // Routes that were held back because they were flapping can be advertised again
+        let released_prefixes = self
+            .flap_dampening
+            .lock()
+            .take_released(tokio::time::Instant::now());
+
         // TODO: Should we extract this to a function and #[inline] it?
-        let (better_routes, withdrawn_routes) = {
+        let (better_routes, withdrawn_routes) = self.with_route_scorer(|scorer| {
             // Note we only use a read lock here and later get a write lock if we need to update the table
             let local_table = local_table.read();
             let incoming_tables = incoming_tables.read();
@@ -558,10 +1182,16 @@ where
                     &local_routes,
                     &configured_routes,
                     &incoming_tables,
+                    scorer,
                     prefix,
                 ) {
-                    if let Some((ref next_account, ref _route)) = local_table.get_route(prefix) {
-                        if next_account.id() == best_next_account.id() {
+                    if let Some((ref next_account, ref route)) = local_table.get_route(prefix) {
+                        // Routes through the same account are only updated if their cost changed,
+                        // so that we advertise the new cost to our peers
+                        if next_account.id() == best_next_account.id()
+                            && RouteScorer::cost(next_account, route)
+                                == RouteScorer::cost(&best_next_account, &best_route)
+                        {
                             continue;
                         } else {
                             better_routes.push((
@@ -579,14 +1209,19 @@ where
                 }
             }
             (better_routes, withdrawn_routes)
-        };
+        });
 
         // Update the local and forwarding tables
-        if !better_routes.is_empty() || !withdrawn_routes.is_empty() {
+        if !better_routes.is_empty()
+            || !withdrawn_routes.is_empty()
+            || !released_prefixes.is_empty()
+        {
             let update_routes = {
                 let mut local_table = local_table.write();
                 let mut forwarding_table = forwarding_table.write();
                 let mut forwarding_table_updates = forwarding_table_updates.write();
+                let mut flap_dampening = self.flap_dampening.lock();
+                let now = tokio::time::Instant::now();
 
                 let mut new_routes: Vec<Route> = Vec::with_capacity(better_routes.len());
 
@@ -618,24 +1253,67 @@ where
                     let not_withdrawn_route = !withdrawn_routes.contains(&prefix);
 
                     if correct_address_scheme && not_local_route && not_withdrawn_route {
+                        let is_our_route = is_our_address && route.path.is_empty();
+                        // Peers reach the prefix through us, so they pay for the hop to the next account too
+                        let advertised_cost = if is_our_route {
+                            0
+                        } else {
+                            RouteScorer::cost(&account, &route)
+                        };
                         let old_route = forwarding_table.get_route(prefix);
-                        if old_route.is_none() || old_route.unwrap().0.id() != account.id() {
+                        if old_route.map_or(true, |(old_account, old_route)| {
+                            old_account.id() != account.id()
+                                || route_cost(old_route) != advertised_cost
+                        }) {
+                            if is_our_route {
+                                if let Some(ref secret) = self.routing_secret {
+                                    sign_route(secret, &mut route);
+                                }
+                            }
+                            set_route_cost(&mut route, advertised_cost);
                             route.path.insert(0, ilp_address.to_string());
                             // Each hop hashes the auth before forwarding
                             route.auth = hash(&route.auth);
+                            // The forwarding table follows the local table even while the route
+                            // is suppressed, so that it has the right next hop once it is released
                             forwarding_table.set_route(
                                 prefix.to_string(),
                                 account.clone(),
                                 route.clone(),
                             );
-                            new_routes.push(route);
+                            if flap_dampening.suppress(prefix, now) {
+                                debug!(
+                                    "Not advertising route for prefix {} because it was withdrawn too often recently",
+                                    prefix
+                                );
+                            } else {
+                                new_routes.push(route);
+                            }
                         }
                     }
                 }
 
+                // Advertise the routes that were held back while they were flapping
+                for prefix in released_prefixes.iter() {
+                    if withdrawn_routes.contains(&prefix.as_str())
+                        || new_routes.iter().any(|route| route.prefix == *prefix)
+                    {
+                        continue;
+                    }
+                    if let Some((_, route)) = forwarding_table.get_exact_route(prefix) {
+                        debug!(
+                            "Route for prefix {} stopped flapping, advertising it again",
+                            prefix
+                        );
+                        new_routes.push(route.clone());
+                    }
+                }
+
                 for prefix in withdrawn_routes.iter() {
                     debug!("Removed route for prefix: {}", prefix);
-                    local_table.delete_route(prefix);
+                    if local_table.delete_route(prefix) {
+                        flap_dampening.record_withdrawal(prefix, now);
+                    }
                     forwarding_table.delete_route(prefix);
                 }
 
@@ -647,16 +1325,101 @@ where
                         .map(|s| s.to_string())
                         .collect(),
                 ));
-                debug_assert_eq!(epoch as usize + 1, forwarding_table_updates.len());
+                debug_assert_eq!(epoch as usize + 1, forwarding_table_updates.epochs());
 
                 store.set_routes(local_table.get_simplified_table())
             };
 
-            update_routes.await
-        } else {
-            // The routing table hasn't changed
-            Ok(())
+            update_routes.await?;
+        }
+
+        // Even if the best routes haven't changed, other peers may have
+        // started or stopped advertising the same prefixes
+        self.update_fallback_routes(prefixes).await
+    }
+
+    /// Recompute the ranked alternative next hops for the given prefixes (or every prefix
+    /// in the Local Routing Table) and save the ones that changed to the store.
+    /// The Router fails over to these if the best next hop for a prefix is unreachable.
+    async fn update_fallback_routes(
+        &self,
+        prefixes: Option<Vec<String>>,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let (changed_routes, removed_prefixes) = self.with_route_scorer(|scorer| {
+            let local_table = self.local_table.read();
+            let incoming_tables = self.incoming_tables.read();
+            let fallback_routes = self.fallback_routes.lock();
+
+            let mut prefixes: Vec<String> = match prefixes {
+                Some(prefixes) => prefixes,
+                // Prefixes we no longer have a route to are removed as well
+                None => local_table
+                    .routes()
+                    .map(|(prefix, _)| prefix.to_string())
+                    .chain(fallback_routes.keys().cloned())
+                    .collect(),
+            };
+            prefixes.sort();
+            prefixes.dedup();
+
+            let mut changed_routes: Vec<(String, Vec<A>)> = Vec::new();
+            let mut removed_prefixes: Vec<String> = Vec::new();
+            for prefix in prefixes {
+                let fallbacks = match local_table.get_exact_route(&prefix) {
+                    Some((account, route)) => get_fallback_routes_for_prefix(
+                        &incoming_tables,
+                        scorer,
+                        &prefix,
+                        account,
+                        route,
+                    ),
+                    None => Vec::new(),
+                };
+                let current = fallback_routes.get(&prefix);
+                if fallbacks.is_empty() {
+                    if current.is_some() {
+                        removed_prefixes.push(prefix);
+                    }
+                } else if current.map_or(true, |ids| {
+                    !ids.iter()
+                        .copied()
+                        .eq(fallbacks.iter().map(|account| account.id()))
+                }) {
+                    changed_routes.push((prefix, fallbacks));
+                }
+            }
+            (changed_routes, removed_prefixes)
+        });
+
+        if changed_routes.is_empty() && removed_prefixes.is_empty() {
+            return Ok(());
+        }
+
+        trace!(
+            "Setting fallback routes: {:?}, removing them for: {:?}",
+            changed_routes
+                .iter()
+                .map(|(prefix, accounts)| (prefix, accounts.len()))
+                .collect::<Vec<_>>(),
+            removed_prefixes
+        );
+        let changed_ids: Vec<(String, Vec<Uuid>)> = changed_routes
+            .iter()
+            .map(|(prefix, accounts)| {
+                let ids = accounts.iter().map(|account| account.id()).collect();
+                (prefix.clone(), ids)
+            })
+            .collect();
+        let mut store = self.store.clone();
+        store
+            .update_fallback_routes(changed_routes, removed_prefixes.clone())
+            .await?;
+        let mut fallback_routes = self.fallback_routes.lock();
+        for prefix in removed_prefixes {
+            fallback_routes.remove(&prefix);
         }
+        fallback_routes.extend(changed_ids);
+        Ok(())
     }
 
     /// Send RouteUpdateRequests to all peers that we send routing messages to
@@ -667,7 +1430,13 @@ where
         let accounts_to_skip: Vec<Uuid> = {
             trace!("Checking accounts to skip");
             let mut unavailable_accounts = self.unavailable_accounts.lock();
-            let mut skip = Vec::new();
+            let mut skip: Vec<Uuid> = self
+                .peer_modes
+                .lock()
+                .iter()
+                .filter(|(_, mode)| **mode == Mode::Idle)
+                .map(|(id, _)| *id)
+                .collect();
             for (id, mut backoff) in unavailable_accounts.iter_mut() {
                 if backoff.skip_intervals > 0 {
                     skip.push(*id);
@@ -688,19 +1457,15 @@ where
             .last_epoch_updates_sent_for
             .swap(to_epoch_index, Ordering::SeqCst);
 
-        let route_update_request = self_clone.create_route_update(from_epoch_index, to_epoch_index);
-
-        let prepare = route_update_request.to_prepare();
         accounts.sort_unstable_by_key(|a| a.id().to_string());
         accounts.dedup_by_key(|a| a.id());
 
         let broadcasting = !accounts.is_empty();
         if broadcasting {
             trace!(
-                "Sending route update for epochs {} - {} to accounts: {:?} {}",
+                "Sending route update for epochs {} - {} to accounts: {}",
                 from_epoch_index,
                 to_epoch_index,
-                route_update_request,
                 {
                     let account_list: Vec<String> = accounts
                         .iter()
@@ -723,6 +1488,16 @@ where
             let mut outgoing = self_clone.outgoing.clone();
             let mut results = Vec::new();
             for account in accounts.into_iter() {
+                // Each account gets its own update because of per-account export policies
+                let route_update_request =
+                    self_clone.create_route_update(&account, from_epoch_index, to_epoch_index);
+                trace!(
+                    "Route update for account {}: {:?}",
+                    account.id(),
+                    route_update_request
+                );
+                let prepare = route_update_request.to_prepare();
+                let sent_at = Instant::now();
                 let res = outgoing
                     .send_request(OutgoingRequest {
                         from: account.clone(),
@@ -731,6 +1506,9 @@ where
                         prepare: prepare.clone(),
                     })
                     .await;
+                if res.is_ok() {
+                    self_clone.record_round_trip_time(account.id(), sent_at.elapsed());
+                }
                 results.push((account, res));
             }
 
@@ -781,17 +1559,38 @@ where
         }
     }
 
-    /// Create a RouteUpdateRequest representing the given range of Forwarding Routing Table epochs.
-    /// If the epoch range is not specified, it will create an update for the last epoch only.
+    /// Create a RouteUpdateRequest for the given account representing the given range of
+    /// Forwarding Routing Table epochs. Routes the account's export policy does not allow are
+    /// withdrawn instead, since the account may have been sent them before the policy changed.
     fn create_route_update(
         &self,
+        account: &A,
         from_epoch_index: u32,
         to_epoch_index: u32,
     ) -> RouteUpdateRequest {
         let (start, end) = (from_epoch_index as usize, to_epoch_index as usize);
-        let (routing_table_id, current_epoch_index) = {
+        let (allow, deny) = (account.route_export_allow(), account.route_export_deny());
+        let export_policy = (allow.to_vec(), deny.to_vec());
+        let policy_changed = self
+            .export_policies
+            .lock()
+            .insert(account.id(), export_policy.clone())
+            != Some(export_policy);
+        let (routing_table_id, current_epoch_index, denied_prefixes) = {
             let table = self.forwarding_table.read();
-            (table.id(), table.epoch())
+            // If the policy changed (or we don't know which one the account was last sent
+            // routes with), the account may still have routes from earlier epochs that it denies
+            let denied_prefixes: Vec<String> = if policy_changed {
+                table
+                    .routes()
+                    .map(|(prefix, _)| prefix)
+                    .filter(|prefix| !is_permitted(prefix, allow, deny))
+                    .map(|prefix| prefix.to_string())
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            (table.id(), table.epoch(), denied_prefixes)
         };
         let forwarding_table_updates = self.forwarding_table_updates.read();
         let epochs_to_take = end.saturating_sub(start);
@@ -806,21 +1605,20 @@ where
         // or the updates is that there isn't necessarily an Account that
         // corresponds to this ILP address)
         if start == 0 {
-            new_routes.push(Route {
+            let mut route = Route {
                 prefix: self.ilp_address.read().to_string(),
                 path: Vec::new(),
-                // TODO what should we include here?
                 auth: [0; 32],
                 props: Vec::new(),
-            });
+            };
+            if let Some(ref secret) = self.routing_secret {
+                sign_route(secret, &mut route);
+            }
+            new_routes.push(route);
         }
 
         // Iterate through each of the given epochs
-        for (new, withdrawn) in forwarding_table_updates
-            .iter()
-            .skip(start)
-            .take(epochs_to_take)
-        {
+        for (new, withdrawn) in forwarding_table_updates.between(start, end) {
             for new_route in new {
                 new_routes.push(new_route.clone());
                 // If the route was previously withdrawn, ignore that now since it was added back
@@ -847,15 +1645,31 @@ where
             }
         }
 
-        RouteUpdateRequest {
-            routing_table_id,
+        for prefix in denied_prefixes {
+            if !withdrawn_routes.contains(&prefix) {
+                withdrawn_routes.push(prefix);
+            }
+        }
+        new_routes.retain(|route| {
+            if is_permitted(&route.prefix, allow, deny) {
+                true
+            } else {
+                if !withdrawn_routes.contains(&route.prefix) {
+                    withdrawn_routes.push(route.prefix.clone());
+                }
+                false
+            }
+        });
+
+        RouteUpdateRequest {
+            routing_table_id,
             from_epoch_index,
             to_epoch_index,
             current_epoch_index,
             new_routes,
             withdrawn_routes,
             speaker: self.ilp_address.read().clone(),
-            hold_down_time: DEFAULT_ROUTE_EXPIRY_TIME,
+            hold_down_time: self.route_expiry_time,
         }
     }
 
@@ -863,13 +1677,14 @@ where
     /// This is used when the peer has fallen behind and has requested a specific range of updates.
     async fn send_route_update(&self, account: A, from_epoch_index: u32, to_epoch_index: u32) {
         let prepare = self
-            .create_route_update(from_epoch_index, to_epoch_index)
+            .create_route_update(&account, from_epoch_index, to_epoch_index)
             .to_prepare();
         let account_id = account.id();
         debug!(
             "Sending individual route update to account: {} for epochs from: {} to: {}",
             account_id, from_epoch_index, to_epoch_index
         );
+        let sent_at = Instant::now();
         let result = self
             .outgoing
             .clone()
@@ -881,11 +1696,73 @@ where
             })
             .await;
 
-        if let Err(err) = result {
-            error!(
+        match result {
+            Ok(_) => self.record_round_trip_time(account_id, sent_at.elapsed()),
+            Err(err) => error!(
                 "Error sending route update to account {}: {:?}",
                 account_id, err
-            )
+            ),
+        }
+    }
+}
+
+/// Returns true if the prefix is the account's own address or under it
+fn is_own_prefix<A: Account>(account: &A, prefix: &str) -> bool {
+    let address = account.ilp_address() as &str;
+    prefix.starts_with(address)
+        && (prefix.len() == address.len() || prefix[address.len()..].starts_with('.'))
+}
+
+/// Drop routes for new prefixes once the account's incoming table holds as many
+/// routes as the account is allowed to send us. Updates to prefixes we already
+/// have a route for are always applied.
+fn limit_imported_routes<A: CcpRoutingAccount>(
+    account: &A,
+    table: &RoutingTable<A>,
+    mut update: RouteUpdateRequest,
+) -> RouteUpdateRequest {
+    let max_routes = match account.max_imported_routes() {
+        Some(max_routes) => max_routes as usize,
+        None => return update,
+    };
+    let withdrawn = update
+        .withdrawn_routes
+        .iter()
+        .filter(|prefix| table.has_route(prefix))
+        .count();
+    let mut num_routes = table.len().saturating_sub(withdrawn);
+    let (new_routes, filtered_routes): (Vec<Route>, Vec<Route>) =
+        update.new_routes.into_iter().partition(|route| {
+            if table.has_route(&route.prefix) {
+                true
+            } else if num_routes < max_routes {
+                num_routes += 1;
+                true
+            } else {
+                warn!(
+                    "Ignoring route broadcast from account {} because it has reached its limit of {} routes: {:?}",
+                    account.id(),
+                    max_routes,
+                    route
+                );
+                false
+            }
+        });
+    update.new_routes = new_routes;
+    withdraw_filtered_routes(&mut update, filtered_routes);
+    update
+}
+
+/// Withdraw the prefixes of routes that were filtered out of the update, so that a route the
+/// account sent us for them before is not kept when it has since been replaced by one we ignore
+fn withdraw_filtered_routes(update: &mut RouteUpdateRequest, filtered_routes: Vec<Route>) {
+    for route in filtered_routes {
+        let is_still_advertised = update
+            .new_routes
+            .iter()
+            .any(|new_route| new_route.prefix == route.prefix);
+        if !is_still_advertised && !update.withdrawn_routes.contains(&route.prefix) {
+            update.withdrawn_routes.push(route.prefix);
         }
     }
 }
@@ -894,6 +1771,7 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     local_routes: &HashMap<String, A>,
     configured_routes: &HashMap<String, A>,
     incoming_tables: &HashMap<Uuid, RoutingTable<A>>,
+    scorer: &RouteScorer,
     prefix: &str,
 ) -> Option<(A, Route)> {
     // Check if we have a configured route for that specific prefix
@@ -929,41 +1807,224 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
         ));
     }
 
-    let mut candidate_routes = incoming_tables
+    rank_incoming_routes(incoming_tables, scorer, prefix)
+        .into_iter()
+        .next()
+        .map(|(account, route)| (account.clone(), route.clone()))
+}
+
+/// Get the routes our peers have sent us for the given prefix, best first
+fn rank_incoming_routes<'a, A: CcpRoutingAccount>(
+    incoming_tables: &'a HashMap<Uuid, RoutingTable<A>>,
+    scorer: &RouteScorer,
+    prefix: &str,
+) -> Vec<(&'a A, &'a Route)> {
+    let mut candidate_routes: Vec<(&A, &Route)> = incoming_tables
         .values()
-        .filter_map(|incoming_table| incoming_table.get_route(prefix));
-    if let Some((account, route)) = candidate_routes.next() {
-        let (best_account, best_route) = candidate_routes.fold(
-            (account, route),
-            |(best_account, best_route), (account, route)| {
-                // Prioritize child > peer > parent
-                match best_account
-                    .routing_relation()
-                    .cmp(&account.routing_relation())
-                {
-                    StdOrdering::Greater => (best_account, best_route),
-                    StdOrdering::Less => (account, route),
-                    _ => {
-                        // Prioritize shortest path
-                        match best_route.path.len().cmp(&route.path.len()) {
-                            StdOrdering::Less => (best_account, best_route),
-                            StdOrdering::Greater => (account, route),
-                            _ => {
-                                // Finally base it on account ID
-                                if best_account.id().to_string() < account.id().to_string() {
-                                    (best_account, best_route)
-                                } else {
-                                    (account, route)
-                                }
-                            }
-                        }
-                    }
+        .filter_map(|incoming_table| incoming_table.get_route(prefix))
+        .map(|(account, route)| (account, route))
+        .collect();
+    candidate_routes.sort_by(|(a_account, a_route), (b_account, b_route)| {
+        // Prioritize child > peer > parent
+        b_account
+            .routing_relation()
+            .cmp(&a_account.routing_relation())
+            // Prioritize the lowest score according to the route selection policy
+            .then_with(|| {
+                scorer
+                    .score(*a_account, a_route)
+                    .cmp(&scorer.score(*b_account, b_route))
+            })
+            // Prioritize shortest path
+            .then_with(|| a_route.path.len().cmp(&b_route.path.len()))
+            // Finally base it on account ID
+            .then_with(|| a_account.id().to_string().cmp(&b_account.id().to_string()))
+    });
+    candidate_routes
+}
+
+/// Get all the routes `get_best_route_for_prefix` chooses from, in the order it prefers them
+fn get_route_candidates_for_prefix<A: CcpRoutingAccount>(
+    local_routes: &HashMap<String, A>,
+    configured_routes: &HashMap<String, A>,
+    incoming_tables: &HashMap<Uuid, RoutingTable<A>>,
+    scorer: &RouteScorer,
+    prefix: &str,
+) -> Vec<RouteCandidate> {
+    let candidate =
+        |account: &A, source: RouteSource, prefix: &str, path: &[String]| RouteCandidate {
+            account_id: account.id(),
+            username: account.username().clone(),
+            source,
+            prefix: prefix.to_string(),
+            path: path.to_vec(),
+            epoch: None,
+            cost: account.route_cost(),
+        };
+    let mut candidates = Vec::new();
+
+    let segments: Vec<&str> = prefix.split(|c| c == '.').collect();
+    for i in 0..segments.len() {
+        let prefix = &segments[0..segments.len() - i].join(".");
+        if let Some(account) = configured_routes.get(prefix) {
+            candidates.push(candidate(account, RouteSource::Static, prefix, &[]));
+            break;
+        }
+    }
+
+    if let Some(account) = local_routes.get(prefix) {
+        candidates.push(candidate(account, RouteSource::Local, prefix, &[]));
+    }
+
+    for (account, route) in rank_incoming_routes(incoming_tables, scorer, prefix) {
+        let mut ccp_candidate = candidate(account, RouteSource::Ccp, &route.prefix, &route.path);
+        ccp_candidate.epoch = incoming_tables
+            .get(&account.id())
+            .map(|table| table.epoch());
+        ccp_candidate.cost = RouteScorer::cost(account, route);
+        candidates.push(ccp_candidate);
+    }
+    candidates
+}
+
+/// Get the alternative next hops for a prefix, ranked the same way as the best route.
+/// Configured and local routes are authoritative, so they have no fallbacks.
+fn get_fallback_routes_for_prefix<A: CcpRoutingAccount>(
+    incoming_tables: &HashMap<Uuid, RoutingTable<A>>,
+    scorer: &RouteScorer,
+    prefix: &str,
+    best_account: &A,
+    best_route: &Route,
+) -> Vec<A> {
+    if best_route.path.is_empty() {
+        return Vec::new();
+    }
+    rank_incoming_routes(incoming_tables, scorer, prefix)
+        .into_iter()
+        .filter(|(account, _route)| account.id() != best_account.id())
+        .map(|(account, _route)| account.clone())
+        .collect()
+}
+
+fn snapshot_table<A: Account>(table: &RoutingTable<A>) -> TableSnapshot {
+    TableSnapshot {
+        id: table.id(),
+        epoch: table.epoch(),
+        routes: table
+            .routes()
+            .map(|(_prefix, (account, route))| (account.id(), route.clone()))
+            .collect(),
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[async_trait]
+impl<I, O, S, A> RouteExplainer for CcpRouteManager<I, O, S, A>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Clone + Send + Sync + 'static,
+    S: AddressStore + CcpRoutingStore<Account = A> + Clone + Send + Sync + 'static,
+    A: CcpRoutingAccount + Send + Sync + 'static,
+{
+    async fn route_candidates(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<RouteCandidate>, CcpRoutingStoreError> {
+        let (local_routes, configured_routes) =
+            self.store.get_local_and_configured_routes().await?;
+        let incoming_tables = self.incoming_tables.read();
+        Ok(self.with_route_scorer(|scorer| {
+            get_route_candidates_for_prefix(
+                &local_routes,
+                &configured_routes,
+                &incoming_tables,
+                scorer,
+                prefix,
+            )
+        }))
+    }
+}
+
+#[async_trait]
+impl<I, O, S, A> PeerInspector for CcpRouteManager<I, O, S, A>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Clone + Send + Sync + 'static,
+    S: AddressStore + CcpRoutingStore<Account = A> + Clone + Send + Sync + 'static,
+    A: CcpRoutingAccount + Send + Sync + 'static,
+{
+    async fn peers(&self) -> Result<CcpPeers, CcpRoutingStoreError> {
+        let mut accounts = self
+            .store
+            .get_accounts_to_send_routes_to(Vec::new())
+            .await?;
+        accounts.extend(self.store.get_accounts_to_receive_routes_from().await?);
+        accounts.sort_by_key(|account| account.id());
+        accounts.dedup_by_key(|account| account.id());
+
+        let (routing_table_id, epoch) = {
+            let forwarding_table = self.forwarding_table.read();
+            (forwarding_table.id(), forwarding_table.epoch())
+        };
+        let incoming_tables = self.incoming_tables.read();
+        let peer_modes = self.peer_modes.lock();
+        let unavailable_accounts = self.unavailable_accounts.lock();
+        let peers = accounts
+            .into_iter()
+            .map(|account| {
+                let table = incoming_tables.get(&account.id());
+                PeerState {
+                    account_id: account.id(),
+                    username: account.username().clone(),
+                    relation: account.routing_relation(),
+                    mode: peer_modes.get(&account.id()).cloned().unwrap_or(Mode::Sync),
+                    routing_table_id: table.map(|table| table.id()),
+                    epoch: table.map(|table| table.epoch()),
+                    routes: table.map_or(0, |table| table.len()),
+                    backoff: unavailable_accounts
+                        .get(&account.id())
+                        .map(|backoff| PeerBackoff {
+                            max_intervals: backoff.max,
+                            skip_intervals: backoff.skip_intervals,
+                        }),
                 }
-            },
-        );
-        Some((best_account.clone(), best_route.clone()))
-    } else {
-        None
+            })
+            .collect();
+
+        Ok(CcpPeers {
+            routing_table_id,
+            epoch,
+            peers,
+        })
+    }
+
+    /// Sends the peer a Route Control Request for an unknown routing table,
+    /// which asks for all of its routes from epoch 0
+    async fn resync_peer(&self, account_id: Uuid) -> Result<bool, CcpRoutingStoreError> {
+        let account = self
+            .store
+            .get_accounts_to_receive_routes_from()
+            .await?
+            .into_iter()
+            .find(|account| account.id() == account_id);
+        match account {
+            Some(account) => {
+                debug!(
+                    "Requesting all routes from account {} (id: {})",
+                    account.username(),
+                    account_id
+                );
+                self.send_route_control_request(account, DUMMY_ROUTING_TABLE_ID, 0)
+                    .await;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 }
 
@@ -1084,10 +2145,53 @@ mod ranking_routes {
             (Uuid::from_slice(&[8; 16]).unwrap(), peer_table_2),
         ])
     });
+    static NO_ROUND_TRIP_TIMES: Lazy<HashMap<Uuid, Duration>> = Lazy::new(HashMap::new);
+    static SCORER: Lazy<RouteScorer<'static>> = Lazy::new(|| RouteScorer {
+        policy: RouteSelectionPolicy::default(),
+        round_trip_times: &NO_ROUND_TRIP_TIMES,
+    });
+
+    /// Two peers with routes for "example.f": peer 9 has the shorter path
+    /// but advertises a higher cost than peer 10
+    fn costed_tables() -> HashMap<Uuid, RoutingTable<TestAccount>> {
+        let mut short_table = RoutingTable::default();
+        let short_peer = TestAccount::new(Uuid::from_slice(&[9; 16]).unwrap(), "example.peer9");
+        let mut short_route = Route {
+            prefix: "example.f".to_string(),
+            path: vec!["example.one".to_string()],
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        set_route_cost(&mut short_route, 50);
+        short_table.add_route(short_peer, short_route);
+
+        let mut long_table = RoutingTable::default();
+        let mut long_peer =
+            TestAccount::new(Uuid::from_slice(&[10; 16]).unwrap(), "example.peer10");
+        long_peer.policy.route_cost = 10;
+        long_table.add_route(
+            long_peer,
+            Route {
+                prefix: "example.f".to_string(),
+                path: vec![
+                    "example.one".to_string(),
+                    "example.two".to_string(),
+                    "example.three".to_string(),
+                ],
+                auth: [0; 32],
+                props: Vec::new(),
+            },
+        );
+        HashMap::from_iter(vec![
+            (Uuid::from_slice(&[9; 16]).unwrap(), short_table),
+            (Uuid::from_slice(&[10; 16]).unwrap(), long_table),
+        ])
+    }
 
     #[test]
     fn prioritizes_configured_routes() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.a");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, "example.a");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[4; 16]).unwrap()
@@ -1096,8 +2200,13 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_shorter_configured_routes() {
-        let best_route =
-            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.a.sub-prefix");
+        let best_route = get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            &SCORER,
+            "example.a.sub-prefix",
+        );
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[4; 16]).unwrap()
@@ -1106,7 +2215,8 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_local_routes_over_broadcasted_ones() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.c");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, "example.c");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[3; 16]).unwrap()
@@ -1115,7 +2225,8 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_children_over_peers() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.d");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, "example.d");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[6; 16]).unwrap()
@@ -1124,18 +2235,280 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_shorter_paths() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.e");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, "example.e");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[7; 16]).unwrap()
         );
     }
 
+    #[test]
+    fn prioritizes_cheaper_routes_with_cost_weight() {
+        let incoming = costed_tables();
+        let (account, _) =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &incoming, &SCORER, "example.f")
+                .unwrap();
+        assert_eq!(account.id(), Uuid::from_slice(&[9; 16]).unwrap());
+
+        let scorer = RouteScorer {
+            policy: RouteSelectionPolicy {
+                path_length_weight: 1,
+                cost_weight: 1,
+                rtt_weight: 0,
+            },
+            round_trip_times: &HashMap::new(),
+        };
+        let candidates =
+            get_route_candidates_for_prefix(&LOCAL, &CONFIGURED, &incoming, &scorer, "example.f");
+        assert_eq!(
+            candidates[0].account_id,
+            Uuid::from_slice(&[10; 16]).unwrap()
+        );
+        assert_eq!(candidates[0].cost, 10);
+        assert_eq!(candidates[1].cost, 50);
+    }
+
+    #[test]
+    fn prioritizes_faster_peers_with_rtt_weight() {
+        let incoming = costed_tables();
+        let scorer = RouteScorer {
+            policy: RouteSelectionPolicy {
+                path_length_weight: 1,
+                cost_weight: 0,
+                rtt_weight: 1,
+            },
+            round_trip_times: &HashMap::from_iter(vec![
+                (
+                    Uuid::from_slice(&[9; 16]).unwrap(),
+                    Duration::from_millis(100),
+                ),
+                (
+                    Uuid::from_slice(&[10; 16]).unwrap(),
+                    Duration::from_millis(10),
+                ),
+            ]),
+        };
+        let (account, _) =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &incoming, &scorer, "example.f")
+                .unwrap();
+        assert_eq!(account.id(), Uuid::from_slice(&[10; 16]).unwrap());
+    }
+
     #[test]
     fn returns_none_for_no_route() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.z");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, "example.z");
         assert!(best_route.is_none());
     }
+
+    #[test]
+    fn lists_route_candidates_best_first() {
+        let candidates = get_route_candidates_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            &SCORER,
+            "example.a.sub-prefix",
+        );
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(
+            candidates[0].account_id,
+            Uuid::from_slice(&[4; 16]).unwrap()
+        );
+        assert_eq!(candidates[0].source, RouteSource::Static);
+        assert_eq!(candidates[0].prefix, "example.a");
+        assert_eq!(
+            candidates[1].account_id,
+            Uuid::from_slice(&[7; 16]).unwrap()
+        );
+        assert_eq!(candidates[1].source, RouteSource::Ccp);
+        assert_eq!(candidates[1].prefix, "example.a.sub-prefix");
+        assert_eq!(candidates[1].path, vec!["example.one".to_string()]);
+        assert_eq!(candidates[1].epoch, Some(0));
+
+        for prefix in &["example.a", "example.c", "example.d", "example.e"] {
+            let candidates =
+                get_route_candidates_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, prefix);
+            let (best_account, _) =
+                get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, prefix).unwrap();
+            assert_eq!(candidates[0].account_id, best_account.id());
+        }
+        assert!(get_route_candidates_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            &SCORER,
+            "example.z"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn ranks_fallback_routes() {
+        let (account, route) =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, &SCORER, "example.d")
+                .unwrap();
+        let fallbacks =
+            get_fallback_routes_for_prefix(&INCOMING, &SCORER, "example.d", &account, &route);
+        assert_eq!(fallbacks.len(), 1);
+        assert_eq!(fallbacks[0].id(), Uuid::from_slice(&[7; 16]).unwrap());
+    }
+
+    #[test]
+    fn no_fallbacks_for_configured_routes() {
+        let (account, route) = get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            &SCORER,
+            "example.a.sub-prefix",
+        )
+        .unwrap();
+        let fallbacks = get_fallback_routes_for_prefix(
+            &INCOMING,
+            &SCORER,
+            "example.a.sub-prefix",
+            &account,
+            &route,
+        );
+        assert!(fallbacks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn writes_fallback_routes_to_store() {
+        let service = test_service();
+        *service.incoming_tables.write() = INCOMING.clone();
+        service
+            .update_best_routes(Some(vec!["example.d".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(
+            service.store.routes.lock()["example.d"].id(),
+            Uuid::from_slice(&[6; 16]).unwrap()
+        );
+        let fallback_routes = service.store.fallback_routes.lock();
+        assert_eq!(fallback_routes.len(), 1);
+        assert_eq!(
+            fallback_routes["example.d"]
+                .iter()
+                .map(|account| account.id())
+                .collect::<Vec<Uuid>>(),
+            vec![Uuid::from_slice(&[7; 16]).unwrap()]
+        );
+        drop(fallback_routes);
+
+        // The fallback route is removed once its account stops advertising the prefix
+        service
+            .incoming_tables
+            .write()
+            .remove(&Uuid::from_slice(&[7; 16]).unwrap());
+        service
+            .update_best_routes(Some(vec!["example.d".to_string()]))
+            .await
+            .unwrap();
+        assert!(service.store.fallback_routes.lock().is_empty());
+        assert!(service.fallback_routes.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn advertises_route_cost_through_next_hop() {
+        let mut service = test_service();
+        service.route_selection_policy = RouteSelectionPolicy {
+            path_length_weight: 1,
+            cost_weight: 1,
+            rtt_weight: 0,
+        };
+        *service.incoming_tables.write() = costed_tables();
+        service
+            .update_best_routes(Some(vec!["example.f".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(
+            service.store.routes.lock()["example.f"].id(),
+            Uuid::from_slice(&[10; 16]).unwrap()
+        );
+        let forwarding_table = service.forwarding_table.read();
+        let (_, route) = forwarding_table.get_route("example.f").unwrap();
+        assert_eq!(route_cost(route), 10);
+    }
+
+    #[tokio::test]
+    async fn records_round_trip_times() {
+        let service = test_service();
+        let id = Uuid::from_slice(&[9; 16]).unwrap();
+        service.record_round_trip_time(id, Duration::from_millis(80));
+        service.record_round_trip_time(id, Duration::from_millis(160));
+        assert_eq!(
+            service.round_trip_times.lock().smoothed[&id],
+            Duration::from_millis(90)
+        );
+    }
+
+    #[tokio::test]
+    async fn forgets_state_of_removed_accounts() {
+        let service = test_service();
+        service
+            .store
+            .routes
+            .lock()
+            .insert("example.routing".to_string(), ROUTING_ACCOUNT.clone());
+        let removed_id = Uuid::new_v4();
+        service.record_round_trip_time(ROUTING_ACCOUNT.id(), Duration::from_millis(80));
+        service.record_round_trip_time(removed_id, Duration::from_millis(80));
+        service
+            .peer_modes
+            .lock()
+            .insert(ROUTING_ACCOUNT.id(), Mode::Idle);
+        service.peer_modes.lock().insert(removed_id, Mode::Idle);
+        service.forget_removed_accounts().await.unwrap();
+        let round_trip_times = service.round_trip_times.lock();
+        assert!(round_trip_times
+            .smoothed
+            .contains_key(&ROUTING_ACCOUNT.id()));
+        assert!(!round_trip_times.smoothed.contains_key(&removed_id));
+        let peer_modes = service.peer_modes.lock();
+        assert!(peer_modes.contains_key(&ROUTING_ACCOUNT.id()));
+        assert!(!peer_modes.contains_key(&removed_id));
+    }
+
+    #[tokio::test]
+    async fn ranks_routes_again_when_round_trip_time_changes() {
+        let mut service = test_service();
+        service.route_selection_policy = RouteSelectionPolicy {
+            path_length_weight: 1,
+            cost_weight: 0,
+            rtt_weight: 1,
+        };
+        *service.incoming_tables.write() = costed_tables();
+        service
+            .update_best_routes(Some(vec!["example.f".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(
+            service.store.routes.lock()["example.f"].id(),
+            Uuid::from_slice(&[9; 16]).unwrap()
+        );
+
+        // The peer with the shorter path turns out to be much slower,
+        // but its routes are only ranked again before the next broadcast
+        service.record_round_trip_time(
+            Uuid::from_slice(&[9; 16]).unwrap(),
+            Duration::from_millis(100),
+        );
+        assert_eq!(
+            service.store.routes.lock()["example.f"].id(),
+            Uuid::from_slice(&[9; 16]).unwrap()
+        );
+        service
+            .rank_routes_with_changed_round_trip_times()
+            .await
+            .unwrap();
+        assert_eq!(
+            service.store.routes.lock()["example.f"].id(),
+            Uuid::from_slice(&[10; 16]).unwrap()
+        );
+    }
 }
 
 #[cfg(test)]
@@ -1364,7 +2737,7 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, "example.valid".to_string());
     }
@@ -1385,7 +2758,7 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, "example.valid".to_string());
     }
@@ -1410,7 +2783,7 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, "example.valid".to_string());
     }
@@ -1431,34 +2804,310 @@ mod handle_route_update_request {
             auth: [0; 32],
             props: Vec::new(),
         });
-        let request = service.filter_routes(request);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
         assert_eq!(request.new_routes.len(), 1);
         assert_eq!(request.new_routes[0].prefix, "example.valid".to_string());
     }
 
+    fn signed_route(prefix: &str, secret: &[u8; 32]) -> Route {
+        let mut route = Route {
+            prefix: prefix.to_string(),
+            path: Vec::new(),
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        sign_route(secret, &mut route);
+        route.path.push("example.origin".to_string());
+        route.auth = hash(&route.auth);
+        route
+    }
+
+    fn update_with_routes(routes: Vec<Route>) -> RouteUpdateRequest {
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.new_routes = routes;
+        request
+    }
+
     #[tokio::test]
-    async fn updates_local_routing_table() {
-        let mut service = test_service();
-        let mut request = UPDATE_REQUEST_COMPLEX.clone();
-        request.to_epoch_index = 1;
-        request.from_epoch_index = 0;
-        service
-            .handle_request(IncomingRequest {
-                from: ROUTING_ACCOUNT.clone(),
-                prepare: request.to_prepare(),
-            })
-            .await
-            .unwrap();
-        assert_eq!(
-            (*service.local_table.read())
-                .get_route("example.prefix1")
-                .unwrap()
-                .0
-                .id(),
-            ROUTING_ACCOUNT.id()
-        );
-        assert_eq!(
-            (*service.local_table.read())
+    async fn filters_routes_that_fail_authentication() {
+        let service = test_service();
+        let mut tampered = signed_route("example.tampered", &[1; 32]);
+        tampered.auth = [0; 32];
+        let request = update_with_routes(vec![signed_route("example.valid", &[1; 32]), tampered]);
+        let request = service.filter_routes(&ROUTING_ACCOUNT, request);
+        assert_eq!(request.new_routes.len(), 1);
+        assert_eq!(request.new_routes[0].prefix, "example.valid");
+    }
+
+    /// Filter the routes like `handle_route_update_request` does and pin the anchors of the accepted ones
+    fn accept_routes(
+        service: &CcpRouteManager<
+            impl IncomingService<TestAccount> + Clone + Send + Sync + 'static,
+            impl OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+            TestStore,
+            TestAccount,
+        >,
+        account: &TestAccount,
+        routes: Vec<Route>,
+    ) -> Vec<Route> {
+        let request = service.filter_routes(account, update_with_routes(routes));
+        service.pin_route_anchors(account, &request.new_routes);
+        request.new_routes
+    }
+
+    #[tokio::test]
+    async fn pins_anchor_for_peers_own_prefix() {
+        let service = test_service();
+        let routes = accept_routes(
+            &service,
+            &ROUTING_ACCOUNT,
+            vec![signed_route("example.peer.a", &[1; 32])],
+        );
+        assert_eq!(routes.len(), 1);
+
+        // Another node cannot take over the prefix, whether or not it authenticates its routes
+        let mut unauthenticated = UPDATE_REQUEST_COMPLEX.new_routes[0].clone();
+        unauthenticated.prefix = "example.peer.a".to_string();
+        let routes = accept_routes(
+            &service,
+            &ROUTING_ACCOUNT,
+            vec![signed_route("example.peer.a", &[2; 32]), unauthenticated],
+        );
+        assert!(routes.is_empty());
+
+        // The original owner's routes are still accepted
+        let routes = accept_routes(
+            &service,
+            &ROUTING_ACCOUNT,
+            vec![signed_route("example.peer.a", &[1; 32])],
+        );
+        assert_eq!(routes.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_trust_anchors_for_other_nodes_prefixes() {
+        let service = test_service();
+        // A peer can authenticate routes for any prefix with its own secret,
+        // so the anchor is not pinned and does not lock the owner out
+        let routes = accept_routes(
+            &service,
+            &ROUTING_ACCOUNT,
+            vec![signed_route("example.valid", &[2; 32])],
+        );
+        assert_eq!(routes.len(), 1);
+        assert!(service.route_auth_anchors.lock().is_empty());
+        let routes = accept_routes(
+            &service,
+            &ROUTING_ACCOUNT,
+            vec![signed_route("example.valid", &[1; 32])],
+        );
+        assert_eq!(routes.len(), 1);
+
+        // Strict accounts cannot send routes that are not authenticated with a trusted anchor
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.strict_auth = true;
+        let routes = accept_routes(
+            &service,
+            &account,
+            vec![signed_route("example.valid", &[2; 32])],
+        );
+        assert!(routes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_accepts_configured_anchor() {
+        let owner_route = signed_route("example.valid", &[1; 32]);
+        let anchor = match verify_route(&owner_route) {
+            RouteAuth::Valid(anchor) => anchor,
+            other => panic!("Expected valid route, got: {:?}", other),
+        };
+        let (service, _outgoing_requests) = test_service_with_routes();
+        let service = CcpRouteManagerBuilder::new(
+            EXAMPLE_CONNECTOR.clone(),
+            service.store.clone(),
+            service.outgoing.clone(),
+            service.next_incoming.clone(),
+        )
+        .trusted_route_anchor("example.valid".to_string(), anchor)
+        .to_service();
+
+        let mut unauthenticated = UPDATE_REQUEST_COMPLEX.new_routes[0].clone();
+        unauthenticated.prefix = "example.valid".to_string();
+        let routes = accept_routes(
+            &service,
+            &ROUTING_ACCOUNT,
+            vec![signed_route("example.valid", &[2; 32]), unauthenticated],
+        );
+        assert!(routes.is_empty());
+
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.strict_auth = true;
+        let routes = accept_routes(&service, &account, vec![owner_route]);
+        assert_eq!(routes.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_pin_anchors_of_routes_rejected_by_import_policy() {
+        let service = test_service();
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.import_deny = vec!["example.peer.a".to_string()];
+        let mut request = update_with_routes(vec![signed_route("example.peer.a", &[1; 32])]);
+        request.from_epoch_index = 0;
+        request.to_epoch_index = 1;
+        service
+            .clone()
+            .handle_request(IncomingRequest {
+                from: account,
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert!(service.route_auth_anchors.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn expires_unused_anchors() {
+        let service = test_service();
+        accept_routes(
+            &service,
+            &ROUTING_ACCOUNT,
+            vec![signed_route("example.peer.a", &[1; 32])],
+        );
+        service.expire_route_auth_anchors();
+        assert_eq!(service.route_auth_anchors.lock().len(), 1);
+
+        service
+            .route_auth_anchors
+            .lock()
+            .get_mut("example.peer.a")
+            .unwrap()
+            .last_seen -= DEFAULT_ROUTE_AUTH_ANCHOR_TTL + 1;
+        service.expire_route_auth_anchors();
+        assert!(service.route_auth_anchors.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn strict_accounts_must_authenticate_routes() {
+        let service = test_service();
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.strict_auth = true;
+        let routes = vec![
+            UPDATE_REQUEST_COMPLEX.new_routes[0].clone(),
+            signed_route("example.peer.valid", &[1; 32]),
+        ];
+
+        let request = service.filter_routes(&account, update_with_routes(routes.clone()));
+        assert_eq!(request.new_routes.len(), 1);
+        assert_eq!(request.new_routes[0].prefix, "example.peer.valid");
+
+        let request = service.filter_routes(&ROUTING_ACCOUNT, update_with_routes(routes));
+        assert_eq!(request.new_routes.len(), 2);
+    }
+
+    async fn handle_update_from(account: &TestAccount) -> RoutingTable<TestAccount> {
+        let service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: account.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        let incoming_tables = service.incoming_tables.read();
+        incoming_tables[&account.id].clone()
+    }
+
+    #[tokio::test]
+    async fn applies_import_policy() {
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.import_deny = vec!["example.prefix1".to_string()];
+        let table = handle_update_from(&account).await;
+        assert!(!table.has_route("example.prefix1"));
+        assert!(table.has_route("example.prefix2"));
+
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.import_allow = vec!["example.prefix1".to_string()];
+        let table = handle_update_from(&account).await;
+        assert!(table.has_route("example.prefix1"));
+        assert!(!table.has_route("example.prefix2"));
+    }
+
+    #[tokio::test]
+    async fn ignores_routes_with_long_paths() {
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.max_path_length = Some(1);
+        let table = handle_update_from(&account).await;
+        assert!(table.has_route("example.prefix1"));
+        assert!(!table.has_route("example.prefix2"));
+    }
+
+    #[tokio::test]
+    async fn withdraws_routes_replaced_by_filtered_ones() {
+        let service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert!(service.local_table.read().has_route("example.prefix2"));
+
+        // The same routes are sent again, but the route for prefix2 is now filtered out
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.max_path_length = Some(1);
+        request.from_epoch_index = 1;
+        request.to_epoch_index = 2;
+        service
+            .handle_request(IncomingRequest {
+                from: account.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        let table = service.incoming_tables.read()[&account.id].clone();
+        assert!(table.has_route("example.prefix1"));
+        assert!(!table.has_route("example.prefix2"));
+        assert!(!service.local_table.read().has_route("example.prefix2"));
+    }
+
+    #[tokio::test]
+    async fn limits_number_of_imported_routes() {
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.max_routes = Some(1);
+        let table = handle_update_from(&account).await;
+        assert_eq!(table.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn updates_local_routing_table() {
+        let mut service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            (*service.local_table.read())
+                .get_route("example.prefix1")
+                .unwrap()
+                .0
+                .id(),
+            ROUTING_ACCOUNT.id()
+        );
+        assert_eq!(
+            (*service.local_table.read())
                 .get_route("example.prefix2")
                 .unwrap()
                 .0
@@ -1591,6 +3240,99 @@ mod handle_route_update_request {
             .is_none());
     }
 
+    #[tokio::test]
+    async fn stops_advertising_flapping_routes() {
+        tokio::time::pause();
+        let mut service = test_service();
+        service.flap_dampening = Arc::new(Mutex::new(FlapDampening::new(
+            Duration::from_millis(200),
+            2,
+        )));
+        let prefix2_route = UPDATE_REQUEST_COMPLEX
+            .new_routes
+            .iter()
+            .find(|route| route.prefix == "example.prefix2")
+            .cloned()
+            .unwrap();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.from_epoch_index = 0;
+        request.to_epoch_index = 1;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+
+        // The peer withdraws and re-adds the route twice
+        for epoch in 1..5 {
+            let (new_routes, withdrawn_routes) = if epoch % 2 == 1 {
+                (Vec::new(), vec!["example.prefix2".to_string()])
+            } else {
+                (vec![prefix2_route.clone()], Vec::new())
+            };
+            service
+                .handle_request(IncomingRequest {
+                    from: ROUTING_ACCOUNT.clone(),
+                    prepare: RouteUpdateRequest {
+                        routing_table_id: UPDATE_REQUEST_COMPLEX.routing_table_id,
+                        from_epoch_index: epoch,
+                        to_epoch_index: epoch + 1,
+                        current_epoch_index: epoch + 1,
+                        hold_down_time: 45000,
+                        speaker: UPDATE_REQUEST_COMPLEX.speaker.clone(),
+                        new_routes,
+                        withdrawn_routes,
+                    }
+                    .to_prepare(),
+                })
+                .await
+                .unwrap();
+        }
+
+        // We still use the route and keep the forwarding table up to date, but don't advertise it
+        assert_eq!(
+            service
+                .local_table
+                .read()
+                .get_route("example.prefix2")
+                .unwrap()
+                .0
+                .id(),
+            ROUTING_ACCOUNT.id()
+        );
+        assert_eq!(
+            service
+                .forwarding_table
+                .read()
+                .get_route("example.prefix2")
+                .unwrap()
+                .0
+                .id(),
+            ROUTING_ACCOUNT.id()
+        );
+        let (new_routes, _) = service
+            .forwarding_table_updates
+            .read()
+            .last()
+            .cloned()
+            .unwrap();
+        assert!(new_routes.is_empty());
+
+        // Once the withdrawals are outside the window, the route is advertised again
+        tokio::time::advance(Duration::from_millis(250)).await;
+        service.update_best_routes(None).await.unwrap();
+        let (new_routes, _) = service
+            .forwarding_table_updates
+            .read()
+            .last()
+            .cloned()
+            .unwrap();
+        assert_eq!(new_routes.len(), 1);
+        assert_eq!(new_routes[0].prefix, "example.prefix2");
+    }
+
     #[tokio::test]
     async fn sends_control_request_if_routing_table_id_changed() {
         let (mut service, outgoing_requests) = test_service_with_routes();
@@ -1667,12 +3409,13 @@ mod handle_route_update_request {
 #[cfg(test)]
 mod create_route_update {
     use super::*;
+    use crate::auth::chain_seed;
     use crate::test_helpers::*;
 
     #[tokio::test]
     async fn heartbeat_message_for_empty_table() {
         let service = test_service();
-        let update = service.create_route_update(0, 0);
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 0);
         assert_eq!(update.from_epoch_index, 0);
         assert_eq!(update.to_epoch_index, 0);
         assert_eq!(update.current_epoch_index, 0);
@@ -1682,6 +3425,49 @@ mod create_route_update {
         assert!(update.withdrawn_routes.is_empty());
     }
 
+    #[tokio::test]
+    async fn authenticates_own_route_if_routing_secret_is_set() {
+        let mut service = test_service();
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 0);
+        assert_eq!(
+            verify_route(&update.new_routes[0]),
+            RouteAuth::Unauthenticated
+        );
+
+        service.routing_secret = Some([1; 32]);
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 0);
+        match verify_route(&update.new_routes[0]) {
+            RouteAuth::Valid(_) => {}
+            other => panic!(
+                "Expected our own route to be authenticated, got: {:?}",
+                other
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_advertise_the_chain_seed_of_our_own_route() {
+        let mut service = test_service();
+        service.routing_secret = Some([1; 32]);
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 0);
+        let route = &update.new_routes[0];
+        assert_eq!(route.prefix, "example.connector");
+        assert!(route.path.is_empty());
+        // Direct peers could use the seed to authenticate routes with shorter paths than the real one
+        assert_ne!(route.auth, chain_seed(&[1; 32], "example.connector"));
+    }
+
+    #[tokio::test]
+    async fn uses_configured_route_expiry_time() {
+        let mut service = test_service();
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 0);
+        assert_eq!(update.hold_down_time, DEFAULT_ROUTE_EXPIRY_TIME);
+
+        service.route_expiry_time = 90000;
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 0, 0);
+        assert_eq!(update.hold_down_time, 90000);
+    }
+
     #[tokio::test]
     async fn includes_the_given_range_of_epochs() {
         let service = test_service();
@@ -1723,8 +3509,9 @@ mod create_route_update {
                 }],
                 vec!["example.n".to_string()],
             ),
-        ];
-        let update = service.create_route_update(1, 3);
+        ]
+        .into();
+        let update = service.create_route_update(&ROUTING_ACCOUNT, 1, 3);
         assert_eq!(update.from_epoch_index, 1);
         assert_eq!(update.to_epoch_index, 3);
         assert_eq!(update.current_epoch_index, 4);
@@ -1740,6 +3527,84 @@ mod create_route_update {
         assert!(!new_routes.contains(&"example.m"));
         assert_eq!(update.withdrawn_routes[0], "example.m");
     }
+
+    #[tokio::test]
+    async fn applies_export_policy() {
+        let service = test_service();
+        (*service.forwarding_table.write()).set_epoch(2);
+        *service.forwarding_table_updates.write() = vec![
+            (
+                vec![Route {
+                    prefix: "example.a".to_string(),
+                    path: vec!["example.x".to_string()],
+                    auth: [1; 32],
+                    props: Vec::new(),
+                }],
+                Vec::new(),
+            ),
+            (
+                vec![Route {
+                    prefix: "example.b.c".to_string(),
+                    path: vec!["example.x".to_string()],
+                    auth: [2; 32],
+                    props: Vec::new(),
+                }],
+                Vec::new(),
+            ),
+        ]
+        .into();
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.export_deny = vec!["example.b".to_string()];
+        let update = service.create_route_update(&account, 0, 2);
+        let new_routes: Vec<&str> = update
+            .new_routes
+            .iter()
+            .map(|r| r.prefix.as_str())
+            .collect();
+        assert_eq!(new_routes, vec!["example.connector", "example.a"]);
+
+        assert_eq!(update.withdrawn_routes, vec!["example.b.c"]);
+
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.policy.export_allow = vec!["example.b".to_string()];
+        let update = service.create_route_update(&account, 0, 2);
+        assert_eq!(update.new_routes.len(), 1);
+        assert_eq!(update.new_routes[0].prefix, "example.b.c");
+    }
+
+    #[tokio::test]
+    async fn withdraws_routes_when_export_policy_changes() {
+        let service = test_service();
+        let route = Route {
+            prefix: "example.b".to_string(),
+            path: vec!["example.connector".to_string(), "example.x".to_string()],
+            auth: [1; 32],
+            props: Vec::new(),
+        };
+        service.forwarding_table.write().set_route(
+            "example.b".to_string(),
+            ROUTING_ACCOUNT.clone(),
+            route.clone(),
+        );
+        service.forwarding_table.write().set_epoch(1);
+        *service.forwarding_table_updates.write() = vec![(vec![route], Vec::new())].into();
+
+        let mut account = ROUTING_ACCOUNT.clone();
+        let update = service.create_route_update(&account, 0, 1);
+        assert_eq!(update.new_routes.len(), 2);
+        assert!(update.withdrawn_routes.is_empty());
+
+        // The route was advertised in an epoch the account already has,
+        // so only the policy change can tell it that the route is gone
+        account.policy.export_deny = vec!["example.b".to_string()];
+        let update = service.create_route_update(&account, 1, 1);
+        assert!(update.new_routes.is_empty());
+        assert_eq!(update.withdrawn_routes, vec!["example.b"]);
+
+        // Once the account has been told, the route is not withdrawn again
+        let update = service.create_route_update(&account, 1, 1);
+        assert!(update.withdrawn_routes.is_empty());
+    }
 }
 
 #[cfg(test)]
@@ -1909,6 +3774,7 @@ mod send_route_updates {
                     id: id2,
                     ilp_address: Address::from_str("example.connector.other-local").unwrap(),
                     relation: RoutingRelation::Child,
+                    policy: TestRoutePolicy::default(),
                 },
             ),
         ]);
@@ -1997,6 +3863,7 @@ mod send_route_updates {
             id: id2,
             ilp_address: Address::from_str("example.connector.other-local").unwrap(),
             relation: RoutingRelation::Child,
+            policy: TestRoutePolicy::default(),
         };
         let local_routes = HashMap::from_iter(vec![
             (
@@ -2074,4 +3941,416 @@ mod send_route_updates {
         // When we send again, we don't skip the child because we got a request from them
         assert_eq!(outgoing_requests.lock().len(), 2);
     }
+
+    /// Returns a service with a peer and a child account to send routes to,
+    /// where the child rejects every route update
+    fn service_with_rejecting_child() -> (
+        CcpRouteManager<
+            impl IncomingService<TestAccount> + Clone,
+            impl OutgoingService<TestAccount> + Clone,
+            TestStore,
+            TestAccount,
+        >,
+        Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>>,
+        TestAccount,
+    ) {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let child_account = TestAccount {
+            id: id2,
+            ilp_address: Address::from_str("example.connector.other-local").unwrap(),
+            relation: RoutingRelation::Child,
+            policy: TestRoutePolicy::default(),
+        };
+        let local_routes = HashMap::from_iter(vec![
+            (
+                "example.local.1".to_string(),
+                TestAccount::new(id1, "example.local.1"),
+            ),
+            (
+                "example.connector.other-local".to_string(),
+                child_account.clone(),
+            ),
+        ]);
+        let store = TestStore::with_routes(local_routes, HashMap::new());
+        let outgoing_requests: Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>> =
+            Arc::new(Mutex::new(Vec::new()));
+        let outgoing_requests_clone = outgoing_requests.clone();
+        let outgoing = outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+            let res = if request.to.routing_relation() == RoutingRelation::Child {
+                Err(RejectBuilder {
+                    code: ErrorCode::F00_BAD_REQUEST,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(request.to.ilp_address()),
+                }
+                .build())
+            } else {
+                Ok(CCP_RESPONSE.clone())
+            };
+            (*outgoing_requests_clone.lock()).push(request);
+            res
+        });
+        let service = CcpRouteManagerBuilder::new(
+            Address::from_str("example.connector").unwrap(),
+            store,
+            outgoing,
+            incoming_service_fn(|_request| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other incoming handler!",
+                    data: &[],
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                }
+                .build())
+            }),
+        )
+        .ilp_address(Address::from_str("example.connector").unwrap())
+        .to_service();
+        (service, outgoing_requests, child_account)
+    }
+
+    fn control_request(mode: Mode) -> RouteControlRequest {
+        RouteControlRequest {
+            mode,
+            ..CONTROL_REQUEST.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_broadcasting_to_idle_accounts() {
+        let (mut service, outgoing_requests, child_account) = service_with_rejecting_child();
+        service.send_route_updates().await.unwrap();
+
+        // The first time, the child request is rejected
+        assert_eq!(outgoing_requests.lock().len(), 2);
+        assert!(service
+            .unavailable_accounts
+            .lock()
+            .contains_key(&child_account.id));
+
+        *outgoing_requests.lock() = Vec::new();
+        service
+            .handle_request(IncomingRequest {
+                prepare: control_request(Mode::Idle).to_prepare(),
+                from: child_account.clone(),
+            })
+            .await
+            .unwrap();
+
+        // We don't respond to an Idle request with an update,
+        // but we no longer consider the child unavailable
+        assert!(outgoing_requests.lock().is_empty());
+        assert!(service
+            .unavailable_accounts
+            .lock()
+            .get(&child_account.id)
+            .is_none());
+        assert_eq!(
+            service.peer_modes.lock().get(&child_account.id),
+            Some(&Mode::Idle)
+        );
+
+        // The child is skipped for as long as it stays Idle, without building up a backoff
+        for _ in 0..3 {
+            service.send_route_updates().await.unwrap();
+            {
+                let requests = outgoing_requests.lock();
+                assert_eq!(requests.len(), 1);
+                assert_ne!(requests[0].to.id, child_account.id);
+            }
+            assert!(service.unavailable_accounts.lock().is_empty());
+            *outgoing_requests.lock() = Vec::new();
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_broadcasting_when_idle_account_syncs() {
+        let (mut service, outgoing_requests, child_account) = service_with_rejecting_child();
+        service
+            .handle_request(IncomingRequest {
+                prepare: control_request(Mode::Idle).to_prepare(),
+                from: child_account.clone(),
+            })
+            .await
+            .unwrap();
+        service.send_route_updates().await.unwrap();
+        assert_eq!(outgoing_requests.lock().len(), 1);
+
+        *outgoing_requests.lock() = Vec::new();
+        service
+            .handle_request(IncomingRequest {
+                prepare: control_request(Mode::Sync).to_prepare(),
+                from: child_account.clone(),
+            })
+            .await
+            .unwrap();
+
+        // Switching back to Sync gets them an update straight away
+        assert_eq!(outgoing_requests.lock().len(), 1);
+        assert_eq!(outgoing_requests.lock()[0].to.id, child_account.id);
+        assert_eq!(
+            service.peer_modes.lock().get(&child_account.id),
+            Some(&Mode::Sync)
+        );
+
+        *outgoing_requests.lock() = Vec::new();
+        service.send_route_updates().await.unwrap();
+
+        // The child is included in the broadcast again, and backed off as usual when it fails
+        assert_eq!(outgoing_requests.lock().len(), 2);
+        {
+            let lock = service.unavailable_accounts.lock();
+            let backoff = lock
+                .get(&child_account.id)
+                .expect("Should have added child to unavailable accounts");
+            assert_eq!(backoff.max, 1);
+            assert_eq!(backoff.skip_intervals, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod inspect_peers {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[tokio::test]
+    async fn reports_state_of_routing_accounts() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        let local_id = Uuid::from_slice(&[1; 16]).unwrap();
+        let configured_id = Uuid::from_slice(&[2; 16]).unwrap();
+        service.forwarding_table.write().set_id([9; 16]);
+        service.forwarding_table.write().set_epoch(4);
+        {
+            let mut table = RoutingTable::new([5; 16]);
+            table.set_epoch(3);
+            service.incoming_tables.write().insert(local_id, table);
+        }
+        service.peer_modes.lock().insert(local_id, Mode::Idle);
+        service.unavailable_accounts.lock().insert(
+            configured_id,
+            BackoffParams {
+                max: 2,
+                skip_intervals: 1,
+            },
+        );
+
+        let peers = service.peers().await.unwrap();
+        assert_eq!(peers.routing_table_id, [9; 16]);
+        assert_eq!(peers.epoch, 4);
+        // The non-routing account is not included
+        assert_eq!(
+            peers.peers,
+            vec![
+                PeerState {
+                    account_id: local_id,
+                    username: ALICE.clone(),
+                    relation: RoutingRelation::Peer,
+                    mode: Mode::Idle,
+                    routing_table_id: Some([5; 16]),
+                    epoch: Some(3),
+                    routes: 0,
+                    backoff: None,
+                },
+                PeerState {
+                    account_id: configured_id,
+                    username: ALICE.clone(),
+                    relation: RoutingRelation::Peer,
+                    mode: Mode::Sync,
+                    routing_table_id: None,
+                    epoch: None,
+                    routes: 0,
+                    backoff: Some(PeerBackoff {
+                        max_intervals: 2,
+                        skip_intervals: 1,
+                    }),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_all_routes_when_resyncing_peer() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        let local_id = Uuid::from_slice(&[1; 16]).unwrap();
+        assert!(service.resync_peer(local_id).await.unwrap());
+
+        let requests = outgoing_requests.lock();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].to.id, local_id);
+        let control = RouteControlRequest::try_from(&requests[0].prepare).unwrap();
+        assert_eq!(control.mode, Mode::Sync);
+        assert_eq!(control.last_known_routing_table_id, DUMMY_ROUTING_TABLE_ID);
+        assert_eq!(control.last_known_epoch, 0);
+    }
+
+    #[tokio::test]
+    async fn does_not_resync_accounts_we_do_not_receive_routes_from() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        let non_routing_id = Uuid::from_slice(&[3; 16]).unwrap();
+        assert!(!service.resync_peer(non_routing_id).await.unwrap());
+        assert!(!service.resync_peer(Uuid::new_v4()).await.unwrap());
+        assert!(outgoing_requests.lock().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod routing_state {
+    use super::*;
+    use crate::fixtures::*;
+    use crate::test_helpers::*;
+
+    async fn service_with_peer_routes() -> (
+        CcpRouteManager<
+            impl IncomingService<TestAccount> + Clone,
+            impl OutgoingService<TestAccount> + Clone,
+            TestStore,
+            TestAccount,
+        >,
+        Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>>,
+    ) {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        (service, outgoing_requests)
+    }
+
+    #[tokio::test]
+    async fn restores_saved_routing_tables() {
+        let (service, outgoing_requests) = service_with_peer_routes().await;
+        service.save_routing_state().await.unwrap();
+
+        // Simulate a restart by creating a new service with the same store
+        let restarted = CcpRouteManagerBuilder::new(
+            EXAMPLE_CONNECTOR.clone(),
+            service.store.clone(),
+            service.outgoing.clone(),
+            service.next_incoming.clone(),
+        )
+        .to_service_with_saved_routes()
+        .await;
+
+        assert_eq!(
+            restarted.forwarding_table.read().id(),
+            service.forwarding_table.read().id()
+        );
+        assert!(restarted
+            .forwarding_table
+            .read()
+            .has_route("example.prefix1"));
+        // Updating the best routes from the loaded tables adds an epoch, but because the
+        // forwarding table already has those routes, the update does not contain any
+        let saved_epoch = service.forwarding_table.read().epoch();
+        assert_eq!(restarted.forwarding_table.read().epoch(), saved_epoch + 1);
+        assert_eq!(
+            restarted.forwarding_table_updates.read().updates[..saved_epoch as usize],
+            service.forwarding_table_updates.read().updates[..]
+        );
+        assert_eq!(
+            restarted.forwarding_table_updates.read().last(),
+            Some(&(Vec::new(), Vec::new()))
+        );
+        // The peer's routes are used for routing straight away
+        assert_eq!(
+            restarted
+                .local_table
+                .read()
+                .get_route("example.prefix2")
+                .unwrap()
+                .0
+                .id(),
+            ROUTING_ACCOUNT.id()
+        );
+
+        // Only the epochs we are missing are requested from the peer
+        *outgoing_requests.lock() = Vec::new();
+        restarted.request_all_routes().await;
+        let requests = outgoing_requests.lock();
+        let request = requests
+            .iter()
+            .find(|request| request.to.id() == ROUTING_ACCOUNT.id())
+            .expect("Should have requested routes from the peer");
+        let control = RouteControlRequest::try_from(&request.prepare).unwrap();
+        assert_eq!(
+            control.last_known_routing_table_id,
+            UPDATE_REQUEST_COMPLEX.routing_table_id
+        );
+        assert_eq!(control.last_known_epoch, 1);
+    }
+
+    #[tokio::test]
+    async fn ignores_stale_routing_tables() {
+        let (service, _outgoing_requests) = service_with_peer_routes().await;
+        service.save_routing_state().await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+
+        let restarted = CcpRouteManagerBuilder::new(
+            EXAMPLE_CONNECTOR.clone(),
+            service.store.clone(),
+            service.outgoing.clone(),
+            service.next_incoming.clone(),
+        )
+        .max_routing_state_age(10)
+        .to_service();
+        assert!(!restarted.restore_routing_state().await.unwrap());
+        assert!(restarted.incoming_tables.read().is_empty());
+        assert_ne!(
+            restarted.forwarding_table.read().id(),
+            service.forwarding_table.read().id()
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_pinned_anchors_across_restarts() {
+        let (service, _outgoing_requests) = service_with_peer_routes().await;
+        let anchor = PinnedAnchor {
+            anchor: [1; 32],
+            last_seen: unix_millis(SystemTime::now()),
+        };
+        service
+            .route_auth_anchors
+            .lock()
+            .insert("example.peer.a".to_string(), anchor);
+        service.save_routing_state().await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+
+        // The anchors are loaded even if the routing tables are out of date
+        let restarted = CcpRouteManagerBuilder::new(
+            EXAMPLE_CONNECTOR.clone(),
+            service.store.clone(),
+            service.outgoing.clone(),
+            service.next_incoming.clone(),
+        )
+        .max_routing_state_age(10)
+        .to_service();
+        assert!(!restarted.restore_routing_state().await.unwrap());
+        assert_eq!(
+            restarted.route_auth_anchors.lock().get("example.peer.a"),
+            Some(&anchor)
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_save_routing_tables_if_disabled() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        let service = CcpRouteManagerBuilder::new(
+            EXAMPLE_CONNECTOR.clone(),
+            service.store.clone(),
+            service.outgoing.clone(),
+            service.next_incoming.clone(),
+        )
+        .max_routing_state_age(0)
+        .to_service();
+        service.save_routing_state().await.unwrap();
+        assert!(service.store.routing_state.lock().is_none());
+    }
 }
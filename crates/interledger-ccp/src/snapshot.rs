@@ -0,0 +1,280 @@
+use crate::{
+    history::{NewAndWithdrawnRoutes, UpdateHistory},
+    packet::Route,
+};
+use byteorder::{BigEndian, ReadBytesExt};
+use bytes::BufMut;
+use interledger_packet::{
+    oer::{BufOerExt, MutBufOerExt},
+    ParseError,
+};
+use std::{convert::TryFrom, io::Read, str};
+use uuid::Uuid;
+
+/// Bumped whenever the format changes so older snapshots are ignored instead of misread
+const SNAPSHOT_VERSION: u8 = 3;
+
+/// A routing table as it is saved to the store.
+/// Each route is saved with the ID of the account it was set for.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TableSnapshot {
+    pub(crate) id: [u8; 16],
+    pub(crate) epoch: u32,
+    pub(crate) routes: Vec<(Uuid, Route)>,
+}
+
+/// The CCP Route Manager's routing tables, saved to the store so that they
+/// can be reloaded when the node restarts
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RoutingSnapshot {
+    /// When the snapshot was taken, in milliseconds since the UNIX epoch
+    pub(crate) saved_at: u64,
+    pub(crate) forwarding_table: TableSnapshot,
+    /// The new and withdrawn routes for each epoch of the forwarding table,
+    /// which are needed to send peers the updates they missed
+    pub(crate) forwarding_table_updates: UpdateHistory,
+    /// The routing table of each peer we receive routes from (keyed by account ID)
+    pub(crate) incoming_tables: Vec<(Uuid, TableSnapshot)>,
+    /// The auth anchor we accepted for each prefix and when it was last used
+    /// (in milliseconds since the UNIX epoch)
+    pub(crate) route_auth_anchors: Vec<(String, [u8; 32], u64)>,
+}
+
+impl RoutingSnapshot {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.put_u8(SNAPSHOT_VERSION);
+        data.put_u64_be(self.saved_at);
+        self.forwarding_table.write_to(&mut data);
+        let history = &self.forwarding_table_updates;
+        data.put_u32_be(history.compacted_epochs);
+        write_update(&mut data, &history.compacted);
+        data.put_var_uint(history.updates.len() as u64);
+        for update in history.updates.iter() {
+            write_update(&mut data, update);
+        }
+        data.put_var_uint(self.incoming_tables.len() as u64);
+        for (account_id, table) in self.incoming_tables.iter() {
+            data.put(&account_id.as_bytes()[..]);
+            table.write_to(&mut data);
+        }
+        data.put_var_uint(self.route_auth_anchors.len() as u64);
+        for (prefix, anchor, last_seen) in self.route_auth_anchors.iter() {
+            data.put_var_octet_string(&prefix[..]);
+            data.put(&anchor[..]);
+            data.put_u64_be(*last_seen);
+        }
+        data
+    }
+}
+
+impl TryFrom<&[u8]> for RoutingSnapshot {
+    type Error = ParseError;
+
+    fn try_from(mut data: &[u8]) -> Result<Self, Self::Error> {
+        let version = data.read_u8()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(ParseError::InvalidPacket(format!(
+                "Unsupported routing snapshot version: {}",
+                version
+            )));
+        }
+        let saved_at = data.read_u64::<BigEndian>()?;
+        let forwarding_table = TableSnapshot::try_from(&mut data)?;
+
+        let compacted_epochs = data.read_u32::<BigEndian>()?;
+        let compacted = read_update(&mut data)?;
+        let updates_len = read_list_len(&mut data)?;
+        let mut updates = Vec::with_capacity(updates_len);
+        for _i in 0..updates_len {
+            updates.push(read_update(&mut data)?);
+        }
+        let forwarding_table_updates = UpdateHistory {
+            compacted_epochs,
+            compacted,
+            updates,
+        };
+
+        let incoming_tables_len = read_list_len(&mut data)?;
+        let mut incoming_tables = Vec::with_capacity(incoming_tables_len);
+        for _i in 0..incoming_tables_len {
+            let account_id = read_uuid(&mut data)?;
+            incoming_tables.push((account_id, TableSnapshot::try_from(&mut data)?));
+        }
+
+        let anchors_len = read_list_len(&mut data)?;
+        let mut route_auth_anchors = Vec::with_capacity(anchors_len);
+        for _i in 0..anchors_len {
+            let prefix = str::from_utf8(data.read_var_octet_string()?)?.to_string();
+            let mut anchor: [u8; 32] = [0; 32];
+            data.read_exact(&mut anchor)?;
+            let last_seen = data.read_u64::<BigEndian>()?;
+            route_auth_anchors.push((prefix, anchor, last_seen));
+        }
+
+        Ok(RoutingSnapshot {
+            saved_at,
+            forwarding_table,
+            forwarding_table_updates,
+            incoming_tables,
+            route_auth_anchors,
+        })
+    }
+}
+
+impl TableSnapshot {
+    fn write_to<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        buf.put(&self.id[..]);
+        buf.put_u32_be(self.epoch);
+        buf.put_var_uint(self.routes.len() as u64);
+        for (account_id, route) in self.routes.iter() {
+            buf.put(&account_id.as_bytes()[..]);
+            route.write_to(buf);
+        }
+    }
+}
+
+impl TryFrom<&mut &[u8]> for TableSnapshot {
+    type Error = ParseError;
+
+    fn try_from(data: &mut &[u8]) -> Result<Self, Self::Error> {
+        let mut id: [u8; 16] = [0; 16];
+        data.read_exact(&mut id)?;
+        let epoch = data.read_u32::<BigEndian>()?;
+        let routes_len = read_list_len(data)?;
+        let mut routes = Vec::with_capacity(routes_len);
+        for _i in 0..routes_len {
+            let account_id = read_uuid(data)?;
+            routes.push((account_id, Route::try_from(data as &mut &[u8])?));
+        }
+        Ok(TableSnapshot { id, epoch, routes })
+    }
+}
+
+fn write_update<B>(buf: &mut B, (new_routes, withdrawn_routes): &NewAndWithdrawnRoutes)
+where
+    B: BufMut,
+{
+    buf.put_var_uint(new_routes.len() as u64);
+    for route in new_routes.iter() {
+        route.write_to(buf);
+    }
+    buf.put_var_uint(withdrawn_routes.len() as u64);
+    for prefix in withdrawn_routes.iter() {
+        buf.put_var_octet_string(&prefix[..]);
+    }
+}
+
+fn read_update(data: &mut &[u8]) -> Result<NewAndWithdrawnRoutes, ParseError> {
+    let new_routes_len = read_list_len(data)?;
+    let mut new_routes = Vec::with_capacity(new_routes_len);
+    for _i in 0..new_routes_len {
+        new_routes.push(Route::try_from(data as &mut &[u8])?);
+    }
+    let withdrawn_routes_len = read_list_len(data)?;
+    let mut withdrawn_routes = Vec::with_capacity(withdrawn_routes_len);
+    for _i in 0..withdrawn_routes_len {
+        withdrawn_routes.push(str::from_utf8(data.read_var_octet_string()?)?.to_string());
+    }
+    Ok((new_routes, withdrawn_routes))
+}
+
+/// Read the length of a list. Every item takes up at least one byte, so a list which is
+/// longer than the data left comes from a truncated or corrupted snapshot. Checking this
+/// also keeps us from reserving memory for more items than the snapshot could hold
+fn read_list_len(data: &mut &[u8]) -> Result<usize, ParseError> {
+    let len = data.read_var_uint()?;
+    if len > data.len() as u64 {
+        return Err(ParseError::InvalidPacket(format!(
+            "Routing snapshot is truncated: expected {} items but only {} bytes are left",
+            len,
+            data.len()
+        )));
+    }
+    Ok(len as usize)
+}
+
+fn read_uuid(data: &mut &[u8]) -> Result<Uuid, ParseError> {
+    let mut bytes: [u8; 16] = [0; 16];
+    data.read_exact(&mut bytes)?;
+    Ok(Uuid::from_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(prefix: &str) -> Route {
+        Route {
+            prefix: prefix.to_string(),
+            path: vec!["example.peer".to_string()],
+            auth: [5; 32],
+            props: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let peer_id = Uuid::from_slice(&[1; 16]).unwrap();
+        let snapshot = RoutingSnapshot {
+            saved_at: 1_580_000_000_000,
+            forwarding_table: TableSnapshot {
+                id: [2; 16],
+                epoch: 2,
+                routes: vec![(peer_id, route("example.a"))],
+            },
+            forwarding_table_updates: UpdateHistory {
+                compacted_epochs: 1,
+                compacted: (vec![route("example.a")], vec!["example.c".to_string()]),
+                updates: vec![(Vec::new(), vec!["example.b".to_string()])],
+            },
+            incoming_tables: vec![(
+                peer_id,
+                TableSnapshot {
+                    id: [3; 16],
+                    epoch: 7,
+                    routes: vec![(peer_id, route("example.a"))],
+                },
+            )],
+            route_auth_anchors: vec![("example.a".to_string(), [4; 32], 1_580_000_000_000)],
+        };
+
+        let bytes = snapshot.to_bytes();
+        assert_eq!(RoutingSnapshot::try_from(&bytes[..]).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn rejects_other_versions_and_truncated_data() {
+        let snapshot = RoutingSnapshot {
+            saved_at: 0,
+            forwarding_table: TableSnapshot {
+                id: [0; 16],
+                epoch: 0,
+                routes: Vec::new(),
+            },
+            forwarding_table_updates: UpdateHistory::default(),
+            incoming_tables: Vec::new(),
+            route_auth_anchors: Vec::new(),
+        };
+        let mut bytes = snapshot.to_bytes();
+        assert!(RoutingSnapshot::try_from(&bytes[..bytes.len() - 1]).is_err());
+
+        bytes[0] = SNAPSHOT_VERSION + 1;
+        assert!(RoutingSnapshot::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_lists_longer_than_the_data() {
+        let mut bytes = vec![SNAPSHOT_VERSION];
+        bytes.put_u64_be(0);
+        bytes.put(&[0; 16][..]);
+        bytes.put_u32_be(0);
+        // The forwarding table claims to have far more routes than there is data for
+        bytes.put_var_uint(u64::max_value());
+        bytes.put(&[0; 32][..]);
+        assert!(RoutingSnapshot::try_from(&bytes[..]).is_err());
+    }
+}
@@ -18,26 +18,42 @@ pub static ROUTING_ACCOUNT: Lazy<TestAccount> = Lazy::new(|| TestAccount {
     id: Uuid::new_v4(),
     ilp_address: Address::from_str("example.peer").unwrap(),
     relation: RoutingRelation::Peer,
+    policy: TestRoutePolicy::default(),
 });
 pub static NON_ROUTING_ACCOUNT: Lazy<TestAccount> = Lazy::new(|| TestAccount {
     id: Uuid::new_v4(),
     ilp_address: Address::from_str("example.me.nonroutingaccount").unwrap(),
     relation: RoutingRelation::NonRoutingAccount,
+    policy: TestRoutePolicy::default(),
 });
 pub static CHILD_ACCOUNT: Lazy<TestAccount> = Lazy::new(|| TestAccount {
     id: Uuid::new_v4(),
     ilp_address: Address::from_str("example.me.child").unwrap(),
     relation: RoutingRelation::Child,
+    policy: TestRoutePolicy::default(),
 });
 pub static EXAMPLE_CONNECTOR: Lazy<Address> =
     Lazy::new(|| Address::from_str("example.connector").unwrap());
 pub static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
 
+#[derive(Clone, Debug, Default)]
+pub struct TestRoutePolicy {
+    pub import_allow: Vec<String>,
+    pub import_deny: Vec<String>,
+    pub export_allow: Vec<String>,
+    pub export_deny: Vec<String>,
+    pub max_path_length: Option<u32>,
+    pub max_routes: Option<u32>,
+    pub strict_auth: bool,
+    pub route_cost: u32,
+}
+
 #[derive(Clone, Debug)]
 pub struct TestAccount {
     pub id: Uuid,
     pub ilp_address: Address,
     pub relation: RoutingRelation,
+    pub policy: TestRoutePolicy,
 }
 
 impl TestAccount {
@@ -46,6 +62,7 @@ impl TestAccount {
             id,
             ilp_address: Address::from_str(ilp_address).unwrap(),
             relation: RoutingRelation::Peer,
+            policy: TestRoutePolicy::default(),
         }
     }
 }
@@ -76,6 +93,38 @@ impl CcpRoutingAccount for TestAccount {
     fn routing_relation(&self) -> RoutingRelation {
         self.relation
     }
+
+    fn route_import_allow(&self) -> &[String] {
+        &self.policy.import_allow
+    }
+
+    fn route_import_deny(&self) -> &[String] {
+        &self.policy.import_deny
+    }
+
+    fn route_export_allow(&self) -> &[String] {
+        &self.policy.export_allow
+    }
+
+    fn route_export_deny(&self) -> &[String] {
+        &self.policy.export_deny
+    }
+
+    fn max_route_path_length(&self) -> Option<u32> {
+        self.policy.max_path_length
+    }
+
+    fn max_imported_routes(&self) -> Option<u32> {
+        self.policy.max_routes
+    }
+
+    fn strict_route_auth(&self) -> bool {
+        self.policy.strict_auth
+    }
+
+    fn route_cost(&self) -> u32 {
+        self.policy.route_cost
+    }
 }
 
 #[derive(Clone)]
@@ -83,6 +132,8 @@ pub struct TestStore {
     pub local: HashMap<String, TestAccount>,
     pub configured: HashMap<String, TestAccount>,
     pub routes: Arc<Mutex<HashMap<String, TestAccount>>>,
+    pub fallback_routes: Arc<Mutex<HashMap<String, Vec<TestAccount>>>>,
+    pub routing_state: Arc<Mutex<Option<Vec<u8>>>>,
 }
 
 impl TestStore {
@@ -91,6 +142,8 @@ impl TestStore {
             local: HashMap::new(),
             configured: HashMap::new(),
             routes: Arc::new(Mutex::new(HashMap::new())),
+            fallback_routes: Arc::new(Mutex::new(HashMap::new())),
+            routing_state: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -102,6 +155,8 @@ impl TestStore {
             local,
             configured,
             routes: Arc::new(Mutex::new(HashMap::new())),
+            fallback_routes: Arc::new(Mutex::new(HashMap::new())),
+            routing_state: Arc::new(Mutex::new(None)),
         }
     }
 }
@@ -175,6 +230,28 @@ impl CcpRoutingStore for TestStore {
         *self.routes.lock() = HashMap::from_iter(routes.into_iter());
         Ok(())
     }
+
+    async fn update_fallback_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<TestAccount>)> + Send + 'async_trait,
+        removed_prefixes: impl IntoIterator<Item = String> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let mut fallback_routes = self.fallback_routes.lock();
+        for prefix in removed_prefixes {
+            fallback_routes.remove(&prefix);
+        }
+        fallback_routes.extend(routes);
+        Ok(())
+    }
+
+    async fn set_routing_state(&mut self, state: Vec<u8>) -> Result<(), CcpRoutingStoreError> {
+        *self.routing_state.lock() = Some(state);
+        Ok(())
+    }
+
+    async fn get_routing_state(&self) -> Result<Option<Vec<u8>>, CcpRoutingStoreError> {
+        Ok(self.routing_state.lock().clone())
+    }
 }
 
 pub fn test_service() -> CcpRouteManager<
@@ -232,6 +309,7 @@ pub fn test_service_with_routes() -> (
                 id: Uuid::from_slice(&[3; 16]).unwrap(),
                 ilp_address: Address::from_str("example.connector.other-local").unwrap(),
                 relation: RoutingRelation::NonRoutingAccount,
+                policy: TestRoutePolicy::default(),
             },
         ),
     ]);
@@ -0,0 +1,29 @@
+use super::NodeStoreError;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// Errors for the IncomingPaymentStore
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum IncomingPaymentStoreError {
+    #[error("{0}")]
+    Other(#[from] Box<dyn StdError + Send + 'static>),
+    #[error("incoming payment `{0}` was not found")]
+    IncomingPaymentNotFound(String),
+}
+
+impl From<NodeStoreError> for IncomingPaymentStoreError {
+    fn from(src: NodeStoreError) -> Self {
+        IncomingPaymentStoreError::Other(Box::new(src))
+    }
+}
+
+#[cfg(feature = "redis_errors")]
+use redis::RedisError;
+
+#[cfg(feature = "redis_errors")]
+impl From<RedisError> for IncomingPaymentStoreError {
+    fn from(src: RedisError) -> IncomingPaymentStoreError {
+        IncomingPaymentStoreError::Other(Box::new(src))
+    }
+}
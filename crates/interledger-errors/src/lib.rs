@@ -23,6 +23,9 @@ pub use balance_store_error::BalanceStoreError;
 mod node_store_error;
 pub use node_store_error::NodeStoreError;
 
+mod incoming_payment_store_error;
+pub use incoming_payment_store_error::IncomingPaymentStoreError;
+
 mod exchange_rate_store_error;
 pub use exchange_rate_store_error::ExchangeRateStoreError;
 
@@ -0,0 +1,53 @@
+//! Benchmark routing table lookups.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use interledger_router::PrefixMap;
+
+/// Build a table shaped like a connector's: a default route, a handful of peers
+/// and many local accounts under the node's own address
+fn routing_table(accounts: usize) -> PrefixMap<usize> {
+    let mut table = PrefixMap::new();
+    table.insert(String::new(), 0);
+    for peer in 0..10 {
+        table.insert(format!("example.peer{}", peer), peer);
+    }
+    for account in 0..accounts {
+        table.insert(format!("example.connector.account{}", account), account);
+    }
+    table
+}
+
+fn benchmark_resolve(c: &mut Criterion) {
+    for size in &[100, 10_000, 100_000] {
+        let table = routing_table(*size);
+
+        c.bench_function(&format!("Resolve exact ({} routes)", size), |b| {
+            b.iter(|| {
+                assert_eq!(table.resolve("example.connector.account42"), Some(&42));
+            });
+        });
+
+        c.bench_function(&format!("Resolve prefix ({} routes)", size), |b| {
+            b.iter(|| {
+                assert_eq!(
+                    table.resolve("example.connector.account42.wallet.abc123"),
+                    Some(&42)
+                );
+            });
+        });
+
+        c.bench_function(&format!("Resolve default ({} routes)", size), |b| {
+            b.iter(|| {
+                assert_eq!(table.resolve("test.someone.else"), Some(&0));
+            });
+        });
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default();
+    targets = benchmark_resolve,
+}
+
+criterion_main!(benches);
@@ -0,0 +1,119 @@
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Number of consecutive peer-unreachable rejects before a peer is considered unhealthy
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+/// How long an unhealthy peer is passed over before the Router tries it first again
+const DEFAULT_RECOVERY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+struct PeerState {
+    consecutive_failures: u32,
+    last_failure: Instant,
+}
+
+/// Tracks which next hops have recently been unreachable.
+///
+/// The Router records a failure every time a peer responds with `T01_PEER_UNREACHABLE`
+/// and a success for any other response. Once a peer reaches the failure threshold it is
+/// tried after all healthy alternatives, until the recovery interval has passed since
+/// its last failure. Clones share the same underlying state.
+#[derive(Clone)]
+pub struct PeerHealth {
+    peers: Arc<RwLock<HashMap<Uuid, PeerState>>>,
+    failure_threshold: u32,
+    recovery_interval: Duration,
+}
+
+impl PeerHealth {
+    pub fn new(failure_threshold: u32, recovery_interval: Duration) -> Self {
+        PeerHealth {
+            peers: Arc::new(RwLock::new(HashMap::new())),
+            failure_threshold,
+            recovery_interval,
+        }
+    }
+
+    /// Returns false if the peer has failed too many times in a row and
+    /// the recovery interval has not yet passed
+    pub fn is_available(&self, account_id: Uuid) -> bool {
+        match self.peers.read().get(&account_id) {
+            Some(state) => {
+                state.consecutive_failures < self.failure_threshold
+                    || state.last_failure.elapsed() >= self.recovery_interval
+            }
+            None => true,
+        }
+    }
+
+    /// The number of peer-unreachable rejects seen from this peer since its last success
+    pub fn consecutive_failures(&self, account_id: Uuid) -> u32 {
+        self.peers
+            .read()
+            .get(&account_id)
+            .map(|state| state.consecutive_failures)
+            .unwrap_or(0)
+    }
+
+    pub fn record_success(&self, account_id: Uuid) {
+        // Avoid taking the write lock on the hot path if the peer was already healthy
+        if self.peers.read().contains_key(&account_id) {
+            self.peers.write().remove(&account_id);
+        }
+    }
+
+    pub fn record_failure(&self, account_id: Uuid) {
+        let mut peers = self.peers.write();
+        let state = peers.entry(account_id).or_insert(PeerState {
+            consecutive_failures: 0,
+            last_failure: Instant::now(),
+        });
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        state.last_failure = Instant::now();
+    }
+}
+
+impl Default for PeerHealth {
+    fn default() -> Self {
+        PeerHealth::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_RECOVERY_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_peer_unavailable_after_threshold() {
+        let health = PeerHealth::new(2, Duration::from_secs(60));
+        let id = Uuid::new_v4();
+        assert!(health.is_available(id));
+        health.record_failure(id);
+        assert!(health.is_available(id));
+        health.record_failure(id);
+        assert!(!health.is_available(id));
+        assert_eq!(health.consecutive_failures(id), 2);
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let health = PeerHealth::new(1, Duration::from_secs(60));
+        let id = Uuid::new_v4();
+        health.record_failure(id);
+        assert!(!health.is_available(id));
+        health.record_success(id);
+        assert!(health.is_available(id));
+        assert_eq!(health.consecutive_failures(id), 0);
+    }
+
+    #[test]
+    fn peer_becomes_available_after_recovery_interval() {
+        let health = PeerHealth::new(1, Duration::from_millis(0));
+        let id = Uuid::new_v4();
+        health.record_failure(id);
+        assert!(health.is_available(id));
+    }
+}
@@ -6,6 +6,8 @@
 //!
 //! A routing table could be as simple as a single entry for the empty prefix
 //! ("") that will route all requests to a specific outgoing account.
+//! Routing tables are stored in a [`PrefixMap`](./struct.PrefixMap.html), which
+//! matches prefixes on ILP address segment boundaries.
 //!
 //! Note that the Router is not responsible for building the routing table,
 //! only using the information provided by the store. The routing table in the
@@ -13,11 +15,15 @@
 //! (see the `interledger-ccp` crate for more details).
 
 use interledger_service::AccountStore;
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 use uuid::Uuid;
 
+mod health;
+mod prefix_map;
 mod router;
 
+pub use self::health::PeerHealth;
+pub use self::prefix_map::{Iter, PrefixMap};
 pub use self::router::Router;
 
 /// A trait for Store implmentations that have ILP routing tables.
@@ -27,5 +33,13 @@ pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
     /// keep the routing table in memory and use PubSub or polling to keep it updated.
     /// This ensures that individual packets can be routed without hitting the underlying store.
     /// An Arc is returned to avoid copying the underlying data while processing each packet.
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>>;
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>>;
+
+    /// **Synchronously** return the ranked alternative next hops for each prefix.
+    /// The Router fails over to these, in order, if the account in the routing table
+    /// for the same prefix rejects a packet with `T01_PEER_UNREACHABLE`.
+    /// Stores that do not track alternative routes can rely on the default (empty) table.
+    fn fallback_routes(&self) -> Arc<PrefixMap<Vec<Uuid>>> {
+        Arc::new(PrefixMap::new())
+    }
 }
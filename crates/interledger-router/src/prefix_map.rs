@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+
+/// ILP addresses are made up of segments separated by this character
+const SEPARATOR: char = '.';
+
+/// A map from ILP address prefixes to values that supports longest-prefix lookups.
+///
+/// Prefixes are stored in a trie keyed by address segment, so resolving an address
+/// only walks as many nodes as the address has segments, regardless of how many
+/// routes are in the table. Matching honors segment boundaries: the prefix
+/// `example.alice` matches `example.alice` and `example.alice.wallet` but not
+/// `example.alicia`. A prefix ending in `.` (for example `example.`) matches every
+/// address below it, and the empty prefix matches every address.
+#[derive(Clone, PartialEq)]
+pub struct PrefixMap<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+#[derive(Clone, PartialEq)]
+struct Node<T> {
+    /// The full prefix is kept alongside the value so iteration doesn't need to rebuild it
+    entry: Option<(String, T)>,
+    children: HashMap<String, Node<T>>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            entry: None,
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.entry.is_none() && self.children.is_empty()
+    }
+
+    /// Remove the entry at the given path, pruning any nodes left empty along the way
+    fn remove(&mut self, segments: &[&str]) -> Option<T> {
+        match segments.split_first() {
+            None => self.entry.take().map(|(_, value)| value),
+            Some((segment, rest)) => {
+                let child = self.children.get_mut(*segment)?;
+                let removed = child.remove(rest);
+                if child.is_empty() {
+                    self.children.remove(*segment);
+                }
+                removed
+            }
+        }
+    }
+}
+
+/// Split a prefix into the path of trie segments it is stored under.
+/// The empty prefix is the root of the trie.
+fn segments(prefix: &str) -> Vec<&str> {
+    if prefix.is_empty() {
+        Vec::new()
+    } else {
+        prefix.split(SEPARATOR).collect()
+    }
+}
+
+impl<T> PrefixMap<T> {
+    pub fn new() -> Self {
+        PrefixMap {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Insert a value for the given prefix, returning the value it replaced (if any)
+    pub fn insert(&mut self, prefix: String, value: T) -> Option<T> {
+        let mut node = &mut self.root;
+        for segment in segments(&prefix) {
+            node = node.children.entry(segment.to_string()).or_default();
+        }
+        let previous = node.entry.replace((prefix, value)).map(|(_, value)| value);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Remove the value stored for exactly the given prefix
+    pub fn remove(&mut self, prefix: &str) -> Option<T> {
+        let removed = self.root.remove(&segments(prefix));
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Get the value stored for exactly the given prefix
+    pub fn get(&self, prefix: &str) -> Option<&T> {
+        let mut node = &self.root;
+        for segment in segments(prefix) {
+            node = node.children.get(segment)?;
+        }
+        node.entry.as_ref().map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, prefix: &str) -> bool {
+        self.get(prefix).is_some()
+    }
+
+    /// Find the entry with the longest prefix that matches the given address
+    pub fn resolve_entry(&self, address: &str) -> Option<(&str, &T)> {
+        let mut node = &self.root;
+        let mut best = node.entry.as_ref();
+        if !address.is_empty() {
+            for segment in address.split(SEPARATOR) {
+                // Prefixes ending in the separator are stored under an empty child
+                // segment and match any address that continues past them
+                if let Some(entry) = node.children.get("").and_then(|child| child.entry.as_ref()) {
+                    best = Some(entry);
+                }
+                match node.children.get(segment) {
+                    Some(child) => {
+                        node = child;
+                        if node.entry.is_some() {
+                            best = node.entry.as_ref();
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+        best.map(|(prefix, value)| (prefix.as_str(), value))
+    }
+
+    /// Find the value for the longest prefix that matches the given address
+    pub fn resolve(&self, address: &str) -> Option<&T> {
+        self.resolve_entry(address).map(|(_, value)| value)
+    }
+
+    /// Iterate over all prefixes and their values, in no particular order
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            stack: vec![&self.root],
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(prefix, _)| prefix)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<T> Default for PrefixMap<T> {
+    fn default() -> Self {
+        PrefixMap::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PrefixMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T> FromIterator<(String, T)> for PrefixMap<T> {
+    fn from_iter<I: IntoIterator<Item = (String, T)>>(iter: I) -> Self {
+        let mut map = PrefixMap::new();
+        for (prefix, value) in iter {
+            map.insert(prefix, value);
+        }
+        map
+    }
+}
+
+impl<T> Index<&str> for PrefixMap<T> {
+    type Output = T;
+
+    fn index(&self, prefix: &str) -> &T {
+        self.get(prefix).expect("no entry found for prefix")
+    }
+}
+
+/// Iterator over the entries of a `PrefixMap`
+pub struct Iter<'a, T> {
+    stack: Vec<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a str, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            self.stack.extend(node.children.values());
+            if let Some((prefix, value)) = &node.entry {
+                return Some((prefix.as_str(), value));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PrefixMap<T> {
+    type Item = (&'a str, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_and_replaces_entries() {
+        let mut map = PrefixMap::new();
+        assert_eq!(map.insert("example.a".to_string(), 1), None);
+        assert_eq!(map.insert("example.a".to_string(), 2), Some(1));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("example.a"), Some(&2));
+        assert_eq!(map.get("example"), None);
+    }
+
+    #[test]
+    fn removes_entries_and_prunes_nodes() {
+        let mut map = PrefixMap::new();
+        map.insert("example.a.b".to_string(), 1);
+        map.insert("example.a".to_string(), 2);
+        assert_eq!(map.remove("example.a.b"), Some(1));
+        assert_eq!(map.remove("example.a.b"), None);
+        assert_eq!(map.remove("example"), None);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.remove("example.a"), Some(2));
+        assert!(map.is_empty());
+        assert!(map.root.is_empty());
+    }
+
+    #[test]
+    fn resolves_to_longest_matching_prefix() {
+        let mut map = PrefixMap::new();
+        map.insert("example.a".to_string(), 1);
+        map.insert("example.a.b.c".to_string(), 2);
+        map.insert("example.a.b".to_string(), 3);
+
+        assert_eq!(map.resolve("example.a"), Some(&1));
+        assert_eq!(map.resolve("example.a.x"), Some(&1));
+        assert_eq!(map.resolve("example.a.b.c"), Some(&2));
+        assert_eq!(map.resolve("example.a.b.c.d.e"), Some(&2));
+        assert_eq!(map.resolve("example.a.b.x"), Some(&3));
+        assert_eq!(map.resolve("example.other"), None);
+    }
+
+    #[test]
+    fn respects_segment_boundaries() {
+        let mut map = PrefixMap::new();
+        map.insert("example.alice".to_string(), 1);
+
+        assert_eq!(map.resolve("example.alice"), Some(&1));
+        assert_eq!(map.resolve("example.alice.wallet"), Some(&1));
+        assert_eq!(map.resolve("example.alicia"), None);
+        assert_eq!(map.resolve("example"), None);
+    }
+
+    #[test]
+    fn trailing_separator_matches_descendants_only() {
+        let mut map = PrefixMap::new();
+        map.insert("example.".to_string(), 1);
+        map.insert("example.a.".to_string(), 2);
+
+        assert_eq!(map.resolve("example.destination"), Some(&1));
+        assert_eq!(map.resolve("example.a"), Some(&1));
+        assert_eq!(map.resolve("example.a.b"), Some(&2));
+        assert_eq!(map.resolve("example"), None);
+        assert_eq!(map.resolve_entry("example.a.b"), Some(("example.a.", &2)));
+    }
+
+    #[test]
+    fn empty_prefix_is_catch_all() {
+        let mut map = PrefixMap::new();
+        map.insert(String::new(), 0);
+        map.insert("example.a".to_string(), 1);
+
+        assert_eq!(map.resolve("example.a.b"), Some(&1));
+        assert_eq!(map.resolve("example.b"), Some(&0));
+        assert_eq!(map.resolve("test.a"), Some(&0));
+        assert_eq!(map[""], 0);
+    }
+
+    #[test]
+    fn iterates_over_all_entries() {
+        let map: PrefixMap<u32> = vec![
+            (String::new(), 0),
+            ("example.".to_string(), 1),
+            ("example.a".to_string(), 2),
+            ("example.a.b".to_string(), 3),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut entries: Vec<(&str, u32)> = map.iter().map(|(p, v)| (p, *v)).collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                ("", 0),
+                ("example.", 1),
+                ("example.a", 2),
+                ("example.a.b", 3)
+            ]
+        );
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.values().count(), 4);
+    }
+}
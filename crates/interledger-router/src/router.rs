@@ -1,9 +1,11 @@
-use super::RouterStore;
+use super::{PeerHealth, RouterStore};
 use async_trait::async_trait;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::*;
 use std::str;
-use tracing::{error, trace};
+use std::time::SystemTime;
+use tracing::{debug, error, trace};
+use uuid::Uuid;
 
 /// # Interledger Router
 ///
@@ -15,6 +17,11 @@ use tracing::{error, trace};
 /// The router implements the IncomingService trait and uses the routing table
 /// to determine the `to` (or "next hop") Account for the given request.
 ///
+/// If the next hop rejects the packet with a `T01_PEER_UNREACHABLE` that it (or this node)
+/// triggered, the router retries the packet with the store's fallback routes for the same
+/// prefix (as long as the packet has not expired). Peers that are repeatedly unreachable
+/// are tried last.
+///
 /// Note that the router does **not**:
 ///   - apply exchange rates or fees to the Prepare packet
 ///   - adjust account balances
@@ -26,6 +33,7 @@ use tracing::{error, trace};
 pub struct Router<S, O> {
     store: S,
     next: O,
+    health: PeerHealth,
 }
 
 impl<S, O> Router<S, O>
@@ -34,7 +42,41 @@ where
     O: OutgoingService<S::Account>,
 {
     pub fn new(store: S, next: O) -> Self {
-        Router { store, next }
+        Router {
+            store,
+            next,
+            health: PeerHealth::default(),
+        }
+    }
+
+    /// Use the given health tracker instead of one with the default thresholds.
+    /// Useful for sharing peer health between Routers or reporting it elsewhere.
+    pub fn with_peer_health(mut self, health: PeerHealth) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub fn peer_health(&self) -> &PeerHealth {
+        &self.health
+    }
+
+    /// Order the next hops for a prefix: the routing table's choice followed by
+    /// the fallback routes, with peers that are currently unhealthy moved to the end
+    fn rank_next_hops(&self, prefix: &str, account_id: Uuid) -> Vec<Uuid> {
+        let fallback_routes = self.store.fallback_routes();
+        let mut next_hops = vec![account_id];
+        if let Some(fallbacks) = fallback_routes.get(prefix) {
+            for fallback in fallbacks {
+                if !next_hops.contains(fallback) {
+                    next_hops.push(*fallback);
+                }
+            }
+        }
+
+        let (healthy, unhealthy): (Vec<Uuid>, Vec<Uuid>) = next_hops
+            .into_iter()
+            .partition(|account_id| self.health.is_available(*account_id));
+        healthy.into_iter().chain(unhealthy).collect()
     }
 }
 
@@ -46,68 +88,35 @@ where
 {
     /// Figures out the next node to pass the received Prepare packet to.
     ///
-    /// Firstly, it checks if there is a direct path for that account and uses that.
-    /// If not it scans through the routing table and checks if the route prefix matches
-    /// the prepare packet's destination or if it's a catch-all address (i.e. empty prefix)
+    /// It resolves the longest route prefix in the routing table that matches the
+    /// prepare packet's destination on segment boundaries, falling back to the
+    /// catch-all route (i.e. empty prefix) if there is one
     async fn handle_request(&mut self, request: IncomingRequest<S::Account>) -> IlpResult {
         let destination = request.prepare.destination();
-        let mut next_hop = None;
         let routing_table = self.store.routing_table();
         let ilp_address = self.store.get_ilp_address();
 
-        // Check if we have a direct path for that account or if we need to scan
-        // through the routing table
+        // Walk the routing table one address segment at a time to find the
+        // longest matching prefix (an empty prefix is a catch-all route)
         let dest: &str = &destination;
-        if let Some(account_id) = routing_table.get(dest) {
+        let next_hops = if let Some((prefix, account_id)) = routing_table.resolve_entry(dest) {
+            let next_hops = self.rank_next_hops(prefix, *account_id);
             trace!(
-                "Found direct route for address: \"{}\". Account: {}",
+                "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}, next hops: {:?}",
                 destination,
-                account_id
+                prefix,
+                account_id,
+                next_hops,
             );
-            next_hop = Some(*account_id);
-        } else if !routing_table.is_empty() {
-            let mut matching_prefix = "";
-            let routing_table = self.store.routing_table();
-            for (ref prefix, account) in (*routing_table).iter() {
-                // Check if the route prefix matches or is empty (meaning it's a catch-all address)
-                if (prefix.is_empty() || dest.starts_with(prefix.as_str()))
-                    && prefix.len() >= matching_prefix.len()
-                {
-                    next_hop.replace(*account);
-                    matching_prefix = prefix.as_str();
-                }
-            }
-            if let Some(account_id) = next_hop {
-                trace!(
-                    "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
-                    destination,
-                    matching_prefix,
-                    account_id,
-                );
-            }
+            next_hops
         } else {
-            error!("Unable to route request because routing table is empty");
-        }
-
-        if let Some(account_id) = next_hop {
-            let mut next = self.next.clone();
-            match self.store.get_accounts(vec![account_id]).await {
-                Ok(mut accounts) => {
-                    let request = request.into_outgoing(accounts.remove(0));
-                    next.send_request(request).await
-                }
-                Err(_) => {
-                    error!("No record found for account: {}", account_id);
-                    Err(RejectBuilder {
-                        code: ErrorCode::F02_UNREACHABLE,
-                        message: &[],
-                        triggered_by: Some(&ilp_address),
-                        data: &[],
-                    }
-                    .build())
-                }
+            if routing_table.is_empty() {
+                error!("Unable to route request because routing table is empty");
             }
-        } else {
+            Vec::new()
+        };
+
+        if next_hops.is_empty() {
             error!(
                 "No route found for request {}: {:?}",
                 {
@@ -126,30 +135,93 @@ where
                 },
                 request
             );
-            Err(RejectBuilder {
+            return Err(RejectBuilder {
                 code: ErrorCode::F02_UNREACHABLE,
                 message: &[],
                 triggered_by: Some(&ilp_address),
                 data: &[],
             }
-            .build())
+            .build());
         }
+
+        let attempts = next_hops.len();
+        // The request is only cloned if there is another next hop to fail over to
+        let mut request = Some(request);
+        let mut last_reject = None;
+        for (attempt, account_id) in next_hops.into_iter().enumerate() {
+            let prepare = &request.as_ref().unwrap().prepare;
+            if attempt > 0 && prepare.expires_at() <= SystemTime::now() {
+                debug!(
+                    "Not failing over to account {} because the packet has expired",
+                    account_id
+                );
+                break;
+            }
+
+            let account = match self.store.get_accounts(vec![account_id]).await {
+                Ok(mut accounts) => accounts.remove(0),
+                Err(_) => {
+                    error!("No record found for account: {}", account_id);
+                    continue;
+                }
+            };
+
+            let next_hop_address = account.ilp_address().clone();
+            let outgoing = if attempt + 1 < attempts {
+                request.as_ref().unwrap().clone().into_outgoing(account)
+            } else {
+                request.take().unwrap().into_outgoing(account)
+            };
+            let mut next = self.next.clone();
+            match next.send_request(outgoing).await {
+                // Only fail over if the next hop itself (or we) could not be reached.
+                // A peer further along the path being unreachable says nothing about
+                // the next hop, which may well have tried its own alternatives already
+                Err(reject)
+                    if reject.code() == ErrorCode::T01_PEER_UNREACHABLE
+                        && reject.triggered_by().map_or(false, |triggered_by| {
+                            triggered_by == next_hop_address || triggered_by == ilp_address
+                        }) =>
+                {
+                    debug!(
+                        "Next hop {} is unreachable, trying the next route (if any): {:?}",
+                        account_id, reject
+                    );
+                    self.health.record_failure(account_id);
+                    last_reject = Some(reject);
+                }
+                result => {
+                    self.health.record_success(account_id);
+                    return result;
+                }
+            }
+        }
+
+        Err(last_reject.unwrap_or_else(|| {
+            RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: &[],
+                triggered_by: Some(&ilp_address),
+                data: &[],
+            }
+            .build()
+        }))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::PrefixMap;
     use interledger_errors::*;
     use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
     use interledger_service::outgoing_service_fn;
     use once_cell::sync::Lazy;
     use parking_lot::Mutex;
-    use std::collections::HashMap;
     use std::iter::FromIterator;
     use std::str::FromStr;
     use std::sync::Arc;
-    use std::time::UNIX_EPOCH;
+    use std::time::{Duration, UNIX_EPOCH};
     use uuid::Uuid;
 
     #[derive(Debug, Clone)]
@@ -183,7 +255,8 @@ mod tests {
 
     #[derive(Clone)]
     struct TestStore {
-        routes: HashMap<String, Uuid>,
+        routes: PrefixMap<Uuid>,
+        fallback_routes: PrefixMap<Vec<Uuid>>,
     }
 
     #[async_trait]
@@ -224,16 +297,21 @@ mod tests {
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
+        fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
             Arc::new(self.routes.clone())
         }
+
+        fn fallback_routes(&self) -> Arc<PrefixMap<Vec<Uuid>>> {
+            Arc::new(self.fallback_routes.clone())
+        }
     }
 
     #[tokio::test]
     async fn empty_routing_table() {
         let mut router = Router::new(
             TestStore {
-                routes: HashMap::new(),
+                routes: PrefixMap::new(),
+                fallback_routes: PrefixMap::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -264,9 +342,10 @@ mod tests {
     async fn no_route() {
         let mut router = Router::new(
             TestStore {
-                routes: HashMap::from_iter(
+                routes: PrefixMap::from_iter(
                     vec![("example.other".to_string(), Uuid::new_v4())].into_iter(),
                 ),
+                fallback_routes: PrefixMap::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -297,9 +376,10 @@ mod tests {
     async fn finds_exact_route() {
         let mut router = Router::new(
             TestStore {
-                routes: HashMap::from_iter(
+                routes: PrefixMap::from_iter(
                     vec![("example.destination".to_string(), Uuid::new_v4())].into_iter(),
                 ),
+                fallback_routes: PrefixMap::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -330,7 +410,8 @@ mod tests {
     async fn catch_all_route() {
         let mut router = Router::new(
             TestStore {
-                routes: HashMap::from_iter(vec![(String::new(), Uuid::new_v4())].into_iter()),
+                routes: PrefixMap::from_iter(vec![(String::new(), Uuid::new_v4())].into_iter()),
+                fallback_routes: PrefixMap::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -361,9 +442,10 @@ mod tests {
     async fn finds_matching_prefix() {
         let mut router = Router::new(
             TestStore {
-                routes: HashMap::from_iter(
+                routes: PrefixMap::from_iter(
                     vec![("example.".to_string(), Uuid::new_v4())].into_iter(),
                 ),
+                fallback_routes: PrefixMap::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -390,6 +472,40 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn does_not_match_partial_segment() {
+        let mut router = Router::new(
+            TestStore {
+                routes: PrefixMap::from_iter(
+                    vec![("example.dest".to_string(), Uuid::new_v4())].into_iter(),
+                ),
+                fallback_routes: PrefixMap::new(),
+            },
+            outgoing_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let result = router
+            .handle_request(IncomingRequest {
+                from: TestAccount(Uuid::new_v4()),
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: &[],
+                }
+                .build(),
+            })
+            .await;
+        assert!(result.is_err());
+    }
+
     #[tokio::test]
     async fn finds_longest_matching_prefix() {
         let id0 = Uuid::from_slice(&[0; 16]).unwrap();
@@ -399,7 +515,7 @@ mod tests {
         let to_clone = to.clone();
         let mut router = Router::new(
             TestStore {
-                routes: HashMap::from_iter(
+                routes: PrefixMap::from_iter(
                     vec![
                         (String::new(), id0),
                         ("example.destination".to_string(), id2),
@@ -407,6 +523,7 @@ mod tests {
                     ]
                     .into_iter(),
                 ),
+                fallback_routes: PrefixMap::new(),
             },
             outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
                 *to_clone.lock() = Some(request.to);
@@ -435,4 +552,180 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(to.lock().take().unwrap().0, id2);
     }
+
+    fn unreachable_unless(
+        reachable: Uuid,
+        to: Arc<Mutex<Vec<Uuid>>>,
+    ) -> impl OutgoingService<TestAccount> + Clone {
+        outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+            to.lock().push(request.to.0);
+            if request.to.0 == reachable {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            } else {
+                Err(RejectBuilder {
+                    code: ErrorCode::T01_PEER_UNREACHABLE,
+                    message: &[],
+                    triggered_by: Some(request.to.ilp_address()),
+                    data: &[],
+                }
+                .build())
+            }
+        })
+    }
+
+    fn prepare_for(destination: &str, expires_at: SystemTime) -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount(Uuid::new_v4()),
+            prepare: PrepareBuilder {
+                destination: Address::from_str(destination).unwrap(),
+                amount: 100,
+                execution_condition: &[1; 32],
+                expires_at,
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_fallback_route() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let to = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            TestStore {
+                routes: PrefixMap::from_iter(vec![("example.".to_string(), id1)].into_iter()),
+                fallback_routes: PrefixMap::from_iter(
+                    vec![("example.".to_string(), vec![id1, id2])].into_iter(),
+                ),
+            },
+            unreachable_unless(id2, to.clone()),
+        );
+
+        let result = router
+            .handle_request(prepare_for(
+                "example.destination",
+                SystemTime::now() + Duration::from_secs(30),
+            ))
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(*to.lock(), vec![id1, id2]);
+        assert_eq!(router.peer_health().consecutive_failures(id1), 1);
+        assert_eq!(router.peer_health().consecutive_failures(id2), 0);
+    }
+
+    #[tokio::test]
+    async fn returns_last_reject_if_all_next_hops_unreachable() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let to = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            TestStore {
+                routes: PrefixMap::from_iter(vec![("example.".to_string(), id1)].into_iter()),
+                fallback_routes: PrefixMap::from_iter(
+                    vec![("example.".to_string(), vec![id2])].into_iter(),
+                ),
+            },
+            unreachable_unless(Uuid::new_v4(), to.clone()),
+        );
+
+        let result = router
+            .handle_request(prepare_for(
+                "example.destination",
+                SystemTime::now() + Duration::from_secs(30),
+            ))
+            .await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_eq!(*to.lock(), vec![id1, id2]);
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_if_downstream_peer_unreachable() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let to = Arc::new(Mutex::new(Vec::new()));
+        let to_clone = to.clone();
+        let mut router = Router::new(
+            TestStore {
+                routes: PrefixMap::from_iter(vec![("example.".to_string(), id1)].into_iter()),
+                fallback_routes: PrefixMap::from_iter(
+                    vec![("example.".to_string(), vec![id2])].into_iter(),
+                ),
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                to_clone.lock().push(request.to.0);
+                Err(RejectBuilder {
+                    code: ErrorCode::T01_PEER_UNREACHABLE,
+                    message: &[],
+                    triggered_by: Some(&Address::from_str("example.downstream").unwrap()),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let result = router
+            .handle_request(prepare_for(
+                "example.destination",
+                SystemTime::now() + Duration::from_secs(30),
+            ))
+            .await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_eq!(*to.lock(), vec![id1]);
+        assert_eq!(router.peer_health().consecutive_failures(id1), 0);
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_expired_packets() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let to = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new(
+            TestStore {
+                routes: PrefixMap::from_iter(vec![("example.".to_string(), id1)].into_iter()),
+                fallback_routes: PrefixMap::from_iter(
+                    vec![("example.".to_string(), vec![id2])].into_iter(),
+                ),
+            },
+            unreachable_unless(id2, to.clone()),
+        );
+
+        let result = router
+            .handle_request(prepare_for("example.destination", UNIX_EPOCH))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(*to.lock(), vec![id1]);
+    }
+
+    #[tokio::test]
+    async fn tries_unhealthy_peers_last() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let to = Arc::new(Mutex::new(Vec::new()));
+        let health = PeerHealth::new(1, Duration::from_secs(60));
+        health.record_failure(id1);
+        let mut router = Router::new(
+            TestStore {
+                routes: PrefixMap::from_iter(vec![("example.".to_string(), id1)].into_iter()),
+                fallback_routes: PrefixMap::from_iter(
+                    vec![("example.".to_string(), vec![id2])].into_iter(),
+                ),
+            },
+            unreachable_unless(id2, to.clone()),
+        )
+        .with_peer_health(health);
+
+        let result = router
+            .handle_request(prepare_for(
+                "example.destination",
+                SystemTime::now() + Duration::from_secs(30),
+            ))
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(*to.lock(), vec![id2]);
+    }
 }
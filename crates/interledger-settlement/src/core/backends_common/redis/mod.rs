@@ -8,9 +8,10 @@ use futures::TryFutureExt;
 use http::StatusCode;
 use interledger_errors::{IdempotentStoreError, LeftoversStoreError};
 use num_bigint::BigUint;
+use once_cell::sync::Lazy;
 use redis_crate::{
     self, aio::MultiplexedConnection, AsyncCommands, Client, ConnectionInfo, ErrorKind,
-    FromRedisValue, RedisError, RedisWrite, ToRedisArgs, Value,
+    FromRedisValue, RedisError, RedisWrite, Script, ToRedisArgs, Value,
 };
 use std::collections::HashMap;
 use std::str::FromStr;
@@ -30,6 +31,21 @@ fn uncredited_amount_key(account_id: &str) -> String {
     format!("{}:{}", UNCREDITED_AMOUNT_KEY, account_id)
 }
 
+/// Lua script which saves idempotent data unless the idempotency key is already taken.
+/// Returns 1 if the data was saved
+static RESERVE_IDEMPOTENT_DATA: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r#"
+if redis.call('EXISTS', KEYS[1]) == 1 then
+    return 0
+end
+redis.call('HMSET', KEYS[1], 'status_code', ARGV[1], 'data', ARGV[2], 'input_hash', ARGV[3])
+redis.call('EXPIRE', KEYS[1], 86400)
+return 1
+"#,
+    )
+});
+
 /// Builder object to create a Redis connection for the engine
 pub struct EngineRedisStoreBuilder {
     redis_url: ConnectionInfo,
@@ -127,6 +143,41 @@ impl IdempotentStore for EngineRedisStore {
         );
         Ok(())
     }
+
+    async fn reserve_idempotent_data(
+        &self,
+        idempotency_key: String,
+        input_hash: [u8; 32],
+        status_code: StatusCode,
+        data: Bytes,
+    ) -> Result<bool, IdempotentStoreError> {
+        let mut connection = self.connection.clone();
+        let reserved: bool = RESERVE_IDEMPOTENT_DATA
+            .key(&idempotency_key)
+            .arg(status_code.as_u16())
+            .arg(data.as_ref())
+            .arg(&input_hash)
+            .invoke_async(&mut connection)
+            .await?;
+        trace!(
+            "Reserved {:?}: {}, {:?}, {:?}",
+            idempotency_key,
+            reserved,
+            status_code,
+            data,
+        );
+        Ok(reserved)
+    }
+
+    async fn delete_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Result<(), IdempotentStoreError> {
+        let mut connection = self.connection.clone();
+        connection.del::<_, ()>(&idempotency_key).await?;
+        trace!("Deleted idempotency key {:?}", idempotency_key);
+        Ok(())
+    }
 }
 
 /// Helper datatype for storing and loading quantities of a number with different scales
@@ -342,5 +393,36 @@ mod tests {
                 .unwrap();
             assert!(data2.is_none());
         }
+
+        #[tokio::test]
+        async fn reserves_idempotency_key_only_once() {
+            let (store, _context) = test_store().await.unwrap();
+            let input_hash: [u8; 32] = Default::default();
+            let reserve = |data: &'static str| {
+                store.reserve_idempotent_data(
+                    IDEMPOTENCY_KEY.clone(),
+                    input_hash,
+                    StatusCode::ACCEPTED,
+                    Bytes::from(data),
+                )
+            };
+            assert!(reserve("FIRST").await.unwrap());
+            assert!(!reserve("SECOND").await.unwrap());
+            assert_eq!(
+                store
+                    .load_idempotent_data(IDEMPOTENCY_KEY.clone())
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                IdempotentData::new(StatusCode::ACCEPTED, Bytes::from("FIRST"), input_hash)
+            );
+
+            // The key can be reserved again once it is deleted
+            store
+                .delete_idempotent_data(IDEMPOTENCY_KEY.clone())
+                .await
+                .unwrap();
+            assert!(reserve("THIRD").await.unwrap());
+        }
     }
 }
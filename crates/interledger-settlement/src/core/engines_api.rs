@@ -281,6 +281,32 @@ mod tests {
             );
             Ok(())
         }
+
+        async fn reserve_idempotent_data(
+            &self,
+            idempotency_key: String,
+            input_hash: [u8; 32],
+            status_code: StatusCode,
+            data: Bytes,
+        ) -> Result<bool, IdempotentStoreError> {
+            let mut cache = self.cache.write();
+            if cache.contains_key(&idempotency_key) {
+                return Ok(false);
+            }
+            cache.insert(
+                idempotency_key,
+                IdempotentData::new(status_code, data, input_hash),
+            );
+            Ok(true)
+        }
+
+        async fn delete_idempotent_data(
+            &self,
+            idempotency_key: String,
+        ) -> Result<(), IdempotentStoreError> {
+            self.cache.write().remove(&idempotency_key);
+            Ok(())
+        }
     }
 
     pub static IDEMPOTENCY: &str = "abcd01234";
@@ -50,12 +50,29 @@ pub trait IdempotentStore {
         status_code: StatusCode,
         data: Bytes,
     ) -> Result<(), IdempotentStoreError>;
+
+    /// Saves the data like `save_idempotent_data`, but only if nothing is saved under the
+    /// idempotency key yet, as a single atomic operation
+    /// Returns false if the idempotency key is already taken
+    async fn reserve_idempotent_data(
+        &self,
+        idempotency_key: String,
+        input_hash: [u8; 32],
+        status_code: StatusCode,
+        data: Bytes,
+    ) -> Result<bool, IdempotentStoreError>;
+
+    /// Removes the data saved under the idempotency key, so that the request can be made again
+    async fn delete_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Result<(), IdempotentStoreError>;
 }
 
 /// Helper function that returns any idempotent data that corresponds to a
 /// provided idempotency key. It fails if the hash of the input that
 /// generated the idempotent data does not match the hash of the provided input.
-async fn check_idempotency<S>(
+pub async fn check_idempotency<S>(
     store: S,
     idempotency_key: String,
     input_hash: [u8; 32],
@@ -86,6 +103,28 @@ where
     }
 }
 
+/// Turns the response returned by `check_idempotency` back into the result of the original call
+pub fn idempotent_result(
+    status_code: StatusCode,
+    data: Bytes,
+) -> Result<(StatusCode, Bytes), ApiError> {
+    if status_code.is_success() {
+        // Return an OK response if the idempotent call was successful
+        Ok((status_code, data))
+    } else {
+        // Return an HTTP Error otherwise
+        let err_msg = ApiErrorType {
+            r#type: &ProblemType::Default,
+            status: status_code,
+            title: "Idempotency Error",
+        };
+        // if check_idempotency returns an error, then it
+        // has to be an idempotency error
+        Err(ApiError::from_api_error_type(&err_msg)
+            .detail(String::from_utf8_lossy(&data).to_string()))
+    }
+}
+
 // make_idempotent_call takes a function instead of direct arguments so that we
 // can reuse it for both the messages and the settlements calls
 pub async fn make_idempotent_call<S>(
@@ -105,24 +144,7 @@ where
     if let Some(idempotency_key) = idempotency_key {
         // If there an idempotency key was provided, check idempotency
         match check_idempotency(store.clone(), idempotency_key.clone(), input_hash).await? {
-            Some(ret) => {
-                if ret.0.is_success() {
-                    // Return an OK response if the idempotent call was successful
-                    Ok((ret.0, ret.1))
-                } else {
-                    // Return an HTTP Error otherwise
-                    let err_msg = ApiErrorType {
-                        r#type: &ProblemType::Default,
-                        status: ret.0,
-                        title: "Idempotency Error",
-                    };
-                    // if check_idempotency returns an error, then it
-                    // has to be an idempotency error
-                    let ret_error = ApiError::from_api_error_type(&err_msg)
-                        .detail(String::from_utf8_lossy(&ret.1).to_string());
-                    Err(ret_error)
-                }
-            }
+            Some((status_code, data)) => idempotent_result(status_code, data),
             None => {
                 // If there was no previous entry, make the idempotent call and save it
                 // Note: The error is also saved idempotently
@@ -2,7 +2,11 @@ use super::{Error, SpspResponse};
 use futures::TryFutureExt;
 use interledger_rates::ExchangeRateStore;
 use interledger_service::{Account, IncomingService};
-use interledger_stream::{send_money, StreamDelivery};
+use interledger_stream::{
+    send_money_at_rate, send_money_fixed_delivery, send_money_with_options, CongestionStrategy,
+    Error as StreamError, SendMoneyOptions, StreamDelivery, StreamQuote, StreamingPaymentHandle,
+    StreamingRate,
+};
 use reqwest::Client;
 use tracing::{debug, error, trace};
 
@@ -39,6 +43,37 @@ pub async fn pay<I, A, S>(
     source_amount: u64,
     slippage: f64,
 ) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    pay_with_options(
+        service,
+        from_account,
+        store,
+        receiver,
+        source_amount,
+        SendMoneyOptions {
+            slippage,
+            ..SendMoneyOptions::default()
+        },
+    )
+    .await
+}
+
+/// Query the details of the given Payment Pointer and send a payment using the STREAM protocol,
+/// configured by the given options (such as the congestion control to pace it with).
+///
+/// This returns the amount delivered, as reported by the receiver and in the receiver's asset's units.
+pub async fn pay_with_options<I, A, S>(
+    service: I,
+    from_account: A,
+    store: S,
+    receiver: &str,
+    source_amount: u64,
+    options: SendMoneyOptions,
+) -> Result<StreamDelivery, Error>
 where
     I: IncomingService<A> + Clone + Send + Sync + 'static,
     A: Account + Send + Sync + 'static,
@@ -49,18 +84,67 @@ where
     let addr = spsp.destination_account;
     debug!("Sending SPSP payment to address: {}", addr);
 
-    let receipt = send_money(
+    let receipt = send_money_with_options(
         service,
         &from_account,
         store,
         addr,
         shared_secret,
         source_amount,
+        options,
+    )
+    .map_err(move |err| {
+        error!("Error sending payment: {:?}", err);
+        send_money_error(err, source_amount)
+    })
+    .await?;
+
+    debug!("Sent SPSP payment. StreamDelivery: {:?}", receipt);
+    Ok(receipt)
+}
+
+/// Query the details of the given Payment Pointer and send a payment using the STREAM protocol
+/// so that the receiver gets exactly the given destination amount, in the receiver's asset's units.
+///
+/// At most the given maximum source amount is sent. This returns the amounts sent and delivered.
+#[allow(clippy::too_many_arguments)]
+pub async fn pay_fixed_delivery<I, A, S>(
+    service: I,
+    from_account: A,
+    store: S,
+    receiver: &str,
+    destination_amount: u64,
+    max_source_amount: u64,
+    slippage: f64,
+    congestion_strategy: CongestionStrategy,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let spsp = query(receiver).await?;
+    let shared_secret = spsp.shared_secret;
+    let addr = spsp.destination_account;
+    debug!(
+        "Sending SPSP payment to deliver {} to address: {}",
+        destination_amount, addr
+    );
+
+    let receipt = send_money_fixed_delivery(
+        service,
+        &from_account,
+        store,
+        addr,
+        shared_secret,
+        destination_amount,
+        max_source_amount,
         slippage,
+        congestion_strategy,
     )
     .map_err(move |err| {
         error!("Error sending payment: {:?}", err);
-        Error::SendMoneyError(source_amount)
+        send_money_error(err, max_source_amount)
     })
     .await?;
 
@@ -68,6 +152,102 @@ where
     Ok(receipt)
 }
 
+/// Query the details of the given Payment Pointer and send money at the given rate over a single
+/// STREAM connection, until the payment is stopped through its handle or reaches the rate's limits.
+///
+/// This returns the cumulative amounts sent and delivered.
+#[allow(clippy::too_many_arguments)]
+pub async fn pay_at_rate<I, A, S>(
+    service: I,
+    from_account: A,
+    store: S,
+    receiver: &str,
+    rate: StreamingRate,
+    slippage: f64,
+    congestion_strategy: CongestionStrategy,
+    handle: StreamingPaymentHandle,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let spsp = match query(receiver).await {
+        Ok(spsp) => spsp,
+        Err(err) => {
+            // The payment never started, so it shouldn't look like it's still streaming
+            handle.fail(err.to_string());
+            return Err(err);
+        }
+    };
+    let shared_secret = spsp.shared_secret;
+    let addr = spsp.destination_account;
+    debug!(
+        "Streaming SPSP payment of {} per second to address: {}",
+        rate.amount_per_second, addr
+    );
+
+    let receipt = send_money_at_rate(
+        service,
+        &from_account,
+        store,
+        addr,
+        shared_secret,
+        rate,
+        slippage,
+        congestion_strategy,
+        handle,
+    )
+    .map_err(move |err| {
+        error!("Error streaming payment: {:?}", err);
+        send_money_error(err, rate.max_amount)
+    })
+    .await?;
+
+    debug!("Streamed SPSP payment. StreamDelivery: {:?}", receipt);
+    Ok(receipt)
+}
+
+/// Query the details of the given Payment Pointer and probe the path to the receiver
+/// to quote how much they would get for the given source amount.
+///
+/// No money is sent: the quote uses unfulfillable test packets.
+pub async fn quote<I, A>(
+    service: I,
+    from_account: A,
+    receiver: &str,
+    source_amount: u64,
+) -> Result<StreamQuote, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+{
+    let spsp = query(receiver).await?;
+    let shared_secret = spsp.shared_secret;
+    let addr = spsp.destination_account;
+    debug!("Quoting SPSP payment to address: {}", addr);
+
+    let quote =
+        interledger_stream::quote(service, &from_account, addr, shared_secret, source_amount)
+            .map_err(move |err| {
+                error!("Error quoting payment: {:?}", err);
+                Error::StreamError(err)
+            })
+            .await?;
+
+    debug!("Quoted SPSP payment: {:?}", quote);
+    Ok(quote)
+}
+
+/// Keep the amounts sent and delivered before a payment stopped, so they can be recorded and
+/// the payment resumed
+fn send_money_error(err: StreamError, amount: u64) -> Error {
+    match err {
+        StreamError::IncompletePayment(_) => Error::StreamError(err),
+        _ => Error::SendMoneyError(amount),
+    }
+}
+
 fn payment_pointer_to_url(payment_pointer: &str) -> String {
     let mut url: String = if payment_pointer.starts_with('$') {
         let mut url = "https://".to_string();
@@ -14,7 +14,7 @@ mod client;
 /// An SPSP Server implementing an HTTP Service which generates ILP Addresses and Shared Secrets
 mod server;
 
-pub use client::{pay, query};
+pub use client::{pay, pay_at_rate, pay_fixed_delivery, pay_with_options, query, quote};
 pub use server::SpspResponder;
 
 #[derive(Debug, thiserror::Error)]
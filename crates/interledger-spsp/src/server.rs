@@ -1,8 +1,8 @@
-use super::SpspResponse;
+use super::{Error as SpspError, SpspResponse};
 use bytes::Bytes;
-use hyper::{service::Service as HttpService, Body, Error, Request, Response};
+use hyper::{service::Service as HttpService, Body, Error, HeaderMap, Request, Response};
 use interledger_packet::Address;
-use interledger_stream::ConnectionGenerator;
+use interledger_stream::{ConnectionGenerator, ReceiptDetails};
 use std::error::Error as StdError;
 use std::{
     fmt, str,
@@ -33,9 +33,64 @@ impl SpspResponder {
     /// and shared secret for this connection
     /// These fields are generated via [Stream's `ConnectionGenerator`](../interledger_stream/struct.ConnectionGenerator.html#method.generate_address_and_secret)
     pub fn generate_http_response(&self) -> Response<Body> {
-        let (destination_account, shared_secret) = self
+        self.spsp_response(None)
+    }
+
+    /// Like [`generate_http_response`](#method.generate_http_response), but if the request has
+    /// `Receipt-Nonce` and `Receipt-Secret` headers, the connection is set up so the receiver
+    /// issues [STREAM receipts](https://interledger.org/rfcs/0039-stream-receipts/) signed with them.
+    ///
+    /// Responds with 400 Bad Request if the receipt headers are invalid.
+    pub fn generate_http_response_from_headers(&self, headers: &HeaderMap) -> Response<Body> {
+        let nonce = headers.get("Receipt-Nonce");
+        let secret = headers.get("Receipt-Secret");
+        if nonce.is_none() && secret.is_none() {
+            return self.spsp_response(None);
+        }
+
+        let decode = |value: Option<&hyper::header::HeaderValue>| {
+            value
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| base64::decode(value).ok())
+        };
+        match (decode(nonce), decode(secret)) {
+            (Some(nonce), Some(secret)) => match ReceiptDetails::from_slices(&nonce, &secret) {
+                Some(receipt_details) => self.spsp_response(Some(&receipt_details)),
+                None => bad_request("Receipt-Nonce must be 16 bytes and Receipt-Secret 32 bytes"),
+            },
+            _ => bad_request("Receipt-Nonce and Receipt-Secret must both be base64 encoded"),
+        }
+    }
+
+    /// Generates the STREAM details for a connection with the given tag, such as the id of
+    /// an invoice, so that the money received over it can be attributed to what it pays for
+    pub fn generate_tagged_address_and_secret(
+        &self,
+        tag: &str,
+    ) -> Result<(Address, [u8; 32]), SpspError> {
+        let details = self
             .connection_generator
-            .generate_address_and_secret(&self.ilp_address);
+            .generate_tagged_address_and_secret(&self.ilp_address, tag, None)?;
+        Ok(details)
+    }
+
+    /// Rederives the shared secret of a connection whose details this responder generated
+    pub fn rederive_secret(&self, destination_account: &Address) -> [u8; 32] {
+        // The connection generator never fails to rederive secrets
+        self.connection_generator
+            .rederive_secret(destination_account)
+            .unwrap_or_default()
+    }
+
+    fn spsp_response(&self, receipt_details: Option<&ReceiptDetails>) -> Response<Body> {
+        let (destination_account, shared_secret) = match receipt_details {
+            Some(receipt_details) => self
+                .connection_generator
+                .generate_address_and_secret_with_receipts(&self.ilp_address, receipt_details),
+            None => self
+                .connection_generator
+                .generate_address_and_secret(&self.ilp_address),
+        };
         debug!(
             "Generated address and secret for: {:?}",
             destination_account
@@ -54,6 +109,13 @@ impl SpspResponder {
     }
 }
 
+fn bad_request(message: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(400)
+        .body(Body::from(message))
+        .unwrap()
+}
+
 impl HttpService<Request<Body>> for SpspResponder {
     type Response = Response<Body>;
     type Error = Error;
@@ -63,8 +125,8 @@ impl HttpService<Request<Body>> for SpspResponder {
         Ok(()).into()
     }
 
-    fn call(&mut self, _request: Request<Body>) -> Self::Future {
-        futures::future::ok(self.generate_http_response())
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        futures::future::ok(self.generate_http_response_from_headers(request.headers()))
     }
 }
 
@@ -113,4 +175,35 @@ mod spsp_server_test {
             "max-age=60"
         );
     }
+
+    #[tokio::test]
+    async fn spsp_response_with_receipt_headers() {
+        let addr = Address::from_str("example.receiver").unwrap();
+        let mut responder = SpspResponder::new(addr, Bytes::from(&[0; 32][..]));
+        let request = |nonce: &str| {
+            Request::builder()
+                .method("GET")
+                .uri("http://example.com")
+                .header("Accept", "application/spsp4+json")
+                .header("Receipt-Nonce", nonce)
+                .header("Receipt-Secret", base64::encode(&[1; 32][..]))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = responder
+            .call(request(&base64::encode(&[2; 16][..])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let response = responder
+            .call(request(&base64::encode(&[2; 15][..])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        let response = responder.call(request("not base64!")).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
 }
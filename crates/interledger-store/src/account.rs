@@ -10,6 +10,7 @@ use interledger_service_util::{
     MaxPacketAmountAccount, RateLimitAccount, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
 use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
+use interledger_stream::StreamReceiverAccount;
 use ring::aead;
 use secrecy::{ExposeSecret, SecretBytesMut, SecretString};
 use serde::Serializer;
@@ -79,6 +80,26 @@ pub struct Account {
     pub(crate) packets_per_minute_limit: Option<u32>,
     /// The maximum amount the account can send per minute
     pub(crate) amount_per_minute_limit: Option<u64>,
+    /// The minimum amount each STREAM packet paying the account must deliver
+    pub(crate) min_incoming_packet_amount: Option<u64>,
+    /// The minimum exchange rate at which STREAM packets paying the account may have been converted
+    pub(crate) min_incoming_exchange_rate: Option<f64>,
+    /// Prefixes we accept CCP routes for from the account (all if empty)
+    pub(crate) route_import_allow: Vec<String>,
+    /// Prefixes we never accept CCP routes for from the account
+    pub(crate) route_import_deny: Vec<String>,
+    /// Prefixes we advertise CCP routes for to the account (all if empty)
+    pub(crate) route_export_allow: Vec<String>,
+    /// Prefixes we never advertise CCP routes for to the account
+    pub(crate) route_export_deny: Vec<String>,
+    /// The maximum number of hops in routes accepted from the account
+    pub(crate) max_route_path_length: Option<u32>,
+    /// The maximum number of routes accepted from the account
+    pub(crate) max_imported_routes: Option<u32>,
+    /// Whether we drop unauthenticated routes from the account
+    pub(crate) strict_route_auth: bool,
+    /// The cost of sending packets through the account
+    pub(crate) route_cost: u32,
     /// The account's settlement engine URL. If a global engine url is configured
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
@@ -172,6 +193,16 @@ impl Account {
             round_trip_time: details.round_trip_time.unwrap_or(DEFAULT_ROUND_TRIP_TIME),
             packets_per_minute_limit: details.packets_per_minute_limit,
             amount_per_minute_limit: details.amount_per_minute_limit,
+            min_incoming_packet_amount: details.min_incoming_packet_amount,
+            min_incoming_exchange_rate: details.min_incoming_exchange_rate,
+            route_import_allow: details.route_import_allow.unwrap_or_default(),
+            route_import_deny: details.route_import_deny.unwrap_or_default(),
+            route_export_allow: details.route_export_allow.unwrap_or_default(),
+            route_export_deny: details.route_export_deny.unwrap_or_default(),
+            max_route_path_length: details.max_route_path_length,
+            max_imported_routes: details.max_imported_routes,
+            strict_route_auth: details.strict_route_auth.unwrap_or_default(),
+            route_cost: details.route_cost.unwrap_or_default(),
             settlement_engine_url,
         })
     }
@@ -331,6 +362,38 @@ impl CcpRoutingAccount for Account {
     fn routing_relation(&self) -> RoutingRelation {
         self.routing_relation
     }
+
+    fn route_import_allow(&self) -> &[String] {
+        &self.route_import_allow
+    }
+
+    fn route_import_deny(&self) -> &[String] {
+        &self.route_import_deny
+    }
+
+    fn route_export_allow(&self) -> &[String] {
+        &self.route_export_allow
+    }
+
+    fn route_export_deny(&self) -> &[String] {
+        &self.route_export_deny
+    }
+
+    fn max_route_path_length(&self) -> Option<u32> {
+        self.max_route_path_length
+    }
+
+    fn max_imported_routes(&self) -> Option<u32> {
+        self.max_imported_routes
+    }
+
+    fn strict_route_auth(&self) -> bool {
+        self.strict_route_auth
+    }
+
+    fn route_cost(&self) -> u32 {
+        self.route_cost
+    }
 }
 
 impl RoundTripTimeAccount for Account {
@@ -339,6 +402,16 @@ impl RoundTripTimeAccount for Account {
     }
 }
 
+impl StreamReceiverAccount for Account {
+    fn min_incoming_packet_amount(&self) -> u64 {
+        self.min_incoming_packet_amount.unwrap_or(0)
+    }
+
+    fn min_incoming_exchange_rate(&self) -> Option<f64> {
+        self.min_incoming_exchange_rate
+    }
+}
+
 impl RateLimitAccount for Account {
     fn amount_per_minute_limit(&self) -> Option<u64> {
         self.amount_per_minute_limit
@@ -384,6 +457,16 @@ mod test {
         round_trip_time: Some(600),
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
+        min_incoming_packet_amount: None,
+        min_incoming_exchange_rate: None,
+        route_import_allow: None,
+        route_import_deny: None,
+        route_export_allow: None,
+        route_export_deny: None,
+        max_route_path_length: None,
+        max_imported_routes: None,
+        strict_route_auth: None,
+        route_cost: None,
         settlement_engine_url: None,
     });
 
@@ -0,0 +1,14 @@
+local payment = 'incoming_payment:' .. ARGV[1] .. ':' .. ARGV[2]
+local amount = tonumber(ARGV[3])
+local expected_amount = tonumber(ARGV[4])
+
+if redis.call('EXISTS', payment) == 0 then
+    return 0
+end
+
+local received_amount = redis.call('HINCRBY', payment, 'received_amount', -amount)
+-- The incoming payment is no longer completed if the packet which completed it was rejected
+if received_amount < expected_amount then
+    redis.call('HDEL', payment, 'completed_at')
+end
+return 1
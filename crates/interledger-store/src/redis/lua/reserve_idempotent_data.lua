@@ -0,0 +1,9 @@
+local key = KEYS[1]
+
+-- Returns 0 if the idempotency key is already taken and 1 if the data was saved
+if redis.call('EXISTS', key) == 1 then
+    return 0
+end
+redis.call('HMSET', key, 'status_code', ARGV[1], 'data', ARGV[2], 'input_hash', ARGV[3])
+redis.call('EXPIRE', key, 86400)
+return 1
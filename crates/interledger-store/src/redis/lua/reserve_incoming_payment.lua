@@ -0,0 +1,25 @@
+local payment = 'incoming_payment:' .. ARGV[1] .. ':' .. ARGV[2]
+local amount = tonumber(ARGV[3])
+local expected_amount = tonumber(ARGV[4])
+local now = ARGV[5]
+
+-- Returns -1 if the incoming payment does not exist, 0 if it is completed,
+-- 1 if the amount exceeds what it still expects, 2 if the amount was reserved
+-- and 3 if the amount was reserved and completed the incoming payment
+local received_amount, completed_at = unpack(redis.call('HMGET', payment, 'received_amount', 'completed_at'))
+if not received_amount then
+    return -1
+end
+if completed_at then
+    return 0
+end
+if tonumber(received_amount) + amount > expected_amount then
+    return 1
+end
+
+received_amount = redis.call('HINCRBY', payment, 'received_amount', amount)
+if received_amount >= expected_amount then
+    redis.call('HSET', payment, 'completed_at', now)
+    return 3
+end
+return 2
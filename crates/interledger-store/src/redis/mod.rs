@@ -5,6 +5,7 @@
 //   rates:current          hash        exchange rates
 //   routes:current         hash        dynamic routing table
 //   routes:static          hash        static routing table
+//   routes:fallback        hash        ranked alternative next hops for dynamic routes
 //   accounts:<id>          hash        information for each account
 //   btp_outgoing
 // For interactive exploration of the store,
@@ -21,16 +22,20 @@ use super::account::{Account, AccountWithEncryptedTokens};
 use super::crypto::{encrypt_token, generate_keys, DecryptionKey, EncryptionKey};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
+use chrono::Utc;
 use futures::channel::mpsc::UnboundedSender;
 use http::StatusCode;
-use interledger_api::{AccountDetails, AccountSettings, EncryptedAccountSettings, NodeStore};
+use interledger_api::{
+    AccountDetails, AccountSettings, EncryptedAccountSettings, IncomingPayment, NodeStore,
+    OutgoingPayment,
+};
 use interledger_btp::BtpStore;
 use interledger_ccp::{CcpRoutingAccount, CcpRoutingStore, RoutingRelation};
 use interledger_errors::*;
 use interledger_http::HttpStore;
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{PrefixMap, RouterStore};
 use interledger_service::{Account as AccountTrait, AccountStore, AddressStore, Username};
 use interledger_service_util::{
     BalanceStore, RateLimitError, RateLimitStore, DEFAULT_ROUND_TRIP_TIME,
@@ -40,7 +45,9 @@ use interledger_settlement::core::{
     scale_with_precision_loss,
     types::{Convert, ConvertDetails, LeftoversStore, SettlementStore},
 };
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_stream::{
+    IncomingPaymentReservation, IncomingPaymentStore, PaymentNotification, StreamNotificationsStore,
+};
 use num_bigint::BigUint;
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
@@ -53,6 +60,7 @@ use secrecy::{ExposeSecret, Secret, SecretBytesMut};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
+    convert::TryFrom,
     fmt::Display,
 };
 use std::{
@@ -69,12 +77,14 @@ use uuid::Uuid;
 use zeroize::Zeroize;
 
 const DEFAULT_POLL_INTERVAL: u64 = 30000; // 30 seconds
-const ACCOUNT_DETAILS_FIELDS: usize = 21;
+const ACCOUNT_DETAILS_FIELDS: usize = 31;
 
 static PARENT_ILP_KEY: &str = "parent_node_account_address";
 static ROUTES_KEY: &str = "routes:current";
 static STATIC_ROUTES_KEY: &str = "routes:static";
 static DEFAULT_ROUTE_KEY: &str = "routes:default";
+static FALLBACK_ROUTES_KEY: &str = "routes:fallback";
+static ROUTING_STATE_KEY: &str = "routes:ccp_state";
 static STREAM_NOTIFICATIONS_PREFIX: &str = "stream_notifications:";
 static SETTLEMENT_ENGINES_KEY: &str = "settlement_engines";
 
@@ -93,6 +103,30 @@ fn accounts_key(account_id: Uuid) -> String {
     format!("accounts:{}", account_id)
 }
 
+/// Domain separator for the list of an account's outgoing payments
+fn outgoing_payments_key(account_id: Uuid) -> String {
+    format!("outgoing_payments:{}", account_id)
+}
+
+/// The start and stop indices to pass to LRANGE to get a page of a list,
+/// or `None` if the page is empty no matter how long the list is
+fn page_range(offset: usize, limit: usize) -> Option<(isize, isize)> {
+    let start = isize::try_from(offset).ok()?;
+    let last = isize::try_from(limit.checked_sub(1)?).unwrap_or_else(|_| isize::max_value());
+    Some((start, start.saturating_add(last)))
+}
+
+/// Domain separator for the list of an account's incoming payment ids
+fn incoming_payments_key(account_id: Uuid) -> String {
+    format!("incoming_payments:{}", account_id)
+}
+
+/// Domain separator for an incoming payment, which is a hash with its serialized details,
+/// the amount it received and when it was completed
+fn incoming_payment_key(account_id: Uuid, id: Uuid) -> String {
+    format!("incoming_payment:{}:{}", account_id, id)
+}
+
 // TODO: Add descriptive errors inside the lua scripts!
 
 // The following are Lua scripts that are used to atomically execute the given logic
@@ -136,6 +170,19 @@ static REFUND_SETTLEMENT: Lazy<Script> =
 static PROCESS_INCOMING_SETTLEMENT: Lazy<Script> =
     Lazy::new(|| Script::new(include_str!("lua/process_incoming_settlement.lua")));
 
+/// Lua script which reserves the amount of a packet on an incoming payment, unless it is
+/// completed or the amount exceeds what it still expects
+static RESERVE_INCOMING_PAYMENT: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("lua/reserve_incoming_payment.lua")));
+
+/// Lua script which gives back the amount reserved on an incoming payment for a rejected packet
+static RELEASE_INCOMING_PAYMENT: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("lua/release_incoming_payment.lua")));
+
+/// Lua script which saves idempotent data unless the idempotency key is already taken
+static RESERVE_IDEMPOTENT_DATA: Lazy<Script> =
+    Lazy::new(|| Script::new(include_str!("lua/reserve_idempotent_data.lua")));
+
 /// Builder for the Redis Store
 pub struct RedisStoreBuilder {
     redis_url: ConnectionInfo,
@@ -219,7 +266,7 @@ impl RedisStoreBuilder {
             subscriptions: Arc::new(RwLock::new(HashMap::new())),
             payment_publisher: all_payment_publisher,
             exchange_rates: Arc::new(RwLock::new(HashMap::new())),
-            routes: Arc::new(RwLock::new(Arc::new(HashMap::new()))),
+            routes: RoutesCache::default(),
             encryption_key: Arc::new(encryption_key),
             decryption_key: Arc::new(decryption_key),
         };
@@ -325,11 +372,7 @@ pub struct RedisStore {
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
     /// The store keeps the routing table in memory so that it can be returned
     /// synchronously while the Router is processing packets.
-    /// The outer `Arc<RwLock>` is used so that we can update the stored routing
-    /// table after polling the store for updates.
-    /// The inner `Arc<HashMap>` is used so that the `routing_table` method can
-    /// return a reference to the routing table without cloning the underlying data.
-    routes: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    routes: RoutesCache,
     /// Encryption Key so that the no cleartext data are stored
     encryption_key: Arc<Secret<EncryptionKey>>,
     /// Decryption Key to provide cleartext data to users
@@ -588,6 +631,11 @@ impl RedisStore {
     ) -> Result<AccountWithEncryptedTokens, NodeStoreError> {
         let encrypted = self.redis_get_account(id).await?;
         let account = &encrypted.account;
+        let incoming_payment_ids: Vec<String> = self
+            .connection
+            .clone()
+            .lrange(incoming_payments_key(id), 0, -1)
+            .await?;
         let mut pipe = redis_crate::pipe();
         pipe.atomic();
 
@@ -614,6 +662,16 @@ impl RedisStore {
         pipe.hdel(ROUTES_KEY, account.ilp_address.to_bytes().to_vec())
             .ignore();
 
+        pipe.del(outgoing_payments_key(id)).ignore();
+
+        for payment_id in incoming_payment_ids
+            .iter()
+            .filter_map(|payment_id| Uuid::from_str(payment_id).ok())
+        {
+            pipe.del(incoming_payment_key(id, payment_id)).ignore();
+        }
+        pipe.del(incoming_payments_key(id)).ignore();
+
         pipe.del(uncredited_amount_key(id));
 
         let mut connection = self.connection.clone();
@@ -938,8 +996,12 @@ impl HttpStore for RedisStore {
 }
 
 impl RouterStore for RedisStore {
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-        self.routes.read().clone()
+    fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+        self.routes.routing_table.read().clone()
+    }
+
+    fn fallback_routes(&self) -> Arc<PrefixMap<Vec<Uuid>>> {
+        self.routes.fallback_routes.read().clone()
     }
 }
 
@@ -1184,6 +1246,200 @@ impl NodeStore for RedisStore {
             Ok(None)
         }
     }
+
+    async fn save_outgoing_payment(
+        &self,
+        account_id: Uuid,
+        payment: OutgoingPayment,
+    ) -> Result<(), NodeStoreError> {
+        let serialized =
+            serde_json::to_string(&payment).map_err(|err| NodeStoreError::Other(Box::new(err)))?;
+        // Newest payments are kept at the head of the list
+        self.connection
+            .clone()
+            .lpush(outgoing_payments_key(account_id), serialized)
+            .await?;
+        trace!(
+            "Saved outgoing payment {} for account {}",
+            payment.id,
+            account_id
+        );
+        Ok(())
+    }
+
+    async fn get_outgoing_payments(
+        &self,
+        account_id: Uuid,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<OutgoingPayment>, NodeStoreError> {
+        let (start, stop) = match page_range(offset, limit) {
+            Some(range) => range,
+            None => return Ok(Vec::new()),
+        };
+        let payments: Vec<String> = self
+            .connection
+            .clone()
+            .lrange(outgoing_payments_key(account_id), start, stop)
+            .await?;
+        payments
+            .iter()
+            .map(|payment| {
+                serde_json::from_str(payment).map_err(|err| NodeStoreError::Other(Box::new(err)))
+            })
+            .collect()
+    }
+
+    async fn create_incoming_payment(
+        &self,
+        account_id: Uuid,
+        payment: IncomingPayment,
+    ) -> Result<(), NodeStoreError> {
+        let serialized =
+            serde_json::to_string(&payment).map_err(|err| NodeStoreError::Other(Box::new(err)))?;
+        let mut pipe = redis_crate::pipe();
+        pipe.atomic();
+        pipe.hset_multiple(
+            incoming_payment_key(account_id, payment.id),
+            &[
+                ("details", serialized),
+                ("received_amount", "0".to_string()),
+            ],
+        )
+        .ignore();
+        // Newest payments are kept at the head of the list
+        pipe.lpush(incoming_payments_key(account_id), payment.id.to_string())
+            .ignore();
+        pipe.query_async(&mut self.connection.clone()).await?;
+        trace!(
+            "Created incoming payment {} for account {}",
+            payment.id,
+            account_id
+        );
+        Ok(())
+    }
+
+    async fn get_incoming_payment(
+        &self,
+        account_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<IncomingPayment>, NodeStoreError> {
+        let fields: HashMap<String, String> = self
+            .connection
+            .clone()
+            .hgetall(incoming_payment_key(account_id, id))
+            .await?;
+        let details = match fields.get("details") {
+            Some(details) => details,
+            None => return Ok(None),
+        };
+        let mut payment: IncomingPayment =
+            serde_json::from_str(details).map_err(|err| NodeStoreError::Other(Box::new(err)))?;
+        payment.received_amount = fields
+            .get("received_amount")
+            .and_then(|amount| amount.parse().ok())
+            .unwrap_or_default();
+        payment.completed_at = fields.get("completed_at").cloned();
+        Ok(Some(payment))
+    }
+
+    async fn get_incoming_payments(
+        &self,
+        account_id: Uuid,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<IncomingPayment>, NodeStoreError> {
+        let (start, stop) = match page_range(offset, limit) {
+            Some(range) => range,
+            None => return Ok(Vec::new()),
+        };
+        let ids: Vec<String> = self
+            .connection
+            .clone()
+            .lrange(incoming_payments_key(account_id), start, stop)
+            .await?;
+        let mut payments = Vec::with_capacity(ids.len());
+        for id in ids {
+            let id = Uuid::from_str(&id).map_err(|err| NodeStoreError::Other(Box::new(err)))?;
+            if let Some(payment) = self.get_incoming_payment(account_id, id).await? {
+                payments.push(payment);
+            }
+        }
+        Ok(payments)
+    }
+}
+
+#[async_trait]
+impl IncomingPaymentStore for RedisStore {
+    async fn reserve_incoming_payment(
+        &self,
+        account_id: Uuid,
+        id: &str,
+        amount: u64,
+    ) -> Result<IncomingPaymentReservation, IncomingPaymentStoreError> {
+        // Connections may be tagged with anything, not just incoming payment ids
+        let id = match Uuid::from_str(id) {
+            Ok(id) => id,
+            Err(_) => return Ok(IncomingPaymentReservation::NotFound),
+        };
+        let payment = match self.get_incoming_payment(account_id, id).await? {
+            Some(payment) => payment,
+            None => return Ok(IncomingPaymentReservation::NotFound),
+        };
+        // Expiry only depends on the time, so it does not need to be checked atomically
+        if payment.remaining_amount() == 0 {
+            return Ok(IncomingPaymentReservation::Closed);
+        }
+
+        let result: i64 = RESERVE_INCOMING_PAYMENT
+            .arg(RedisAccountId(account_id))
+            .arg(id.to_string())
+            .arg(amount)
+            .arg(payment.amount)
+            .arg(Utc::now().to_rfc3339())
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        let reservation = match result {
+            -1 => IncomingPaymentReservation::NotFound,
+            0 => IncomingPaymentReservation::Closed,
+            1 => IncomingPaymentReservation::ExceedsRemaining,
+            completed => IncomingPaymentReservation::Reserved {
+                completed: completed == 3,
+            },
+        };
+        if reservation == (IncomingPaymentReservation::Reserved { completed: true }) {
+            debug!("Incoming payment {} was completed", id);
+        }
+        Ok(reservation)
+    }
+
+    async fn release_incoming_payment(
+        &self,
+        account_id: Uuid,
+        id: &str,
+        amount: u64,
+    ) -> Result<(), IncomingPaymentStoreError> {
+        let payment = match Uuid::from_str(id) {
+            Ok(uuid) => self.get_incoming_payment(account_id, uuid).await?,
+            Err(_) => None,
+        }
+        .ok_or_else(|| IncomingPaymentStoreError::IncomingPaymentNotFound(id.to_string()))?;
+
+        let released: bool = RELEASE_INCOMING_PAYMENT
+            .arg(RedisAccountId(account_id))
+            .arg(payment.id.to_string())
+            .arg(amount)
+            .arg(payment.amount)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        if !released {
+            return Err(IncomingPaymentStoreError::IncomingPaymentNotFound(
+                id.to_string(),
+            ));
+        }
+        trace!("Released {} reserved on incoming payment {}", amount, id);
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -1379,6 +1635,74 @@ impl CcpRoutingStore for RedisStore {
         update_routes(connection, self.routes.clone()).await?;
         Ok(())
     }
+
+    async fn update_fallback_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Vec<Account>)> + Send + 'async_trait,
+        removed_prefixes: impl IntoIterator<Item = String> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let routes: Vec<(String, Vec<Uuid>)> = routes
+            .into_iter()
+            .map(|(prefix, accounts)| {
+                let ids = accounts.iter().map(|account| account.id).collect();
+                (prefix, ids)
+            })
+            .collect();
+        let removed_prefixes: Vec<String> = removed_prefixes.into_iter().collect();
+        if routes.is_empty() && removed_prefixes.is_empty() {
+            return Ok(());
+        }
+        let mut connection = self.connection.clone();
+
+        // Fallback routes are stored as comma-separated account IDs, best first
+        let stored_routes: Vec<(&str, String)> = routes
+            .iter()
+            .map(|(prefix, ids)| {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                (prefix.as_str(), ids.join(","))
+            })
+            .collect();
+        let mut pipe = redis_crate::pipe();
+        pipe.atomic();
+        if !removed_prefixes.is_empty() {
+            pipe.hdel(FALLBACK_ROUTES_KEY, &removed_prefixes).ignore();
+        }
+        if !stored_routes.is_empty() {
+            pipe.hset_multiple(FALLBACK_ROUTES_KEY, &stored_routes)
+                .ignore();
+        }
+
+        pipe.query_async(&mut connection).await?;
+        trace!(
+            "Saved fallback routes for {} prefixes and removed them for {} prefixes in Redis",
+            routes.len(),
+            removed_prefixes.len()
+        );
+
+        // Apply the same changes to the in-memory copy rather than loading all the routes again
+        let mut fallback_routes = self.routes.fallback_routes.write();
+        let fallback_routes = Arc::make_mut(&mut fallback_routes);
+        for prefix in removed_prefixes.iter() {
+            fallback_routes.remove(prefix);
+        }
+        for (prefix, ids) in routes {
+            fallback_routes.insert(prefix, ids);
+        }
+        Ok(())
+    }
+
+    async fn set_routing_state(&mut self, state: Vec<u8>) -> Result<(), CcpRoutingStoreError> {
+        self.connection
+            .clone()
+            .set(ROUTING_STATE_KEY, state)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_routing_state(&self) -> Result<Option<Vec<u8>>, CcpRoutingStoreError> {
+        let state: Option<Vec<u8>> = self.connection.clone().get(ROUTING_STATE_KEY).await?;
+        Ok(state)
+    }
 }
 
 #[async_trait]
@@ -1533,6 +1857,42 @@ impl IdempotentStore for RedisStore {
         );
         Ok(())
     }
+
+    async fn reserve_idempotent_data(
+        &self,
+        idempotency_key: String,
+        input_hash: [u8; 32],
+        status_code: StatusCode,
+        data: Bytes,
+    ) -> Result<bool, IdempotentStoreError> {
+        let reserved: bool = RESERVE_IDEMPOTENT_DATA
+            .key(prefixed_idempotency_key(&idempotency_key))
+            .arg(status_code.as_u16())
+            .arg(data.as_ref())
+            .arg(&input_hash)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        trace!(
+            "Reserved {:?}: {}, {:?}, {:?}",
+            idempotency_key,
+            reserved,
+            status_code,
+            data,
+        );
+        Ok(reserved)
+    }
+
+    async fn delete_idempotent_data(
+        &self,
+        idempotency_key: String,
+    ) -> Result<(), IdempotentStoreError> {
+        let mut connection = self.connection.clone();
+        connection
+            .del::<_, ()>(prefixed_idempotency_key(&idempotency_key))
+            .await?;
+        trace!("Deleted idempotency key {:?}", idempotency_key);
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -1772,17 +2132,33 @@ type RouteVec = Vec<(String, RedisAccountId)>;
 
 use futures::future::TryFutureExt;
 
+/// In-memory copies of the routing tables stored in Redis.
+/// The outer `Arc<RwLock>` is used so that we can update the stored routing
+/// tables after polling the store for updates.
+/// The inner `Arc<PrefixMap>` is used so that the `routing_table` method can
+/// return a reference to the routing table without cloning the underlying data.
+#[derive(Clone, Default)]
+struct RoutesCache {
+    routing_table: Arc<RwLock<Arc<PrefixMap<Uuid>>>>,
+    fallback_routes: Arc<RwLock<Arc<PrefixMap<Vec<Uuid>>>>>,
+}
+
 // TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
 async fn update_routes(
     mut connection: RedisReconnect,
-    routing_table: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    routes_cache: RoutesCache,
 ) -> Result<(), RedisError> {
     let mut pipe = redis_crate::pipe();
     pipe.hgetall(ROUTES_KEY)
         .hgetall(STATIC_ROUTES_KEY)
-        .get(DEFAULT_ROUTE_KEY);
-    let (routes, static_routes, default_route): (RouteVec, RouteVec, Option<RedisAccountId>) =
-        pipe.query_async(&mut connection).await?;
+        .get(DEFAULT_ROUTE_KEY)
+        .hgetall(FALLBACK_ROUTES_KEY);
+    let (routes, static_routes, default_route, fallback_routes): (
+        RouteVec,
+        RouteVec,
+        Option<RedisAccountId>,
+        Vec<(String, String)>,
+    ) = pipe.query_async(&mut connection).await?;
     trace!(
         "Loaded routes from redis. Static routes: {:?}, default route: {:?}, other routes: {:?}",
         static_routes,
@@ -1794,7 +2170,7 @@ async fn update_routes(
     let default_route_iter = iter::once(default_route)
         .filter_map(|r| r)
         .map(|rid| (String::new(), rid.0));
-    let routes = HashMap::from_iter(
+    let routes = PrefixMap::from_iter(
         routes
             .into_iter()
             .map(|(s, rid)| (s, rid.0))
@@ -1804,10 +2180,19 @@ async fn update_routes(
             // any routes with the same prefix from the first set
             .chain(static_routes.into_iter().map(|(s, rid)| (s, rid.0))),
     );
+    // Fallback routes are stored as comma-separated account IDs, best first
+    let fallback_routes = PrefixMap::from_iter(fallback_routes.into_iter().map(|(prefix, ids)| {
+        let ids = ids
+            .split(',')
+            .filter_map(|id| Uuid::from_str(id).ok())
+            .collect();
+        (prefix, ids)
+    }));
     // TODO we may not want to print this because the routing table will be very big
     // if the node has a lot of local accounts
     trace!("Routing table is: {:?}", routes);
-    *routing_table.write() = Arc::new(routes);
+    *routes_cache.routing_table.write() = Arc::new(routes);
+    *routes_cache.fallback_routes.write() = Arc::new(fallback_routes);
     Ok(())
 }
 
@@ -1938,6 +2323,42 @@ impl ToRedisArgs for &AccountWithEncryptedTokens {
             "min_balance".write_redis_args(&mut rv);
             min_balance.write_redis_args(&mut rv);
         }
+        if let Some(min_amount) = account.min_incoming_packet_amount {
+            "min_incoming_packet_amount".write_redis_args(&mut rv);
+            min_amount.write_redis_args(&mut rv);
+        }
+        if let Some(min_rate) = account.min_incoming_exchange_rate {
+            "min_incoming_exchange_rate".write_redis_args(&mut rv);
+            min_rate.write_redis_args(&mut rv);
+        }
+        // Route policy prefix lists are stored as comma-separated strings
+        for (field, prefixes) in &[
+            ("route_import_allow", &account.route_import_allow),
+            ("route_import_deny", &account.route_import_deny),
+            ("route_export_allow", &account.route_export_allow),
+            ("route_export_deny", &account.route_export_deny),
+        ] {
+            if !prefixes.is_empty() {
+                field.write_redis_args(&mut rv);
+                prefixes.join(",").write_redis_args(&mut rv);
+            }
+        }
+        if let Some(max_length) = account.max_route_path_length {
+            "max_route_path_length".write_redis_args(&mut rv);
+            max_length.write_redis_args(&mut rv);
+        }
+        if let Some(max_routes) = account.max_imported_routes {
+            "max_imported_routes".write_redis_args(&mut rv);
+            max_routes.write_redis_args(&mut rv);
+        }
+        if account.strict_route_auth {
+            "strict_route_auth".write_redis_args(&mut rv);
+            true.write_redis_args(&mut rv);
+        }
+        if account.route_cost > 0 {
+            "route_cost".write_redis_args(&mut rv);
+            account.route_cost.write_redis_args(&mut rv);
+        }
         if let Some(settlement_engine_url) = &account.settlement_engine_url {
             "settlement_engine_url".write_redis_args(&mut rv);
             settlement_engine_url.as_str().write_redis_args(&mut rv);
@@ -2008,6 +2429,17 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 round_trip_time,
                 packets_per_minute_limit: get_value_option("packets_per_minute_limit", &hash)?,
                 amount_per_minute_limit: get_value_option("amount_per_minute_limit", &hash)?,
+                min_incoming_packet_amount: get_value_option("min_incoming_packet_amount", &hash)?,
+                min_incoming_exchange_rate: get_value_option("min_incoming_exchange_rate", &hash)?,
+                route_import_allow: get_list("route_import_allow", &hash)?,
+                route_import_deny: get_list("route_import_deny", &hash)?,
+                route_export_allow: get_list("route_export_allow", &hash)?,
+                route_export_deny: get_list("route_export_deny", &hash)?,
+                max_route_path_length: get_value_option("max_route_path_length", &hash)?,
+                max_imported_routes: get_value_option("max_imported_routes", &hash)?,
+                strict_route_auth: get_value_option("strict_route_auth", &hash)?
+                    .unwrap_or_default(),
+                route_cost: get_value_option("route_cost", &hash)?.unwrap_or_default(),
                 settlement_engine_url: get_url_option("settlement_engine_url", &hash)?,
             },
         })
@@ -2040,6 +2472,19 @@ where
     }
 }
 
+fn get_list(key: &str, map: &HashMap<String, Value>) -> Result<Vec<String>, RedisError> {
+    let value: Option<String> = get_value_option(key, map)?;
+    Ok(value
+        .map(|value| {
+            value
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
 fn get_bytes_option(
     key: &str,
     map: &HashMap<String, Value>,
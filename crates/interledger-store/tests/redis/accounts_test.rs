@@ -1,5 +1,8 @@
 use super::{fixtures::*, redis_helpers::*, store_helpers::*};
-use interledger_api::{AccountSettings, NodeStore};
+use interledger_api::{
+    AccountSettings, IncomingPayment, IncomingPaymentStatus, NodeStore, OutgoingPayment,
+    OutgoingPaymentStatus,
+};
 use interledger_btp::BtpAccount;
 use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
 use interledger_http::HttpAccount;
@@ -8,6 +11,7 @@ use interledger_service::Account as AccountTrait;
 use interledger_service::{AccountStore, AddressStore, Username};
 use interledger_service_util::BalanceStore;
 use interledger_store::redis::RedisStoreBuilder;
+use interledger_stream::{IncomingPaymentReservation, IncomingPaymentStore};
 use redis_crate::Client;
 use secrecy::ExposeSecret;
 use secrecy::SecretString;
@@ -344,3 +348,189 @@ async fn errors_for_unknown_accounts() {
         .unwrap_err();
     assert_eq!(err.to_string(), "wrong account length (expected 2, got 0)");
 }
+
+#[tokio::test]
+async fn saves_and_pages_outgoing_payments() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    for amount in 1..=5 {
+        store
+            .save_outgoing_payment(
+                id,
+                OutgoingPayment {
+                    id: Uuid::new_v4(),
+                    receiver: Some("$example.com/bob".to_string()),
+                    destination_account: Some(Address::from_str("example.bob.1234").unwrap()),
+                    source_asset_code: "XYZ".to_string(),
+                    source_asset_scale: 6,
+                    source_amount: amount,
+                    destination_amount: None,
+                    sent_amount: amount,
+                    delivered_amount: amount,
+                    destination_asset_code: Some("XYZ".to_string()),
+                    destination_asset_scale: Some(6),
+                    status: OutgoingPaymentStatus::Completed,
+                    error: None,
+                    started_at: "2020-01-01T00:00:00+00:00".to_string(),
+                    finished_at: "2020-01-01T00:00:01+00:00".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+    }
+
+    // Most recent payments come first
+    let payments = store.get_outgoing_payments(id, 0, 2).await.unwrap();
+    let amounts: Vec<u64> = payments.iter().map(|p| p.source_amount).collect();
+    assert_eq!(amounts, vec![5, 4]);
+
+    let payments = store.get_outgoing_payments(id, 4, 10).await.unwrap();
+    let amounts: Vec<u64> = payments.iter().map(|p| p.source_amount).collect();
+    assert_eq!(amounts, vec![1]);
+
+    // Pages past the end of the list or without any items are empty
+    let payments = store
+        .get_outgoing_payments(id, usize::max_value(), usize::max_value())
+        .await
+        .unwrap();
+    assert!(payments.is_empty());
+    let payments = store.get_outgoing_payments(id, 0, 0).await.unwrap();
+    assert!(payments.is_empty());
+    let payments = store
+        .get_outgoing_payments(id, 1, usize::max_value())
+        .await
+        .unwrap();
+    assert_eq!(payments.len(), 4);
+
+    // Other accounts' payments are kept separately
+    let payments = store
+        .get_outgoing_payments(accs[1].id(), 0, 10)
+        .await
+        .unwrap();
+    assert!(payments.is_empty());
+
+    // Payments are deleted with the account
+    store.delete_account(id).await.unwrap();
+    let payments = store.get_outgoing_payments(id, 0, 10).await.unwrap();
+    assert!(payments.is_empty());
+}
+
+#[tokio::test]
+async fn reserves_and_completes_incoming_payments() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    let payment = IncomingPayment {
+        id: Uuid::new_v4(),
+        destination_account: Address::from_str("example.alice.1234~invoice").unwrap(),
+        asset_code: "XYZ".to_string(),
+        asset_scale: 6,
+        amount: 100,
+        received_amount: 0,
+        description: Some("Invoice #1".to_string()),
+        created_at: "2020-01-01T00:00:00+00:00".to_string(),
+        expires_at: None,
+        completed_at: None,
+    };
+    store
+        .create_incoming_payment(id, payment.clone())
+        .await
+        .unwrap();
+    let payment_id = payment.id.to_string();
+
+    // Tags which aren't incoming payments of the account are ignored
+    assert_eq!(
+        store
+            .reserve_incoming_payment(accs[1].id(), &payment_id, 10)
+            .await
+            .unwrap(),
+        IncomingPaymentReservation::NotFound
+    );
+    assert_eq!(
+        store
+            .reserve_incoming_payment(id, "order-1", 10)
+            .await
+            .unwrap(),
+        IncomingPaymentReservation::NotFound
+    );
+
+    assert_eq!(
+        store
+            .reserve_incoming_payment(id, &payment_id, 60)
+            .await
+            .unwrap(),
+        IncomingPaymentReservation::Reserved { completed: false }
+    );
+    assert_eq!(
+        store
+            .reserve_incoming_payment(id, &payment_id, 50)
+            .await
+            .unwrap(),
+        IncomingPaymentReservation::ExceedsRemaining
+    );
+    assert_eq!(
+        store
+            .reserve_incoming_payment(id, &payment_id, 40)
+            .await
+            .unwrap(),
+        IncomingPaymentReservation::Reserved { completed: true }
+    );
+    assert_eq!(
+        store
+            .reserve_incoming_payment(id, &payment_id, 10)
+            .await
+            .unwrap(),
+        IncomingPaymentReservation::Closed
+    );
+
+    // Releasing the amount of the packet which completed the payment reopens it
+    store
+        .release_incoming_payment(id, &payment_id, 40)
+        .await
+        .unwrap();
+    let stored = store
+        .get_incoming_payment(id, payment.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.received_amount, 60);
+    assert_eq!(stored.status(), IncomingPaymentStatus::Pending);
+    assert!(store
+        .release_incoming_payment(id, "order-1", 40)
+        .await
+        .is_err());
+
+    assert_eq!(
+        store
+            .reserve_incoming_payment(id, &payment_id, 40)
+            .await
+            .unwrap(),
+        IncomingPaymentReservation::Reserved { completed: true }
+    );
+    let stored = store
+        .get_incoming_payment(id, payment.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.received_amount, 100);
+    assert_eq!(stored.status(), IncomingPaymentStatus::Completed);
+    let payments = store.get_incoming_payments(id, 0, 10).await.unwrap();
+    assert_eq!(payments, vec![stored]);
+    let payments = store
+        .get_incoming_payments(id, 0, usize::max_value())
+        .await
+        .unwrap();
+    assert_eq!(payments.len(), 1);
+    let payments = store
+        .get_incoming_payments(id, usize::max_value(), 10)
+        .await
+        .unwrap();
+    assert!(payments.is_empty());
+
+    // Incoming payments are deleted with the account
+    store.delete_account(id).await.unwrap();
+    assert!(store
+        .get_incoming_payment(id, payment.id)
+        .await
+        .unwrap()
+        .is_none());
+}
@@ -36,6 +36,16 @@ mod fixtures {
         round_trip_time: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(2),
+        min_incoming_packet_amount: None,
+        min_incoming_exchange_rate: None,
+        route_import_allow: None,
+        route_import_deny: None,
+        route_export_allow: None,
+        route_export_deny: None,
+        max_route_path_length: None,
+        max_imported_routes: None,
+        strict_route_auth: None,
+        route_cost: None,
         settlement_engine_url: Some("http://settlement.example".to_string()),
     });
     pub static ACCOUNT_DETAILS_1: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
@@ -58,6 +68,16 @@ mod fixtures {
         round_trip_time: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(20),
+        min_incoming_packet_amount: None,
+        min_incoming_exchange_rate: None,
+        route_import_allow: None,
+        route_import_deny: None,
+        route_export_allow: None,
+        route_export_deny: None,
+        max_route_path_length: None,
+        max_imported_routes: None,
+        strict_route_auth: None,
+        route_cost: None,
         settlement_engine_url: None,
     });
     pub static ACCOUNT_DETAILS_2: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
@@ -79,6 +99,16 @@ mod fixtures {
         round_trip_time: None,
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
+        min_incoming_packet_amount: None,
+        min_incoming_exchange_rate: None,
+        route_import_allow: None,
+        route_import_deny: None,
+        route_export_allow: None,
+        route_export_deny: None,
+        max_route_path_length: None,
+        max_imported_routes: None,
+        strict_route_auth: None,
+        route_cost: None,
         settlement_engine_url: None,
     });
 }
@@ -51,6 +51,16 @@ async fn polls_for_route_updates() {
             round_trip_time: None,
             amount_per_minute_limit: None,
             packets_per_minute_limit: None,
+            min_incoming_packet_amount: None,
+            min_incoming_exchange_rate: None,
+            route_import_allow: None,
+            route_import_deny: None,
+            route_export_allow: None,
+            route_export_deny: None,
+            max_route_path_length: None,
+            max_imported_routes: None,
+            strict_route_auth: None,
+            route_cost: None,
             settlement_engine_url: None,
         })
         .await
@@ -170,6 +180,84 @@ async fn saves_routes_to_db() {
     assert_eq!(routes.len(), 3);
 }
 
+#[tokio::test]
+async fn saves_fallback_routes_to_db() {
+    let (store, context, accs) = test_store().await.unwrap();
+    let get_connection = context.async_connection();
+
+    store
+        .clone()
+        .update_fallback_routes(
+            vec![
+                (
+                    "example.a".to_string(),
+                    vec![accs[0].clone(), accs[1].clone()],
+                ),
+                ("example.b".to_string(), vec![accs[1].clone()]),
+            ],
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+
+    let mut connection = get_connection.await.unwrap();
+    let routes: HashMap<String, String> = redis_crate::cmd("HGETALL")
+        .arg("routes:fallback")
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+    assert_eq!(
+        routes["example.a"],
+        format!("{},{}", accs[0].id(), accs[1].id())
+    );
+    assert_eq!(routes.len(), 2);
+
+    // local fallback routes are also updated
+    let routes = store.fallback_routes();
+    assert_eq!(routes["example.a"], vec![accs[0].id(), accs[1].id()]);
+    assert_eq!(routes.len(), 2);
+
+    // only the given prefixes are changed
+    store
+        .clone()
+        .update_fallback_routes(
+            vec![("example.a".to_string(), vec![accs[1].clone()])],
+            vec!["example.b".to_string()],
+        )
+        .await
+        .unwrap();
+    let routes: HashMap<String, String> = redis_crate::cmd("HGETALL")
+        .arg("routes:fallback")
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+    assert_eq!(routes["example.a"], accs[1].id().to_string());
+    assert_eq!(routes.len(), 1);
+    let routes = store.fallback_routes();
+    assert_eq!(routes["example.a"], vec![accs[1].id()]);
+    assert_eq!(routes.len(), 1);
+}
+
+#[tokio::test]
+async fn saves_and_loads_routing_state() {
+    let (store, _context, _accs) = test_store().await.unwrap();
+    assert_eq!(store.get_routing_state().await.unwrap(), None);
+
+    store
+        .clone()
+        .set_routing_state(vec![1, 2, 3])
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_routing_state().await.unwrap(),
+        Some(vec![1, 2, 3])
+    );
+
+    // saving the state again replaces it
+    store.clone().set_routing_state(vec![4]).await.unwrap();
+    assert_eq!(store.get_routing_state().await.unwrap(), Some(vec![4]));
+}
+
 #[tokio::test]
 async fn adds_static_routes_to_redis() {
     let (store, context, accs) = test_store().await.unwrap();
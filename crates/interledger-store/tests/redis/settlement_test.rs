@@ -79,6 +79,37 @@ async fn saves_and_loads_idempotency_key_data_properly() {
     assert!(data2.is_none());
 }
 
+#[tokio::test]
+async fn reserves_idempotency_key_only_once() {
+    let (store, _context, _) = test_store().await.unwrap();
+    let input_hash: [u8; 32] = Default::default();
+    let reserve = |data: &'static str| {
+        store.reserve_idempotent_data(
+            IDEMPOTENCY_KEY.clone(),
+            input_hash,
+            StatusCode::ACCEPTED,
+            Bytes::from(data),
+        )
+    };
+    assert!(reserve("FIRST").await.unwrap());
+    assert!(!reserve("SECOND").await.unwrap());
+    assert_eq!(
+        store
+            .load_idempotent_data(IDEMPOTENCY_KEY.clone())
+            .await
+            .unwrap()
+            .unwrap(),
+        IdempotentData::new(StatusCode::ACCEPTED, Bytes::from("FIRST"), input_hash)
+    );
+
+    // The key can be reserved again once it is deleted
+    store
+        .delete_idempotent_data(IDEMPOTENCY_KEY.clone())
+        .await
+        .unwrap();
+    assert!(reserve("THIRD").await.unwrap());
+}
+
 #[tokio::test]
 async fn idempotent_settlement_calls() {
     let (store, _context, accs) = test_store().await.unwrap();
@@ -1,9 +1,11 @@
-use super::congestion::CongestionController;
+use super::congestion::{CongestionControl, CongestionStrategy};
 use super::crypto::*;
 use super::error::Error;
 use super::packet::*;
+use super::receipt::decode_receipt;
 use bytes::Bytes;
 use bytes::BytesMut;
+use futures::channel::mpsc::UnboundedSender;
 use futures::stream::{FuturesUnordered, StreamExt};
 use interledger_packet::{
     Address, ErrorClass, ErrorCode as IlpErrorCode, PacketType as IlpPacketType, PrepareBuilder,
@@ -24,8 +26,10 @@ use tokio::time::{Duration, Instant};
 use tracing::{debug, error, warn};
 
 use std::cmp::{max, min};
+use std::fmt;
 use std::marker::{Send, Sync};
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -39,6 +43,16 @@ const FAIL_FAST_MINIMUM_PACKET_ATTEMPTS: u64 = 200;
 /// Minimum rate of rejected packets in order to terminate the payment
 const FAIL_FAST_MINIMUM_FAILURE_RATE: f64 = 0.99;
 
+/// Maximum number of bytes of application data to include in a single packet
+/// (ILP Prepare data is limited to 32767 bytes, which must also fit the other frames and encryption overhead)
+const MAX_DATA_PER_PACKET: usize = 16_384;
+
+/// Number of bytes we assume the recipient can buffer until they tell us their limit with a StreamMaxData frame
+const DEFAULT_REMOTE_MAX_DATA_OFFSET: u64 = 65_536;
+
+/// Slippage payments tolerate unless their options say otherwise
+const DEFAULT_SLIPPAGE: f64 = 0.015;
+
 /// Receipt for STREAM payment to account for how much and what assets were sent & delivered
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct StreamDelivery {
@@ -64,6 +78,10 @@ pub struct StreamDelivery {
     /// Receiver's asset code
     /// Updated after we received a `ConnectionAssetDetails` frame.
     pub destination_asset_code: Option<String>,
+    /// The latest [STREAM receipt](https://interledger.org/rfcs/0039-stream-receipts/) issued by
+    /// the receiver, base64-encoded, if the receiver was asked to issue receipts
+    #[serde(default)]
+    pub stream_receipt: Option<String>,
 }
 
 impl StreamDelivery {
@@ -79,14 +97,131 @@ impl StreamDelivery {
             destination_asset_scale: None,
             destination_asset_code: None,
             delivered_amount: 0,
+            stream_receipt: None,
+        }
+    }
+}
+
+/// How a single packet of a payment fared, along with the payment's running totals.
+/// Sent to the payment's [progress channel](./struct.SendMoneyOptions.html#structfield.progress)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentProgress {
+    /// Sequence number of the packet
+    pub sequence: u64,
+    /// Amount of the packet, in source units
+    pub source_amount: u64,
+    /// Amount the packet delivered, in destination units (zero if it was rejected)
+    pub delivered_amount: u64,
+    /// The code the packet was rejected with, if it was rejected
+    pub reject_code: Option<IlpErrorCode>,
+    /// The payment's amounts sent and delivered after this packet
+    pub delivery: StreamDelivery,
+}
+
+/// Gracefully stops a payment sent with [`send_money_with_options`](./fn.send_money_with_options.html).
+/// Once cancelled, no more packets are sent, the packets in flight are waited for and the
+/// connection is closed, and the payment returns the receipt of what was delivered.
+///
+/// Clones of the token cancel the same payment.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    is_cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.is_cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.is_cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Let a payment that was interrupted with this token send again
+    pub(crate) fn reset(&self) {
+        self.is_cancelled.store(false, Ordering::SeqCst);
+    }
+}
+
+/// How to send a payment with [`send_money_with_options`](./fn.send_money_with_options.html)
+#[derive(Clone, Debug)]
+pub struct SendMoneyOptions {
+    /// Maximum acceptable slippage percentage below the calculated minimum exchange rate
+    pub slippage: f64,
+    /// The congestion control algorithm to pace the payment with
+    pub congestion_strategy: CongestionStrategy,
+    /// Receives the outcome of every packet, with the payment's running totals
+    pub progress: Option<UnboundedSender<PaymentProgress>>,
+    /// Stops the payment once cancelled
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl Default for SendMoneyOptions {
+    fn default() -> Self {
+        SendMoneyOptions {
+            slippage: DEFAULT_SLIPPAGE,
+            congestion_strategy: CongestionStrategy::default(),
+            progress: None,
+            cancellation: None,
         }
     }
 }
 
+/// A payment that stopped before it completed, for example because it timed out or too many of
+/// its packets were rejected.
+///
+/// It keeps the connection's shared secret and everything learned about the path, such as the
+/// sequence number, congestion window and maximum packet amount, so that it can be
+/// [resumed](./fn.resume_payment.html) over the same connection for the remaining amount.
+pub struct IncompletePayment {
+    /// Why the payment stopped
+    error: Error,
+    /// The amounts sent and delivered when the payment stopped
+    delivery: StreamDelivery,
+    shared_secret: Bytes,
+    payment: Arc<Mutex<StreamPayment>>,
+}
+
+impl IncompletePayment {
+    /// Why the payment stopped
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// The amounts sent and delivered when the payment stopped.
+    /// Packets still in flight at that time are accounted for if the payment is resumed
+    pub fn delivery(&self) -> &StreamDelivery {
+        &self.delivery
+    }
+}
+
+impl fmt::Debug for IncompletePayment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IncompletePayment")
+            .field("error", &self.error)
+            .field("delivery", &self.delivery)
+            .finish()
+    }
+}
+
+impl fmt::Display for IncompletePayment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (delivered {} before stopping)",
+            self.error, self.delivery.delivered_amount
+        )
+    }
+}
+
 /// Stream payment mutable state: amounts & assets sent and received, sequence, packet counts, and flow control parameters
-struct StreamPayment {
-    /// The [congestion controller](./../congestion/struct.CongestionController.html) to adjust flow control and the in-flight amount
-    congestion_controller: CongestionController,
+pub(crate) struct StreamPayment {
+    /// The [congestion controller](./../congestion/trait.CongestionControl.html) to adjust flow control and the in-flight amount
+    congestion_controller: Box<dyn CongestionControl>,
     /// The [StreamDelivery](./struct.StreamDelivery.html) receipt to account for the delivered amounts
     receipt: StreamDelivery,
     /// Do we need to send our source account information to the recipient?
@@ -101,9 +236,111 @@ struct StreamPayment {
     fail_fast_rejects: u64,
     /// Timestamp when a packet was last fulfilled for this payment
     last_fulfill_time: Instant,
+    /// For fixed-delivery payments, the exact amount the recipient should receive, in destination units.
+    /// The source amount of the receipt is then the maximum amount we're willing to send
+    delivery_amount: Option<u64>,
+    /// Exchange rate the recipient reported in their reply to the last rejected packet
+    /// (destination units per source unit), used until packets are fulfilled
+    probed_rate: Option<BigRational>,
+    /// Did the recipient close the stream or the connection, so no more money can be sent?
+    closed_by_receiver: bool,
+    /// Application data to send to the recipient on the payment's stream
+    data: Bytes,
+    /// Offset up to which the recipient has acknowledged receiving the data
+    data_sent_offset: u64,
+    /// Whether a packet carrying data is in-flight. Data is sent one chunk at a time so it arrives in order
+    is_data_in_flight: bool,
+    /// Largest offset the recipient is willing to receive data up to, per their last StreamMaxData frame
+    remote_max_data_offset: u64,
+    /// Total received according to the latest STREAM receipt, since replies may arrive out of order
+    stream_receipt_total: u64,
 }
 
 impl StreamPayment {
+    fn new<A: Account>(
+        from_account: &A,
+        destination_account: Address,
+        source_amount: u64,
+        delivery_amount: Option<u64>,
+        congestion_strategy: CongestionStrategy,
+    ) -> Self {
+        StreamPayment {
+            congestion_controller: congestion_strategy.controller(source_amount),
+            receipt: StreamDelivery::new(from_account, destination_account, source_amount),
+            should_send_source_account: true,
+            sequence: 1,
+            fulfilled_packets: 0,
+            rejected_packets: 0,
+            fail_fast_rejects: 0,
+            last_fulfill_time: Instant::now(),
+            delivery_amount,
+            probed_rate: None,
+            closed_by_receiver: false,
+            data: Bytes::new(),
+            data_sent_offset: 0,
+            is_data_in_flight: false,
+            remote_max_data_offset: DEFAULT_REMOTE_MAX_DATA_OFFSET,
+            stream_receipt_total: 0,
+        }
+    }
+
+    /// Keep the receipt if it proves more was received than the latest one
+    fn apply_stream_receipt(&mut self, receipt: &[u8]) {
+        match decode_receipt(receipt) {
+            Ok(decoded) if decoded.total_received > self.stream_receipt_total => {
+                self.stream_receipt_total = decoded.total_received;
+                self.receipt.stream_receipt = Some(base64::encode(receipt));
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Ignoring invalid STREAM receipt: {}", err),
+        }
+    }
+
+    /// A payment whose source amount starts at zero and is raised over time, up to the given maximum
+    /// which the congestion controller is sized for
+    pub(crate) fn new_streaming<A: Account>(
+        from_account: &A,
+        destination_account: Address,
+        max_source_amount: u64,
+        congestion_strategy: CongestionStrategy,
+    ) -> Self {
+        let mut payment = StreamPayment::new(
+            from_account,
+            destination_account,
+            max_source_amount,
+            None,
+            congestion_strategy,
+        );
+        payment.receipt.source_amount = 0;
+        payment
+    }
+
+    /// Restart the timeout and the fail-fast threshold to resume a payment that stopped early,
+    /// keeping everything learned about the path
+    fn restart(&mut self) {
+        self.last_fulfill_time = Instant::now();
+        self.fulfilled_packets = 0;
+        self.rejected_packets = 0;
+        self.fail_fast_rejects = 0;
+    }
+
+    /// Raise the total amount to send, for payments whose amount grows over time
+    fn increase_source_amount(&mut self, source_amount: u64) {
+        if source_amount > self.receipt.source_amount {
+            // Time spent waiting for more money to send doesn't count towards the timeout
+            if self.is_complete() {
+                self.last_fulfill_time = Instant::now();
+            }
+            self.receipt.source_amount = source_amount;
+        }
+    }
+
+    /// Send the given application data on the payment's stream alongside the money
+    fn with_data(mut self, data: Bytes) -> Self {
+        self.data = data;
+        self
+    }
+
     /// Determine amount to load in next Prepare and account for it.
     /// Return the source packet amount and minimum destination amount
     #[inline]
@@ -174,13 +411,29 @@ impl StreamPayment {
         // (1) Amount available to send, subtracting fulfilled and in-fligth amounts
         source_amount = min(source_amount, self.get_amount_available_to_send());
 
+        // (0) For fixed-delivery payments, don't deliver more than the recipient still needs.
+        // Until the recipient tells us the exchange rate, the packet must be unfulfillable
+        // so that their reply tells us how much they would have received.
+        let mut is_probe = false;
+        if self.delivery_amount.is_some() {
+            match self.get_max_source_amount_for_delivery() {
+                Some(max_source_amount) => source_amount = min(source_amount, max_source_amount),
+                None => is_probe = true,
+            }
+        }
+
         // Account for the prepare
         self.congestion_controller.prepare(source_amount);
         self.receipt.sent_amount = self.receipt.sent_amount.saturating_add(source_amount);
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_add(source_amount);
 
         // Compute the minimum destination amount using the same rate
-        let min_destination_amount = convert(source_amount, rate).unwrap_or(0);
+        // (a minimum destination amount of 0 makes the packet unfulfillable)
+        let min_destination_amount = if is_probe {
+            0
+        } else {
+            convert(source_amount, rate).unwrap_or(0)
+        };
         (source_amount, min_destination_amount)
     }
 
@@ -253,10 +506,123 @@ impl StreamPayment {
             .saturating_sub(self.get_fulfilled_amount())
     }
 
+    /// Save the exchange rate the recipient reported for a rejected packet
+    #[inline]
+    fn set_probed_rate(&mut self, source_amount: u64, claimed_amount: u64) {
+        if source_amount > 0 {
+            self.probed_rate = Some(BigRational::new(
+                BigInt::from(claimed_amount),
+                BigInt::from(source_amount),
+            ));
+        }
+    }
+
+    /// Exchange rate observed from the amounts the recipient reported, in destination units per source unit.
+    /// Prefers the rate of all fulfilled packets, falling back to the rate of the last rejected packet.
+    #[inline]
+    fn get_observed_rate(&self) -> Option<BigRational> {
+        let fulfilled_amount = self.get_fulfilled_amount();
+        if fulfilled_amount > 0 {
+            Some(BigRational::new(
+                BigInt::from(self.receipt.delivered_amount),
+                BigInt::from(fulfilled_amount),
+            ))
+        } else {
+            self.probed_rate.clone()
+        }
+    }
+
+    /// For fixed-delivery payments, the largest source amount we can send without delivering more than
+    /// the recipient still needs, accounting for the amount in-flight.
+    /// Returns None if this isn't a fixed-delivery payment or if the exchange rate isn't known yet
+    fn get_max_source_amount_for_delivery(&self) -> Option<u64> {
+        let delivery_amount = self.delivery_amount?;
+        let rate = self.get_observed_rate().filter(|rate| !rate.is_zero())?;
+
+        // Estimate how much of the in-flight amount will be delivered, rounding down
+        // so we never assume the recipient gets more than they would
+        let in_flight_delivery = (BigRational::from_u64(self.receipt.in_flight_amount)? * &rate)
+            .floor()
+            .to_integer()
+            .to_u64()
+            .unwrap_or(u64::MAX);
+        let remaining_delivery = delivery_amount
+            .saturating_sub(self.receipt.delivered_amount)
+            .saturating_sub(in_flight_delivery);
+        if remaining_delivery == 0 {
+            return Some(0);
+        }
+
+        // Round down to avoid overpaying. If even a single source unit would deliver more
+        // than the recipient still needs, this is 0 and the remainder can't be delivered
+        let source_amount = (BigRational::from_u64(remaining_delivery)? / rate)
+            .floor()
+            .to_integer()
+            .to_u64()
+            .unwrap_or(u64::MAX);
+        Some(source_amount)
+    }
+
     /// Has the entire intended source amount been fulfilled by the recipient?
+    /// For fixed-delivery payments, has the recipient received the entire destination amount?
+    /// If there is data to send, has the recipient received all of it?
     #[inline]
     fn is_complete(&self) -> bool {
-        self.get_remaining_amount() == 0
+        let is_money_complete = match self.delivery_amount {
+            Some(delivery_amount) => self.receipt.delivered_amount >= delivery_amount,
+            None => self.get_remaining_amount() == 0,
+        };
+        is_money_complete && self.data_sent_offset >= self.data.len() as u64
+    }
+
+    /// Is there data the recipient is ready to receive that isn't in-flight already?
+    #[inline]
+    fn can_send_data(&self) -> bool {
+        !self.is_data_in_flight
+            && self.data_sent_offset < self.data.len() as u64
+            && self.data_sent_offset < self.remote_max_data_offset
+    }
+
+    /// Take the next chunk of data to send, limited by the recipient's flow control.
+    /// Returns the chunk's offset in the stream and its data
+    #[inline]
+    fn next_data_chunk(&mut self) -> Option<(u64, Bytes)> {
+        if !self.can_send_data() {
+            return None;
+        }
+        let start = self.data_sent_offset as usize;
+        let end = min(
+            min(self.data.len(), start + MAX_DATA_PER_PACKET),
+            self.remote_max_data_offset as usize,
+        );
+        self.is_data_in_flight = true;
+        Some((self.data_sent_offset, self.data.slice(start, end)))
+    }
+
+    /// Account for the reply to a packet carrying data ending at the given offset
+    #[inline]
+    fn apply_data_reply(&mut self, end_offset: u64, was_received: bool) {
+        self.is_data_in_flight = false;
+        if was_received {
+            self.data_sent_offset = max(self.data_sent_offset, end_offset);
+        }
+    }
+
+    /// For fixed-delivery payments, has the maximum source amount been fulfilled
+    /// without delivering the entire destination amount?
+    #[inline]
+    fn is_source_amount_exhausted(&self) -> bool {
+        self.delivery_amount.is_some() && !self.is_complete() && self.get_remaining_amount() == 0
+    }
+
+    /// For fixed-delivery payments, is the destination amount still owed smaller than what a single
+    /// source unit delivers at the observed exchange rate, with nothing in-flight?
+    #[inline]
+    fn is_remainder_undeliverable(&self) -> bool {
+        self.delivery_amount.is_some()
+            && !self.is_complete()
+            && self.receipt.in_flight_amount == 0
+            && self.get_max_source_amount_for_delivery() == Some(0)
     }
 
     /// Return the amount of money available to be sent in the payment (amount remaining minus in-flight)
@@ -275,6 +641,7 @@ impl StreamPayment {
     fn is_max_in_flight(&self) -> bool {
         self.congestion_controller.get_amount_left_in_window() == 0
             || self.get_amount_available_to_send() == 0
+            || self.get_max_source_amount_for_delivery() == Some(0)
     }
 
     /// Given we've attempted sending enough packets, does the rate of rejects
@@ -289,6 +656,9 @@ impl StreamPayment {
 
 /// Send the given source amount with packetized Interledger payments using the STREAM transport protocol
 /// Returns the receipt with sent & delivered amounts, asset & account details
+///
+/// The payment is paced with the default congestion control; use
+/// [`send_money_with_options`](./fn.send_money_with_options.html) to choose another one
 pub async fn send_money<I, A, S>(
     service: I,
     from_account: &A,
@@ -303,9 +673,164 @@ where
     A: Account + Send + Sync + 'static,
     S: ExchangeRateStore + Send + Sync + 'static,
 {
-    let shared_secret = Bytes::from(shared_secret);
+    send_money_with_options(
+        service,
+        from_account,
+        store,
+        destination_account,
+        shared_secret,
+        source_amount,
+        SendMoneyOptions {
+            slippage,
+            ..SendMoneyOptions::default()
+        },
+    )
+    .await
+}
+
+/// Send the given source amount with packetized Interledger payments using the STREAM transport protocol,
+/// reporting the outcome of each packet and stopping early if cancelled, as configured by the options.
+/// Returns the receipt with sent & delivered amounts, asset & account details, which only
+/// accounts for part of the source amount if the payment was cancelled
+pub async fn send_money_with_options<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    source_amount: u64,
+    options: SendMoneyOptions,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let payment = StreamPayment::new(
+        from_account,
+        destination_account,
+        source_amount,
+        None,
+        options.congestion_strategy,
+    );
+    run_payment(
+        service,
+        from_account,
+        store,
+        shared_secret,
+        payment,
+        options,
+    )
+    .await
+}
 
+/// Send packetized Interledger payments using the STREAM transport protocol until the recipient
+/// has received exactly the given destination amount, spending at most the given maximum source amount.
+/// Returns the receipt with sent & delivered amounts, asset & account details
+///
+/// Fails rather than overpaying if the rest of the destination amount is less than a single source unit
+/// delivers at the exchange rate the recipient reports
+#[allow(clippy::too_many_arguments)]
+pub async fn send_money_fixed_delivery<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    destination_amount: u64,
+    max_source_amount: u64,
+    slippage: f64,
+    congestion_strategy: CongestionStrategy,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let payment = StreamPayment::new(
+        from_account,
+        destination_account,
+        max_source_amount,
+        Some(destination_amount),
+        congestion_strategy,
+    );
+    let options = SendMoneyOptions {
+        slippage,
+        congestion_strategy,
+        ..SendMoneyOptions::default()
+    };
+    run_payment(
+        service,
+        from_account,
+        store,
+        shared_secret,
+        payment,
+        options,
+    )
+    .await
+}
+
+/// Send the given source amount and application data to the recipient using the STREAM transport protocol.
+/// The data is split into chunks and delivered in order on the same stream as the money,
+/// respecting how much data the recipient is willing to buffer.
+/// Returns the receipt with sent & delivered amounts, asset & account details
+#[allow(clippy::too_many_arguments)]
+pub async fn send_money_with_data<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    source_amount: u64,
+    slippage: f64,
+    congestion_strategy: CongestionStrategy,
+    data: Vec<u8>,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let payment = StreamPayment::new(
+        from_account,
+        destination_account,
+        source_amount,
+        None,
+        congestion_strategy,
+    )
+    .with_data(Bytes::from(data));
+    let options = SendMoneyOptions {
+        slippage,
+        congestion_strategy,
+        ..SendMoneyOptions::default()
+    };
+    run_payment(
+        service,
+        from_account,
+        store,
+        shared_secret,
+        payment,
+        options,
+    )
+    .await
+}
+
+/// Send packets for the given payment until it completes, fails or is cancelled
+async fn run_payment<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    shared_secret: Vec<u8>,
+    payment: StreamPayment,
+    options: SendMoneyOptions,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
     let from = from_account.ilp_address();
+    let destination_account = &payment.receipt.to;
     if from.scheme() != destination_account.scheme() {
         warn!(
             "Destination ILP address starts with a different scheme prefix (\"{}\') than ours (\"{}\'), this probably won't work",
@@ -314,121 +839,89 @@ where
         );
     }
 
-    let mut sender = StreamSender {
+    let sender = StreamSender::new(
+        service,
+        from_account.clone(),
+        shared_secret,
+        store,
+        options.slippage,
+        payment,
+    )
+    .with_hooks(options.progress, options.cancellation);
+    complete_payment(sender).await
+}
+
+/// Resume a payment that stopped before it completed, over the same connection and with the
+/// same shared secret, until the rest of its source amount (or delivery amount) is sent.
+/// The payment should be resumed from the account that started it.
+///
+/// Returns the receipt with sent & delivered amounts, including those sent before the payment stopped
+pub async fn resume_payment<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    payment: IncompletePayment,
+    slippage: f64,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let IncompletePayment {
+        delivery,
+        shared_secret,
+        payment,
+        ..
+    } = payment;
+    debug!(
+        "Resuming payment to {} after delivering {}",
+        delivery.to, delivery.delivered_amount
+    );
+    payment.lock().await.restart();
+
+    let sender = StreamSender {
         next: service,
         from_account: from_account.clone(),
         shared_secret,
         store,
         slippage,
-        payment: Arc::new(Mutex::new(StreamPayment {
-            // TODO Make configurable to get money flowing ASAP vs as much as possible per-packet
-            congestion_controller: CongestionController::new(
-                source_amount,
-                source_amount / 10,
-                2.0,
-            ),
-            receipt: StreamDelivery::new(from_account, destination_account, source_amount),
-            should_send_source_account: true,
-            sequence: 1,
-            fulfilled_packets: 0,
-            rejected_packets: 0,
-            fail_fast_rejects: 0,
-            last_fulfill_time: Instant::now(),
-        })),
+        payment,
+        progress: None,
+        cancellation: None,
     };
+    complete_payment(sender).await
+}
 
-    let mut pending_requests = FuturesUnordered::new();
-
-    /// Actions corresponding to the state of the payment
-    enum PaymentEvent {
-        /// Send more money: send a packet with the given source amount and minimum destination amount
-        SendMoney((u64, u64)),
-        /// Congestion controller limited in-flight amount: wait for pending requests until given deadline
-        MaxInFlight(Instant),
-        /// Sent full source amount: close the connection and return success
-        CloseConnection,
-        /// Maximum timeout since last fulfill has elapsed: terminate the payment
-        Timeout,
-        /// Too many packets are rejected, such as if the exchange rate is too low: terminate the payment
-        FailFast,
+/// Send packets until the payment completes, then close the connection and return the final receipt.
+/// If the payment stops early, the connection is left open so the payment may be resumed
+async fn complete_payment<I, A, S>(
+    mut sender: StreamSender<I, A, S>,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    if let Err(error) = sender.send_until_complete().await {
+        return Err(sender.incomplete(error).await);
     }
 
-    loop {
-        let event = {
-            let mut payment = sender.payment.lock().await;
-
-            if payment.last_fulfill_time.elapsed() >= MAX_TIME_SINCE_LAST_FULFILL {
-                PaymentEvent::Timeout
-            } else if payment.is_failing() {
-                PaymentEvent::FailFast
-            } else if payment.is_complete() {
-                PaymentEvent::CloseConnection
-            } else if payment.is_max_in_flight() {
-                let deadline = payment
-                    .last_fulfill_time
-                    .checked_add(MAX_TIME_SINCE_LAST_FULFILL)
-                    .unwrap();
-                PaymentEvent::MaxInFlight(deadline)
-            } else {
-                PaymentEvent::SendMoney(payment.apply_prepare(&sender.store, sender.slippage))
-            }
-        };
-
-        match event {
-            PaymentEvent::SendMoney((source_amount, dest_amount)) => {
-                let mut sender = sender.clone();
-                pending_requests.push(tokio::spawn(async move {
-                    sender.send_money_packet(source_amount, dest_amount).await
-                }));
-            }
-            PaymentEvent::MaxInFlight(deadline) => {
-                // Wait for any request to complete, or if after reach deadline since last fulfill,
-                // run loop again, which should timeout the payment
-                let result = timeout_at(deadline, pending_requests.select_next_some()).await;
-
-                if let Ok(Ok(Err(error))) = result {
-                    error!("Send money stopped because of error: {:?}", error);
-                    return Err(error);
-                }
-            }
-            PaymentEvent::CloseConnection => {
-                // Wait for all pending requests to complete before closing the connection
-                pending_requests.map(|_| ()).collect::<()>().await;
-
-                // Try to the tell the recipient the connection is closed
-                sender.try_send_connection_close().await;
+    // Try to the tell the recipient the connection is closed
+    sender.try_send_connection_close().await;
 
-                // Return final receipt
-                let payment = sender.payment.lock().await;
-                debug!(
-                    "Send money future finished. Delivered: {} ({} packets fulfilled, {} packets rejected)",
-                    payment.receipt.delivered_amount,
-                    payment.fulfilled_packets,
-                    payment.rejected_packets,
-                );
-                return Ok(payment.receipt.clone());
-            }
-            PaymentEvent::Timeout => {
-                // Error if we haven't received a fulfill over a timeout period
-                return Err(Error::TimeoutError(
-                    "Time since last fulfill exceeded the maximum time limit".to_string(),
-                ));
-            }
-            PaymentEvent::FailFast => {
-                let payment = sender.payment.lock().await;
-                return Err(Error::SendMoneyError(
-                    format!("Terminating payment since too many packets are rejected ({} packets fulfilled, {} packets rejected)",
-                    payment.fulfilled_packets,
-                    payment.rejected_packets,
-                )));
-            }
-        }
-    }
+    // Return final receipt
+    let payment = sender.payment.lock().await;
+    debug!(
+        "Send money future finished. Delivered: {} ({} packets fulfilled, {} packets rejected)",
+        payment.receipt.delivered_amount, payment.fulfilled_packets, payment.rejected_packets,
+    );
+    Ok(payment.receipt.clone())
 }
 
 /// Sends and handles all ILP & STREAM packets, encapsulating all payment state
 #[derive(Clone)]
-struct StreamSender<I, A, S> {
+pub(crate) struct StreamSender<I, A, S> {
     /// Next service to send and forward Interledger packets to the network
     next: I,
     /// The account sending the STREAM payment
@@ -441,6 +934,215 @@ struct StreamSender<I, A, S> {
     slippage: f64,
     /// Mutable payment state
     payment: Arc<Mutex<StreamPayment>>,
+    /// Where to report the outcome of each packet
+    progress: Option<UnboundedSender<PaymentProgress>>,
+    /// Stops the payment once cancelled
+    cancellation: Option<CancellationToken>,
+}
+
+impl<I, A, S> StreamSender<I, A, S>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    pub(crate) fn new(
+        next: I,
+        from_account: A,
+        shared_secret: Vec<u8>,
+        store: S,
+        slippage: f64,
+        payment: StreamPayment,
+    ) -> Self {
+        StreamSender {
+            next,
+            from_account,
+            shared_secret: Bytes::from(shared_secret),
+            store,
+            slippage,
+            payment: Arc::new(Mutex::new(payment)),
+            progress: None,
+            cancellation: None,
+        }
+    }
+
+    /// Report the outcome of each packet to the given channel, and stop sending once the
+    /// given token is cancelled
+    pub(crate) fn with_hooks(
+        mut self,
+        progress: Option<UnboundedSender<PaymentProgress>>,
+        cancellation: Option<CancellationToken>,
+    ) -> Self {
+        self.progress = progress;
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// The payment's amounts sent and delivered so far
+    pub(crate) async fn delivery(&self) -> StreamDelivery {
+        self.payment.lock().await.receipt.clone()
+    }
+
+    /// Wrap the error that stopped the payment with the amounts sent and delivered so far,
+    /// and the state needed to resume it
+    pub(crate) async fn incomplete(&self, error: Error) -> Error {
+        Error::IncompletePayment(Box::new(IncompletePayment {
+            error,
+            delivery: self.delivery().await,
+            shared_secret: self.shared_secret.clone(),
+            payment: self.payment.clone(),
+        }))
+    }
+
+    /// Raise the total amount the payment should send
+    pub(crate) async fn increase_source_amount(&self, source_amount: u64) {
+        self.payment
+            .lock()
+            .await
+            .increase_source_amount(source_amount);
+    }
+
+    /// Reset the payment's timeout and fail-fast counts, such as after it was paused
+    pub(crate) async fn restart(&self) {
+        self.payment.lock().await.restart();
+    }
+
+    /// Send packets until the payment's current source amount (or delivery amount) is fulfilled
+    /// and all of its data is received, without closing the connection
+    pub(crate) async fn send_until_complete(&self) -> Result<(), Error> {
+        let mut pending_requests = FuturesUnordered::new();
+
+        /// Actions corresponding to the state of the payment
+        enum PaymentEvent {
+            /// Send more money: send a packet with the given source amount and minimum destination amount
+            SendMoney((u64, u64)),
+            /// Congestion controller limited in-flight amount: wait for pending requests until given deadline
+            MaxInFlight(Instant),
+            /// Sent full source amount: wait for the pending requests and return success
+            Complete,
+            /// Maximum timeout since last fulfill has elapsed: terminate the payment
+            Timeout,
+            /// Too many packets are rejected, such as if the exchange rate is too low: terminate the payment
+            FailFast,
+            /// Sent the maximum source amount without delivering the fixed destination amount: terminate the payment
+            SourceAmountExhausted,
+            /// The rest of the fixed destination amount is less than one source unit delivers: terminate the payment
+            RemainderUndeliverable,
+            /// Recipient closed the stream or connection before the payment completed: terminate the payment
+            ClosedByReceiver,
+            /// The application cancelled the payment: wait for the pending requests and stop sending
+            Cancelled,
+        }
+
+        loop {
+            let event = {
+                let mut payment = self.payment.lock().await;
+                let is_cancelled = self
+                    .cancellation
+                    .as_ref()
+                    .map_or(false, CancellationToken::is_cancelled);
+
+                if is_cancelled {
+                    PaymentEvent::Cancelled
+                } else if payment.last_fulfill_time.elapsed() >= MAX_TIME_SINCE_LAST_FULFILL {
+                    PaymentEvent::Timeout
+                } else if payment.is_failing() {
+                    PaymentEvent::FailFast
+                } else if payment.is_complete() {
+                    PaymentEvent::Complete
+                } else if payment.is_source_amount_exhausted() {
+                    PaymentEvent::SourceAmountExhausted
+                } else if payment.is_remainder_undeliverable() {
+                    PaymentEvent::RemainderUndeliverable
+                } else if payment.closed_by_receiver {
+                    PaymentEvent::ClosedByReceiver
+                } else if payment.is_max_in_flight() && payment.can_send_data() {
+                    // No money can be sent right now, but the data doesn't need to wait for it
+                    PaymentEvent::SendMoney((0, 0))
+                } else if payment.is_max_in_flight() {
+                    let deadline = payment
+                        .last_fulfill_time
+                        .checked_add(MAX_TIME_SINCE_LAST_FULFILL)
+                        .unwrap();
+                    PaymentEvent::MaxInFlight(deadline)
+                } else {
+                    PaymentEvent::SendMoney(payment.apply_prepare(&self.store, self.slippage))
+                }
+            };
+
+            match event {
+                PaymentEvent::SendMoney((source_amount, dest_amount)) => {
+                    let mut sender = self.clone();
+                    pending_requests.push(tokio::spawn(async move {
+                        sender.send_money_packet(source_amount, dest_amount).await
+                    }));
+                }
+                PaymentEvent::MaxInFlight(deadline) => {
+                    // Wait for any request to complete, or if after reach deadline since last fulfill,
+                    // run loop again, which should timeout the payment
+                    let result = timeout_at(deadline, pending_requests.select_next_some()).await;
+
+                    if let Ok(Ok(Err(error))) = result {
+                        error!("Send money stopped because of error: {:?}", error);
+                        return Err(error);
+                    }
+                }
+                PaymentEvent::Complete => {
+                    // Wait for all pending requests to complete so the receipt accounts for them
+                    pending_requests.map(|_| ()).collect::<()>().await;
+                    return Ok(());
+                }
+                PaymentEvent::Cancelled => {
+                    debug!("Payment was cancelled, waiting for the packets in flight");
+                    pending_requests.map(|_| ()).collect::<()>().await;
+                    return Ok(());
+                }
+                PaymentEvent::Timeout => {
+                    // Error if we haven't received a fulfill over a timeout period
+                    return Err(Error::TimeoutError(
+                        "Time since last fulfill exceeded the maximum time limit".to_string(),
+                    ));
+                }
+                PaymentEvent::FailFast => {
+                    let payment = self.payment.lock().await;
+                    return Err(Error::SendMoneyError(
+                        format!("Terminating payment since too many packets are rejected ({} packets fulfilled, {} packets rejected)",
+                        payment.fulfilled_packets,
+                        payment.rejected_packets,
+                    )));
+                }
+                PaymentEvent::SourceAmountExhausted => {
+                    let payment = self.payment.lock().await;
+                    return Err(Error::SendMoneyError(format!(
+                        "Sent the maximum source amount of {} but only delivered {} of {}",
+                        payment.receipt.source_amount,
+                        payment.receipt.delivered_amount,
+                        payment.delivery_amount.unwrap_or_default(),
+                    )));
+                }
+                PaymentEvent::RemainderUndeliverable => {
+                    let payment = self.payment.lock().await;
+                    let delivery_amount = payment.delivery_amount.unwrap_or_default();
+                    return Err(Error::SendMoneyError(format!(
+                        "Delivered {} of {}, but the remaining {} can't be delivered at the current exchange rate without overpaying",
+                        payment.receipt.delivered_amount,
+                        delivery_amount,
+                        delivery_amount.saturating_sub(payment.receipt.delivered_amount),
+                    )));
+                }
+                PaymentEvent::ClosedByReceiver => {
+                    // Wait for the pending requests so the receipt accounts for them
+                    pending_requests.map(|_| ()).collect::<()>().await;
+
+                    let payment = self.payment.lock().await;
+                    return Err(Error::SendMoneyError(format!(
+                        "Recipient closed the connection after receiving {} of the payment",
+                        payment.receipt.delivered_amount,
+                    )));
+                }
+            }
+        }
+    }
 }
 
 impl<I, A, S> StreamSender<I, A, S>
@@ -456,15 +1158,30 @@ where
         source_amount: u64,
         min_destination_amount: u64,
     ) -> Result<(), Error> {
-        let (prepare, sequence) = {
+        let (prepare, sequence, data_end_offset) = {
             let mut payment = self.payment.lock().await;
 
             // Build the STREAM packet
             let sequence = payment.next_sequence();
+            let data_chunk = payment.next_data_chunk();
             let mut frames = vec![Frame::StreamMoney(StreamMoneyFrame {
                 stream_id: 1,
                 shares: 1,
             })];
+            if let Some((offset, data)) = &data_chunk {
+                frames.push(Frame::StreamData(StreamDataFrame {
+                    stream_id: 1,
+                    offset: *offset,
+                    data: &data[..],
+                }));
+            }
+            // Lets the recipient check the exchange rate the packet was converted at
+            if source_amount > 0 {
+                frames.push(Frame::SourceAmount(SourceAmountFrame {
+                    source_amount,
+                    source_asset_scale: payment.receipt.source_asset_scale,
+                }));
+            }
             if payment.should_send_source_account {
                 frames.push(Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
                     source_account: payment.receipt.from.clone(),
@@ -504,7 +1221,10 @@ where
             }
             .build();
 
-            (prepare, sequence)
+            let data_end_offset = data_chunk
+                .as_ref()
+                .map(|(offset, data)| offset + data.len() as u64);
+            (prepare, sequence, data_end_offset)
         };
 
         // Send it!
@@ -525,6 +1245,8 @@ where
             StreamPacket::from_encrypted(&self.shared_secret, BytesMut::from(reply_data));
 
         let mut payment = self.payment.lock().await;
+        // Whether the recipient decrypted our packet, which means they also received its data
+        let mut was_read = false;
 
         // Parse the stream packet and determine the amount the recipient claims they received
         let claimed_amount: u64 = match stream_reply_packet {
@@ -546,6 +1268,38 @@ where
                 } else {
                     // Since we decrypted the response, the recipient read the request packet and knows our account
                     payment.should_send_source_account = false;
+                    was_read = true;
+
+                    // Respect how much more data the recipient is willing to receive
+                    // and keep the receipt for the largest amount received
+                    for frame in stream_reply_packet.frames() {
+                        match frame {
+                            Frame::StreamMaxData(frame) if frame.stream_id == 1 => {
+                                payment.remote_max_data_offset = frame.max_offset;
+                            }
+                            Frame::StreamReceipt(frame) if frame.stream_id == 1 => {
+                                payment.apply_stream_receipt(frame.receipt);
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    // A rejected packet still tells us how much the recipient would have received
+                    if packet_type == IlpPacketType::Reject {
+                        payment
+                            .set_probed_rate(source_amount, stream_reply_packet.prepare_amount());
+                    }
+
+                    // Stop sending once the recipient closes our stream or the connection
+                    let is_closed = stream_reply_packet.frames().any(|frame| match frame {
+                        Frame::StreamClose(frame) => frame.stream_id == 1,
+                        Frame::ConnectionClose(_) => true,
+                        _ => false,
+                    });
+                    if is_closed {
+                        debug!("Recipient closed the connection");
+                        payment.closed_by_receiver = true;
+                    }
 
                     // Update the destination asset scale & code
                     // https://github.com/interledger/rfcs/pull/551 ensures that this won't change
@@ -575,6 +1329,10 @@ where
             }
         };
 
+        if let Some(end_offset) = data_end_offset {
+            payment.apply_data_reply(end_offset, was_read);
+        }
+
         match reply {
             // Handle ILP Fulfill
             Ok(_) => {
@@ -583,6 +1341,7 @@ where
                 let delivered_amount = max(min_destination_amount, claimed_amount);
 
                 payment.apply_fulfill(source_amount, delivered_amount);
+                self.report_progress(&payment, sequence, source_amount, delivered_amount, None);
 
                 debug!(
                     "Prepare {} with amount {} was fulfilled ({} left to send)",
@@ -595,7 +1354,12 @@ where
             }
             // Handle ILP Reject
             Err(reject) => {
-                payment.apply_reject(source_amount, &reject);
+                // Packets with only data are expected to be rejected,
+                // so they shouldn't count towards failing the payment
+                if source_amount > 0 {
+                    payment.apply_reject(source_amount, &reject);
+                }
+                self.report_progress(&payment, sequence, source_amount, 0, Some(reject.code()));
 
                 debug!(
                     "Prepare {} with amount {} was rejected with code: {} ({} left to send)",
@@ -623,10 +1387,33 @@ where
         }
     }
 
+    /// Tell the application how the packet fared, if it is listening
+    fn report_progress(
+        &self,
+        payment: &StreamPayment,
+        sequence: u64,
+        source_amount: u64,
+        delivered_amount: u64,
+        reject_code: Option<IlpErrorCode>,
+    ) {
+        if let Some(progress) = &self.progress {
+            let progress = progress.unbounded_send(PaymentProgress {
+                sequence,
+                source_amount,
+                delivered_amount,
+                reject_code,
+                delivery: payment.receipt.clone(),
+            });
+            if progress.is_err() {
+                debug!("Progress channel was closed, not reporting the payment's progress");
+            }
+        }
+    }
+
     /// Send an unfulfillable Prepare with a ConnectionClose frame to the peer
     /// There's no ACK from the recipient, so we can't confirm it closed
     #[inline]
-    async fn try_send_connection_close(&mut self) {
+    pub(crate) async fn try_send_connection_close(&mut self) {
         let prepare = {
             let mut payment = self.payment.lock().await;
             let sequence = payment.next_sequence();
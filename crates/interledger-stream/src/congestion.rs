@@ -3,16 +3,85 @@ use chrono::Utc;
 use interledger_packet::{ErrorCode, MaxPacketAmountDetails, Reject};
 #[cfg(test)]
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
 #[cfg(feature = "metrics_csv")]
 use std::io;
+use std::str::FromStr;
 use tracing::{debug, warn};
 
+/// Number of packets the [low latency controller](./struct.LowLatencyCongestionController.html)
+/// splits a payment into
+const LOW_LATENCY_PACKETS_PER_PAYMENT: u64 = 100;
+/// Number of packets the low latency controller allows in flight before any are fulfilled
+const LOW_LATENCY_INITIAL_PACKETS_IN_FLIGHT: u64 = 10;
+
+/// Flow control for a STREAM payment: decides how much money may be in flight
+/// and how much to send per packet, based on how earlier packets fared
+pub trait CongestionControl: Send {
+    /// Maximum amount to send in a single packet
+    fn get_max_packet_amount(&self) -> u64;
+
+    /// Amount that can still be sent before reaching the maximum amount in flight
+    fn get_amount_left_in_window(&self) -> u64;
+
+    /// Called when a packet with the given amount is sent
+    fn prepare(&mut self, amount: u64);
+
+    /// Called when a packet with the given amount is fulfilled
+    fn fulfill(&mut self, prepare_amount: u64);
+
+    /// Called when a packet with the given amount is rejected
+    fn reject(&mut self, prepare_amount: u64, reject: &Reject);
+}
+
+/// The congestion control algorithm a STREAM payment uses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CongestionStrategy {
+    /// Sends as much as possible per packet, using the [AIMD controller](./struct.CongestionController.html)
+    Aimd,
+    /// Gets money flowing as soon as possible with small packets, using the
+    /// [low latency controller](./struct.LowLatencyCongestionController.html)
+    LowLatency,
+}
+
+impl Default for CongestionStrategy {
+    fn default() -> Self {
+        CongestionStrategy::Aimd
+    }
+}
+
+impl FromStr for CongestionStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aimd" => Ok(CongestionStrategy::Aimd),
+            "low_latency" => Ok(CongestionStrategy::LowLatency),
+            _ => Err(format!("Unknown congestion control strategy: {}", s)),
+        }
+    }
+}
+
+impl CongestionStrategy {
+    /// Creates the congestion controller for a payment of the given source amount
+    pub fn controller(self, source_amount: u64) -> Box<dyn CongestionControl> {
+        match self {
+            CongestionStrategy::Aimd => Box::new(CongestionController::new(
+                source_amount,
+                source_amount / 10,
+                2.0,
+            )),
+            CongestionStrategy::LowLatency => {
+                Box::new(LowLatencyCongestionController::new(source_amount))
+            }
+        }
+    }
+}
+
 /// A basic congestion controller that implements an
 /// Additive Increase, Multiplicative Decrease (AIMD) algorithm.
-///
-/// Future implementations of this will use more advanced congestion
-/// control algorithms.
 pub struct CongestionController {
     state: CongestionState,
     /// Amount which is added to `max_in_flight` per fulfill
@@ -60,18 +129,37 @@ impl CongestionController {
         }
     }
 
+    #[cfg(test)]
+    fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
+        self.max_packet_amount = Some(max_packet_amount)
+    }
+
+    #[cfg(feature = "metrics_csv")]
+    fn log_stats(&mut self, amount_sent: u64) {
+        self.csv_writer
+            .write_record(&[
+                format!("{}", Utc::now().timestamp_millis()),
+                format!("{}", self.max_in_flight),
+                format!("{}", amount_sent),
+            ])
+            .unwrap();
+        self.csv_writer.flush().unwrap();
+    }
+}
+
+impl CongestionControl for CongestionController {
     /// Maximium allowed packet amount allowed to send in a packet per F08s
-    pub fn get_max_packet_amount(&self) -> u64 {
+    fn get_max_packet_amount(&self) -> u64 {
         self.max_packet_amount.unwrap_or(u64::max_value())
     }
 
     /// The maximum amount availble to be sent is the maximum amount in flight minus the current amount in flight
-    pub fn get_amount_left_in_window(&self) -> u64 {
+    fn get_amount_left_in_window(&self) -> u64 {
         self.max_in_flight.saturating_sub(self.amount_in_flight)
     }
 
     /// Increments the amount in flight by the provided amount
-    pub fn prepare(&mut self, amount: u64) {
+    fn prepare(&mut self, amount: u64) {
         if amount > 0 {
             self.amount_in_flight += amount;
             debug!(
@@ -83,7 +171,7 @@ impl CongestionController {
 
     /// Decrements the amount in flight by the provided amount
     /// Increases the allowed max in flight amount cap
-    pub fn fulfill(&mut self, prepare_amount: u64) {
+    fn fulfill(&mut self, prepare_amount: u64) {
         self.amount_in_flight -= prepare_amount;
 
         // Before we know how much we should be sending at a time,
@@ -120,7 +208,7 @@ impl CongestionController {
 
     /// Decrements the amount in flight by the provided amount
     /// Decreases the allowed max in flight amount cap
-    pub fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
+    fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
         self.amount_in_flight -= prepare_amount;
 
         match reject.code() {
@@ -136,44 +224,115 @@ impl CongestionController {
                 self.log_stats(0);
             }
             ErrorCode::F08_AMOUNT_TOO_LARGE => {
-                if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
-                    let new_max_packet_amount: u64 =
-                        prepare_amount * details.max_amount() / details.amount_received();
-                    if let Some(max_packet_amount) = self.max_packet_amount {
-                        self.max_packet_amount =
-                            Some(min(max_packet_amount, new_max_packet_amount));
-                    } else {
-                        self.max_packet_amount = Some(new_max_packet_amount);
-                    }
-                } else {
-                    warn!("Got F08: Amount Too Large Error without max packet amount details attached");
-                    if let Some(max_packet_amount) = self.max_packet_amount {
-                        self.max_packet_amount =
-                            Some((max_packet_amount as f64 / self.decrease_factor) as u64);
-                    }
-                }
+                self.max_packet_amount = reduce_max_packet_amount(
+                    self.max_packet_amount,
+                    prepare_amount,
+                    reject,
+                    self.decrease_factor,
+                );
             }
             _ => {
                 // No special treatment for other errors
             }
         }
     }
+}
 
-    #[cfg(test)]
-    fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
-        self.max_packet_amount = Some(max_packet_amount)
+/// A congestion controller tuned for latency rather than throughput.
+///
+/// It splits the payment into small packets of a fixed size and allows several of
+/// them in flight right away, so money starts flowing without waiting for the window
+/// to grow. The window doubles on every fulfill and halves on insufficient liquidity.
+pub struct LowLatencyCongestionController {
+    /// Amount sent in each packet, unless a connector's maximum packet amount is lower
+    packet_amount: u64,
+    /// The maximum packet amount learned from `F08_AMOUNT_TOO_LARGE` errors
+    max_packet_amount: Option<u64>,
+    /// The current amount in flight
+    amount_in_flight: u64,
+    /// The maximum allowed amount to be in flight
+    max_in_flight: u64,
+}
+
+impl LowLatencyCongestionController {
+    /// Constructs a new controller for a payment of the given source amount
+    pub fn new(source_amount: u64) -> Self {
+        let packet_amount = max(source_amount / LOW_LATENCY_PACKETS_PER_PAYMENT, 1);
+        LowLatencyCongestionController {
+            packet_amount,
+            max_packet_amount: None,
+            amount_in_flight: 0,
+            max_in_flight: packet_amount.saturating_mul(LOW_LATENCY_INITIAL_PACKETS_IN_FLIGHT),
+        }
     }
+}
 
-    #[cfg(feature = "metrics_csv")]
-    fn log_stats(&mut self, amount_sent: u64) {
-        self.csv_writer
-            .write_record(&[
-                format!("{}", Utc::now().timestamp_millis()),
-                format!("{}", self.max_in_flight),
-                format!("{}", amount_sent),
-            ])
-            .unwrap();
-        self.csv_writer.flush().unwrap();
+impl CongestionControl for LowLatencyCongestionController {
+    fn get_max_packet_amount(&self) -> u64 {
+        min(
+            self.packet_amount,
+            self.max_packet_amount.unwrap_or(u64::max_value()),
+        )
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
+        self.max_in_flight.saturating_sub(self.amount_in_flight)
+    }
+
+    fn prepare(&mut self, amount: u64) {
+        self.amount_in_flight += amount;
+    }
+
+    fn fulfill(&mut self, prepare_amount: u64) {
+        self.amount_in_flight -= prepare_amount;
+        self.max_in_flight = self.max_in_flight.saturating_mul(2);
+        debug!(
+            "Fulfilled packet of {}, doubling max in flight to: {}",
+            prepare_amount, self.max_in_flight
+        );
+    }
+
+    fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
+        self.amount_in_flight -= prepare_amount;
+
+        match reject.code() {
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY => {
+                // Always allow at least one packet in flight
+                self.max_in_flight = max(self.max_in_flight / 2, self.get_max_packet_amount());
+                debug!(
+                    "Rejected packet with T04 error, decreasing max in flight to: {}",
+                    self.max_in_flight
+                );
+            }
+            ErrorCode::F08_AMOUNT_TOO_LARGE => {
+                self.max_packet_amount =
+                    reduce_max_packet_amount(self.max_packet_amount, prepare_amount, reject, 2.0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Lower the maximum packet amount after a packet was rejected with an `F08_AMOUNT_TOO_LARGE` error,
+/// using the details attached to the error or else dividing it by the decrease factor
+fn reduce_max_packet_amount(
+    max_packet_amount: Option<u64>,
+    prepare_amount: u64,
+    reject: &Reject,
+    decrease_factor: f64,
+) -> Option<u64> {
+    if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
+        let new_max_packet_amount: u64 =
+            prepare_amount * details.max_amount() / details.amount_received();
+        if let Some(max_packet_amount) = max_packet_amount {
+            Some(min(max_packet_amount, new_max_packet_amount))
+        } else {
+            Some(new_max_packet_amount)
+        }
+    } else {
+        warn!("Got F08: Amount Too Large Error without max packet amount details attached");
+        max_packet_amount
+            .map(|max_packet_amount| (max_packet_amount as f64 / decrease_factor) as u64)
     }
 }
 
@@ -373,6 +532,71 @@ mod tests {
         }
     }
 
+    mod low_latency {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+
+        #[test]
+        fn sends_fixed_small_packets() {
+            let mut controller = LowLatencyCongestionController::new(10_000);
+            assert_eq!(controller.get_max_packet_amount(), 100);
+            assert_eq!(controller.get_amount_left_in_window(), 1000);
+
+            controller.prepare(100);
+            controller.fulfill(100);
+            assert_eq!(controller.get_amount_left_in_window(), 2000);
+            assert_eq!(controller.get_max_packet_amount(), 100);
+        }
+
+        #[test]
+        fn halves_window_on_insufficient_liquidity() {
+            let mut controller = LowLatencyCongestionController::new(10_000);
+            let reject = RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build();
+            for _ in 0..10 {
+                controller.prepare(100);
+                controller.reject(100, &reject);
+            }
+            // Never drops below a single packet
+            assert_eq!(controller.get_amount_left_in_window(), 100);
+        }
+
+        #[test]
+        fn respects_max_packet_amount() {
+            let mut controller = LowLatencyCongestionController::new(10_000);
+            controller.prepare(100);
+            controller.reject(
+                100,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &MaxPacketAmountDetails::new(100, 40).to_bytes(),
+                }
+                .build(),
+            );
+            assert_eq!(controller.get_max_packet_amount(), 40);
+        }
+
+        #[test]
+        fn parses_strategy() {
+            assert_eq!(
+                CongestionStrategy::from_str("low_latency").unwrap(),
+                CongestionStrategy::LowLatency
+            );
+            assert_eq!(
+                CongestionStrategy::from_str("aimd").unwrap(),
+                CongestionStrategy::Aimd
+            );
+            assert!(CongestionStrategy::from_str("fast").is_err());
+        }
+    }
+
     mod tracking_amount_in_flight {
         use super::*;
 
@@ -1,3 +1,5 @@
+use super::client::{IncompletePayment, StreamDelivery};
+
 /// Stream Errors
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -9,4 +11,22 @@ pub enum Error {
     SendMoneyError(String),
     #[error("Error maximum time exceeded: {0}")]
     TimeoutError(String),
+    #[error("Error quoting: {0}")]
+    QuoteError(String),
+    #[error("Invalid receipt: {0}")]
+    InvalidReceipt(String),
+    #[error("Invalid connection tag: {0}")]
+    InvalidConnectionTag(String),
+    #[error("Payment stopped: {0}")]
+    IncompletePayment(Box<IncompletePayment>),
+}
+
+impl Error {
+    /// The amounts sent and delivered before the payment stopped, if this error stopped a payment
+    pub fn delivery(&self) -> Option<&StreamDelivery> {
+        match self {
+            Error::IncompletePayment(payment) => Some(payment.delivery()),
+            _ => None,
+        }
+    }
 }
@@ -6,7 +6,7 @@
 
 /// Stream client
 mod client;
-/// Congestion controller consumed by the [stream client](./client/fn.send_money.html)
+/// Congestion controllers consumed by the [stream client](./client/fn.send_money.html)
 mod congestion;
 /// Cryptographic utilities for generating fulfillments and encrypting/decrypting STREAM packets
 mod crypto;
@@ -14,14 +14,32 @@ mod crypto;
 mod error;
 /// Stream Packet implementation, [as specified in the RFC](https://interledger.org/rfcs/0029-stream/#5-packet-and-frame-specification)
 mod packet;
+/// Rate probing to quote a STREAM payment before sending it
+mod quote;
+/// STREAM receipts, [as specified in the RFC](https://interledger.org/rfcs/0039-stream-receipts/)
+mod receipt;
 /// A stream server implementing an [Outgoing Service](../interledger_service/trait.OutgoingService.html) for receiving STREAM payments from peers
 mod server;
+/// Payments sent continuously at a fixed rate over a single STREAM connection
+mod streaming;
 
-pub use client::{send_money, StreamDelivery};
+pub use client::{
+    resume_payment, send_money, send_money_fixed_delivery, send_money_with_data,
+    send_money_with_options, CancellationToken, IncompletePayment, PaymentProgress,
+    SendMoneyOptions, StreamDelivery,
+};
+pub use congestion::{
+    CongestionControl, CongestionController, CongestionStrategy, LowLatencyCongestionController,
+};
 pub use error::Error;
+pub use quote::{quote, StreamQuote};
+pub use receipt::{create_receipt, decode_receipt, verify_receipt, Receipt, ReceiptDetails};
 pub use server::{
-    ConnectionGenerator, PaymentNotification, StreamNotificationsStore, StreamReceiverService,
+    ConnectionGenerator, ConnectionState, InMemoryConnectionStore, IncomingPaymentReservation,
+    IncomingPaymentStore, PaymentNotification, StreamConnectionStore, StreamDataMessage,
+    StreamNotificationsStore, StreamReceiverAccount, StreamReceiverService, StreamState,
 };
+pub use streaming::{send_money_at_rate, StreamingPaymentHandle, StreamingRate, StreamingStatus};
 
 #[cfg(test)]
 pub mod test_helpers {
@@ -31,7 +49,7 @@ pub mod test_helpers {
     use interledger_errors::{AccountStoreError, AddressStoreError, ExchangeRateStoreError};
     use interledger_packet::Address;
     use interledger_rates::ExchangeRateStore;
-    use interledger_router::RouterStore;
+    use interledger_router::{PrefixMap, RouterStore};
     use interledger_service::{Account, AccountStore, AddressStore, Username};
     use interledger_service_util::MaxPacketAmountAccount;
     use once_cell::sync::Lazy;
@@ -85,6 +103,8 @@ pub mod test_helpers {
         }
     }
 
+    impl StreamReceiverAccount for TestAccount {}
+
     #[derive(Clone)]
     pub struct DummyStore;
 
@@ -133,8 +153,8 @@ pub mod test_helpers {
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-            Arc::new(HashMap::from_iter(
+        fn routing_table(&self) -> Arc<PrefixMap<Uuid>> {
+            Arc::new(PrefixMap::from_iter(
                 vec![(
                     self.route.clone().unwrap().0,
                     self.route.clone().unwrap().1.id(),
@@ -190,15 +210,109 @@ pub mod test_helpers {
 mod send_money_to_receiver {
     use super::test_helpers::*;
     use super::*;
+    use async_trait::async_trait;
     use bytes::Bytes;
+    use futures::StreamExt;
     use interledger_packet::Address;
     use interledger_packet::{ErrorCode, RejectBuilder};
     use interledger_router::Router;
-    use interledger_service::outgoing_service_fn;
-    use interledger_service_util::ExchangeRateService;
+    use interledger_service::{
+        outgoing_service_fn, IlpResult, IncomingRequest, IncomingService, OutgoingService,
+    };
+    use interledger_service_util::{ExchangeRateService, MaxPacketAmountService};
     use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
     use uuid::Uuid;
 
+    const SERVER_SECRET: [u8; 32] = [0; 32];
+
+    /// A STREAM receiver that rejects packets which aren't for one of its connections
+    fn test_receiver(
+    ) -> StreamReceiverService<DummyStore, impl OutgoingService<TestAccount> + Clone, TestAccount>
+    {
+        StreamReceiverService::new(
+            Bytes::from(&SERVER_SECRET[..]),
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        )
+    }
+
+    /// A store that routes packets for the receiver to its account
+    fn receiver_store() -> TestStore {
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: EXAMPLE_RECEIVER.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        TestStore {
+            route: Some((EXAMPLE_RECEIVER.to_string(), account)),
+            price_1: None,
+            price_2: None,
+        }
+    }
+
+    /// The destination address and shared secret of a new connection to the receiver
+    fn connect() -> (Address, Vec<u8>) {
+        let (destination_account, shared_secret) =
+            ConnectionGenerator::new(Bytes::from(&SERVER_SECRET[..]))
+                .generate_address_and_secret(&EXAMPLE_RECEIVER);
+        (destination_account, shared_secret.to_vec())
+    }
+
+    /// The account payments are sent from, in the same asset as the receiver's
+    fn test_sender() -> TestAccount {
+        TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: Address::from_str("example.sender").unwrap(),
+            max_packet_amount: None,
+        }
+    }
+
+    /// The sender's store, which has no exchange rates since the assets are the same
+    fn sender_store() -> TestStore {
+        TestStore {
+            route: None,
+            price_1: None,
+            price_2: None,
+        }
+    }
+
+    /// An account in an asset with 3 fewer decimal places than the receiver's, and a store which
+    /// routes to the receiver and converts between the assets, so every source unit delivers 1000
+    fn converting_sender() -> (TestAccount, TestStore) {
+        let sender_account = TestAccount {
+            asset_scale: 6,
+            ..test_sender()
+        };
+        let recipient_account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: EXAMPLE_RECEIVER.clone(),
+            asset_code: "ABC".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((EXAMPLE_RECEIVER.to_string(), recipient_account)),
+            price_1: Some(1.0),
+            price_2: Some(1.0),
+        };
+        (sender_account, store)
+    }
+
     #[tokio::test]
     async fn send_money_test() {
         let server_secret = Bytes::from(&[0; 32][..]);
@@ -260,6 +374,213 @@ mod send_money_to_receiver {
         assert_eq!(receipt.delivered_amount, 100);
     }
 
+    #[tokio::test]
+    async fn streams_money_until_max_amount() {
+        let server = Router::new(receiver_store(), test_receiver());
+        let (destination_account, shared_secret) = connect();
+        let handle = StreamingPaymentHandle::new();
+        let receipt = send_money_at_rate(
+            server,
+            &test_sender(),
+            sender_store(),
+            destination_account,
+            shared_secret,
+            StreamingRate {
+                amount_per_second: 1000,
+                max_amount: 500,
+                duration: None,
+            },
+            0.0,
+            CongestionStrategy::default(),
+            handle.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(receipt.delivered_amount, 500);
+        assert_eq!(handle.status(), StreamingStatus::Stopped);
+        assert_eq!(handle.delivery().unwrap().delivered_amount, 500);
+    }
+
+    #[tokio::test]
+    async fn resumes_streaming_after_long_pause() {
+        /// Advance the paused clock one tick at a time so the payment sees every status change
+        async fn advance(duration: Duration) {
+            for _ in 0..duration.as_millis() / 500 {
+                tokio::time::advance(Duration::from_millis(500)).await;
+            }
+        }
+
+        tokio::time::pause();
+        let server = Router::new(receiver_store(), test_receiver());
+        let (destination_account, shared_secret) = connect();
+        let handle = StreamingPaymentHandle::new();
+        let payment = {
+            let handle = handle.clone();
+            let sender_account = test_sender();
+            tokio::spawn(async move {
+                send_money_at_rate(
+                    server,
+                    &sender_account,
+                    sender_store(),
+                    destination_account,
+                    shared_secret,
+                    StreamingRate {
+                        amount_per_second: 1000,
+                        max_amount: 2000,
+                        duration: None,
+                    },
+                    0.0,
+                    CongestionStrategy::default(),
+                    handle,
+                )
+                .await
+            })
+        };
+
+        advance(Duration::from_secs(1)).await;
+        handle.pause();
+        // Longer than the maximum time since the last fulfill
+        advance(Duration::from_secs(40)).await;
+        assert_eq!(handle.status(), StreamingStatus::Paused);
+        handle.resume();
+        advance(Duration::from_secs(2)).await;
+
+        let receipt = payment.await.unwrap().unwrap();
+        assert_eq!(receipt.delivered_amount, 2000);
+        assert_eq!(handle.status(), StreamingStatus::Stopped);
+        assert!(handle.error().is_none());
+    }
+
+    #[tokio::test]
+    async fn sends_money_with_low_latency_congestion_control() {
+        let server = Router::new(receiver_store(), test_receiver());
+        let (destination_account, shared_secret) = connect();
+        let receipt = send_money_with_options(
+            server,
+            &test_sender(),
+            sender_store(),
+            destination_account,
+            shared_secret,
+            1000,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_strategy: CongestionStrategy::LowLatency,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(receipt.delivered_amount, 1000);
+    }
+
+    #[tokio::test]
+    async fn stops_when_receiver_closes_stream() {
+        let store = receiver_store();
+        let connections = InMemoryConnectionStore::new();
+        let server = test_receiver().with_connection_store(connections.clone(), 60);
+        let server = Router::new(store.clone(), server);
+        // Limit the packet amount so the stream reaches its receive max exactly
+        let server = MaxPacketAmountService::new(store, server);
+
+        let (destination_account, shared_secret) = connect();
+        let connection_id = destination_account
+            .segments()
+            .rev()
+            .next()
+            .unwrap()
+            .to_string();
+
+        let result = send_money(
+            server,
+            &TestAccount {
+                max_packet_amount: Some(10),
+                ..test_sender()
+            },
+            sender_store(),
+            destination_account,
+            shared_secret,
+            100,
+            0.0,
+        )
+        .await;
+
+        match result {
+            Err(Error::IncompletePayment(payment))
+                if matches!(payment.error(), Error::SendMoneyError(_)) => {}
+            _ => panic!("Payment should stop once the receiver closes the stream"),
+        }
+        let connection = connections.get_connection(&connection_id).unwrap();
+        assert_eq!(connection.total_received, 60);
+        assert!(connection.streams[&1].closed);
+    }
+
+    #[tokio::test]
+    async fn sends_data_with_money() {
+        let (data_sender, mut data_receiver) = futures::channel::mpsc::channel(16);
+        let server = test_receiver()
+            .with_connection_store(InMemoryConnectionStore::new(), u64::max_value())
+            .with_data_channel(data_sender);
+        let server = Router::new(receiver_store(), server);
+        let (destination_account, shared_secret) = connect();
+
+        // Large enough to need several packets and for the receiver to extend its data limit
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let receipt = send_money_with_data(
+            server,
+            &test_sender(),
+            sender_store(),
+            destination_account,
+            shared_secret,
+            100,
+            0.0,
+            CongestionStrategy::default(),
+            data.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(receipt.delivered_amount, 100);
+
+        let mut received = Vec::new();
+        while let Ok(Some(message)) = data_receiver.try_next() {
+            assert_eq!(message.stream_id, 1);
+            received.extend(message.data);
+        }
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn collects_verifiable_receipt() {
+        let server =
+            test_receiver().with_connection_store(InMemoryConnectionStore::new(), u64::max_value());
+        let server = Router::new(receiver_store(), server);
+
+        // The verifier would pass these to the receiver via SPSP
+        let receipt_details = ReceiptDetails::random();
+        let (destination_account, shared_secret) =
+            ConnectionGenerator::new(Bytes::from(&SERVER_SECRET[..]))
+                .generate_address_and_secret_with_receipts(&EXAMPLE_RECEIVER, &receipt_details);
+
+        let delivery = send_money(
+            server,
+            &test_sender(),
+            sender_store(),
+            destination_account,
+            shared_secret.to_vec(),
+            100,
+            0.0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(delivery.delivered_amount, 100);
+
+        let stream_receipt = base64::decode(delivery.stream_receipt.unwrap()).unwrap();
+        let receipt = verify_receipt(&stream_receipt, &receipt_details.secret).unwrap();
+        assert_eq!(receipt.nonce, receipt_details.nonce);
+        assert_eq!(receipt.total_received, 100);
+    }
+
     #[tokio::test]
     async fn payment_fails_if_large_spread() {
         let server_secret = Bytes::from(&[0; 32][..]);
@@ -322,8 +643,246 @@ mod send_money_to_receiver {
 
         // Connector takes 2% spread, but we're only willing to tolerate 1.4%
         match result {
-            Err(Error::SendMoneyError(_)) => {}
+            Err(Error::IncompletePayment(payment))
+                if matches!(payment.error(), Error::SendMoneyError(_)) => {}
             _ => panic!("Payment should fail fast due to poor exchange rates"),
         }
     }
+
+    #[tokio::test]
+    async fn delivers_fixed_destination_amount() {
+        let (sender_account, store) = converting_sender();
+        let server = ExchangeRateService::new(0.0, store.clone(), test_receiver());
+        let server = Router::new(store.clone(), server);
+
+        let (destination_account, shared_secret) = connect();
+        let receipt = send_money_fixed_delivery(
+            server.clone(),
+            &sender_account,
+            store.clone(),
+            destination_account,
+            shared_secret,
+            500_000_000,
+            1_000_000,
+            0.0,
+            CongestionStrategy::default(),
+        )
+        .await
+        .unwrap();
+
+        // Recipient gets exactly the destination amount, and only the source amount
+        // needed for it is sent rather than the maximum
+        assert_eq!(receipt.delivered_amount, 500_000_000);
+        assert_eq!(receipt.sent_amount, 500_000);
+
+        let (destination_account, shared_secret) = connect();
+        let result = send_money_fixed_delivery(
+            server,
+            &sender_account,
+            store,
+            destination_account,
+            shared_secret,
+            500_000_000,
+            400_000,
+            0.0,
+            CongestionStrategy::default(),
+        )
+        .await;
+
+        match result {
+            Err(Error::IncompletePayment(payment))
+                if matches!(payment.error(), Error::SendMoneyError(_)) => {}
+            _ => panic!("Payment should fail since the maximum source amount is too low"),
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_overdeliver_remainder_smaller_than_one_source_unit() {
+        let (sender_account, store) = converting_sender();
+        let server = ExchangeRateService::new(0.0, store.clone(), test_receiver());
+        let server = Router::new(store.clone(), server);
+
+        // Each source unit delivers 1000, so the last 500 can't be delivered exactly
+        let (destination_account, shared_secret) = connect();
+        let result = send_money_fixed_delivery(
+            server,
+            &sender_account,
+            store,
+            destination_account,
+            shared_secret,
+            1500,
+            10,
+            0.0,
+            CongestionStrategy::default(),
+        )
+        .await;
+
+        let payment = match result {
+            Err(Error::IncompletePayment(payment)) => payment,
+            _ => panic!("Payment should fail since the remainder can't be delivered at this rate"),
+        };
+        assert!(matches!(payment.error(), Error::SendMoneyError(_)));
+        assert_eq!(payment.delivery().delivered_amount, 1000);
+        assert_eq!(payment.delivery().sent_amount, 1);
+    }
+
+    #[tokio::test]
+    async fn resumes_incomplete_payment() {
+        /// Fulfills the first few packets, then rejects packets until it is reachable again
+        #[derive(Clone)]
+        struct UnreliableService<I> {
+            next: I,
+            num_fulfilled: Arc<AtomicUsize>,
+            is_reachable: Arc<AtomicBool>,
+        }
+
+        #[async_trait]
+        impl<I> IncomingService<TestAccount> for UnreliableService<I>
+        where
+            I: IncomingService<TestAccount> + Send,
+        {
+            async fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> IlpResult {
+                if self.num_fulfilled.load(Ordering::SeqCst) < 3
+                    || self.is_reachable.load(Ordering::SeqCst)
+                {
+                    let result = self.next.handle_request(request).await;
+                    if result.is_ok() {
+                        self.num_fulfilled.fetch_add(1, Ordering::SeqCst);
+                    }
+                    result
+                } else {
+                    Err(RejectBuilder {
+                        code: ErrorCode::T01_PEER_UNREACHABLE,
+                        message: b"Peer unreachable",
+                        triggered_by: Some(&EXAMPLE_CONNECTOR),
+                        data: &[],
+                    }
+                    .build())
+                }
+            }
+        }
+
+        let store = receiver_store();
+        let server = Router::new(store.clone(), test_receiver());
+        let server = MaxPacketAmountService::new(store, server);
+        let is_reachable = Arc::new(AtomicBool::new(false));
+        let server = UnreliableService {
+            next: server,
+            num_fulfilled: Arc::new(AtomicUsize::new(0)),
+            is_reachable: is_reachable.clone(),
+        };
+
+        let (destination_account, shared_secret) = connect();
+        let sender_account = TestAccount {
+            max_packet_amount: Some(10),
+            ..test_sender()
+        };
+
+        let result = send_money(
+            server.clone(),
+            &sender_account,
+            sender_store(),
+            destination_account.clone(),
+            shared_secret,
+            100,
+            0.0,
+        )
+        .await;
+        let payment = match result {
+            Err(Error::IncompletePayment(payment)) => payment,
+            _ => panic!("Payment should fail fast while the receiver is unreachable"),
+        };
+        assert!(matches!(payment.error(), Error::SendMoneyError(_)));
+        let delivered_amount = payment.delivery().delivered_amount;
+        assert!(delivered_amount > 0 && delivered_amount < 100);
+
+        is_reachable.store(true, Ordering::SeqCst);
+        let receipt = resume_payment(server, &sender_account, sender_store(), *payment, 0.0)
+            .await
+            .unwrap();
+        assert_eq!(receipt.to, destination_account);
+        assert_eq!(receipt.delivered_amount, 100);
+    }
+
+    #[tokio::test]
+    async fn reports_progress_and_stops_when_cancelled() {
+        /// Cancels the payment once the given number of packets were fulfilled
+        #[derive(Clone)]
+        struct CancellingService<I> {
+            next: I,
+            num_fulfilled: Arc<AtomicUsize>,
+            cancel_after: usize,
+            cancellation: CancellationToken,
+        }
+
+        #[async_trait]
+        impl<I> IncomingService<TestAccount> for CancellingService<I>
+        where
+            I: IncomingService<TestAccount> + Send,
+        {
+            async fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> IlpResult {
+                let result = self.next.handle_request(request).await;
+                if result.is_ok()
+                    && self.num_fulfilled.fetch_add(1, Ordering::SeqCst) + 1 >= self.cancel_after
+                {
+                    self.cancellation.cancel();
+                }
+                result
+            }
+        }
+
+        let store = receiver_store();
+        let server = Router::new(store.clone(), test_receiver());
+        let server = MaxPacketAmountService::new(store, server);
+        let cancellation = CancellationToken::new();
+        let server = CancellingService {
+            next: server,
+            num_fulfilled: Arc::new(AtomicUsize::new(0)),
+            cancel_after: 3,
+            cancellation: cancellation.clone(),
+        };
+
+        let (destination_account, shared_secret) = connect();
+        let (progress, progress_receiver) = futures::channel::mpsc::unbounded();
+        let receipt = send_money_with_options(
+            server,
+            &TestAccount {
+                max_packet_amount: Some(10),
+                ..test_sender()
+            },
+            sender_store(),
+            destination_account,
+            shared_secret,
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                progress: Some(progress),
+                cancellation: Some(cancellation),
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // The packets in flight when the payment was cancelled are still accounted for
+        assert!(receipt.delivered_amount >= 30 && receipt.delivered_amount < 100);
+        assert_eq!(receipt.in_flight_amount, 0);
+
+        let progress: Vec<PaymentProgress> = progress_receiver.collect().await;
+        let fulfilled: Vec<&PaymentProgress> = progress
+            .iter()
+            .filter(|progress| progress.reject_code.is_none())
+            .collect();
+        assert_eq!(
+            fulfilled
+                .iter()
+                .map(|progress| progress.delivered_amount)
+                .sum::<u64>(),
+            receipt.delivered_amount
+        );
+        assert_eq!(
+            fulfilled.last().unwrap().delivery.delivered_amount,
+            receipt.delivered_amount
+        );
+    }
 }
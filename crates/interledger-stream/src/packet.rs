@@ -98,6 +98,14 @@ impl<'a> StreamPacketBuilder<'a> {
                     buffer_unencrypted.put_u8(FrameType::StreamDataBlocked as u8);
                     frame.put_contents(&mut contents);
                 }
+                Frame::StreamReceipt(ref frame) => {
+                    buffer_unencrypted.put_u8(FrameType::StreamReceipt as u8);
+                    frame.put_contents(&mut contents);
+                }
+                Frame::SourceAmount(ref frame) => {
+                    buffer_unencrypted.put_u8(FrameType::SourceAmount as u8);
+                    frame.put_contents(&mut contents);
+                }
                 Frame::Unknown => continue,
             }
             buffer_unencrypted.put_var_octet_string(contents);
@@ -283,6 +291,12 @@ impl<'a> FrameIterator<'a> {
             FrameType::StreamDataBlocked => {
                 Frame::StreamDataBlocked(StreamDataBlockedFrame::read_contents(&contents)?)
             }
+            FrameType::StreamReceipt => {
+                Frame::StreamReceipt(StreamReceiptFrame::read_contents(&contents)?)
+            }
+            FrameType::SourceAmount => {
+                Frame::SourceAmount(SourceAmountFrame::read_contents(&contents)?)
+            }
             FrameType::Unknown => {
                 warn!(
                     "Ignoring unknown frame of type {}: {:x?}",
@@ -345,6 +359,8 @@ pub enum Frame<'a> {
     StreamData(StreamDataFrame<'a>),
     StreamMaxData(StreamMaxDataFrame),
     StreamDataBlocked(StreamDataBlockedFrame),
+    StreamReceipt(StreamReceiptFrame<'a>),
+    SourceAmount(SourceAmountFrame),
     Unknown,
 }
 
@@ -365,6 +381,8 @@ impl<'a> fmt::Debug for Frame<'a> {
             Frame::StreamData(frame) => write!(f, "{:?}", frame),
             Frame::StreamMaxData(frame) => write!(f, "{:?}", frame),
             Frame::StreamDataBlocked(frame) => write!(f, "{:?}", frame),
+            Frame::StreamReceipt(frame) => write!(f, "{:?}", frame),
+            Frame::SourceAmount(frame) => write!(f, "{:?}", frame),
             Frame::Unknown => write!(f, "UnknownFrame"),
         }
     }
@@ -388,6 +406,8 @@ pub enum FrameType {
     StreamData = 0x14,
     StreamMaxData = 0x15,
     StreamDataBlocked = 0x16,
+    StreamReceipt = 0x17,
+    SourceAmount = 0x18,
     Unknown,
 }
 
@@ -408,6 +428,8 @@ impl From<u8> for FrameType {
             0x14 => FrameType::StreamData,
             0x15 => FrameType::StreamMaxData,
             0x16 => FrameType::StreamDataBlocked,
+            0x17 => FrameType::StreamReceipt,
+            0x18 => FrameType::SourceAmount,
             _ => FrameType::Unknown,
         }
     }
@@ -850,6 +872,57 @@ impl<'a> SerializableFrame<'a> for StreamDataBlockedFrame {
     }
 }
 
+/// Receipt proving the total amount received on a stream, [as specified in the RFC](https://interledger.org/rfcs/0039-stream-receipts/)
+#[derive(Debug, PartialEq, Clone)]
+pub struct StreamReceiptFrame<'a> {
+    /// Identifier of the stream this frame refers to.
+    pub stream_id: u64,
+    /// The encoded [receipt](../receipt/struct.Receipt.html), signed by the receiver
+    pub receipt: &'a [u8],
+}
+
+impl<'a> SerializableFrame<'a> for StreamReceiptFrame<'a> {
+    fn read_contents(mut reader: &'a [u8]) -> Result<Self, ParseError> {
+        let stream_id = reader.read_var_uint()?;
+        let receipt = reader.read_var_octet_string()?;
+
+        Ok(StreamReceiptFrame { stream_id, receipt })
+    }
+
+    fn put_contents(&self, buf: &mut impl MutBufOerExt) {
+        buf.put_var_uint(self.stream_id);
+        buf.put_var_octet_string(self.receipt);
+    }
+}
+
+/// The amount the sender sent the packet with, in its own asset, so that the receiver can tell
+/// the exchange rate the packet was converted at. This frame is not part of the RFC, so other
+/// implementations ignore it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceAmountFrame {
+    /// Amount of the ILP Prepare the sender sent.
+    pub source_amount: u64,
+    /// Asset scale of the sender's account.
+    pub source_asset_scale: u8,
+}
+
+impl<'a> SerializableFrame<'a> for SourceAmountFrame {
+    fn read_contents(mut reader: &[u8]) -> Result<Self, ParseError> {
+        let source_amount = reader.read_var_uint()?;
+        let source_asset_scale = reader.read_u8()?;
+
+        Ok(SourceAmountFrame {
+            source_amount,
+            source_asset_scale,
+        })
+    }
+
+    fn put_contents(&self, buf: &mut impl MutBufOerExt) {
+        buf.put_var_uint(self.source_amount);
+        buf.put_u8(self.source_asset_scale);
+    }
+}
+
 /// See: https://github.com/interledger/rfcs/blob/master/0029-stream/0029-stream.md#514-maximum-varuint-size
 fn saturating_read_var_uint<'a>(reader: &mut impl BufOerExt<'a>) -> Result<u64, ParseError> {
     if reader.peek_var_octet_string()?.len() > 8 {
@@ -972,6 +1045,64 @@ mod serialization {
         assert_eq!(iter.count(), 12);
     }
 
+    #[test]
+    fn it_serializes_receipt_frame() {
+        let packet = StreamPacketBuilder {
+            sequence: 1,
+            ilp_packet_type: IlpPacketType::Fulfill,
+            prepare_amount: 99,
+            frames: &[Frame::StreamReceipt(StreamReceiptFrame {
+                stream_id: 1,
+                receipt: &[1, 2, 3],
+            })],
+        }
+        .build();
+        assert_eq!(
+            &packet.buffer_unencrypted[..],
+            &[1, 13, 1, 1, 1, 99, 1, 1, 23, 5, 1, 1, 3, 1, 2, 3][..]
+        );
+        assert_eq!(
+            StreamPacket::from_bytes_unencrypted(packet.buffer_unencrypted.clone())
+                .unwrap()
+                .frames()
+                .next()
+                .unwrap(),
+            Frame::StreamReceipt(StreamReceiptFrame {
+                stream_id: 1,
+                receipt: &[1, 2, 3],
+            })
+        );
+    }
+
+    #[test]
+    fn it_serializes_source_amount_frame() {
+        let packet = StreamPacketBuilder {
+            sequence: 1,
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 99,
+            frames: &[Frame::SourceAmount(SourceAmountFrame {
+                source_amount: 1000,
+                source_asset_scale: 9,
+            })],
+        }
+        .build();
+        assert_eq!(
+            &packet.buffer_unencrypted[..],
+            &[1, 12, 1, 1, 1, 99, 1, 1, 24, 4, 2, 3, 232, 9][..]
+        );
+        assert_eq!(
+            StreamPacket::from_bytes_unencrypted(packet.buffer_unencrypted.clone())
+                .unwrap()
+                .frames()
+                .next()
+                .unwrap(),
+            Frame::SourceAmount(SourceAmountFrame {
+                source_amount: 1000,
+                source_asset_scale: 9,
+            })
+        );
+    }
+
     #[test]
     fn it_saturates_max_money_frame_receive_max() {
         let mut buffer = BytesMut::new();
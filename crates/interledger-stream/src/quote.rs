@@ -0,0 +1,354 @@
+use super::crypto::*;
+use super::error::Error;
+use super::packet::*;
+use bytes::{Bytes, BytesMut};
+use futures::future::join_all;
+use interledger_packet::{
+    Address, ErrorCode as IlpErrorCode, MaxPacketAmountDetails, PacketType as IlpPacketType,
+    PrepareBuilder,
+};
+use interledger_service::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+/// Largest amount that will be used to probe the path, in source units
+const MAX_PROBE_AMOUNT: u64 = 1_000_000_000_000;
+
+/// Quote for a STREAM payment, describing the exchange rate of the path to the receiver
+/// and how much the receiver is expected to get for the given source amount
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StreamQuote {
+    /// Sender's ILP Address
+    pub from: Address,
+    /// Receiver's ILP Address
+    pub to: Address,
+    /// Asset scale of sender
+    pub source_asset_scale: u8,
+    /// Asset code of sender
+    pub source_asset_code: String,
+    /// Amount the quote was requested for, in source units
+    pub source_amount: u64,
+    /// Estimated amount the receiver will get if the source amount is sent, in destination units
+    pub estimated_delivery_amount: u64,
+    /// Probed exchange rate of the path (destination units per source unit)
+    pub exchange_rate: f64,
+    /// Maximum packet amount allowed by the path, in source units, if one was discovered
+    pub max_packet_amount: Option<u64>,
+    /// Receiver's asset scale, if they reported it
+    pub destination_asset_scale: Option<u8>,
+    /// Receiver's asset code, if they reported it
+    pub destination_asset_code: Option<String>,
+}
+
+/// Result of a single unfulfillable test packet
+#[derive(Debug, Default)]
+struct ProbeResult {
+    /// Amount the probe was sent with, in source units
+    source_amount: u64,
+    /// Amount the receiver claims it received, if the probe reached them
+    received_amount: Option<u64>,
+    /// Maximum packet amount reported by a connector along the path, in source units
+    max_packet_amount: Option<u64>,
+    /// Receiver's asset details, if they were included in the reply
+    destination_asset_details: Option<(String, u8)>,
+}
+
+/// Probe the exchange rate and maximum packet amount of the path to the receiver by sending
+/// unfulfillable test packets of decreasing amounts, and return a quote for the given source amount.
+///
+/// No money is sent: every test packet uses a random condition so the receiver must reject it.
+pub async fn quote<I, A>(
+    service: I,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    source_amount: u64,
+) -> Result<StreamQuote, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+{
+    let shared_secret = Bytes::from(shared_secret);
+
+    // Probe with powers of 10 at or below the source amount, so that even if a connector
+    // along the path limits the packet amount, some of the probes should get through
+    let mut probe_amounts = Vec::new();
+    let mut amount = min(source_amount, MAX_PROBE_AMOUNT);
+    while amount > 0 {
+        probe_amounts.push(amount);
+        amount /= 10;
+    }
+
+    let results = join_all(probe_amounts.iter().enumerate().map(|(i, amount)| {
+        send_probe(
+            service.clone(),
+            from_account.clone(),
+            destination_account.clone(),
+            shared_secret.clone(),
+            i as u64 + 1,
+            *amount,
+        )
+    }))
+    .await;
+
+    let mut max_packet_amount: Option<u64> = None;
+    let mut destination_asset_details = None;
+    let mut best_probe: Option<(u64, u64)> = None;
+    for result in results.iter() {
+        if let Some(amount) = result.max_packet_amount {
+            max_packet_amount = Some(max_packet_amount.map_or(amount, |max| min(max, amount)));
+        }
+        if let Some(details) = result.destination_asset_details.clone() {
+            destination_asset_details = Some(details);
+        }
+        if let Some(received) = result.received_amount {
+            // The largest probe has the most precise exchange rate
+            if best_probe.map_or(true, |(sent, _)| result.source_amount > sent) {
+                best_probe = Some((result.source_amount, received));
+            }
+        }
+    }
+
+    // If the path limits the packet amount and none of the probes at or below that limit
+    // got through (for example because of rounding), try once more with the exact limit
+    if let Some(max_packet_amount) = max_packet_amount {
+        if best_probe.is_none() && max_packet_amount > 0 {
+            let result = send_probe(
+                service.clone(),
+                from_account.clone(),
+                destination_account.clone(),
+                shared_secret.clone(),
+                probe_amounts.len() as u64 + 1,
+                min(max_packet_amount, source_amount),
+            )
+            .await;
+            if let Some(details) = result.destination_asset_details {
+                destination_asset_details = Some(details);
+            }
+            if let Some(received) = result.received_amount {
+                best_probe = Some((result.source_amount, received));
+            }
+        }
+    }
+
+    let (probe_amount, probe_received) = best_probe.ok_or_else(|| {
+        Error::QuoteError("None of the test packets reached the receiver".to_string())
+    })?;
+
+    let exchange_rate = probe_received as f64 / probe_amount as f64;
+    // Round down, since this is an estimate of what the receiver will get
+    let estimated_delivery_amount = (source_amount as u128 * probe_received as u128
+        / probe_amount as u128)
+        .min(u64::MAX as u128) as u64;
+
+    debug!(
+        "Quoted payment of {} to {}: rate {}, estimated delivery {}, max packet amount {:?}",
+        source_amount,
+        destination_account,
+        exchange_rate,
+        estimated_delivery_amount,
+        max_packet_amount
+    );
+
+    let (destination_asset_code, destination_asset_scale) = match destination_asset_details {
+        Some((code, scale)) => (Some(code), Some(scale)),
+        None => (None, None),
+    };
+
+    Ok(StreamQuote {
+        from: from_account.ilp_address().clone(),
+        to: destination_account,
+        source_asset_scale: from_account.asset_scale(),
+        source_asset_code: from_account.asset_code().to_string(),
+        source_amount,
+        estimated_delivery_amount,
+        exchange_rate,
+        max_packet_amount,
+        destination_asset_scale,
+        destination_asset_code,
+    })
+}
+
+/// Send a single unfulfillable Prepare for the given amount and parse the receiver's reply
+async fn send_probe<I, A>(
+    mut service: I,
+    from_account: A,
+    destination_account: Address,
+    shared_secret: Bytes,
+    sequence: u64,
+    source_amount: u64,
+) -> ProbeResult
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    let stream_packet = StreamPacketBuilder {
+        ilp_packet_type: IlpPacketType::Prepare,
+        prepare_amount: 0,
+        sequence,
+        frames: &[
+            Frame::StreamMoney(StreamMoneyFrame {
+                stream_id: 1,
+                shares: 1,
+            }),
+            Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
+                source_account: from_account.ilp_address().clone(),
+            }),
+        ],
+    }
+    .build();
+    let data = stream_packet.into_encrypted(&shared_secret);
+
+    // Packet will always be rejected since the condition is random
+    let prepare = PrepareBuilder {
+        destination: destination_account,
+        amount: source_amount,
+        execution_condition: &random_condition(),
+        expires_at: SystemTime::now() + Duration::from_secs(30),
+        data: &data[..],
+    }
+    .build();
+
+    debug!(
+        "Sending test packet {} with amount: {}",
+        sequence, source_amount
+    );
+    let reply = service
+        .handle_request(IncomingRequest {
+            from: from_account,
+            prepare,
+        })
+        .await;
+
+    let mut result = ProbeResult {
+        source_amount,
+        ..Default::default()
+    };
+
+    let reject = match reply {
+        Ok(_) => {
+            warn!("Receiver fulfilled a test packet with a random condition, ignoring it");
+            return result;
+        }
+        Err(reject) => reject,
+    };
+
+    if reject.code() == IlpErrorCode::F08_AMOUNT_TOO_LARGE {
+        if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
+            if details.amount_received() > 0 {
+                let max_packet_amount = source_amount as u128 * details.max_amount() as u128
+                    / details.amount_received() as u128;
+                result.max_packet_amount = Some(min(max_packet_amount, u64::MAX as u128) as u64);
+            }
+        }
+        return result;
+    }
+
+    if let Ok(stream_packet) =
+        StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data()))
+    {
+        if stream_packet.sequence() != sequence
+            || stream_packet.ilp_packet_type() != IlpPacketType::Reject
+        {
+            warn!(
+                "Discarding reply to test packet {} (sequence {}, type {:?})",
+                sequence,
+                stream_packet.sequence(),
+                stream_packet.ilp_packet_type()
+            );
+            return result;
+        }
+
+        for frame in stream_packet.frames() {
+            if let Frame::ConnectionAssetDetails(frame) = frame {
+                result.destination_asset_details = Some((
+                    frame.source_asset_code.to_string(),
+                    frame.source_asset_scale,
+                ));
+            }
+        }
+        result.received_amount = Some(stream_packet.prepare_amount());
+    } else {
+        debug!(
+            "Test packet {} was rejected with code {} before reaching the receiver",
+            sequence,
+            reject.code()
+        );
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod quote_tests {
+    use super::*;
+    use crate::test_helpers::{DummyStore, TestAccount, TestStore, EXAMPLE_RECEIVER};
+    use crate::{ConnectionGenerator, StreamReceiverService};
+    use interledger_packet::RejectBuilder;
+    use interledger_router::Router;
+    use interledger_service::outgoing_service_fn;
+    use interledger_service_util::MaxPacketAmountService;
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn discovers_max_packet_amount_and_rate() {
+        let server_secret = bytes::Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let receiver_account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "ABC".to_string(),
+            asset_scale: 6,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), receiver_account)),
+            price_1: None,
+            price_2: None,
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: IlpErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let server = Router::new(store.clone(), server);
+        let server = MaxPacketAmountService::new(store, server);
+
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+
+        let quote = quote(
+            server,
+            &TestAccount {
+                id: Uuid::new_v4(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                max_packet_amount: Some(5000),
+            },
+            destination_account,
+            shared_secret.to_vec(),
+            123_456,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(quote.max_packet_amount, Some(5000));
+        assert_eq!(quote.exchange_rate, 1.0);
+        assert_eq!(quote.estimated_delivery_amount, 123_456);
+        assert_eq!(quote.destination_asset_code, Some("ABC".to_string()));
+        assert_eq!(quote.destination_asset_scale, Some(6));
+    }
+}
@@ -0,0 +1,160 @@
+use super::crypto::hmac_sha256;
+use super::error::Error;
+use byteorder::{BigEndian, ReadBytesExt};
+use bytes::BufMut;
+use interledger_packet::oer::{BufOerExt, MutBufOerExt};
+use ring::constant_time;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// The receipt format version defined in RFC 0039
+const RECEIPT_VERSION: u8 = 1;
+const NONCE_LENGTH: usize = 16;
+const SECRET_LENGTH: usize = 32;
+const HMAC_LENGTH: usize = 32;
+
+/// The nonce and secret a verifier gives the receiver so that it can issue
+/// receipts for a connection. Both are passed to the receiver via SPSP.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceiptDetails {
+    pub nonce: [u8; NONCE_LENGTH],
+    pub secret: [u8; SECRET_LENGTH],
+}
+
+impl ReceiptDetails {
+    /// Builds the receipt details from the raw nonce and secret, returning
+    /// `None` if either has the wrong length
+    pub fn from_slices(nonce: &[u8], secret: &[u8]) -> Option<Self> {
+        if nonce.len() != NONCE_LENGTH || secret.len() != SECRET_LENGTH {
+            return None;
+        }
+        let mut details = ReceiptDetails {
+            nonce: [0; NONCE_LENGTH],
+            secret: [0; SECRET_LENGTH],
+        };
+        details.nonce.copy_from_slice(nonce);
+        details.secret.copy_from_slice(secret);
+        Some(details)
+    }
+
+    /// Generates a random nonce and secret
+    pub fn random() -> Self {
+        let rng = SystemRandom::new();
+        let mut details = ReceiptDetails {
+            nonce: [0; NONCE_LENGTH],
+            secret: [0; SECRET_LENGTH],
+        };
+        rng.fill(&mut details.nonce)
+            .expect("Failed to securely generate a receipt nonce!");
+        rng.fill(&mut details.secret)
+            .expect("Failed to securely generate a receipt secret!");
+        details
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(NONCE_LENGTH + SECRET_LENGTH);
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.secret);
+        bytes
+    }
+}
+
+/// A verified STREAM receipt, proving that the receiver got at least
+/// `total_received` on the given stream of the connection identified by `nonce`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Receipt {
+    pub nonce: [u8; NONCE_LENGTH],
+    pub stream_id: u64,
+    pub total_received: u64,
+}
+
+/// Creates a receipt for the total amount received on a stream, signed with the verifier's secret
+pub fn create_receipt(details: &ReceiptDetails, stream_id: u64, total_received: u64) -> Vec<u8> {
+    let mut receipt = Vec::with_capacity(1 + NONCE_LENGTH + 9 + 8 + HMAC_LENGTH);
+    receipt.put_u8(RECEIPT_VERSION);
+    receipt.put_slice(&details.nonce);
+    receipt.put_var_uint(stream_id);
+    receipt.put_u64_be(total_received);
+    let hmac = hmac_sha256(&details.secret, &receipt);
+    receipt.put_slice(&hmac);
+    receipt
+}
+
+/// Decodes a receipt without checking its signature
+pub fn decode_receipt(receipt: &[u8]) -> Result<Receipt, Error> {
+    let invalid = |reason: &str| Error::InvalidReceipt(reason.to_string());
+
+    let mut reader = receipt;
+    let version = reader.read_u8().map_err(|_| invalid("receipt is empty"))?;
+    if version != RECEIPT_VERSION {
+        return Err(invalid("unsupported receipt version"));
+    }
+    if reader.len() < NONCE_LENGTH {
+        return Err(invalid("receipt is too short"));
+    }
+    let mut nonce = [0; NONCE_LENGTH];
+    nonce.copy_from_slice(&reader[..NONCE_LENGTH]);
+    reader = &reader[NONCE_LENGTH..];
+    let stream_id = reader
+        .read_var_uint()
+        .map_err(|_| invalid("invalid stream id"))?;
+    let total_received = reader
+        .read_u64::<BigEndian>()
+        .map_err(|_| invalid("invalid total received"))?;
+    if reader.len() != HMAC_LENGTH {
+        return Err(invalid("invalid receipt length"));
+    }
+
+    Ok(Receipt {
+        nonce,
+        stream_id,
+        total_received,
+    })
+}
+
+/// Checks that the receipt was signed with the given secret and decodes it.
+///
+/// This is what third parties use to confirm that a receiver got paid,
+/// without having to trust the sender.
+pub fn verify_receipt(receipt: &[u8], secret: &[u8]) -> Result<Receipt, Error> {
+    let decoded = decode_receipt(receipt)?;
+    let (message, hmac) = receipt.split_at(receipt.len() - HMAC_LENGTH);
+    let expected_hmac = hmac_sha256(secret, message);
+    if constant_time::verify_slices_are_equal(&expected_hmac, hmac).is_err() {
+        return Err(Error::InvalidReceipt("invalid signature".to_string()));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_and_verifies_receipts() {
+        let details = ReceiptDetails::random();
+        let receipt = create_receipt(&details, 1, 1000);
+        assert_eq!(receipt.len(), 1 + 16 + 2 + 8 + 32);
+        assert_eq!(
+            verify_receipt(&receipt, &details.secret).unwrap(),
+            Receipt {
+                nonce: details.nonce,
+                stream_id: 1,
+                total_received: 1000,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_receipts() {
+        let details = ReceiptDetails::random();
+        let mut receipt = create_receipt(&details, 1, 1000);
+        assert!(verify_receipt(&receipt, &[0; 32]).is_err());
+
+        // bump the total received
+        receipt[26] += 1;
+        assert!(decode_receipt(&receipt).is_ok());
+        assert!(verify_receipt(&receipt, &details.secret).is_err());
+
+        assert!(verify_receipt(&receipt[..40], &details.secret).is_err());
+    }
+}
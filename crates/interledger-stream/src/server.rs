@@ -1,19 +1,29 @@
 use super::crypto::*;
-use super::packet::*;
+use super::error::Error;
+use super::packet::{ErrorCode as StreamErrorCode, *};
+use super::receipt::{create_receipt, ReceiptDetails};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use chrono::{DateTime, Utc};
-use futures::channel::mpsc::UnboundedSender;
+use futures::channel::mpsc::{Sender, UnboundedSender};
+use interledger_errors::IncomingPaymentStoreError;
 use interledger_packet::{
     Address, ErrorCode, Fulfill, FulfillBuilder, PacketType as IlpPacketType, Prepare, Reject,
     RejectBuilder,
 };
 use interledger_service::{Account, IlpResult, OutgoingRequest, OutgoingService, Username};
+use num::rational::BigRational;
+use num::traits::cast::FromPrimitive;
+use num::traits::pow::pow;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::marker::PhantomData;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::broadcast;
-use tracing::debug;
+use tracing::{debug, error};
 use uuid::Uuid;
 
 // Note we are using the same magic bytes as the Javascript
@@ -24,6 +34,22 @@ use uuid::Uuid;
 // running the same STREAM implementation so it doesn't matter what
 // this string is.
 const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_shared_secret";
+/// Used to derive the key the receipt nonce and secret are encrypted with inside connection tokens
+const RECEIPT_DETAILS_ENCRYPTION_STRING: &[u8] = b"ilp_stream_receipt_details";
+/// Length of the encrypted receipt nonce and secret: the AES-GCM nonce, auth tag and the encrypted 48 bytes
+const ENCRYPTED_RECEIPT_DETAILS_LENGTH: usize = 12 + 16 + 48;
+
+/// Number of connections the in-memory connection store holds by default
+const DEFAULT_MAX_CONNECTIONS: usize = 10_000;
+/// How long a connection may go without packets before the in-memory connection store may drop it
+const DEFAULT_CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Separates the random token from the connection tag in the last segment of a connection's address
+const CONNECTION_TAG_SEPARATOR: char = '~';
+
+/// Number of bytes each stream buffers beyond the data delivered to the application
+/// (the sender may send data up to this many bytes past the data delivered)
+const MAX_BUFFERED_DATA: u64 = 65_536;
 
 /// A STREAM connection generator that creates `destination_account` and `shared_secret` values
 /// based on a single root secret.
@@ -62,6 +88,108 @@ impl ConnectionGenerator {
         (destination_account, shared_secret)
     }
 
+    /// Generate the STREAM parameters for a connection the receiver should issue receipts for.
+    ///
+    /// The receipt nonce and secret are encrypted into the token, so that the receiver
+    /// can recover them from the destination of each packet without storing them.
+    pub fn generate_address_and_secret_with_receipts(
+        &self,
+        base_address: &Address,
+        receipt_details: &ReceiptDetails,
+    ) -> (Address, [u8; 32]) {
+        let encrypted_details = encrypt(
+            &self.receipt_details_key(),
+            BytesMut::from(receipt_details.to_bytes()),
+        );
+        let token = base64::encode_config(&encrypted_details, base64::URL_SAFE_NO_PAD);
+        let shared_secret = hmac_sha256(&self.secret_generator[..], &token.as_bytes()[..]);
+        let destination_account = base_address.with_suffix(&token.as_ref()).unwrap();
+
+        debug!("Generated address with receipts: {}", destination_account);
+        (destination_account, shared_secret)
+    }
+
+    /// Generate the STREAM parameters for a connection with the given tag, such as the id of the
+    /// invoice or order the connection is paying for. The tag is included in the
+    /// [`PaymentNotification`](./struct.PaymentNotification.html) of each packet received on the connection.
+    ///
+    /// The tag is appended to the token, and since the shared secret is derived from both, a sender
+    /// cannot change the tag without the receiver failing to decrypt its packets.
+    /// Tags may only contain alphanumeric characters, `_` and `-`.
+    pub fn generate_tagged_address_and_secret(
+        &self,
+        base_address: &Address,
+        tag: &str,
+        receipt_details: Option<&ReceiptDetails>,
+    ) -> Result<(Address, [u8; 32]), Error> {
+        let is_valid_tag = !tag.is_empty()
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !is_valid_tag {
+            return Err(Error::InvalidConnectionTag(tag.to_string()));
+        }
+
+        let token = match receipt_details {
+            Some(receipt_details) => encrypt(
+                &self.receipt_details_key(),
+                BytesMut::from(receipt_details.to_bytes()),
+            )
+            .to_vec(),
+            None => generate_token().to_vec(),
+        };
+        let token = format!(
+            "{}{}{}",
+            base64::encode_config(&token, base64::URL_SAFE_NO_PAD),
+            CONNECTION_TAG_SEPARATOR,
+            tag
+        );
+        let shared_secret = hmac_sha256(&self.secret_generator[..], &token.as_bytes()[..]);
+        let destination_account = base_address
+            .with_suffix(&token.as_ref())
+            .map_err(|_| Error::InvalidConnectionTag(tag.to_string()))?;
+
+        debug!("Generated tagged address: {}", destination_account);
+        Ok((destination_account, shared_secret))
+    }
+
+    /// Get the tag of the connection a `destination_account` belongs to, if it was generated with
+    /// [`generate_tagged_address_and_secret`](#method.generate_tagged_address_and_secret).
+    ///
+    /// Note the tag is only authentic if the packet sent to the address could be decrypted.
+    pub fn connection_tag(destination_account: &Address) -> Option<&str> {
+        let local_part = destination_account.segments().rev().next()?;
+        let mut parts = local_part.splitn(2, CONNECTION_TAG_SEPARATOR);
+        parts.next();
+        parts.next().filter(|tag| !tag.is_empty())
+    }
+
+    /// Recover the receipt nonce and secret from a `destination_account`, if it was generated
+    /// with [`generate_address_and_secret_with_receipts`](#method.generate_address_and_secret_with_receipts)
+    pub fn receipt_details(&self, destination_account: &Address) -> Option<ReceiptDetails> {
+        let local_part = destination_account.segments().rev().next()?;
+        let token = local_part.split(CONNECTION_TAG_SEPARATOR).next()?;
+        let encrypted_details = base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()?;
+        // Plain tokens are much shorter, so don't bother trying to decrypt them
+        if encrypted_details.len() != ENCRYPTED_RECEIPT_DETAILS_LENGTH {
+            return None;
+        }
+        let details = decrypt(
+            &self.receipt_details_key(),
+            BytesMut::from(encrypted_details),
+        )
+        .ok()?;
+        let (nonce, secret) = details.split_at(16);
+        ReceiptDetails::from_slices(nonce, secret)
+    }
+
+    fn receipt_details_key(&self) -> [u8; 32] {
+        hmac_sha256(
+            &self.secret_generator[..],
+            RECEIPT_DETAILS_ENCRYPTION_STRING,
+        )
+    }
+
     /// Rederive the `shared_secret` from a `destination_account`.
     ///
     /// Although it is not strictly necessary, this uses the same logic as the Javascript
@@ -97,6 +225,22 @@ pub struct PaymentNotification {
     pub amount: u64,
     /// The time this payment notification was fired in RFC3339 format
     pub timestamp: String,
+    /// Asset code of the account that received the payment
+    #[serde(default)]
+    pub asset_code: String,
+    /// Asset scale of the account that received the payment
+    #[serde(default)]
+    pub asset_scale: u8,
+    /// The tag the receiver gave the connection when generating its address, if any
+    #[serde(default)]
+    pub connection_tag: Option<String>,
+    /// Total amount received on the connection so far, including this payment.
+    /// Only available if the receiver tracks connection state
+    #[serde(default)]
+    pub connection_total_received: Option<u64>,
+    /// Whether this payment completed the incoming payment identified by the connection tag
+    #[serde(default)]
+    pub incoming_payment_completed: bool,
 }
 
 /// A trait representing the Publish side of a pub/sub store
@@ -118,18 +262,405 @@ pub trait StreamNotificationsStore {
     fn all_payment_subscription(&self) -> broadcast::Receiver<PaymentNotification>;
 }
 
+/// The outcome of reserving part of an incoming payment for a packet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IncomingPaymentReservation {
+    /// The account has no incoming payment with the connection's tag
+    NotFound,
+    /// The amount was reserved for the packet. `completed` is set if it completed the incoming payment
+    Reserved { completed: bool },
+    /// The incoming payment is completed or expired, so it does not accept anything more
+    Closed,
+    /// The amount exceeds what the incoming payment still expects
+    ExceedsRemaining,
+}
+
+/// A store for incoming payments, such as invoices, which are paid over connections
+/// tagged with the incoming payment's id
+#[async_trait]
+pub trait IncomingPaymentStore {
+    /// Atomically reserve the amount of a packet for the incoming payment with the given id,
+    /// so that concurrent packets cannot together receive more than the incoming payment expects.
+    /// Nothing is reserved unless `Reserved` is returned
+    async fn reserve_incoming_payment(
+        &self,
+        account_id: Uuid,
+        id: &str,
+        amount: u64,
+    ) -> Result<IncomingPaymentReservation, IncomingPaymentStoreError>;
+
+    /// Release an amount reserved for a packet which was rejected after all
+    async fn release_incoming_payment(
+        &self,
+        account_id: Uuid,
+        id: &str,
+        amount: u64,
+    ) -> Result<(), IncomingPaymentStoreError>;
+}
+
+/// An account which may refuse STREAM packets that carry too little money or that were
+/// converted at too low an exchange rate on their way to the account
+pub trait StreamReceiverAccount: Account {
+    /// The minimum amount each packet carrying money must deliver to the account.
+    /// Packets carrying less are rejected, but the connection is left open
+    fn min_incoming_packet_amount(&self) -> u64 {
+        0
+    }
+
+    /// The minimum exchange rate, in units of the account's asset per unit of the sender's
+    /// asset, that the account accepts. Packets converted at a lower rate are rejected and the
+    /// connection is closed with an `ApplicationError`.
+    ///
+    /// The rate is measured from the source amount the sender declares in the packet's
+    /// `SourceAmount` frame. Packets carrying money without that frame are refused the same way,
+    /// since their rate can't be checked.
+    fn min_incoming_exchange_rate(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// The limits the receiving account sets on the packet being received
+#[derive(Clone, Copy, Debug, Default)]
+struct IncomingLimits {
+    /// The minimum amount of each packet carrying money
+    min_packet_amount: u64,
+    /// The minimum exchange rate the account accepts, if any
+    min_exchange_rate: Option<f64>,
+    /// The outcome of reserving the packet's amount on the incoming payment the connection is tagged with
+    incoming_payment: Option<IncomingPaymentReservation>,
+}
+
+impl IncomingLimits {
+    fn new<A: StreamReceiverAccount>(request: &OutgoingRequest<A>) -> Self {
+        IncomingLimits {
+            min_packet_amount: request.to.min_incoming_packet_amount(),
+            min_exchange_rate: request.to.min_incoming_exchange_rate(),
+            incoming_payment: None,
+        }
+    }
+}
+
+/// Whether converting the source amount into the destination amount, after adjusting
+/// both for their asset scales, was done at a lower rate than the given minimum
+fn is_below_min_exchange_rate(
+    source_amount: u64,
+    source_scale: u8,
+    dest_amount: u64,
+    dest_scale: u8,
+    min_rate: f64,
+) -> bool {
+    // Nothing was converted, so the rate doesn't matter
+    if source_amount == 0 {
+        return false;
+    }
+    let min_rate = match BigRational::from_f64(min_rate) {
+        Some(min_rate) => min_rate,
+        None => return false,
+    };
+    let ten = BigRational::from_u64(10).unwrap();
+    let source_amount =
+        BigRational::from_u64(source_amount).unwrap() / pow(ten.clone(), source_scale as usize);
+    let dest_amount = BigRational::from_u64(dest_amount).unwrap() / pow(ten, dest_scale as usize);
+    dest_amount < source_amount * min_rate
+}
+
+/// Amount of money and data received on a single stream of a STREAM connection
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamState {
+    /// Total amount received on this stream, in the receiver's units
+    pub total_received: u64,
+    /// Whether the stream was closed by either endpoint
+    pub closed: bool,
+    /// Offset up to which data was received in order and delivered to the application
+    pub data_offset: u64,
+    /// Data received but not delivered to the application yet, by offset.
+    /// The chunks never overlap
+    pub pending_data: BTreeMap<u64, Vec<u8>>,
+}
+
+impl StreamState {
+    /// Largest offset the sender may send data up to. Data the application has not
+    /// taken yet uses up buffer space, so this only grows as the application reads
+    pub fn max_data_offset(&self) -> u64 {
+        self.data_offset.saturating_add(MAX_BUFFERED_DATA)
+    }
+}
+
+/// Application data received in order on a stream of a STREAM connection
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamDataMessage {
+    /// The connection's id, which is the last segment of its ILP address
+    pub connection_id: String,
+    /// The stream the data was sent on
+    pub stream_id: u64,
+    /// The data, which directly follows the data previously delivered for this stream
+    pub data: Vec<u8>,
+}
+
+/// State of a STREAM connection, as tracked by the receiver
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectionState {
+    /// Total amount received on all of the connection's streams, in the receiver's units
+    pub total_received: u64,
+    /// State of each stream the sender has sent money on, by stream id
+    pub streams: HashMap<u64, StreamState>,
+    /// Whether the connection was closed by either endpoint
+    pub closed: bool,
+}
+
+impl ConnectionState {
+    /// Credit the amount to the given streams in proportion to their shares, unless a stream is closed
+    /// or would receive more than the receive maximum. Streams that reach the maximum are closed.
+    ///
+    /// Returns whether the money was accepted.
+    fn try_receive(
+        &mut self,
+        amount: u64,
+        money_frames: &[StreamMoneyFrame],
+        receive_max: u64,
+    ) -> bool {
+        if self.closed {
+            return false;
+        }
+        let total_shares: u128 = money_frames.iter().map(|frame| frame.shares as u128).sum();
+        if total_shares == 0 {
+            return amount == 0;
+        }
+
+        // Amounts are rounded down and the remainder goes to the lowest-numbered stream
+        let mut stream_amounts: Vec<(u64, u64)> = money_frames
+            .iter()
+            .map(|frame| {
+                let stream_amount = amount as u128 * frame.shares as u128 / total_shares;
+                (frame.stream_id, stream_amount as u64)
+            })
+            .collect();
+        let remainder = amount - stream_amounts.iter().map(|(_, amount)| amount).sum::<u64>();
+        if let Some(lowest) = stream_amounts
+            .iter_mut()
+            .min_by_key(|(stream_id, _)| *stream_id)
+        {
+            lowest.1 += remainder;
+        }
+
+        for (stream_id, stream_amount) in stream_amounts.iter() {
+            let stream = self.streams.get(stream_id).cloned().unwrap_or_default();
+            let exceeds_max = stream
+                .total_received
+                .checked_add(*stream_amount)
+                .map_or(true, |total| total > receive_max);
+            if stream.closed || exceeds_max {
+                return false;
+            }
+        }
+
+        for (stream_id, stream_amount) in stream_amounts {
+            let stream = self.streams.entry(stream_id).or_default();
+            stream.total_received += stream_amount;
+            if stream.total_received == receive_max {
+                stream.closed = true;
+            }
+        }
+        self.total_received = self.total_received.saturating_add(amount);
+        true
+    }
+
+    /// Buffer data received on a stream until it can be delivered in order.
+    ///
+    /// Fails if the stream is closed already, and closes the stream if the data goes past
+    /// how much the stream is willing to buffer.
+    fn receive_data(
+        &mut self,
+        stream_id: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), StreamErrorCode> {
+        let stream = self.streams.entry(stream_id).or_default();
+        if stream.closed {
+            return Err(StreamErrorCode::StreamStateError);
+        }
+        let end_offset = offset.saturating_add(data.len() as u64);
+        if end_offset > stream.max_data_offset() {
+            stream.closed = true;
+            return Err(StreamErrorCode::FlowControlError);
+        }
+
+        // Only buffer what was not delivered or buffered already, such as if the
+        // sender retried a packet or split its data differently
+        let mut start = max(offset, stream.data_offset);
+        if let Some((chunk_offset, chunk)) = stream.pending_data.range(..=start).next_back() {
+            start = max(start, chunk_offset + chunk.len() as u64);
+        }
+        while start < end_offset {
+            let next_chunk = stream
+                .pending_data
+                .range(start..end_offset)
+                .next()
+                .map(|(chunk_offset, chunk)| (*chunk_offset, chunk.len() as u64));
+            let gap_end = next_chunk.map_or(end_offset, |(chunk_offset, _)| chunk_offset);
+            if gap_end > start {
+                let gap = &data[(start - offset) as usize..(gap_end - offset) as usize];
+                stream.pending_data.insert(start, gap.to_vec());
+            }
+            match next_chunk {
+                Some((chunk_offset, chunk_len)) => start = chunk_offset + chunk_len,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass the data of each stream that can be delivered in order to `deliver`.
+    /// Data it hands back stays buffered, which keeps the sender from sending much more
+    /// until it can be delivered
+    fn deliver_data(&mut self, deliver: &mut dyn FnMut(u64, Vec<u8>) -> Result<(), Vec<u8>>) {
+        for (stream_id, stream) in self.streams.iter_mut() {
+            let mut data: Vec<u8> = Vec::new();
+            while let Some(chunk) = stream
+                .pending_data
+                .remove(&(stream.data_offset + data.len() as u64))
+            {
+                data.extend(chunk);
+            }
+            if data.is_empty() {
+                continue;
+            }
+            let len = data.len() as u64;
+            match deliver(*stream_id, data) {
+                Ok(()) => stream.data_offset += len,
+                Err(data) => {
+                    stream.pending_data.insert(stream.data_offset, data);
+                }
+            }
+        }
+    }
+}
+
+/// A store for the state of the connections a STREAM receiver is handling
+pub trait StreamConnectionStore {
+    /// *Atomically* apply the update to the state of the connection with the given id,
+    /// starting from the default state if the connection is new
+    fn update_connection(&self, connection_id: &str, update: &mut dyn FnMut(&mut ConnectionState));
+
+    /// Get the state of the connection with the given id, if any packets were received for it
+    fn get_connection(&self, connection_id: &str) -> Option<ConnectionState>;
+}
+
+/// A [StreamConnectionStore](./trait.StreamConnectionStore.html) which keeps the connections' state in memory.
+///
+/// The store holds a bounded number of connections. Once it is full, connections which were
+/// idle for longer than the idle timeout are dropped to make room for new ones, or else the
+/// connection which was idle the longest. A connection which is dropped starts over from the
+/// default state if more packets arrive for it.
+#[derive(Clone)]
+pub struct InMemoryConnectionStore {
+    /// The state of each connection and when it was last updated, by connection id
+    connections: Arc<RwLock<HashMap<String, (ConnectionState, Instant)>>>,
+    max_connections: usize,
+    idle_timeout: Duration,
+}
+
+impl InMemoryConnectionStore {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_CONNECTIONS, DEFAULT_CONNECTION_IDLE_TIMEOUT)
+    }
+
+    /// Hold at most `max_connections` connections, preferring to drop the ones which were
+    /// not updated for longer than `idle_timeout` when it is full
+    pub fn with_limits(max_connections: usize, idle_timeout: Duration) -> Self {
+        InMemoryConnectionStore {
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            max_connections: max_connections.max(1),
+            idle_timeout,
+        }
+    }
+}
+
+impl Default for InMemoryConnectionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamConnectionStore for InMemoryConnectionStore {
+    fn update_connection(&self, connection_id: &str, update: &mut dyn FnMut(&mut ConnectionState)) {
+        let mut connections = self.connections.write();
+        let now = Instant::now();
+        if !connections.contains_key(connection_id) && connections.len() >= self.max_connections {
+            let idle_timeout = self.idle_timeout;
+            connections
+                .retain(|_, (_, last_updated)| now.duration_since(*last_updated) < idle_timeout);
+            if connections.len() >= self.max_connections {
+                let least_recent = connections
+                    .iter()
+                    .min_by_key(|(_, (_, last_updated))| *last_updated)
+                    .map(|(id, _)| id.clone());
+                if let Some(id) = least_recent {
+                    connections.remove(&id);
+                }
+            }
+        }
+        let (connection, last_updated) = connections
+            .entry(connection_id.to_string())
+            .or_insert_with(|| (ConnectionState::default(), now));
+        update(connection);
+        *last_updated = now;
+    }
+
+    fn get_connection(&self, connection_id: &str) -> Option<ConnectionState> {
+        self.connections
+            .read()
+            .get(connection_id)
+            .map(|(connection, _)| connection.clone())
+    }
+}
+
+/// The connection store and receive maximum used when the receiver tracks connection state
+#[derive(Clone)]
+struct ConnectionTracking {
+    store: Arc<dyn StreamConnectionStore + Send + Sync>,
+    /// Maximum amount each stream may receive, in the receiver's units
+    receive_max: u64,
+}
+
+/// A packet's connection, when the receiver tracks connection state
+struct ConnectionContext<'a> {
+    /// The connection's id, which is the last segment of its ILP address
+    connection_id: &'a str,
+    tracking: &'a ConnectionTracking,
+    /// Where to deliver the data received on the connection's streams
+    data_sender: Option<&'a Mutex<Sender<StreamDataMessage>>>,
+    /// Used to sign receipts, if the connection was set up with them
+    receipt_details: Option<ReceiptDetails>,
+}
+
 /// An OutgoingService that fulfills incoming STREAM packets.
 ///
-/// Note this does **not** maintain STREAM state, but instead fulfills
-/// all incoming packets to collect the money.
+/// By default this does **not** maintain STREAM state, but instead fulfills
+/// all incoming packets to collect the money. Use
+/// [`with_connection_store`](#method.with_connection_store) to track the amount received
+/// per connection and stream and to limit how much each stream may receive.
 ///
-/// This does not currently support handling data sent via STREAM.
+/// Data sent via STREAM is only handled if the connection state is tracked, in which case
+/// it is delivered in order to the channel given to [`with_data_channel`](#method.with_data_channel).
+/// Likewise, receipts are only issued for tracked connections.
+///
+/// Connections tagged with the id of an incoming payment are limited to the amount the incoming
+/// payment still expects, if an [incoming payment store](#method.with_incoming_payment_store) is set.
+///
+/// Packets carrying less than the receiving account's minimum amount, or converted at a lower
+/// rate than it accepts, are rejected (see [`StreamReceiverAccount`](./trait.StreamReceiverAccount.html)).
 #[derive(Clone)]
 pub struct StreamReceiverService<S, O: OutgoingService<A>, A: Account> {
     connection_generator: ConnectionGenerator,
     next: O,
     account_type: PhantomData<A>,
     store: S,
+    connections: Option<ConnectionTracking>,
+    /// Whether only the connections set up with receipts are tracked
+    track_receipt_connections_only: bool,
+    data_sender: Option<Arc<Mutex<Sender<StreamDataMessage>>>>,
+    incoming_payments: Option<Arc<dyn IncomingPaymentStore + Send + Sync>>,
 }
 
 impl<S, O, A> StreamReceiverService<S, O, A>
@@ -145,8 +676,54 @@ where
             next,
             account_type: PhantomData,
             store,
+            connections: None,
+            track_receipt_connections_only: false,
+            data_sender: None,
+            incoming_payments: None,
         }
     }
+
+    /// Track the state of each connection in the given store, so that the receiver reports the
+    /// actual amounts received on each stream and closes streams once they have received
+    /// `receive_max` (denominated in the receiving account's units)
+    pub fn with_connection_store<C>(mut self, connections: C, receive_max: u64) -> Self
+    where
+        C: StreamConnectionStore + Send + Sync + 'static,
+    {
+        self.connections = Some(ConnectionTracking {
+            store: Arc::new(connections),
+            receive_max,
+        });
+        self
+    }
+
+    /// Only track the state of connections which were set up with receipts, which need the
+    /// total received on each stream to sign them. Money on other connections is fulfilled
+    /// without tracking it, and data sent on them is ignored
+    pub fn track_receipt_connections_only(mut self) -> Self {
+        self.track_receipt_connections_only = true;
+        self
+    }
+
+    /// Send the data received on each stream to the given channel, in order.
+    /// This requires the connection state to be tracked with a connection store.
+    ///
+    /// While the channel is full, received data is buffered and senders may not send more
+    /// than 64 KiB past the data the channel took on each stream
+    pub fn with_data_channel(mut self, data_sender: Sender<StreamDataMessage>) -> Self {
+        self.data_sender = Some(Arc::new(Mutex::new(data_sender)));
+        self
+    }
+
+    /// Credit the money received on connections tagged with an incoming payment's id to the
+    /// incoming payment, and reject money once it is completed or expired
+    pub fn with_incoming_payment_store<P>(mut self, incoming_payments: P) -> Self
+    where
+        P: IncomingPaymentStore + Send + Sync + 'static,
+    {
+        self.incoming_payments = Some(Arc::new(incoming_payments));
+        self
+    }
 }
 
 #[async_trait]
@@ -154,7 +731,7 @@ impl<S, O, A> OutgoingService<A> for StreamReceiverService<S, O, A>
 where
     S: StreamNotificationsStore + Send + Sync + 'static + Clone,
     O: OutgoingService<A> + Send + Sync + Clone,
-    A: Account + Send + Sync + Clone,
+    A: StreamReceiverAccount + Send + Sync + Clone,
 {
     /// Try fulfilling the request if it is for this STREAM server or pass it to the next
     /// outgoing handler if not.
@@ -171,21 +748,112 @@ where
         // The case where the request is bound for this server
         if dest.starts_with(to_address.as_ref()) {
             if let Ok(shared_secret) = self.connection_generator.rederive_secret(&destination) {
+                // The connection is identified by the token the connection generator appended to our address
+                let connection_id = destination.segments().rev().next().unwrap_or_default();
+                let connection_tag = ConnectionGenerator::connection_tag(&destination);
+                let account_id = request.to.id();
+
+                // Only fulfillable packets carrying money need to reserve part of the incoming payment.
+                // Probes, such as the unfulfillable packets used for quotes, get a STREAM reply
+                let reserving = match (&self.incoming_payments, connection_tag) {
+                    (Some(incoming_payments), Some(tag))
+                        if amount > 0 && is_fulfillable(&shared_secret, &request.prepare) =>
+                    {
+                        Some((incoming_payments.clone(), tag))
+                    }
+                    _ => None,
+                };
+                let reservation = match reserving.as_ref() {
+                    Some((incoming_payments, tag)) => Some(
+                        incoming_payments
+                            .reserve_incoming_payment(account_id, tag, amount)
+                            .await
+                            .map_err(|err| {
+                                error!(
+                                    "Error reserving {} on incoming payment {}: {}",
+                                    amount, tag, err
+                                );
+                                RejectBuilder {
+                                    code: ErrorCode::T00_INTERNAL_ERROR,
+                                    message: b"Error loading incoming payment",
+                                    triggered_by: Some(&to_address),
+                                    data: &[],
+                                }
+                                .build()
+                            })?,
+                    ),
+                    None => None,
+                };
+
+                let connection_generator = &self.connection_generator;
+                let data_sender = self.data_sender.as_deref();
+                let receipt_details = connection_generator.receipt_details(&destination);
+                let track_receipt_connections_only = self.track_receipt_connections_only;
+                let connection = self
+                    .connections
+                    .as_ref()
+                    .filter(|_| !track_receipt_connections_only || receipt_details.is_some())
+                    .map(|tracking| ConnectionContext {
+                        connection_id,
+                        tracking,
+                        data_sender,
+                        receipt_details,
+                    });
                 let response = receive_money(
                     &shared_secret,
                     &to_address,
                     request.to.asset_code(),
                     request.to.asset_scale(),
                     &request.prepare,
+                    IncomingLimits {
+                        incoming_payment: reservation,
+                        ..IncomingLimits::new(&request)
+                    },
+                    connection,
                 );
+                // Rejected packets give back what they reserved
+                if let (
+                    Err(_),
+                    Some((incoming_payments, tag)),
+                    Some(IncomingPaymentReservation::Reserved { .. }),
+                ) = (&response, reserving.as_ref(), reservation)
+                {
+                    if let Err(err) = incoming_payments
+                        .release_incoming_payment(account_id, tag, amount)
+                        .await
+                    {
+                        error!(
+                            "Error releasing {} reserved on incoming payment {}: {}",
+                            amount, tag, err
+                        );
+                    }
+                }
                 match response {
-                    Ok(ref _fulfill) => store.publish_payment_notification(PaymentNotification {
-                        to_username,
-                        from_username,
-                        amount,
-                        destination: destination.clone(),
-                        timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
-                    }),
+                    Ok(ref _fulfill) => {
+                        let incoming_payment_completed = reservation
+                            == Some(IncomingPaymentReservation::Reserved { completed: true });
+                        // Other packets on the connection may have been received since this one,
+                        // in which case their amounts are included in the total
+                        let connection_total_received =
+                            self.connections.as_ref().and_then(|tracking| {
+                                tracking
+                                    .store
+                                    .get_connection(connection_id)
+                                    .map(|connection| connection.total_received)
+                            });
+                        store.publish_payment_notification(PaymentNotification {
+                            to_username,
+                            from_username,
+                            amount,
+                            destination: destination.clone(),
+                            timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
+                            asset_code: request.to.asset_code().to_string(),
+                            asset_scale: request.to.asset_scale(),
+                            connection_tag: connection_tag.map(str::to_string),
+                            connection_total_received,
+                            incoming_payment_completed,
+                        })
+                    }
                     Err(ref reject) => {
                         if reject.code() == ErrorCode::F06_UNEXPECTED_PAYMENT {
                             // Assume the packet isn't for us if the decryption step fails.
@@ -204,6 +872,13 @@ where
     }
 }
 
+/// Whether the sender derived the packet's condition from its data and our shared secret.
+/// Senders use packets with other conditions to probe the path, for example to get a quote
+fn is_fulfillable(shared_secret: &[u8; 32], prepare: &Prepare) -> bool {
+    let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
+    hash_sha256(&fulfillment) == prepare.execution_condition()
+}
+
 // TODO send asset code and scale back to sender also
 #[allow(clippy::cognitive_complexity)]
 fn receive_money(
@@ -214,6 +889,9 @@ fn receive_money(
     asset_code: &str,
     asset_scale: u8,
     prepare: &Prepare,
+    limits: IncomingLimits,
+    // The connection the packet is for, if the receiver is stateful
+    connection: Option<ConnectionContext<'_>>,
 ) -> Result<Fulfill, Reject> {
     // Generate fulfillment
     let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
@@ -226,8 +904,8 @@ fn receive_money(
 
     // Note that we are copying the Prepare packet data. This is a bad idea
     // in cases where STREAM is used to send a significant amount of data.
-    // This implementation doesn't currently support handling the STREAM data
-    // so copying the bytes of the other STREAM frames shouldn't be a big
+    // Data is limited to what fits in a Prepare packet anyways, so
+    // copying the bytes of the STREAM frames shouldn't be a big
     // performance hit in practice.
     // The data is copied so that we can take the Prepare packet by
     // reference in the case that the decryption fails and we want to pass
@@ -245,35 +923,235 @@ fn receive_money(
         .build()
     })?;
 
+    // Declared before the response frames, which borrow from them
+    let mut receipts: Vec<(u64, Vec<u8>)> = Vec::new();
     let mut response_frames: Vec<Frame> = Vec::new();
+    let mut money_frames: Vec<StreamMoneyFrame> = Vec::new();
+    let mut data_frames: Vec<StreamDataFrame> = Vec::new();
+    let mut closed_streams: Vec<u64> = Vec::new();
+    let mut close_connection = false;
+    let mut source_amount: Option<SourceAmountFrame> = None;
 
     // Handle STREAM frames
-    // TODO reject if they send data?
     for frame in stream_packet.frames() {
-        // Tell the sender the stream can handle lots of money
-        if let Frame::StreamMoney(ref frame) = frame {
-            response_frames.push(Frame::StreamMaxMoney(StreamMaxMoneyFrame {
-                stream_id: frame.stream_id,
-                // TODO will returning zero here cause problems?
-                total_received: 0,
-                receive_max: u64::max_value(),
-            }));
+        match frame {
+            Frame::SourceAmount(frame) => source_amount = Some(frame),
+            Frame::StreamMoney(frame) => money_frames.push(frame),
+            Frame::StreamData(frame) => data_frames.push(frame),
+            Frame::StreamClose(frame) => closed_streams.push(frame.stream_id),
+            Frame::ConnectionClose(_) => close_connection = true,
+            // If we receive a ConnectionNewAddress frame, then send them our asset
+            // code & scale. The client is suppoesd to only send the
+            // ConnectionNewAddress frame once, so we expect that we will only have
+            // to respond with the ConnectionAssetDetails frame only one time.
+            Frame::ConnectionNewAddress(_) => {
+                response_frames.push(Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                    source_asset_code: asset_code,
+                    source_asset_scale: asset_scale,
+                }))
+            }
+            _ => {}
         }
+    }
+
+    let has_enough_money = prepare_amount >= stream_packet.prepare_amount();
+    // Packets without money are never refused by the receiving account's limits
+    let is_below_min_amount = prepare_amount > 0 && prepare_amount < limits.min_packet_amount;
+    // The rate is measured from the amount the sender declares it sent, since the ILP packet
+    // itself only carries the amount it arrived at this node with
+    let is_below_min_rate = prepare_amount > 0
+        && match (limits.min_exchange_rate, source_amount) {
+            (Some(min_rate), Some(source_amount)) => is_below_min_exchange_rate(
+                source_amount.source_amount,
+                source_amount.source_asset_scale,
+                prepare_amount,
+                asset_scale,
+                min_rate,
+            ),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+    let is_incoming_payment_closed =
+        limits.incoming_payment == Some(IncomingPaymentReservation::Closed);
+    let exceeds_incoming_payment =
+        limits.incoming_payment == Some(IncomingPaymentReservation::ExceedsRemaining);
+    let mut should_fulfill = is_fulfillable
+        && has_enough_money
+        && !is_below_min_amount
+        && !is_below_min_rate
+        && !is_incoming_payment_closed
+        && !exceeds_incoming_payment;
+
+    match connection {
+        Some(ConnectionContext {
+            connection_id,
+            tracking,
+            data_sender,
+            receipt_details,
+        }) => {
+            let mut state = ConnectionState::default();
+            let mut accepted = false;
+            let mut flow_control_violations: Vec<u64> = Vec::new();
+            // Connections are only recorded once the receiver accepts a packet or data on them,
+            // so that probes and rejected packets do not fill up the store
+            let is_recorded = should_fulfill
+                || !data_frames.is_empty()
+                || tracking.store.get_connection(connection_id).is_some();
+            if is_recorded {
+                tracking.store.update_connection(
+                    connection_id,
+                    &mut |connection: &mut ConnectionState| {
+                        // Data is handled even if the packet is rejected, since the sender
+                        // sends data on its own in unfulfillable packets
+                        for frame in data_frames.iter() {
+                            if let Err(StreamErrorCode::FlowControlError) =
+                                connection.receive_data(frame.stream_id, frame.offset, frame.data)
+                            {
+                                flow_control_violations.push(frame.stream_id);
+                            }
+                        }
+                        // This also retries delivering data the channel was too full for before
+                        connection.deliver_data(&mut |stream_id, data| {
+                            let data_sender = match data_sender {
+                                Some(data_sender) => data_sender,
+                                // Without a channel to deliver it to, the data is dropped
+                                None => return Ok(()),
+                            };
+                            let message = StreamDataMessage {
+                                connection_id: connection_id.to_string(),
+                                stream_id,
+                                data,
+                            };
+                            match data_sender.lock().try_send(message) {
+                                Ok(()) => Ok(()),
+                                Err(err) if err.is_full() => Err(err.into_inner().data),
+                                Err(_) => {
+                                    debug!("Data channel was closed, dropping data received on connection");
+                                    Ok(())
+                                }
+                            }
+                        });
+                        for stream_id in closed_streams.iter() {
+                            connection.streams.entry(*stream_id).or_default().closed = true;
+                        }
+                        if close_connection {
+                            connection.closed = true;
+                        }
+                        accepted = should_fulfill
+                            && connection.try_receive(
+                                prepare_amount,
+                                &money_frames,
+                                tracking.receive_max,
+                            );
+                        state = connection.clone();
+                    },
+                );
+            }
+            if should_fulfill && !accepted {
+                debug!(
+                    "Connection {} is closed or cannot receive {} more",
+                    connection_id, prepare_amount
+                );
+            }
+            should_fulfill = accepted;
+
+            // Receipts are only issued for money the receiver accepted
+            if let Some(receipt_details) = receipt_details.filter(|_| accepted) {
+                for frame in money_frames.iter() {
+                    if let Some(stream) = state.streams.get(&frame.stream_id) {
+                        let receipt = create_receipt(
+                            &receipt_details,
+                            frame.stream_id,
+                            stream.total_received,
+                        );
+                        receipts.push((frame.stream_id, receipt));
+                    }
+                }
+            }
 
-        // If we receive a ConnectionNewAddress frame, then send them our asset
-        // code & scale. The client is suppoesd to only send the
-        // ConnectionNewAddress frame once, so we expect that we will only have
-        // to respond with the ConnectionAssetDetails frame only one time.
-        if let Frame::ConnectionNewAddress(_) = frame {
-            response_frames.push(Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
-                source_asset_code: asset_code,
-                source_asset_scale: asset_scale,
-            }));
+            // Tell the sender how much money and data each stream has received, and which streams are closed
+            let stream_ids: BTreeSet<u64> = money_frames
+                .iter()
+                .map(|frame| frame.stream_id)
+                .chain(data_frames.iter().map(|frame| frame.stream_id))
+                .collect();
+            for stream_id in stream_ids {
+                let stream = state.streams.get(&stream_id).cloned().unwrap_or_default();
+                if money_frames
+                    .iter()
+                    .any(|frame| frame.stream_id == stream_id)
+                {
+                    response_frames.push(Frame::StreamMaxMoney(StreamMaxMoneyFrame {
+                        stream_id,
+                        total_received: stream.total_received,
+                        receive_max: tracking.receive_max,
+                    }));
+                }
+                if let Some((_, receipt)) = receipts.iter().find(|(id, _)| *id == stream_id) {
+                    response_frames.push(Frame::StreamReceipt(StreamReceiptFrame {
+                        stream_id,
+                        receipt,
+                    }));
+                }
+                if data_frames.iter().any(|frame| frame.stream_id == stream_id) {
+                    response_frames.push(Frame::StreamMaxData(StreamMaxDataFrame {
+                        stream_id,
+                        max_offset: stream.max_data_offset(),
+                    }));
+                }
+                if stream.closed {
+                    let code = if flow_control_violations.contains(&stream_id) {
+                        StreamErrorCode::FlowControlError
+                    } else {
+                        StreamErrorCode::NoError
+                    };
+                    response_frames.push(Frame::StreamClose(StreamCloseFrame {
+                        stream_id,
+                        code,
+                        message: "",
+                    }));
+                }
+            }
+            if state.closed {
+                response_frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+                    code: StreamErrorCode::NoError,
+                    message: "",
+                }));
+            }
+        }
+        None => {
+            if !data_frames.is_empty() {
+                debug!("Ignoring STREAM data since connection state is not tracked");
+            }
+            // Without connection state, tell the sender the stream can handle lots of money
+            for frame in money_frames.iter() {
+                response_frames.push(Frame::StreamMaxMoney(StreamMaxMoneyFrame {
+                    stream_id: frame.stream_id,
+                    total_received: 0,
+                    receive_max: u64::max_value(),
+                }));
+            }
         }
     }
 
+    // Tell the sender to stop, since every packet sent along the same path would be refused
+    let is_closing = response_frames
+        .iter()
+        .any(|frame| matches!(frame, Frame::ConnectionClose(_)));
+    if is_below_min_rate && !is_closing {
+        response_frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+            code: StreamErrorCode::ApplicationError,
+            message: "Exchange rate is below the receiver's minimum",
+        }));
+    } else if is_incoming_payment_closed && !is_closing {
+        response_frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+            code: StreamErrorCode::ApplicationError,
+            message: "Incoming payment is completed or expired",
+        }));
+    }
+
     // Return Fulfill or Reject Packet
-    if is_fulfillable && prepare_amount >= stream_packet.prepare_amount() {
+    if should_fulfill {
         let response_packet = StreamPacketBuilder {
             sequence: stream_packet.sequence(),
             ilp_packet_type: IlpPacketType::Fulfill,
@@ -304,20 +1182,40 @@ fn receive_money(
         .build();
         if !is_fulfillable {
             debug!("Packet is unfulfillable");
-        } else if prepare_amount < stream_packet.prepare_amount() {
+        } else if !has_enough_money {
             debug!(
                 "Received only: {} when we should have received at least: {}",
                 prepare_amount,
                 stream_packet.prepare_amount()
             );
+        } else if is_below_min_amount {
+            debug!(
+                "Received only: {} when the account accepts packets of at least: {}",
+                prepare_amount, limits.min_packet_amount
+            );
+        } else if is_below_min_rate {
+            debug!("Packet was converted at a lower rate than the account accepts");
+        } else if is_incoming_payment_closed {
+            debug!("Incoming payment is completed or expired");
+        } else if exceeds_incoming_payment {
+            debug!(
+                "Received: {} which exceeds what the incoming payment expects",
+                prepare_amount
+            );
         }
         debug!(
             "Rejecting Prepare and including encrypted stream packet {:?}",
             response_packet
         );
         let encrypted_response = response_packet.into_encrypted(shared_secret);
+        // The sender backs off and retries with smaller packets
+        let code = if exceeds_incoming_payment {
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        } else {
+            ErrorCode::F99_APPLICATION_ERROR
+        };
         let reject = RejectBuilder {
-            code: ErrorCode::F99_APPLICATION_ERROR,
+            code,
             message: &[],
             triggered_by: Some(&ilp_address),
             data: &encrypted_response[..],
@@ -351,6 +1249,111 @@ mod connection_generator {
             shared_secret
         );
     }
+
+    #[test]
+    fn encrypts_receipt_details_in_address() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[9; 32][..]));
+        let receipt_details = ReceiptDetails::random();
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&receiver_address, &receipt_details);
+
+        assert_eq!(
+            connection_generator
+                .rederive_secret(&destination_account)
+                .unwrap(),
+            shared_secret
+        );
+        assert_eq!(
+            connection_generator.receipt_details(&destination_account),
+            Some(receipt_details)
+        );
+
+        let (destination_account, _) =
+            connection_generator.generate_address_and_secret(&receiver_address);
+        assert_eq!(
+            connection_generator.receipt_details(&destination_account),
+            None
+        );
+        // Details encrypted by another receiver are ignored
+        let other_generator = ConnectionGenerator::new(Bytes::from(&[8; 32][..]));
+        let (destination_account, _) = other_generator.generate_address_and_secret_with_receipts(
+            &receiver_address,
+            &ReceiptDetails::random(),
+        );
+        assert_eq!(
+            connection_generator.receipt_details(&destination_account),
+            None
+        );
+    }
+
+    #[test]
+    fn authenticates_connection_tags() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[9; 32][..]));
+        let (destination_account, shared_secret) = connection_generator
+            .generate_tagged_address_and_secret(&receiver_address, "invoice-123", None)
+            .unwrap();
+        assert!(destination_account.to_string().ends_with("~invoice-123"));
+        assert_eq!(
+            ConnectionGenerator::connection_tag(&destination_account),
+            Some("invoice-123")
+        );
+        assert_eq!(
+            connection_generator
+                .rederive_secret(&destination_account)
+                .unwrap(),
+            shared_secret
+        );
+
+        // Changing the tag changes the shared secret, so packets sent with the original one fail to decrypt
+        let forged = Address::from_str(
+            &destination_account
+                .to_string()
+                .replace("invoice-123", "invoice-456"),
+        )
+        .unwrap();
+        assert_ne!(
+            connection_generator.rederive_secret(&forged).unwrap(),
+            shared_secret
+        );
+
+        let (destination_account, _) =
+            connection_generator.generate_address_and_secret(&receiver_address);
+        assert_eq!(
+            ConnectionGenerator::connection_tag(&destination_account),
+            None
+        );
+
+        assert!(connection_generator
+            .generate_tagged_address_and_secret(&receiver_address, "not.valid", None)
+            .is_err());
+        assert!(connection_generator
+            .generate_tagged_address_and_secret(&receiver_address, "", None)
+            .is_err());
+    }
+
+    #[test]
+    fn tags_connections_with_receipts() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[9; 32][..]));
+        let receipt_details = ReceiptDetails::random();
+        let (destination_account, _) = connection_generator
+            .generate_tagged_address_and_secret(
+                &receiver_address,
+                "order_1",
+                Some(&receipt_details),
+            )
+            .unwrap();
+        assert_eq!(
+            ConnectionGenerator::connection_tag(&destination_account),
+            Some("order_1")
+        );
+        assert_eq!(
+            connection_generator.receipt_details(&destination_account),
+            Some(receipt_details)
+        );
+    }
 }
 
 #[cfg(test)]
@@ -398,7 +1401,15 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            IncomingLimits::default(),
+            None,
+        );
         assert!(result.is_ok());
     }
 
@@ -425,7 +1436,15 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            IncomingLimits::default(),
+            None,
+        );
         assert!(result.is_ok());
     }
 
@@ -453,7 +1472,15 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            IncomingLimits::default(),
+            None,
+        );
         assert!(result.is_err());
     }
 
@@ -491,8 +1518,542 @@ mod receiving_money {
         let shared_secret = connection_generator
             .rederive_secret(&prepare.destination())
             .unwrap();
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            IncomingLimits::default(),
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_packets_below_min_amount() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret);
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+        let execution_condition = generate_condition(&shared_secret[..], &data);
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build();
+
+        let limits = IncomingLimits {
+            min_packet_amount: 101,
+            ..IncomingLimits::default()
+        };
+        let reject = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            limits,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        let reply =
+            StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data())).unwrap();
+        assert_eq!(reply.ilp_packet_type(), IlpPacketType::Reject);
+        assert!(!reply
+            .frames()
+            .any(|frame| matches!(frame, Frame::ConnectionClose(_))));
+
+        let limits = IncomingLimits {
+            min_packet_amount: 100,
+            ..IncomingLimits::default()
+        };
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            limits,
+            None,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn closes_connection_below_min_exchange_rate() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret);
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let prepare = |frames: &[Frame]| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence: 1,
+                frames,
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let execution_condition = generate_condition(&shared_secret[..], &data);
+            PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &execution_condition,
+            }
+            .build()
+        };
+        let receive = |prepare: &Prepare, min_rate: f64| {
+            let limits = IncomingLimits {
+                min_exchange_rate: Some(min_rate),
+                ..IncomingLimits::default()
+            };
+            receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                prepare,
+                limits,
+                None,
+            )
+        };
+        let money = Frame::StreamMoney(StreamMoneyFrame {
+            stream_id: 1,
+            shares: 1,
+        });
+        // The sender sent the same amount that arrived, in an asset with the same scale
+        let source_amount = Frame::SourceAmount(SourceAmountFrame {
+            source_amount: 100,
+            source_asset_scale: 9,
+        });
+
+        assert!(receive(&prepare(&[money.clone(), source_amount.clone()]), 0.9).is_ok());
+
+        let reject = receive(&prepare(&[money.clone(), source_amount]), 1.1).unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        let reply =
+            StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data())).unwrap();
+        match reply.frames().next() {
+            Some(Frame::ConnectionClose(frame)) => {
+                assert_eq!(frame.code, StreamErrorCode::ApplicationError)
+            }
+            frame => panic!("Expected a ConnectionClose frame, got: {:?}", frame),
+        }
+
+        // The rate of packets that don't declare their source amount can't be checked
+        let reject = receive(&prepare(&[money]), 0.9).unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+    }
+
+    #[test]
+    fn compares_exchange_rates_across_asset_scales() {
+        // 1.00 source units to 0.900 destination units is a rate of 0.9
+        assert!(!is_below_min_exchange_rate(100, 2, 900, 3, 0.9));
+        assert!(is_below_min_exchange_rate(100, 2, 899, 3, 0.9));
+        assert!(!is_below_min_exchange_rate(100, 2, 899, 3, 0.8));
+        // Nothing was converted
+        assert!(!is_below_min_exchange_rate(0, 2, 0, 3, 0.9));
+    }
+
+    #[test]
+    fn tracks_amount_received_per_stream() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret);
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let connection_id = destination_account.segments().rev().next().unwrap();
+        let connections = ConnectionTracking {
+            store: Arc::new(InMemoryConnectionStore::new()),
+            receive_max: 250,
+        };
+
+        let send_packet = |sequence: u64, amount: u64, frames: &[Frame]| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames,
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount,
+                expires_at: UNIX_EPOCH,
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+                data: &data[..],
+            }
+            .build();
+            let result = receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                IncomingLimits::default(),
+                Some(ConnectionContext {
+                    connection_id,
+                    tracking: &connections,
+                    data_sender: None,
+                    receipt_details: None,
+                }),
+            );
+            let reply_data = match &result {
+                Ok(fulfill) => fulfill.data(),
+                Err(reject) => reject.data(),
+            };
+            let reply =
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reply_data)).unwrap();
+            (result.is_ok(), reply)
+        };
+        let money = [Frame::StreamMoney(StreamMoneyFrame {
+            stream_id: 1,
+            shares: 1,
+        })];
+
+        let (fulfilled, reply) = send_packet(1, 100, &money);
+        assert!(fulfilled);
+        assert_eq!(
+            reply.frames().collect::<Vec<_>>(),
+            vec![Frame::StreamMaxMoney(StreamMaxMoneyFrame {
+                stream_id: 1,
+                receive_max: 250,
+                total_received: 100,
+            })]
+        );
+
+        // Exceeding the receive max is rejected without crediting the stream
+        let (fulfilled, _) = send_packet(2, 200, &money);
+        assert!(!fulfilled);
+        assert_eq!(
+            connections
+                .store
+                .get_connection(connection_id)
+                .unwrap()
+                .total_received,
+            100
+        );
+
+        // Reaching the receive max closes the stream
+        let (fulfilled, reply) = send_packet(3, 150, &money);
+        assert!(fulfilled);
+        assert_eq!(
+            reply.frames().collect::<Vec<_>>(),
+            vec![
+                Frame::StreamMaxMoney(StreamMaxMoneyFrame {
+                    stream_id: 1,
+                    receive_max: 250,
+                    total_received: 250,
+                }),
+                Frame::StreamClose(StreamCloseFrame {
+                    stream_id: 1,
+                    code: StreamErrorCode::NoError,
+                    message: "",
+                }),
+            ]
+        );
+        let (fulfilled, _) = send_packet(4, 0, &money);
+        assert!(!fulfilled);
+    }
+
+    #[test]
+    fn issues_receipts_with_total_received() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let receipt_details = ReceiptDetails::random();
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&ilp_address, &receipt_details);
+        let connection_id = destination_account.segments().rev().next().unwrap();
+        let connections = ConnectionTracking {
+            store: Arc::new(InMemoryConnectionStore::new()),
+            receive_max: u64::max_value(),
+        };
+
+        let mut receipts = Vec::new();
+        for amount in [100, 50].iter() {
+            let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: *amount,
+                expires_at: UNIX_EPOCH,
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+                data: &data[..],
+            }
+            .build();
+            let fulfill = receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                IncomingLimits::default(),
+                Some(ConnectionContext {
+                    connection_id,
+                    tracking: &connections,
+                    data_sender: None,
+                    receipt_details: connection_generator.receipt_details(&destination_account),
+                }),
+            )
+            .unwrap();
+            let reply =
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(fulfill.data()))
+                    .unwrap();
+            for frame in reply.frames() {
+                if let Frame::StreamReceipt(frame) = frame {
+                    receipts.push(frame.receipt.to_vec());
+                }
+            }
+        }
+
+        assert_eq!(receipts.len(), 2);
+        let receipt =
+            crate::receipt::verify_receipt(&receipts[1], &receipt_details.secret).unwrap();
+        assert_eq!(receipt.nonce, receipt_details.nonce);
+        assert_eq!(receipt.stream_id, 1);
+        assert_eq!(receipt.total_received, 150);
+    }
+
+    fn deliver_all(connection: &mut ConnectionState) -> Vec<(u64, Vec<u8>)> {
+        let mut delivered = Vec::new();
+        connection.deliver_data(&mut |stream_id, data| {
+            delivered.push((stream_id, data));
+            Ok(())
+        });
+        delivered
+    }
+
+    #[test]
+    fn reassembles_data_in_order() {
+        let mut connection = ConnectionState::default();
+        assert!(connection.receive_data(1, 5, b"world").is_ok());
+        assert!(deliver_all(&mut connection).is_empty());
+        assert!(connection.receive_data(1, 0, b"hello").is_ok());
+        assert_eq!(
+            deliver_all(&mut connection),
+            vec![(1, b"helloworld".to_vec())]
+        );
+        // Retried data is only delivered once
+        assert!(connection.receive_data(1, 5, b"world").is_ok());
+        assert!(deliver_all(&mut connection).is_empty());
+        assert_eq!(connection.streams[&1].data_offset, 10);
+
+        // Data past the buffer closes the stream
+        assert_eq!(
+            connection.receive_data(2, MAX_BUFFERED_DATA, b"too far"),
+            Err(StreamErrorCode::FlowControlError)
+        );
+        assert!(connection.streams[&2].closed);
+        // Data on a closed stream is refused
+        assert_eq!(
+            connection.receive_data(2, 0, b"closed"),
+            Err(StreamErrorCode::StreamStateError)
+        );
+    }
+
+    #[test]
+    fn trims_overlapping_data() {
+        let mut connection = ConnectionState::default();
+        assert!(connection.receive_data(1, 2, b"cd").is_ok());
+        assert!(connection.receive_data(1, 6, b"gh").is_ok());
+        // Overlaps both of the chunks buffered before
+        assert!(connection.receive_data(1, 3, b"DEFG").is_ok());
+        let pending: Vec<(u64, &[u8])> = connection.streams[&1]
+            .pending_data
+            .iter()
+            .map(|(offset, chunk)| (*offset, &chunk[..]))
+            .collect();
+        assert_eq!(
+            pending,
+            vec![(2, &b"cd"[..]), (4, &b"EF"[..]), (6, &b"gh"[..])]
+        );
+        assert!(connection.receive_data(1, 0, b"abc").is_ok());
+        assert_eq!(
+            deliver_all(&mut connection),
+            vec![(1, b"abcdEFgh".to_vec())]
+        );
+    }
+
+    #[test]
+    fn keeps_data_buffered_until_delivered() {
+        let mut connection = ConnectionState::default();
+        assert!(connection.receive_data(1, 0, b"hello").is_ok());
+        connection.deliver_data(&mut |_stream_id, data| Err(data));
+        // The sender may not send more until the application takes the data
+        assert_eq!(connection.streams[&1].data_offset, 0);
+        assert_eq!(connection.streams[&1].max_data_offset(), MAX_BUFFERED_DATA);
+
+        assert!(connection.receive_data(1, 5, b"world").is_ok());
+        assert_eq!(
+            deliver_all(&mut connection),
+            vec![(1, b"helloworld".to_vec())]
+        );
+        assert_eq!(
+            connection.streams[&1].max_data_offset(),
+            10 + MAX_BUFFERED_DATA
+        );
+    }
+
+    #[test]
+    fn rejects_money_after_connection_close() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret);
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let connection_id = destination_account.segments().rev().next().unwrap();
+        let store = InMemoryConnectionStore::new();
+        let connections = ConnectionTracking {
+            store: Arc::new(store.clone()),
+            receive_max: u64::max_value(),
+        };
+
+        let send_money = |amount: u64| {
+            let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount,
+                expires_at: UNIX_EPOCH,
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+                data: &data[..],
+            }
+            .build();
+            receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                IncomingLimits::default(),
+                Some(ConnectionContext {
+                    connection_id,
+                    tracking: &connections,
+                    data_sender: None,
+                    receipt_details: None,
+                }),
+            )
+        };
+        assert!(send_money(50).is_ok());
+
+        let close_packet = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence: 1,
+            frames: &[Frame::ConnectionClose(ConnectionCloseFrame {
+                code: StreamErrorCode::NoError,
+                message: "",
+            })],
+        }
+        .build();
+        let data = close_packet.into_encrypted(&shared_secret[..]);
+        let prepare = PrepareBuilder {
+            destination: destination_account.clone(),
+            amount: 0,
+            expires_at: UNIX_EPOCH,
+            execution_condition: &random_condition(),
+            data: &data[..],
+        }
+        .build();
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            IncomingLimits::default(),
+            Some(ConnectionContext {
+                connection_id,
+                tracking: &connections,
+                data_sender: None,
+                receipt_details: None,
+            }),
+        );
+        assert!(result.is_err());
+        assert!(store.get_connection(connection_id).unwrap().closed);
+
+        assert!(send_money(100).is_err());
+        assert_eq!(
+            store.get_connection(connection_id).unwrap().total_received,
+            50
+        );
+    }
+
+    #[test]
+    fn does_not_record_connections_for_rejected_packets() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let connection_id = destination_account.segments().rev().next().unwrap();
+        let store = InMemoryConnectionStore::new();
+        let connections = ConnectionTracking {
+            store: Arc::new(store.clone()),
+            receive_max: u64::max_value(),
+        };
+
+        let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            execution_condition: &random_condition(),
+            data: &data[..],
+        }
+        .build();
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            IncomingLimits::default(),
+            Some(ConnectionContext {
+                connection_id,
+                tracking: &connections,
+                data_sender: None,
+                receipt_details: None,
+            }),
+        );
         assert!(result.is_err());
+        assert!(store.get_connection(connection_id).is_none());
+    }
+
+    #[test]
+    fn drops_idle_connections_when_full() {
+        let store = InMemoryConnectionStore::with_limits(2, Duration::from_secs(60));
+        let receive = |connection_id: &str| {
+            store.update_connection(connection_id, &mut |connection: &mut ConnectionState| {
+                connection.total_received += 1
+            })
+        };
+        for connection_id in ["a", "b", "a", "c"].iter() {
+            receive(connection_id);
+            // Make sure each update happens at a different time
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // b was idle the longest, so it made room for c
+        assert_eq!(store.get_connection("a").unwrap().total_received, 2);
+        assert!(store.get_connection("b").is_none());
+        assert_eq!(store.get_connection("c").unwrap().total_received, 1);
+
+        let store = InMemoryConnectionStore::with_limits(2, Duration::from_secs(0));
+        store.update_connection("a", &mut |_: &mut ConnectionState| {});
+        store.update_connection("b", &mut |_: &mut ConnectionState| {});
+        store.update_connection("c", &mut |_: &mut ConnectionState| {});
+        // Every connection was idle for longer than the timeout
+        assert!(store.get_connection("a").is_none());
+        assert!(store.get_connection("b").is_none());
+        assert!(store.get_connection("c").is_some());
     }
 
     #[test]
@@ -513,8 +2074,16 @@ mod receiving_money {
                 .as_ref() as &[u8],
             "did not regenerate the same shared secret",
         );
-        let fulfill = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare)
-            .expect("Receiver should be able to generate the fulfillment");
+        let fulfill = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            IncomingLimits::default(),
+            None,
+        )
+        .expect("Receiver should be able to generate the fulfillment");
         assert_eq!(
             &hash_sha256(fulfill.fulfillment())[..],
             &condition[..],
@@ -584,6 +2153,70 @@ mod stream_receiver_service {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn only_tracks_receipt_connections_if_configured() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let connections = InMemoryConnectionStore::new();
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        )
+        .with_connection_store(connections.clone(), u64::max_value())
+        .track_receipt_connections_only();
+
+        let (without_receipts, _) = connection_generator.generate_address_and_secret(&ilp_address);
+        let (with_receipts, _) = connection_generator
+            .generate_address_and_secret_with_receipts(&ilp_address, &ReceiptDetails::random());
+        for destination_account in vec![without_receipts.clone(), with_receipts.clone()] {
+            let shared_secret = connection_generator
+                .rederive_secret(&destination_account)
+                .unwrap();
+            let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account,
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+                data: &data[..],
+            }
+            .build();
+            let account = TestAccount {
+                id: Uuid::new_v4(),
+                ilp_address: ilp_address.clone(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                max_packet_amount: None,
+            };
+            let result = service
+                .send_request(OutgoingRequest {
+                    from: account.clone(),
+                    to: account,
+                    original_amount: prepare.amount(),
+                    prepare,
+                })
+                .await;
+            assert!(result.is_ok());
+        }
+
+        let connection_id =
+            |address: &Address| address.segments().rev().next().unwrap().to_string();
+        assert!(connections
+            .get_connection(&connection_id(&without_receipts))
+            .is_none());
+        assert_eq!(
+            connections
+                .get_connection(&connection_id(&with_receipts))
+                .unwrap()
+                .total_received,
+            100
+        );
+    }
+
     #[tokio::test]
     async fn rejects_invalid_packets() {
         let ilp_address = Address::from_str("example.destination").unwrap();
@@ -707,4 +2340,164 @@ mod stream_receiver_service {
             Address::from_str("example.other-receiver").unwrap(),
         );
     }
+
+    /// Incoming payments which expect the given amount in total
+    #[derive(Clone)]
+    struct TestIncomingPayments {
+        remaining: Arc<parking_lot::Mutex<HashMap<String, u64>>>,
+    }
+
+    #[async_trait]
+    impl IncomingPaymentStore for TestIncomingPayments {
+        async fn reserve_incoming_payment(
+            &self,
+            _account_id: Uuid,
+            id: &str,
+            amount: u64,
+        ) -> Result<IncomingPaymentReservation, IncomingPaymentStoreError> {
+            let mut remaining = self.remaining.lock();
+            Ok(match remaining.get_mut(id) {
+                None => IncomingPaymentReservation::NotFound,
+                Some(0) => IncomingPaymentReservation::Closed,
+                Some(remaining) if amount > *remaining => {
+                    IncomingPaymentReservation::ExceedsRemaining
+                }
+                Some(remaining) => {
+                    *remaining -= amount;
+                    IncomingPaymentReservation::Reserved {
+                        completed: *remaining == 0,
+                    }
+                }
+            })
+        }
+
+        async fn release_incoming_payment(
+            &self,
+            _account_id: Uuid,
+            id: &str,
+            amount: u64,
+        ) -> Result<(), IncomingPaymentStoreError> {
+            *self.remaining.lock().get_mut(id).unwrap() += amount;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_tagged_connections_to_incoming_payment() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, shared_secret) = connection_generator
+            .generate_tagged_address_and_secret(&ilp_address, "invoice", None)
+            .unwrap();
+        let incoming_payments = TestIncomingPayments {
+            remaining: Arc::new(parking_lot::Mutex::new(
+                vec![("invoice".to_string(), 150)].into_iter().collect(),
+            )),
+        };
+
+        let mut service = StreamReceiverService::new(
+            server_secret.clone(),
+            DummyStore,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        )
+        .with_incoming_payment_store(incoming_payments.clone());
+
+        let prepare = |amount: u64, is_fulfillable: bool| {
+            let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+            let execution_condition = if is_fulfillable {
+                generate_condition(&shared_secret[..], &data)
+            } else {
+                random_condition()
+            };
+            PrepareBuilder {
+                destination: destination_account.clone(),
+                amount,
+                expires_at: UNIX_EPOCH,
+                execution_condition: &execution_condition,
+                data: &data[..],
+            }
+            .build()
+        };
+        let request = |prepare: Prepare| {
+            let amount = prepare.amount();
+            let account = TestAccount {
+                id: Uuid::new_v4(),
+                ilp_address: ilp_address.clone(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                max_packet_amount: None,
+            };
+            OutgoingRequest {
+                from: account.clone(),
+                to: account,
+                original_amount: amount,
+                prepare,
+            }
+        };
+
+        assert!(service
+            .send_request(request(prepare(100, true)))
+            .await
+            .is_ok());
+        let reject = service
+            .send_request(request(prepare(100, true)))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+        // The sender gets a STREAM reply
+        assert!(
+            StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data())).is_ok()
+        );
+
+        // Probes do not reserve anything, even if they carry more than the incoming payment expects
+        let reject = service
+            .send_request(request(prepare(1000, false)))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert!(
+            StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data())).is_ok()
+        );
+        assert_eq!(incoming_payments.remaining.lock()["invoice"], 50);
+
+        // Packets rejected for other reasons give back what they reserved
+        let data = StreamPacketBuilder {
+            sequence: 1,
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 50,
+            frames: &[],
+        }
+        .build()
+        .into_encrypted(&shared_secret[..]);
+        let underpaid = PrepareBuilder {
+            destination: destination_account.clone(),
+            amount: 10,
+            expires_at: UNIX_EPOCH,
+            execution_condition: &generate_condition(&shared_secret[..], &data),
+            data: &data[..],
+        }
+        .build();
+        assert!(service.send_request(request(underpaid)).await.is_err());
+        assert_eq!(incoming_payments.remaining.lock()["invoice"], 50);
+
+        assert!(service
+            .send_request(request(prepare(50, true)))
+            .await
+            .is_ok());
+        assert_eq!(incoming_payments.remaining.lock()["invoice"], 0);
+        let reject = service
+            .send_request(request(prepare(1, true)))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::F99_APPLICATION_ERROR);
+        // The sender is told to stop
+        let reply =
+            StreamPacket::from_encrypted(&shared_secret, BytesMut::from(reject.data())).unwrap();
+        assert!(reply
+            .frames()
+            .any(|frame| matches!(frame, Frame::ConnectionClose(_))));
+    }
 }
@@ -0,0 +1,297 @@
+use super::client::{CancellationToken, StreamDelivery, StreamPayment, StreamSender};
+use super::congestion::CongestionStrategy;
+use super::error::Error;
+use interledger_packet::Address;
+use interledger_rates::ExchangeRateStore;
+use interledger_service::{Account, IncomingService};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::sync::Arc;
+use tokio::time::{interval, Duration, Instant};
+use tracing::debug;
+
+/// How often a streaming payment sends the money accrued since the previous tick
+const STREAMING_TICK: Duration = Duration::from_millis(500);
+
+/// Rate and limits of a [streaming payment](./fn.send_money_at_rate.html)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamingRate {
+    /// Amount to send per second, in source units
+    pub amount_per_second: u64,
+    /// Maximum total amount to send, in source units
+    pub max_amount: u64,
+    /// How long to stream for, not counting the time the payment is paused
+    pub duration: Option<Duration>,
+}
+
+impl StreamingRate {
+    /// Total amount owed after streaming for the given time
+    fn amount_owed(&self, streamed: Duration) -> u64 {
+        let owed = self.amount_per_second as u128 * streamed.as_millis() / 1000;
+        min(owed, self.max_amount as u128) as u64
+    }
+
+    fn is_finished(&self, streamed: Duration) -> bool {
+        self.amount_owed(streamed) >= self.max_amount
+            || self.duration.map_or(false, |duration| streamed >= duration)
+    }
+}
+
+/// Whether a streaming payment is sending money
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamingStatus {
+    Streaming,
+    Paused,
+    /// Stopped by the sender, or finished because it reached its maximum amount,
+    /// its duration or an error
+    Stopped,
+}
+
+#[derive(Debug)]
+struct StreamingState {
+    status: StreamingStatus,
+    delivery: Option<StreamDelivery>,
+    error: Option<String>,
+    stopped_at: Option<std::time::Instant>,
+}
+
+/// Controls a [streaming payment](./fn.send_money_at_rate.html) and reports its progress.
+///
+/// Clones of the handle control the same payment.
+#[derive(Clone, Debug)]
+pub struct StreamingPaymentHandle {
+    state: Arc<Mutex<StreamingState>>,
+    /// Cancelled while the payment is paused or stopped, so that it stops sending
+    /// the money of the current tick straight away
+    interrupted: CancellationToken,
+}
+
+impl Default for StreamingPaymentHandle {
+    fn default() -> Self {
+        StreamingPaymentHandle {
+            state: Arc::new(Mutex::new(StreamingState {
+                status: StreamingStatus::Streaming,
+                delivery: None,
+                error: None,
+                stopped_at: None,
+            })),
+            interrupted: CancellationToken::new(),
+        }
+    }
+}
+
+impl StreamingPaymentHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop sending money until the payment is resumed
+    pub fn pause(&self) {
+        let mut state = self.state.lock();
+        if state.status == StreamingStatus::Streaming {
+            state.status = StreamingStatus::Paused;
+            self.interrupted.cancel();
+        }
+    }
+
+    /// Resume sending money after the payment was paused
+    pub fn resume(&self) {
+        let mut state = self.state.lock();
+        if state.status == StreamingStatus::Paused {
+            state.status = StreamingStatus::Streaming;
+            self.interrupted.reset();
+        }
+    }
+
+    /// Stop the payment and close its connection. A stopped payment cannot be resumed
+    pub fn stop(&self) {
+        let mut state = self.state.lock();
+        state.status = StreamingStatus::Stopped;
+        state.stopped_at.get_or_insert_with(std::time::Instant::now);
+        self.interrupted.cancel();
+    }
+
+    /// Stop the payment because it failed before or while sending money
+    pub fn fail(&self, error: String) {
+        self.finish(Some(error));
+    }
+
+    pub fn status(&self) -> StreamingStatus {
+        self.state.lock().status
+    }
+
+    /// The cumulative amounts sent and delivered, once the payment has sent money
+    pub fn delivery(&self) -> Option<StreamDelivery> {
+        self.state.lock().delivery.clone()
+    }
+
+    /// Why the payment stopped, if it failed
+    pub fn error(&self) -> Option<String> {
+        self.state.lock().error.clone()
+    }
+
+    /// When the payment was stopped, or finished
+    pub fn stopped_at(&self) -> Option<std::time::Instant> {
+        self.state.lock().stopped_at
+    }
+
+    fn update_delivery(&self, delivery: StreamDelivery) {
+        self.state.lock().delivery = Some(delivery);
+    }
+
+    fn finish(&self, error: Option<String>) {
+        let mut state = self.state.lock();
+        state.status = StreamingStatus::Stopped;
+        state.error = error;
+        state.stopped_at.get_or_insert_with(std::time::Instant::now);
+        self.interrupted.cancel();
+    }
+}
+
+/// Send money at the given rate over a single STREAM connection, until the payment is stopped
+/// through its handle or reaches the rate's maximum amount or duration.
+///
+/// Returns the receipt with the cumulative sent & delivered amounts.
+#[allow(clippy::too_many_arguments)]
+pub async fn send_money_at_rate<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    rate: StreamingRate,
+    slippage: f64,
+    congestion_strategy: CongestionStrategy,
+    handle: StreamingPaymentHandle,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let payment = StreamPayment::new_streaming(
+        from_account,
+        destination_account,
+        rate.max_amount,
+        congestion_strategy,
+    );
+    // Pausing or stopping the payment also stops it from sending what is owed for the current tick
+    let mut sender = StreamSender::new(
+        service,
+        from_account.clone(),
+        shared_secret,
+        store,
+        slippage,
+        payment,
+    )
+    .with_hooks(None, Some(handle.interrupted.clone()));
+
+    let mut ticks = interval(STREAMING_TICK);
+    let mut last_tick = Instant::now();
+    let mut streamed = Duration::from_secs(0);
+    let mut was_paused = false;
+    loop {
+        ticks.tick().await;
+        let now = Instant::now();
+        let elapsed = now - last_tick;
+        last_tick = now;
+
+        match handle.status() {
+            StreamingStatus::Stopped => break,
+            StreamingStatus::Paused => {
+                was_paused = true;
+                continue;
+            }
+            StreamingStatus::Streaming => {}
+        }
+        // Time spent paused doesn't count towards the timeout since the last fulfill
+        if was_paused {
+            sender.restart().await;
+            was_paused = false;
+        }
+        streamed += elapsed;
+        if let Some(duration) = rate.duration {
+            streamed = min(streamed, duration);
+        }
+
+        sender
+            .increase_source_amount(rate.amount_owed(streamed))
+            .await;
+        let result = sender.send_until_complete().await;
+        handle.update_delivery(sender.delivery().await);
+        if let Err(error) = result {
+            handle.finish(Some(error.to_string()));
+            return Err(sender.incomplete(error).await);
+        }
+        if rate.is_finished(streamed) {
+            break;
+        }
+    }
+
+    sender.try_send_connection_close().await;
+    let delivery = sender.delivery().await;
+    debug!(
+        "Streaming payment finished. Delivered: {}",
+        delivery.delivered_amount
+    );
+    handle.finish(None);
+    Ok(delivery)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accrues_amount_up_to_max() {
+        let rate = StreamingRate {
+            amount_per_second: 100,
+            max_amount: 250,
+            duration: None,
+        };
+        assert_eq!(rate.amount_owed(Duration::from_millis(500)), 50);
+        assert_eq!(rate.amount_owed(Duration::from_secs(2)), 200);
+        assert!(!rate.is_finished(Duration::from_secs(2)));
+        assert_eq!(rate.amount_owed(Duration::from_secs(3)), 250);
+        assert!(rate.is_finished(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn finishes_after_duration() {
+        let rate = StreamingRate {
+            amount_per_second: 100,
+            max_amount: 1000,
+            duration: Some(Duration::from_secs(1)),
+        };
+        assert!(!rate.is_finished(Duration::from_millis(500)));
+        assert!(rate.is_finished(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn stopped_payments_cannot_resume() {
+        let handle = StreamingPaymentHandle::new();
+        handle.pause();
+        assert_eq!(handle.status(), StreamingStatus::Paused);
+        handle.resume();
+        assert_eq!(handle.status(), StreamingStatus::Streaming);
+        handle.stop();
+        handle.resume();
+        assert_eq!(handle.status(), StreamingStatus::Stopped);
+    }
+
+    #[test]
+    fn interrupts_sending_while_paused_or_stopped() {
+        let handle = StreamingPaymentHandle::new();
+        assert!(!handle.interrupted.is_cancelled());
+        handle.pause();
+        assert!(handle.interrupted.is_cancelled());
+        handle.resume();
+        assert!(!handle.interrupted.is_cancelled());
+        handle.fail("receiver not found".to_string());
+        assert!(handle.interrupted.is_cancelled());
+        assert_eq!(handle.status(), StreamingStatus::Stopped);
+        assert_eq!(handle.error(), Some("receiver not found".to_string()));
+    }
+}